@@ -20,6 +20,9 @@ fn main() -> Result<()> {
         benchmark_provider: None,
         onnx_variant: None,
         model_dir: None,
+        mask_threshold: None,
+        collect_timings: false,
+        emit_mask_rle: false,
     })?;
 
     let android = android_unbg::process_image(android_unbg::AndroidBridgeRequest {
@@ -32,6 +32,9 @@ fn adapters_produce_consistent_mask_shape() -> Result<()> {
         benchmark_provider: None,
         onnx_variant: None,
         model_dir: None,
+        mask_threshold: None,
+        collect_timings: false,
+        emit_mask_rle: false,
     })?;
 
     let android = android_unbg::process_image(android_unbg::AndroidBridgeRequest {
@@ -1,5 +1,7 @@
+use std::collections::{HashSet, VecDeque};
+use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::time::Instant;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 use anyhow::{anyhow, Result};
 use clap::{Args, Parser, Subcommand};
@@ -7,13 +9,34 @@ use image::GenericImageView;
 use regex::Regex;
 use walkdir::WalkDir;
 use unbg_core::{
-    run_inference_with_telemetry, ExecutionProvider, GpuBackendPreference, InferenceRequest, ModelKind, OnnxVariant, PlatformTarget,
-    RuntimeConfig, RuntimePolicy,
+    default_overlay_filename, run_inference, run_inference_batch_with_telemetry, run_inference_with_telemetry, validate_alpha_curve, validate_cutout_extension, CancelToken,
+    EmptyMaskPolicy, EnsembleMode, ExecutionProvider, GpuBackendPreference, GraphOptLevel, InferenceRequest, InferenceResult, MaskActivation, ModelKind, OnnxVariant,
+    OutputNaming, PlatformTarget, RegionOfInterest, RuntimeConfig, RuntimePolicy, SessionTuning, TelemetryEvent, TelemetryEventType,
+    ThreadPriority, TrimapThresholds, DEFAULT_BACKEND_ID,
+};
+use unbg_bench::{default_cases, run_case};
+use unbg_image::{
+    apply_effects, apply_mask_to_rgba, composite_over_background, contact_sheet, decode_image, decode_source_and_mask,
+    embed_png_text, encode_image, encode_rgba_png, has_exif_rotation, read_icc_profile, set_alpha_from_mask, sniff_format,
+    supported_extensions,
+    Backdrop, CutoutStream, CutoutStreamError, DecodeHints, Effect, EncodeOptions, SourceFormat,
+};
+use unbg_installer::{
+    export_models, install_from_archive, install_models, migrate_legacy_layout, verify_models, InstallRequest,
+    DEFAULT_DOWNLOAD_BUFFER_SIZE,
+};
+use unbg_model_registry::{
+    built_in_manifest, check_lock_against_manifest, find_installed_model, model_revision_dir, model_search_path, read_lockfile,
+    resolve_model_paths, search_path_from, CustomModel, KnownModel, ModelLock, ModelManifest, ModelPaths, Normalization, MODEL_PATH_ENV,
 };
-use unbg_installer::{install_models, verify_models, InstallRequest};
-use unbg_model_registry::{model_revision_dir, read_lockfile, resolve_model_paths, KnownModel};
 use unbg_telemetry::sink_from_env;
-use unbg_runtime_ort::LocalOrtBackend;
+use unbg_remote::{register_remote_backend, RemoteBackendConfig, REMOTE_BACKEND_ID};
+use unbg_runtime_ort::{
+    cache_stats, default_registry, detect_available_variants, execution_plan, inspect_model, rank_onnx_files, read_run_history, run_history_file,
+    self_test_in, set_current_thread_priority, ExecutionPlan, LocalOrtBackend, RunHistoryEntry,
+};
+
+mod serve;
 
 #[derive(Parser, Debug)]
 #[command(name = "unbg", version, about = "UNBG local model tooling")]
@@ -26,7 +49,15 @@ struct Cli {
 enum TopLevelCommand {
     Models(ModelsCommand),
     #[command(name = "exec")]
-    Exec(ExecArgs),
+    Exec(Box<ExecArgs>),
+    /// Time inference on synthetic images and report latency percentiles.
+    Bench(BenchArgs),
+    /// Serve the v1 remove-background API over HTTP.
+    Serve(ServeArgs),
+    /// Build a cutout from an existing mask without running inference.
+    Composite(CompositeArgs),
+    /// Show the most recent runs recorded in the run-history log (`UNBG_RUN_HISTORY`).
+    History(HistoryArgs),
 }
 
 #[derive(Args, Debug)]
@@ -38,9 +69,21 @@ struct ModelsCommand {
 #[derive(Subcommand, Debug)]
 enum ModelsSubcommand {
     Install(InstallArgs),
-    List(CommonModelArgs),
+    /// Install models from a .zip or .tar.gz bundle instead of downloading them.
+    InstallArchive(InstallArchiveArgs),
+    /// Package installed models into a .tar.gz that install-archive accepts.
+    Export(ExportArgs),
+    List(ListArgs),
     Verify(CommonModelArgs),
     Update(UpdateArgs),
+    /// Show which onnx file would be loaded for a model and why.
+    Explain(ExplainArgs),
+    /// Print the inputs and outputs of an arbitrary onnx file.
+    InspectFile(InspectFileArgs),
+    /// Compare installed revisions against the model manifest and flag drift.
+    Check(CheckArgs),
+    /// Move model files from an older directory layout into the current one and rebuild the lockfile.
+    Migrate(MigrateArgs),
 }
 
 #[derive(Args, Debug)]
@@ -49,6 +92,15 @@ struct CommonModelArgs {
     model_dir: Option<PathBuf>,
 }
 
+#[derive(Args, Debug)]
+struct ListArgs {
+    #[arg(long)]
+    model_dir: Option<PathBuf>,
+    /// Include per-model totals (file count, bytes, onnx variants) next to the raw lockfile entries.
+    #[arg(long)]
+    summary: bool,
+}
+
 #[derive(Args, Debug)]
 struct InstallArgs {
     #[arg(long)]
@@ -67,6 +119,63 @@ struct InstallArgs {
     verify_only: bool,
     #[arg(long, default_value = "fp16")]
     onnx_variant: String,
+    /// Delete and re-download revisions that are already on disk.
+    #[arg(long)]
+    force: bool,
+    /// Keep the partial download directory when an install fails.
+    #[arg(long)]
+    keep_temp_on_failure: bool,
+    /// Stage downloads here instead of `cache/downloads` in the model dir (or set `UNBG_DOWNLOAD_TMP`).
+    #[arg(long)]
+    temp_dir: Option<PathBuf>,
+    /// Report bytes per second for each downloaded file under `throughput`.
+    #[arg(long)]
+    report_throughput: bool,
+    /// Also install this Hugging Face repo as a custom model, e.g. `acme/product-matting`.
+    #[arg(long)]
+    custom_model: Option<String>,
+    #[arg(long, default_value = "main")]
+    custom_revision: String,
+    /// Square input edge the custom model's export expects.
+    #[arg(long, default_value_t = 1024)]
+    custom_input_size: u32,
+    /// Input normalization of the custom model: `imagenet` or `centered` (`pixel / max - 0.5`).
+    #[arg(long, default_value = "imagenet")]
+    custom_normalization: Normalization,
+}
+
+#[derive(Args, Debug)]
+struct InstallArchiveArgs {
+    path: PathBuf,
+    #[arg(long)]
+    model_dir: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct ExportArgs {
+    #[arg(long)]
+    out: PathBuf,
+    /// Models to include; defaults to every model in the lockfile.
+    #[arg(long = "model")]
+    models: Vec<String>,
+    #[arg(long)]
+    model_dir: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct MigrateArgs {
+    /// Directory holding the old install, e.g. `~/old-models` with a `RMBG-1.4/` folder inside.
+    #[arg(long)]
+    from: PathBuf,
+    #[arg(long)]
+    model_dir: Option<PathBuf>,
+    /// Lockfile recording the old install's revisions and file hashes. Legacy files are only moved
+    /// when they match it, an `unbg-model-lock.json` under `--from` or the model dir's lockfile.
+    #[arg(long)]
+    lock: Option<PathBuf>,
+    /// Report what would be moved without touching any files.
+    #[arg(long)]
+    dry_run: bool,
 }
 
 #[derive(Args, Debug)]
@@ -81,6 +190,141 @@ struct UpdateArgs {
     onnx_variant: String,
 }
 
+#[derive(Args, Debug)]
+struct ExplainArgs {
+    #[arg(long, short = 'M', default_value = "fast")]
+    model: String,
+    #[arg(long, short = 'v', default_value = "fp16")]
+    onnx_variant: String,
+    #[arg(long)]
+    model_dir: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct InspectFileArgs {
+    path: PathBuf,
+}
+
+#[derive(Args, Debug)]
+struct CheckArgs {
+    #[arg(long)]
+    model_dir: Option<PathBuf>,
+    /// JSON array of manifest entries to check against instead of the built-in manifest.
+    #[arg(long)]
+    manifest: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct BenchArgs {
+    #[arg(long, short = 'M', default_value = "fast")]
+    model: String,
+    #[arg(long, short = 'v', default_value = "fp16")]
+    onnx_variant: String,
+    #[arg(long, short = 'e', default_value = "gpu")]
+    execution_provider: String,
+    #[arg(long, short = 'g', default_value = "auto")]
+    gpu_backend: String,
+    #[arg(long, short = 'd')]
+    model_dir: Option<PathBuf>,
+    /// Timed runs per case.
+    #[arg(long, default_value_t = 10)]
+    runs: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CollisionPolicy {
+    Overwrite,
+    /// Leave existing outputs alone, so interrupted batch runs can resume.
+    Skip,
+    /// Write to the first free `<name>_<n>.<ext>` instead.
+    Rename,
+}
+
+#[derive(Args, Debug)]
+struct CompositeArgs {
+    #[arg(long, short = 'i')]
+    input: PathBuf,
+    /// Grayscale mask with the same dimensions as the input, e.g. from `exec -m`.
+    #[arg(long, short = 'm')]
+    mask: PathBuf,
+    #[arg(long, short = 'o')]
+    output: PathBuf,
+    /// Flatten onto this hex RGB color instead of leaving the background transparent.
+    #[arg(long)]
+    background: Option<String>,
+    #[arg(long, value_parser = parse_stroke)]
+    stroke: Option<Effect>,
+    #[arg(long, value_parser = parse_shadow)]
+    shadow: Option<Effect>,
+    /// zlib level for PNG outputs, from 0 (fastest, largest) to 9 (smallest).
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=9))]
+    png_compression: Option<u8>,
+    /// Quality for JPEG outputs, 0 to 100 (WebP outputs are always lossless).
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=100))]
+    quality: Option<u8>,
+}
+
+#[derive(Args, Debug)]
+struct HistoryArgs {
+    #[arg(long, short = 'd')]
+    model_dir: Option<PathBuf>,
+    /// Log to read; defaults to `UNBG_RUN_HISTORY`, then `<root>/cache/run-history.jsonl`.
+    #[arg(long)]
+    file: Option<PathBuf>,
+    /// How many of the newest matching entries to print.
+    #[arg(long, short = 'n', default_value_t = 20)]
+    tail: usize,
+    #[arg(long, short = 'M', default_value = "auto")]
+    model: String,
+    /// Only runs on this provider (`cpu`, `gpu`) or GPU backend (e.g. `cuda`).
+    #[arg(long)]
+    provider: Option<String>,
+    /// Only runs that took at least this many milliseconds.
+    #[arg(long)]
+    min_ms: Option<u64>,
+    /// Only runs within this long ago, e.g. 90s, 30m, 12h or 7d.
+    #[arg(long, value_parser = parse_age)]
+    since: Option<u64>,
+    /// Only runs that failed or fell back to another provider or model.
+    #[arg(long)]
+    problems: bool,
+}
+
+#[derive(Args, Debug)]
+struct ServeArgs {
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    addr: String,
+    #[arg(long, short = 'M', default_value = "fast")]
+    model: String,
+    #[arg(long, short = 'v', default_value = "fp16")]
+    onnx_variant: String,
+    #[arg(long, short = 'e', default_value = "gpu")]
+    execution_provider: String,
+    #[arg(long, short = 'g', default_value = "auto")]
+    gpu_backend: String,
+    #[arg(long, short = 'd')]
+    model_dir: Option<PathBuf>,
+    #[arg(long, short = 'p', default_value_t = 2_000_000)]
+    max_inference_pixels: u32,
+    #[arg(long, short = 'a', default_value_t = true)]
+    allow_rmbg20: bool,
+    /// Inference backend id from the backend registry.
+    #[arg(long, default_value = DEFAULT_BACKEND_ID)]
+    backend: String,
+    /// ONNX Runtime threads per operator; caps CPU use when several servers share a host.
+    #[arg(long)]
+    intra_threads: Option<usize>,
+    /// ONNX Runtime threads across independent graph branches.
+    #[arg(long)]
+    inter_threads: Option<usize>,
+    /// Graph optimization level: `disable`, `basic`, `extended`, `layout` or `all`.
+    #[arg(long, default_value = "all", value_parser = parse_graph_opt)]
+    graph_opt: GraphOptLevel,
+    /// Requests handled at once; a few more wait in a queue and the rest get a 503.
+    #[arg(long, default_value_t = 4)]
+    workers: usize,
+}
+
 #[derive(Args, Debug)]
 struct ExecArgs {
     #[arg(long, short = 'i')]
@@ -94,6 +338,9 @@ struct ExecArgs {
     /// If set, abort the whole run on the first input error.
     #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
     strict: bool,
+    /// Write a JSON progress line to this file descriptor as each input finishes (stderr if it is not open).
+    #[arg(long)]
+    progress_fd: Option<i32>,
     #[arg(long, short = 'M', default_value = "fast")]
     model: String,
     #[arg(long, short = 'p', default_value_t = 2_000_000)]
@@ -106,9 +353,41 @@ struct ExecArgs {
     output_cutout: Option<PathBuf>,
     #[arg(long, short = 'm')]
     output_mask: Option<PathBuf>,
+    /// Extra cutout in the format of its extension (repeatable): `.png` and `.webp` keep
+    /// transparency, `.jpg` is flattened onto white. With multiple inputs only its directory and
+    /// extension are used.
+    #[arg(long = "output")]
+    outputs: Vec<PathBuf>,
     /// Output directory used when processing multiple inputs.
     #[arg(long)]
     output_dir: Option<PathBuf>,
+    /// Mirror each input's subdirectory (relative to the input dir or regex root) under the output dir.
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    preserve_structure: bool,
+    /// What to do when an output file already exists: overwrite, skip or rename.
+    #[arg(long, default_value = "overwrite", value_parser = parse_collision_policy)]
+    on_collision: CollisionPolicy,
+    /// Write the original with the mask blended on top as a tinted overlay (for visual QA).
+    #[arg(long)]
+    output_overlay: Option<PathBuf>,
+    /// Overlay tint as a hex RGB color.
+    #[arg(long, default_value = "ff0000")]
+    overlay_color: String,
+    /// Composite cutouts over this hex RGB or RGBA color instead of leaving them transparent.
+    #[arg(long)]
+    bg_color: Option<String>,
+    /// Overlay strength at fully-foreground pixels (0.0 - 1.0).
+    #[arg(long, default_value_t = 0.5)]
+    overlay_opacity: f32,
+    /// After the run, tile original, mask and cutout thumbnails of every processed input into one image.
+    #[arg(long)]
+    contact_sheet: Option<PathBuf>,
+    /// Inputs per contact sheet row; each input takes three thumbnails.
+    #[arg(long, default_value_t = 1)]
+    contact_sheet_columns: u32,
+    /// Contact sheet thumbnail size in pixels (longer side).
+    #[arg(long, default_value_t = 256)]
+    contact_sheet_thumb: u32,
     #[arg(long, short = 'v', default_value = "fp16")]
     onnx_variant: String,
     #[arg(long, short = 'e', default_value = "gpu")]
@@ -119,10 +398,115 @@ struct ExecArgs {
     benchmark_provider: bool,
     #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
     profile: bool,
+    /// Write the mask as a trimap (0/128/255) using LOW:HIGH alpha cutoffs, e.g. `0.1:0.9`.
+    #[arg(long, value_parser = parse_trimap)]
+    trimap: Option<TrimapThresholds>,
+    /// Binarize the mask: alpha below this cutoff (0.0-1.0) becomes 0, everything else 255.
+    #[arg(long)]
+    mask_threshold: Option<f32>,
+    /// Remap alpha through a piecewise-linear curve of `IN:OUT` points from 0 to 1,
+    /// e.g. `0:0,0.4:0.8,1:1` to push mid-confidence edges toward foreground.
+    #[arg(long, value_parser = parse_alpha_curve)]
+    alpha_curve: Option<AlphaCurveArg>,
+    /// Soften mask edges with a Gaussian blur of this many pixels (standard deviation).
+    #[arg(long)]
+    feather_radius: Option<f32>,
+    /// Turn raw model output into alpha with `sigmoid`, `clamp` or the per-image `min-max` stretch.
+    /// Defaults to the model's own: `sigmoid` for RMBG-2.0, `min-max` for RMBG-1.4.
+    #[arg(long, value_parser = parse_mask_activation)]
+    mask_activation: Option<MaskActivation>,
+    /// When the mask comes out entirely background or foreground: `error`, `passthrough` (keep the
+    /// source unchanged) or `flag` (keep the mask and report `maskCoverage`).
+    #[arg(long, default_value = "flag", value_parser = parse_empty_mask_policy)]
+    on_empty_mask: EmptyMaskPolicy,
+    /// Retry a failing provider this many times before falling back to the next one.
+    #[arg(long, default_value_t = 0)]
+    inference_retries: u32,
+    /// Downscale inputs so the longer side is at most N pixels before inference; the mask is scaled back up.
+    #[arg(long)]
+    max_dimension: Option<u32>,
+    /// Model input edge in pixels (a multiple of 32); smaller is faster, larger keeps more detail.
+    #[arg(long)]
+    input_size: Option<u32>,
+    /// Only segment this region of the input, `X,Y,WIDTH,HEIGHT` in pixels; the mask is zero elsewhere.
+    #[arg(long, value_parser = parse_roi)]
+    roi: Option<RegionOfInterest>,
+    /// Grayscale hint image the size of the input: white forces foreground, black forces
+    /// background, any other value (e.g. 128) keeps the model's prediction.
+    #[arg(long)]
+    seed_mask: Option<PathBuf>,
+    /// Include the resolved configuration, env overrides and execution plan under `effectiveConfig`.
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    emit_config: bool,
+    /// Do not warn on stderr when inference falls back from GPU to CPU.
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    no_fallback_warning: bool,
+    /// Record model, provider, variant, revision and time in PNG text chunks of the mask and cutout.
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    embed_metadata: bool,
+    /// Apply EXIF orientation, then write outputs without any EXIF, GPS, camera or ICC metadata.
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue, conflicts_with = "embed_metadata")]
+    strip_metadata: bool,
+    /// Embed the input's ICC color profile in cutouts and overlays so wide-gamut photos keep
+    /// their colors; all other metadata is still left out.
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    keep_icc: bool,
     #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
     inference_only: bool,
     #[arg(long, default_value_t = 1)]
     repeat: u32,
+    /// Stack up to N inputs into one model run when they share session settings; the model needs
+    /// a dynamic batch dimension. Inputs the backend cannot stack still run one at a time.
+    #[arg(long, default_value_t = 1)]
+    batch_size: usize,
+    /// Treat model output spanning less than this range as low confidence and keep it unstretched.
+    #[arg(long)]
+    low_confidence_range: Option<f32>,
+    /// Load this installed revision instead of the one recorded in the lockfile.
+    #[arg(long)]
+    revision: Option<String>,
+    /// Pin a symbolic model input dimension, e.g. `--free-dim batch=1` (repeatable).
+    #[arg(long = "free-dim", value_parser = parse_free_dimension)]
+    free_dims: Vec<(String, i64)>,
+    /// Inference backend id from the backend registry.
+    #[arg(long, default_value = DEFAULT_BACKEND_ID)]
+    backend: String,
+    /// OS priority for inference (`normal`, `low` or `lowest`), so long batches leave the desktop responsive.
+    #[arg(long, default_value = "normal")]
+    thread_priority: String,
+    /// ONNX Runtime threads per operator; unset lets ONNX Runtime use one per physical core.
+    #[arg(long)]
+    intra_threads: Option<usize>,
+    /// ONNX Runtime threads across independent graph branches.
+    #[arg(long)]
+    inter_threads: Option<usize>,
+    /// Graph optimization level: `disable`, `basic`, `extended`, `layout` or `all`.
+    #[arg(long, default_value = "all", value_parser = parse_graph_opt)]
+    graph_opt: GraphOptLevel,
+    /// Output channel to use as foreground for multi-class models (repeatable; channels are unioned).
+    #[arg(long = "foreground-channel")]
+    foreground_channels: Vec<usize>,
+    /// Blend both models' masks: `weighted:W` (W = RMBG-2.0 share), `average` or `max`.
+    #[arg(long, value_parser = parse_ensemble)]
+    ensemble: Option<EnsembleMode>,
+    /// Fail instead of using RMBG-1.4 when the auto-selected RMBG-2.0 (or an ensemble member) cannot be loaded.
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    strict_model: bool,
+    /// Run this installed custom model (a Hugging Face repo id) instead of RMBG.
+    #[arg(long)]
+    custom_model: Option<String>,
+    /// Outline the cutout subject: `WIDTH[:RRGGBB]`, e.g. `--stroke 4:ffffff`.
+    #[arg(long, value_parser = parse_stroke)]
+    stroke: Option<Effect>,
+    /// Drop shadow under the cutout: `BLUR:DX:DY[:OPACITY[:RRGGBB]]`, e.g. `--shadow 8:6:6:0.4`.
+    #[arg(long, value_parser = parse_shadow)]
+    shadow: Option<Effect>,
+    /// zlib level for PNG outputs, from 0 (fastest, largest) to 9 (smallest).
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=9))]
+    png_compression: Option<u8>,
+    /// Quality for JPEG outputs, 0 to 100 (WebP outputs are always lossless).
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=100))]
+    quality: Option<u8>,
 }
 
 fn main() -> Result<()> {
@@ -135,17 +519,67 @@ fn main() -> Result<()> {
                     model_dir: args.model_dir,
                     install_all: args.all,
                     models: parse_models_for_install(&args.models)?,
+                    custom_models: args
+                        .custom_model
+                        .map(|id| CustomModel {
+                            id,
+                            revision: args.custom_revision,
+                            input_size: args.custom_input_size,
+                            normalization: args.custom_normalization,
+                        })
+                        .into_iter()
+                        .collect(),
                     hf_token_env: args.hf_token_env,
                     revision_rmbg14: args.revision_rmbg14,
                     revision_rmbg20: args.revision_rmbg20,
                     verify_only: args.verify_only,
                     onnx_variant: parse_onnx_variant(&args.onnx_variant)?,
+                    force: args.force,
+                    keep_temp_on_failure: args.keep_temp_on_failure,
+                    temp_dir: args.temp_dir,
+                    download_buffer_size: DEFAULT_DOWNLOAD_BUFFER_SIZE,
+                    report_throughput: args.report_throughput,
+                    cancel: None,
+                    download_sink: None,
                 })?;
                 println!("{}", serde_json::to_string_pretty(&report)?);
             }
+            ModelsSubcommand::InstallArchive(args) => {
+                let report = install_from_archive(&args.path, args.model_dir.as_deref())?;
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            }
+            ModelsSubcommand::Migrate(args) => {
+                let known = match &args.lock {
+                    Some(path) => {
+                        let bytes = std::fs::read(path).map_err(|e| anyhow!("failed to read lockfile {}: {}", path.display(), e))?;
+                        serde_json::from_slice::<ModelLock>(&bytes)
+                            .map_err(|e| anyhow!("invalid lockfile {}: {}", path.display(), e))?
+                            .models
+                    }
+                    None => Vec::new(),
+                };
+                let report = migrate_legacy_layout(&args.from, args.model_dir.as_deref(), &known, args.dry_run)?;
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            }
+            ModelsSubcommand::Export(args) => {
+                let models = parse_models_for_install(&args.models)?;
+                let report = export_models(args.model_dir.as_deref(), &models, &args.out)?;
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            }
             ModelsSubcommand::List(args) => {
                 let lock = verify_models(args.model_dir)?;
-                println!("{}", serde_json::to_string_pretty(&lock.models)?);
+                if args.summary {
+                    let summary: Vec<_> = lock.models.iter().map(|m| m.summary()).collect();
+                    println!(
+                        "{}",
+                        serde_json::to_string_pretty(&serde_json::json!({
+                            "models": lock.models,
+                            "summary": summary,
+                        }))?
+                    );
+                } else {
+                    println!("{}", serde_json::to_string_pretty(&lock.models)?);
+                }
             }
             ModelsSubcommand::Verify(args) => {
                 let lock = verify_models(args.model_dir)?;
@@ -157,183 +591,709 @@ fn main() -> Result<()> {
                     model_dir: args.model_dir,
                     install_all: parsed.is_empty() || args.models.iter().any(|m| m.eq_ignore_ascii_case("all")),
                     models: parsed,
+                    custom_models: Vec::new(),
                     hf_token_env: args.hf_token_env,
                     revision_rmbg14: "main".to_string(),
                     revision_rmbg20: "main".to_string(),
                     verify_only: false,
                     onnx_variant: parse_onnx_variant(&args.onnx_variant)?,
+                    force: false,
+                    keep_temp_on_failure: false,
+                    temp_dir: None,
+                    download_buffer_size: DEFAULT_DOWNLOAD_BUFFER_SIZE,
+                    report_throughput: false,
+                    cancel: None,
+                    download_sink: None,
                 })?;
                 println!("{}", serde_json::to_string_pretty(&report)?);
             }
+            ModelsSubcommand::Explain(args) => {
+                println!("{}", serde_json::to_string_pretty(&explain_onnx_choice(&args)?)?);
+            }
+            ModelsSubcommand::InspectFile(args) => {
+                set_ort_dylib_path_if_available();
+                let spec = inspect_model(&args.path)?;
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&serde_json::json!({
+                        "path": args.path,
+                        "inputs": spec.inputs,
+                        "outputs": spec.outputs,
+                        "supportedSegmentationModel": spec.is_supported_segmentation_model(),
+                    }))?
+                );
+            }
+            ModelsSubcommand::Check(args) => {
+                let manifest: Vec<ModelManifest> = match &args.manifest {
+                    Some(path) => serde_json::from_slice(&std::fs::read(path)?)?,
+                    None => built_in_manifest(),
+                };
+                let lock = read_lockfile(&resolve_model_paths(args.model_dir.as_deref())?)?;
+                let drift = check_lock_against_manifest(&lock, &manifest);
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&serde_json::json!({
+                        "consistent": drift.is_empty(),
+                        "drift": drift,
+                    }))?
+                );
+                if !drift.is_empty() {
+                    return Err(anyhow!("lockfile differs from the manifest in {} place(s)", drift.len()));
+                }
+            }
         },
-        TopLevelCommand::Exec(args) => {
-            let total_start = Instant::now();
+        TopLevelCommand::Bench(args) => {
             set_ort_dylib_path_if_available();
-            let model_ensure_start = Instant::now();
-            let mut timings = serde_json::Map::new();
-            timings.insert(
-                "setupRuntimePath".to_string(),
-                serde_json::json!(model_ensure_start.duration_since(total_start).as_millis()),
+            let backend = LocalOrtBackend::default();
+            let base = InferenceRequest::builder()
+                .requested_model(parse_model_choice(&args.model)?)
+                .onnx_variant(parse_onnx_variant(&args.onnx_variant)?)
+                .execution_provider(parse_execution_provider(&args.execution_provider)?)
+                .gpu_backend(parse_gpu_backend(&args.gpu_backend)?)
+                .model_dir(args.model_dir.clone())
+                .build();
+            let mut cases = Vec::new();
+            for case in default_cases() {
+                cases.push(run_case(&backend, &base, &case, args.runs)?);
+            }
+            println!("{}", serde_json::to_string_pretty(&serde_json::json!({ "cases": cases }))?);
+        }
+        TopLevelCommand::Composite(args) => {
+            let source = std::fs::read(&args.input)
+                .map_err(|e| anyhow!("failed to read input {}: {}", args.input.display(), e))?;
+            let mask = std::fs::read(&args.mask).map_err(|e| anyhow!("failed to read mask {}: {}", args.mask.display(), e))?;
+            validate_cutout_extension(&args.output)?;
+            let background = match args.background.as_deref() {
+                Some(value) => {
+                    let [r, g, b] = parse_hex_color(value)?;
+                    Backdrop::Color([r, g, b, 255])
+                }
+                None => Backdrop::Transparent,
+            };
+            let effects: Vec<Effect> = args.stroke.into_iter().chain(args.shadow).collect();
+            let encode = EncodeOptions {
+                png_compression: args.png_compression,
+                quality: args.quality,
+                ..EncodeOptions::default()
+            };
+            write_cutout_png(&source, &mask, &effects, &background, &[], &encode, &args.output)?;
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&serde_json::json!({
+                    "input": args.input,
+                    "mask": args.mask,
+                    "outputCutout": args.output,
+                }))?
             );
-
-            let inputs = resolve_exec_inputs(&args)?;
-            if inputs.is_empty() {
-                return Err(anyhow!("no input images matched"));
+        }
+        TopLevelCommand::History(args) => {
+            let setting = std::env::var("UNBG_RUN_HISTORY").ok().filter(|value| !value.trim().is_empty());
+            let path = args
+                .file
+                .clone()
+                .or_else(|| run_history_file(Some(setting.as_deref().unwrap_or("1")), args.model_dir.as_deref()))
+                .ok_or_else(|| anyhow!("run history is disabled by UNBG_RUN_HISTORY; pass --file to read a log"))?;
+            let model = parse_model_choice(&args.model)?;
+            let now_ms = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+            let matching = read_run_history(&path)?
+                .filter(|entry| model == ModelKind::Auto || entry.model == model)
+                .filter(|entry| {
+                    args.provider.as_deref().is_none_or(|wanted| {
+                        [&entry.provider, &entry.gpu_backend]
+                            .into_iter()
+                            .flatten()
+                            .any(|value| value.eq_ignore_ascii_case(wanted))
+                    })
+                })
+                .filter(|entry| args.min_ms.is_none_or(|min| entry.elapsed_ms >= min))
+                .filter(|entry| args.since.is_none_or(|age| entry.timestamp_ms >= now_ms.saturating_sub(age)))
+                .filter(|entry| !args.problems || entry.error.is_some() || entry.fallback_used || entry.model_fallback_used);
+            // Only the last `tail` matches are kept, however long the log has grown.
+            let mut entries: VecDeque<RunHistoryEntry> = VecDeque::new();
+            for entry in matching {
+                entries.push_back(entry);
+                if entries.len() > args.tail {
+                    entries.pop_front();
+                }
             }
-            let runtime_cfg = unbg_core::resolve_runtime_config(RuntimeConfig {
-                model: args.model.clone(),
-                onnx_variant: args.onnx_variant.clone(),
-                execution_provider: args.execution_provider.clone(),
-                gpu_backend: args.gpu_backend.clone(),
-                benchmark_provider: args.benchmark_provider,
-                model_dir: args.model_dir.as_ref().map(|path| path.display().to_string()),
-            });
-            let requested_model = parse_model_choice(&runtime_cfg.model)?;
-            let onnx_variant = parse_onnx_variant(&runtime_cfg.onnx_variant)?;
-            ensure_models_for_exec(&args, requested_model, onnx_variant)?;
-            let model_ensure_done = Instant::now();
+            for entry in &entries {
+                println!("{}", serde_json::to_string(entry)?);
+            }
+        }
+        TopLevelCommand::Serve(args) => {
+            set_ort_dylib_path_if_available();
+            let base_request = InferenceRequest::builder()
+                .requested_model(parse_model_choice(&args.model)?)
+                .onnx_variant(parse_onnx_variant(&args.onnx_variant)?)
+                .execution_provider(parse_execution_provider(&args.execution_provider)?)
+                .gpu_backend(parse_gpu_backend(&args.gpu_backend)?)
+                .model_dir(args.model_dir.clone())
+                .session_tuning(SessionTuning {
+                    intra_threads: args.intra_threads,
+                    inter_threads: args.inter_threads,
+                    graph_opt: args.graph_opt,
+                })
+                .build();
             let policy = RuntimePolicy {
                 max_inference_pixels: args.max_inference_pixels,
-                max_latency_ms: 1_500,
                 allow_rmbg20: args.allow_rmbg20,
+                ..RuntimePolicy::server()
             };
-            let backend = LocalOrtBackend::default();
-            let telemetry = sink_from_env();
-            let telemetry_ref = telemetry.as_ref().map(|sink| sink.as_ref());
-
-            let bulk_mode = inputs.len() > 1;
-            let mut results = Vec::with_capacity(inputs.len());
-            let mut total_inference_ms: u128 = 0;
-            let mut total_write_ms: u128 = 0;
-
-            for input_path in inputs {
-                let read_start = Instant::now();
-                let source = match std::fs::read(&input_path) {
-                    Ok(bytes) => bytes,
-                    Err(err) => {
-                        if bulk_mode && !args.strict {
-                            results.push(serde_json::json!({
-                                "input": input_path,
-                                "error": format!("failed to read input: {}", err),
-                            }));
-                            continue;
-                        }
-                        return Err(anyhow!("failed to read input {}: {}", input_path.display(), err));
-                    }
-                };
-                let read_done = Instant::now();
-                let image = match image::load_from_memory(&source) {
-                    Ok(img) => img,
-                    Err(err) => {
-                        if bulk_mode && !args.strict {
-                            results.push(serde_json::json!({
-                                "input": input_path,
-                                "error": format!("failed to decode input: {}", err),
-                            }));
-                            continue;
-                        }
-                        return Err(anyhow!("failed to decode input {}: {}", input_path.display(), err));
-                    }
-                };
-                let decode_done = Instant::now();
-                let (width, height) = image.dimensions();
-
-                let (output_cutout, output_mask) = resolve_outputs_for_input(&args, &input_path)?;
-                let request = InferenceRequest {
-                    requested_model,
-                    onnx_variant,
-                    execution_provider: parse_execution_provider(&runtime_cfg.execution_provider)?,
-                    gpu_backend: parse_gpu_backend(&runtime_cfg.gpu_backend)?,
-                    benchmark_provider: runtime_cfg.benchmark_provider,
-                    emit_mask_png: !args.inference_only,
-                    input_path: Some(input_path.clone()),
-                    input_bytes: Some(source.clone()),
-                    model_dir: runtime_cfg.model_dir.clone().map(PathBuf::from),
-                    width,
-                    height,
-                };
+            let mut registry = default_registry();
+            if let Some(remote) = RemoteBackendConfig::from_env() {
+                register_remote_backend(&mut registry, remote);
+            }
+            let backend = registry.create(&args.backend)?;
 
-                let mut last_result = None;
-                let inference_start = Instant::now();
-                for _ in 0..args.repeat.max(1) {
-                    let result = run_inference_with_telemetry(&backend, &request, &policy, PlatformTarget::Cli, telemetry_ref)?;
-                    last_result = Some(result);
-                }
-                let inference_done = Instant::now();
-                let result = last_result.ok_or_else(|| anyhow!("inference did not produce a result"))?;
-                total_inference_ms += inference_done.duration_since(inference_start).as_millis();
-
-                let write_start = Instant::now();
-                if let Some(ref mask_path) = output_mask {
-                    if let Some(parent) = mask_path.parent() {
-                        std::fs::create_dir_all(parent)?;
-                    }
-                    std::fs::write(mask_path, &result.mask_png)?;
-                }
-                if let Some(ref cutout_path) = output_cutout {
-                    write_cutout_png(&source, &result.mask_png, &cutout_path)?;
-                }
-                let write_done = Instant::now();
-                total_write_ms += write_done.duration_since(write_start).as_millis();
-
-                let mut per = serde_json::Map::new();
-                if args.profile {
-                    per.insert(
-                        "readInput".to_string(),
-                        serde_json::json!(read_done.duration_since(read_start).as_millis()),
-                    );
-                    per.insert(
-                        "decodeInput".to_string(),
-                        serde_json::json!(decode_done.duration_since(read_done).as_millis()),
-                    );
-                    per.insert(
-                        "inference".to_string(),
-                        serde_json::json!(inference_done.duration_since(inference_start).as_millis()),
-                    );
-                    per.insert(
-                        "writeOutputs".to_string(),
-                        serde_json::json!(write_done.duration_since(write_start).as_millis()),
-                    );
+            let warm_start = Instant::now();
+            let warm = serve_warmup_request(&base_request)?;
+            let warmed = run_inference(backend.as_ref(), &warm, &policy)?;
+            eprintln!(
+                "warmed {} on {} in {}ms",
+                model_kind_label(warmed.model_used),
+                warmed.execution_provider_selected,
+                warm_start.elapsed().as_millis()
+            );
+
+            // Run once here; `/health` reports this result instead of running inference per probe.
+            let health = self_test_in(base_request.requested_model, base_request.execution_provider, args.model_dir.as_deref())
+                .map_err(|err| err.to_string())
+                .and_then(|report| serde_json::to_value(report).map_err(|err| err.to_string()));
+            let state = std::sync::Arc::new(serve::ServeState {
+                backend,
+                base_request,
+                policy,
+                health,
+            });
+            let listener = std::net::TcpListener::bind(&args.addr)?;
+            eprintln!("listening on http://{}", listener.local_addr()?);
+            let shutdown = CancelToken::new();
+            serve::cancel_on_signal(shutdown.clone());
+            serve::serve(listener, state, shutdown, args.workers)?;
+            eprintln!("server stopped");
+        }
+        TopLevelCommand::Exec(args) => run_exec(&args)?,
+    }
+
+    Ok(())
+}
+
+/// Runs `exec`: prepares the runtime once, then reads, infers and writes the inputs batch by batch
+/// and prints one JSON report for the whole run.
+fn run_exec(args: &ExecArgs) -> Result<()> {
+    let total_start = Instant::now();
+    set_ort_dylib_path_if_available();
+    let model_ensure_start = Instant::now();
+    let mut timings = serde_json::Map::new();
+    timings.insert(
+        "setupRuntimePath".to_string(),
+        serde_json::json!(model_ensure_start.duration_since(total_start).as_millis()),
+    );
+
+    let inputs = resolve_exec_inputs(args)?;
+    if inputs.is_empty() {
+        return Err(anyhow!("no input images matched"));
+    }
+    let runtime_cfg = unbg_core::resolve_runtime_config(RuntimeConfig {
+        model: args.model.clone(),
+        onnx_variant: args.onnx_variant.clone(),
+        execution_provider: args.execution_provider.clone(),
+        gpu_backend: args.gpu_backend.clone(),
+        benchmark_provider: args.benchmark_provider,
+        model_dir: args.model_dir.as_ref().map(|path| path.display().to_string()),
+        backend: args.backend.clone(),
+        thread_priority: args.thread_priority.clone(),
+    });
+    let requested_model = parse_model_choice(&runtime_cfg.model)?;
+    let onnx_variant = parse_onnx_variant(&runtime_cfg.onnx_variant)?;
+    let thread_priority = parse_thread_priority(&runtime_cfg.thread_priority)?;
+    if thread_priority != ThreadPriority::Normal {
+        // Sessions are built on this thread, so ONNX Runtime's workers inherit it where the OS allows.
+        set_current_thread_priority(thread_priority)?;
+    }
+    if runtime_cfg.backend != REMOTE_BACKEND_ID {
+        ensure_models_for_exec(args, requested_model, onnx_variant)?;
+    }
+    let model_ensure_done = Instant::now();
+    let policy = RuntimePolicy {
+        max_inference_pixels: args.max_inference_pixels,
+        allow_rmbg20: args.allow_rmbg20,
+        ..RuntimePolicy::desktop()
+    };
+    let mut registry = default_registry();
+    if let Some(remote) = RemoteBackendConfig::from_env() {
+        register_remote_backend(&mut registry, remote);
+    }
+    let backend = registry.create(&runtime_cfg.backend)?;
+    let telemetry = sink_from_env();
+    let telemetry_ref = telemetry.as_ref().map(|sink| sink.as_ref());
+
+    let mut run = ExecRun::new(args, &runtime_cfg, requested_model, onnx_variant, inputs.len())?;
+    let batch_size = args.batch_size.max(1);
+    let mut inputs = inputs.into_iter().enumerate().peekable();
+    while inputs.peek().is_some() {
+        let mut pending = Vec::with_capacity(batch_size);
+        let mut requests = Vec::with_capacity(batch_size);
+        for (index, input_path) in inputs.by_ref() {
+            if let Some((input, request)) = run.prepare_input(index, input_path)? {
+                pending.push(input);
+                requests.push(request);
+                if requests.len() == batch_size {
+                    break;
                 }
+            }
+        }
+        if requests.is_empty() {
+            continue;
+        }
 
-                results.push(serde_json::json!({
-                    "input": input_path,
-                    "modelUsed": model_kind_label(result.model_used),
-                    "providerSelected": result.execution_provider_selected,
-                    "backendSelected": result.gpu_backend_selected,
-                    "fallbackUsed": result.fallback_used,
-                    "width": result.width,
-                    "height": result.height,
-                    "outputMask": output_mask,
-                    "outputCutout": output_cutout,
-                    "timingsMs": if args.profile { Some(serde_json::Value::Object(per)) } else { None }
-                }));
+        let mut last_results = Vec::new();
+        let inference_start = Instant::now();
+        for _ in 0..args.repeat.max(1) {
+            last_results = if let [request] = requests.as_slice() {
+                vec![run_inference_with_telemetry(backend.as_ref(), request, &policy, PlatformTarget::Cli, telemetry_ref, None)?]
+            } else {
+                run_inference_batch_with_telemetry(backend.as_ref(), &requests, &policy, PlatformTarget::Cli, telemetry_ref, None)?
+            };
+        }
+        let inference_ms = inference_start.elapsed().as_millis();
+        if last_results.len() != requests.len() {
+            return Err(anyhow!("inference did not produce a result for every input"));
+        }
+        run.total_inference_ms += inference_ms;
+
+        for ((input, request), result) in pending.into_iter().zip(&requests).zip(last_results) {
+            run.write_result(input, request, result, inference_ms)?;
+        }
+    }
+    // Skipped and failed inputs are recorded as soon as they are seen, ahead of their batch.
+    run.results.sort_by_key(|(index, _)| *index);
+    let results: Vec<serde_json::Value> = std::mem::take(&mut run.results).into_iter().map(|(_, result)| result).collect();
+
+    if let Some(progress) = run.progress.as_mut() {
+        progress.finish();
+    }
+    if let Some(ref sheet_path) = args.contact_sheet {
+        let sheet = contact_sheet(&run.sheet_entries, args.contact_sheet_columns, args.contact_sheet_thumb);
+        if let Some(parent) = sheet_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        save_encoded(image::DynamicImage::ImageRgba8(sheet), &run.encode, sheet_path)?;
+    }
+    if let (Some(sink), true) = (telemetry_ref, runtime_cfg.backend != REMOTE_BACKEND_ID) {
+        sink.emit(TelemetryEvent {
+            event_type: TelemetryEventType::CacheStats,
+            model: requested_model,
+            platform: PlatformTarget::Cli,
+            duration_ms: None,
+            detail: None,
+            fields: Some(serde_json::to_value(cache_stats())?),
+        });
+    }
+    let done = Instant::now();
+    if args.profile {
+        timings.insert(
+            "ensureModels".to_string(),
+            serde_json::json!(model_ensure_done.duration_since(model_ensure_start).as_millis()),
+        );
+        timings.insert("repeat".to_string(), serde_json::json!(args.repeat.max(1)));
+        timings.insert("files".to_string(), serde_json::json!(results.len()));
+        timings.insert("inference".to_string(), serde_json::json!(run.total_inference_ms));
+        timings.insert("writeOutputs".to_string(), serde_json::json!(run.total_write_ms));
+        timings.insert("total".to_string(), serde_json::json!(done.duration_since(total_start).as_millis()));
+    }
+
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&serde_json::json!({
+            "results": results,
+            "contactSheet": args.contact_sheet,
+            "timingsMs": if args.profile { Some(serde_json::Value::Object(timings)) } else { None },
+            "effectiveConfig": if args.emit_config {
+                Some(effective_config(&runtime_cfg, &policy, |key| std::env::var(key).ok(), run.plan.flatten().as_ref())?)
+            } else {
+                None
             }
+        }))?
+    );
+    Ok(())
+}
 
-            let done = Instant::now();
-            if args.profile {
-                timings.insert(
-                    "ensureModels".to_string(),
-                    serde_json::json!(model_ensure_done.duration_since(model_ensure_start).as_millis()),
-                );
-                timings.insert("repeat".to_string(), serde_json::json!(args.repeat.max(1)));
-                timings.insert("files".to_string(), serde_json::json!(results.len()));
-                timings.insert("inference".to_string(), serde_json::json!(total_inference_ms));
-                timings.insert("writeOutputs".to_string(), serde_json::json!(total_write_ms));
-                timings.insert("total".to_string(), serde_json::json!(done.duration_since(total_start).as_millis()));
+/// Per-run state of `exec`, shared by the stages that prepare each input and write its result.
+struct ExecRun<'a> {
+    args: &'a ExecArgs,
+    runtime_cfg: &'a RuntimeConfig,
+    requested_model: ModelKind,
+    onnx_variant: OnnxVariant,
+    bulk_mode: bool,
+    seed_mask: Option<Vec<u8>>,
+    encode: EncodeOptions,
+    fill: Backdrop<'static>,
+    progress: Option<ProgressReporter>,
+    /// Outputs are named before their batch is written, so names chosen earlier in the run count as taken.
+    claimed_outputs: HashSet<PathBuf>,
+    results: Vec<(usize, serde_json::Value)>,
+    sheet_entries: Vec<Vec<image::RgbaImage>>,
+    fallback_warned: bool,
+    plan: Option<Option<ExecutionPlan>>,
+    total_inference_ms: u128,
+    total_write_ms: u128,
+}
+
+impl<'a> ExecRun<'a> {
+    fn new(
+        args: &'a ExecArgs,
+        runtime_cfg: &'a RuntimeConfig,
+        requested_model: ModelKind,
+        onnx_variant: OnnxVariant,
+        input_count: usize,
+    ) -> Result<Self> {
+        let seed_mask = args
+            .seed_mask
+            .as_ref()
+            .map(|path| std::fs::read(path).map_err(|e| anyhow!("failed to read seed mask {}: {}", path.display(), e)))
+            .transpose()?;
+        let encode = EncodeOptions {
+            png_compression: args.png_compression,
+            quality: args.quality,
+            ..EncodeOptions::default()
+        };
+        let fill = match args.bg_color.as_deref() {
+            Some(value) => Backdrop::Color(parse_bg_color(value)?),
+            None => Backdrop::Transparent,
+        };
+        Ok(Self {
+            args,
+            runtime_cfg,
+            requested_model,
+            onnx_variant,
+            bulk_mode: input_count > 1,
+            seed_mask,
+            encode,
+            fill,
+            progress: args.progress_fd.map(|fd| ProgressReporter::new(open_progress_fd(fd), input_count)),
+            claimed_outputs: HashSet::new(),
+            results: Vec::with_capacity(input_count),
+            sheet_entries: Vec::new(),
+            fallback_warned: false,
+            plan: None,
+            total_inference_ms: 0,
+            total_write_ms: 0,
+        })
+    }
+
+    /// Records `entry` as the result for input `index` and reports the input as finished.
+    fn record(&mut self, index: usize, input_path: &Path, entry: serde_json::Value) {
+        self.results.push((index, entry));
+        if let Some(progress) = self.progress.as_mut() {
+            progress.finished(input_path);
+        }
+    }
+
+    /// Names the outputs for one input, then reads and decodes it and builds its request. Returns
+    /// `None` when the input is skipped, or in a non-strict bulk run when it cannot be read or decoded;
+    /// either way its result has already been recorded.
+    fn prepare_input(&mut self, index: usize, input_path: PathBuf) -> Result<Option<(PendingInput, InferenceRequest)>> {
+        let args = self.args;
+        let (output_cutout, output_mask, outputs) = resolve_outputs_for_input(args, &input_path, &mut self.claimed_outputs)?;
+        let output_overlay = resolve_overlay_for_input(args, &input_path, &mut self.claimed_outputs)?;
+        if !args.inference_only && output_cutout.is_none() && output_mask.is_none() && outputs.is_empty() {
+            // Only reachable with `--on-collision skip` when every output already exists.
+            self.record(index, &input_path, serde_json::json!({ "input": input_path, "skipped": true }));
+            return Ok(None);
+        }
+        let read_start = Instant::now();
+        let source = match std::fs::read(&input_path) {
+            Ok(bytes) => bytes,
+            Err(err) if self.bulk_mode && !args.strict => {
+                let entry = serde_json::json!({
+                    "input": input_path,
+                    "error": format!("failed to read input: {}", err),
+                });
+                self.record(index, &input_path, entry);
+                return Ok(None);
+            }
+            Err(err) => return Err(anyhow!("failed to read input {}: {}", input_path.display(), err)),
+        };
+        let read_done = Instant::now();
+        let hints = DecodeHints {
+            apply_orientation: args.strip_metadata,
+            ..DecodeHints::from_path(&input_path)
+        };
+        let image = match decode_image(&source, &hints) {
+            Ok(img) => img,
+            Err(err) if self.bulk_mode && !args.strict => {
+                let entry = serde_json::json!({
+                    "input": input_path,
+                    "error": format!("failed to decode input: {}", err),
+                });
+                self.record(index, &input_path, entry);
+                return Ok(None);
             }
+            Err(err) => return Err(anyhow!("failed to decode input {}: {}", input_path.display(), err)),
+        };
+        let icc_profile = if args.keep_icc { read_icc_profile(&source) } else { None };
+        let source = if args.strip_metadata { oriented_source(source, &image)? } else { source };
+        let decode_done = Instant::now();
 
-            println!(
-                "{}",
-                serde_json::to_string_pretty(&serde_json::json!({
-                    "results": results,
-                    "timingsMs": if args.profile { Some(serde_json::Value::Object(timings)) } else { None }
-                }))?
+        let (width, height) = image.dimensions();
+        let request = self.request_for(&input_path, &source, width, height)?;
+        let input = PendingInput {
+            index,
+            input_path,
+            output_cutout,
+            output_mask,
+            outputs,
+            output_overlay,
+            source,
+            icc_profile,
+            read_start,
+            read_done,
+            decode_done,
+        };
+        Ok(Some((input, request)))
+    }
+
+    fn request_for(&self, input_path: &Path, source: &[u8], width: u32, height: u32) -> Result<InferenceRequest> {
+        let args = self.args;
+        let mut request = InferenceRequest::builder()
+            .requested_model(self.requested_model)
+            .onnx_variant(self.onnx_variant)
+            .execution_provider(parse_execution_provider(&self.runtime_cfg.execution_provider)?)
+            .gpu_backend(parse_gpu_backend(&self.runtime_cfg.gpu_backend)?)
+            .benchmark_provider(self.runtime_cfg.benchmark_provider)
+            .emit_mask_png(!args.inference_only)
+            .input_path(input_path)
+            .input_bytes(source.to_vec())
+            .model_dir(self.runtime_cfg.model_dir.clone().map(PathBuf::from))
+            .dimensions(width, height)
+            .low_confidence_range(args.low_confidence_range)
+            .revision(args.revision.clone())
+            .ensemble(args.ensemble)
+            .strict_model(args.strict_model)
+            .on_empty_mask(args.on_empty_mask)
+            .session_tuning(SessionTuning {
+                intra_threads: args.intra_threads,
+                inter_threads: args.inter_threads,
+                graph_opt: args.graph_opt,
+            })
+            .custom_model(args.custom_model.clone())
+            .foreground_channels(args.foreground_channels.clone())
+            .inference_retries(args.inference_retries)
+            .max_dimension(args.max_dimension)
+            .input_size(args.input_size)
+            .roi(args.roi)
+            .trimap(args.trimap)
+            .mask_threshold(args.mask_threshold)
+            .alpha_curve(args.alpha_curve.clone().map(|curve| curve.0))
+            .feather_radius(args.feather_radius)
+            .mask_activation(args.mask_activation)
+            .seed_mask(self.seed_mask.clone())
+            .png_compression(args.png_compression)
+            .build();
+        request.free_dimension_overrides = args.free_dims.clone();
+        Ok(request)
+    }
+
+    /// Writes the outputs for one inferred input and records its result.
+    fn write_result(
+        &mut self,
+        input: PendingInput,
+        request: &InferenceRequest,
+        result: InferenceResult,
+        inference_ms: u128,
+    ) -> Result<()> {
+        let args = self.args;
+        if result.fallback_used && !args.no_fallback_warning && !self.fallback_warned {
+            eprintln!(
+                "warning: GPU provider failed, running on {} instead ({})",
+                result.execution_provider_selected,
+                result.fallback_reason.as_deref().unwrap_or("no error detail")
             );
+            self.fallback_warned = true;
+        }
+        if args.emit_config && self.plan.is_none() && self.runtime_cfg.backend != REMOTE_BACKEND_ID {
+            self.plan = Some(execution_plan(request, result.model_used).ok());
         }
+
+        let write_start = Instant::now();
+        self.write_outputs(&input, &result)?;
+        let write_done = Instant::now();
+        self.total_write_ms += write_done.duration_since(write_start).as_millis();
+
+        let mut per = serde_json::Map::new();
+        if args.profile {
+            per.insert(
+                "readInput".to_string(),
+                serde_json::json!(input.read_done.duration_since(input.read_start).as_millis()),
+            );
+            per.insert(
+                "decodeInput".to_string(),
+                serde_json::json!(input.decode_done.duration_since(input.read_done).as_millis()),
+            );
+            per.insert("inference".to_string(), serde_json::json!(inference_ms));
+            per.insert(
+                "writeOutputs".to_string(),
+                serde_json::json!(write_done.duration_since(write_start).as_millis()),
+            );
+        }
+
+        let entry = serde_json::json!({
+            "input": input.input_path,
+            "modelUsed": model_used_label(&result),
+            "providerSelected": result.execution_provider_selected,
+            "backendSelected": result.gpu_backend_selected,
+            "fallbackUsed": result.fallback_used,
+            "ensembleUsed": result.ensemble_used,
+            "modelFallbackUsed": result.model_fallback_used,
+            "maskCoverage": result.mask_coverage,
+            "width": result.width,
+            "height": result.height,
+            "outputMask": input.output_mask,
+            "outputCutout": input.output_cutout,
+            "outputs": input.outputs,
+            "outputOverlay": input.output_overlay,
+            "timingsMs": if args.profile { Some(serde_json::Value::Object(per)) } else { None }
+        });
+        self.record(input.index, &input.input_path, entry);
+        Ok(())
     }
 
-    Ok(())
+    /// Writes the mask, cutouts and overlay named for `input`, and adds it to the contact sheet.
+    fn write_outputs(&mut self, input: &PendingInput, result: &InferenceResult) -> Result<()> {
+        let args = self.args;
+        let encode = EncodeOptions {
+            icc_profile: input.icc_profile.clone(),
+            ..self.encode.clone()
+        };
+        let metadata = if args.embed_metadata {
+            provenance_text(result, SystemTime::now())
+        } else {
+            Vec::new()
+        };
+        if let Some(ref mask_path) = input.output_mask {
+            if let Some(parent) = mask_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            if metadata.is_empty() {
+                std::fs::write(mask_path, &result.mask_png)?;
+            } else {
+                std::fs::write(mask_path, embed_png_text(&result.mask_png, &metadata, &encode)?)?;
+            }
+        }
+        let effects: Vec<Effect> = args.stroke.into_iter().chain(args.shadow).collect();
+        if let Some(ref cutout_path) = input.output_cutout {
+            write_cutout_png(&input.source, &result.mask_png, &effects, &self.fill, &metadata, &encode, cutout_path)?;
+        }
+        for path in &input.outputs {
+            write_cutout(&input.source, &result.mask_png, &effects, &self.fill, &metadata, &encode, path)?;
+        }
+        if let Some(ref overlay_path) = input.output_overlay {
+            let color = parse_hex_color(&args.overlay_color)?;
+            write_overlay_png(&input.source, &result.mask_png, color, args.overlay_opacity, &encode, overlay_path)?;
+        }
+        if args.contact_sheet.is_some() && !args.inference_only {
+            self.sheet_entries.push(contact_sheet_entry(&input.source, &result.mask_png, &effects, args.contact_sheet_thumb)?);
+        }
+        Ok(())
+    }
+}
+
+/// An `exec` input that has been read and decoded and is waiting for its batch to run.
+struct PendingInput {
+    index: usize,
+    input_path: PathBuf,
+    output_cutout: Option<PathBuf>,
+    output_mask: Option<PathBuf>,
+    outputs: Vec<PathBuf>,
+    output_overlay: Option<PathBuf>,
+    source: Vec<u8>,
+    icc_profile: Option<Vec<u8>>,
+    read_start: Instant,
+    read_done: Instant,
+    decode_done: Instant,
+}
+
+/// Progress lines for wrappers that shell out to `exec`, e.g. `{"done":3,"total":50,"current":"a.png"}`.
+/// A line is written once an input's outputs are written (or it is skipped or fails): `done` counts
+/// the inputs finished so far and `current` is the one that just finished. A final line with
+/// `current: null` marks the end.
+struct ProgressReporter {
+    out: Box<dyn Write>,
+    done: usize,
+    total: usize,
+}
+
+impl ProgressReporter {
+    fn new(out: Box<dyn Write>, total: usize) -> Self {
+        Self { out, done: 0, total }
+    }
+
+    fn finished(&mut self, input: &Path) {
+        self.done += 1;
+        self.report(Some(input));
+    }
+
+    fn report(&mut self, current: Option<&Path>) {
+        let line = serde_json::json!({ "done": self.done, "total": self.total, "current": current });
+        // Progress is best effort; a closed pipe must not fail the run.
+        let _ = writeln!(self.out, "{}", line).and_then(|_| self.out.flush());
+    }
+
+    fn finish(&mut self) {
+        self.done = self.total;
+        self.report(None);
+    }
+}
+
+fn open_progress_fd(fd: i32) -> Box<dyn Write> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::io::FromRawFd;
+        // SAFETY: F_GETFD only inspects the descriptor table.
+        if fd >= 0 && unsafe { libc::fcntl(fd, libc::F_GETFD) } != -1 {
+            // SAFETY: the descriptor is open and handed to us by the caller for exclusive use.
+            return Box::new(unsafe { std::fs::File::from_raw_fd(fd) });
+        }
+    }
+    eprintln!("warning: progress fd {} is not usable, writing progress to stderr", fd);
+    Box::new(std::io::stderr())
+}
+
+/// Env vars that change how `exec` runs. `UNBG_REMOTE_TOKEN` is deliberately left out.
+const EFFECTIVE_CONFIG_ENV: &[&str] = &[
+    MODEL_PATH_ENV,
+    "ORT_DYLIB_PATH",
+    "UNBG_ORT_LOG_LEVEL",
+    "UNBG_ALLOW_PLACEHOLDER",
+    "UNBG_MASK_CACHE_SIZE",
+    "UNBG_MASK_CACHE_DIR",
+    "UNBG_REMOTE_ENDPOINT",
+    "UNBG_REMOTE_TIMEOUT_MS",
+    "UNBG_TELEMETRY_SINK",
+];
+
+fn effective_config(
+    runtime_cfg: &RuntimeConfig,
+    policy: &RuntimePolicy,
+    env: impl Fn(&str) -> Option<String>,
+    plan: Option<&ExecutionPlan>,
+) -> Result<serde_json::Value> {
+    let search = search_path_from(
+        runtime_cfg.model_dir.as_deref().map(Path::new),
+        env(MODEL_PATH_ENV).map(std::ffi::OsString::from),
+    )?;
+    let overrides: serde_json::Map<String, serde_json::Value> = EFFECTIVE_CONFIG_ENV
+        .iter()
+        .filter_map(|key| env(key).map(|value| (key.to_string(), serde_json::json!(value))))
+        .collect();
+    Ok(serde_json::json!({
+        "runtimeConfig": runtime_cfg,
+        "policy": policy,
+        "modelSearchPath": search.into_iter().map(|paths| paths.root).collect::<Vec<_>>(),
+        "env": overrides,
+        "plan": plan,
+    }))
 }
 
 fn resolve_exec_inputs(args: &ExecArgs) -> Result<Vec<PathBuf>> {
@@ -403,72 +1363,110 @@ fn is_supported_image(path: &Path) -> bool {
         .and_then(|e| e.to_str())
         .map(|e| e.to_ascii_lowercase())
         .unwrap_or_default();
-    matches!(ext.as_str(), "png" | "jpg" | "jpeg")
+    supported_extensions().contains(&ext.as_str())
 }
 
-fn resolve_outputs_for_input(args: &ExecArgs, input_path: &Path) -> Result<(Option<PathBuf>, Option<PathBuf>)> {
+fn resolve_outputs_for_input(
+    args: &ExecArgs,
+    input_path: &Path,
+    claimed: &mut HashSet<PathBuf>,
+) -> Result<(Option<PathBuf>, Option<PathBuf>, Vec<PathBuf>)> {
     if args.inference_only {
-        return Ok((None, None));
+        return Ok((None, None, Vec::new()));
     }
+    let outputs = output_naming(args)?.resolve(input_path)?;
+    Ok((
+        outputs.cutout.and_then(|path| apply_collision_policy(args.on_collision, path, claimed)),
+        outputs.mask.and_then(|path| apply_collision_policy(args.on_collision, path, claimed)),
+        outputs
+            .outputs
+            .into_iter()
+            .filter_map(|path| apply_collision_policy(args.on_collision, path, claimed))
+            .collect(),
+    ))
+}
 
-    let multi_input = {
-        let as_path = PathBuf::from(&args.input);
-        (as_path.exists() && as_path.is_dir()) || !as_path.exists()
-    };
-
-    // When multi-input, prefer explicit --output-dir, otherwise interpret -o/-m as directories.
-    let bulk_out_dir = if multi_input { args.output_dir.clone() } else { None };
+fn output_naming(args: &ExecArgs) -> Result<OutputNaming> {
+    Ok(OutputNaming {
+        multi_input: is_multi_input(args),
+        output_cutout: args.output_cutout.clone(),
+        output_mask: args.output_mask.clone(),
+        output_dir: args.output_dir.clone(),
+        structure_root: if args.preserve_structure { Some(input_base_dir(args)?) } else { None },
+        outputs: args.outputs.clone(),
+    })
+}
 
-    let cutout = if let Some(spec) = args.output_cutout.clone() {
-        if multi_input {
-            let dir = bulk_out_dir.unwrap_or(spec);
-            Some(dir.join(default_cutout_filename(input_path)?))
-        } else {
-            validate_cutout_extension(&spec)?;
-            Some(spec)
-        }
-    } else if args.output_mask.is_none() {
-        if let Some(dir) = bulk_out_dir {
-            Some(dir.join(default_cutout_filename(input_path)?))
-        } else {
-            Some(default_cutout_path(input_path)?)
-        }
+/// Applies `policy` to `path` and records the chosen path in `claimed`. A path counts as taken
+/// when it exists or was already claimed by an earlier output of this run.
+fn apply_collision_policy(policy: CollisionPolicy, path: PathBuf, claimed: &mut HashSet<PathBuf>) -> Option<PathBuf> {
+    let taken = |candidate: &Path| candidate.exists() || claimed.contains(candidate);
+    let chosen = if !taken(&path) {
+        Some(path)
     } else {
-        None
-    };
-
-    let mask = if let Some(spec) = args.output_mask.clone() {
-        if multi_input {
-            let dir = args.output_dir.clone().unwrap_or(spec);
-            Some(dir.join(default_mask_filename(input_path)?))
-        } else {
-            Some(spec)
+        match policy {
+            CollisionPolicy::Overwrite => Some(path),
+            CollisionPolicy::Skip => None,
+            CollisionPolicy::Rename => {
+                let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+                let ext = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+                (1u32..)
+                    .map(|n| path.with_file_name(format!("{}_{}{}", stem, n, ext)))
+                    .find(|candidate| !taken(candidate))
+            }
         }
-    } else {
-        None
     };
+    if let Some(chosen) = &chosen {
+        claimed.insert(chosen.clone());
+    }
+    chosen
+}
+
+fn parse_collision_policy(value: &str) -> std::result::Result<CollisionPolicy, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "overwrite" => Ok(CollisionPolicy::Overwrite),
+        "skip" => Ok(CollisionPolicy::Skip),
+        "rename" => Ok(CollisionPolicy::Rename),
+        other => Err(format!("unknown collision policy '{}'; expected one of: overwrite, skip, rename", other)),
+    }
+}
 
-    Ok((cutout, mask))
+fn resolve_overlay_for_input(args: &ExecArgs, input_path: &Path, claimed: &mut HashSet<PathBuf>) -> Result<Option<PathBuf>> {
+    let Some(spec) = args.output_overlay.clone() else {
+        return Ok(None);
+    };
+    if args.inference_only {
+        return Ok(None);
+    }
+    if is_multi_input(args) {
+        let dir = args.output_dir.clone().unwrap_or(spec);
+        let path = output_naming(args)?.bulk_dir(&dir, input_path).join(default_overlay_filename(input_path)?);
+        return Ok(apply_collision_policy(args.on_collision, path, claimed));
+    }
+    validate_cutout_extension(&spec)?;
+    Ok(apply_collision_policy(args.on_collision, spec, claimed))
 }
 
-fn default_cutout_filename(input: &Path) -> Result<String> {
-    let stem = input
-        .file_stem()
-        .ok_or_else(|| anyhow!("input file must include a valid file name"))?
-        .to_string_lossy();
-    Ok(format!("{}_cutout.png", stem))
+/// Directory inputs are discovered from: the input dir itself, or the regex root.
+fn input_base_dir(args: &ExecArgs) -> Result<PathBuf> {
+    let candidate = PathBuf::from(&args.input);
+    if candidate.is_dir() {
+        return Ok(candidate);
+    }
+    match &args.input_root {
+        Some(root) => Ok(root.clone()),
+        None => std::env::current_dir().map_err(|e| anyhow!(e.to_string())),
+    }
 }
 
-fn default_mask_filename(input: &Path) -> Result<String> {
-    let stem = input
-        .file_stem()
-        .ok_or_else(|| anyhow!("input file must include a valid file name"))?
-        .to_string_lossy();
-    Ok(format!("{}_mask.png", stem))
+fn is_multi_input(args: &ExecArgs) -> bool {
+    let as_path = PathBuf::from(&args.input);
+    (as_path.exists() && as_path.is_dir()) || !as_path.exists()
 }
 
 fn ensure_models_for_exec(args: &ExecArgs, requested_model: ModelKind, onnx_variant: OnnxVariant) -> Result<()> {
     let required_models: Vec<KnownModel> = match requested_model {
+        _ if args.ensemble.is_some() => vec![KnownModel::Rmbg14, KnownModel::Rmbg20],
         ModelKind::Rmbg14 | ModelKind::Auto => vec![KnownModel::Rmbg14],
         ModelKind::Rmbg20 => vec![KnownModel::Rmbg20],
     };
@@ -481,11 +1479,19 @@ fn ensure_models_for_exec(args: &ExecArgs, requested_model: ModelKind, onnx_vari
         model_dir: args.model_dir.clone(),
         install_all: false,
         models: required_models,
+        custom_models: Vec::new(),
         hf_token_env: "HF_TOKEN".to_string(),
         revision_rmbg14: "main".to_string(),
         revision_rmbg20: "main".to_string(),
         verify_only: false,
         onnx_variant,
+        force: false,
+        keep_temp_on_failure: false,
+        temp_dir: None,
+        download_buffer_size: DEFAULT_DOWNLOAD_BUFFER_SIZE,
+        report_throughput: false,
+        cancel: None,
+        download_sink: None,
     })?;
     if report.installed.is_empty() && report.skipped.is_empty() {
         eprintln!("Model install step completed.");
@@ -493,27 +1499,46 @@ fn ensure_models_for_exec(args: &ExecArgs, requested_model: ModelKind, onnx_vari
     Ok(())
 }
 
+fn explain_onnx_choice(args: &ExplainArgs) -> Result<serde_json::Value> {
+    let model = match parse_model_choice(&args.model)? {
+        ModelKind::Rmbg14 | ModelKind::Auto => KnownModel::Rmbg14,
+        ModelKind::Rmbg20 => KnownModel::Rmbg20,
+    };
+    let onnx_variant = parse_onnx_variant(&args.onnx_variant)?;
+    let search = model_search_path(args.model_dir.as_deref())?;
+    let installed = find_installed_model(&search, model)
+        .ok_or_else(|| anyhow!("model not found in lockfile: {}", model.model_id()))?;
+    let entry = &installed.lock;
+    let rev_dir = installed.revision_dir.clone();
+    let candidates = rank_onnx_files(&rev_dir, onnx_variant);
+    Ok(serde_json::json!({
+        "modelId": entry.model_id,
+        "revision": entry.revision,
+        "requestedVariant": args.onnx_variant.to_ascii_lowercase(),
+        "revisionDir": rev_dir,
+        "chosen": candidates.first().map(|c| c.path.clone()),
+        "candidates": candidates,
+        "availableVariants": detect_available_variants(&rev_dir),
+    }))
+}
+
 fn has_required_models_for_exec(model_dir: Option<&Path>, required_models: &[KnownModel]) -> Result<bool> {
-    let paths = resolve_model_paths(model_dir)?;
-    let lock = match read_lockfile(&paths) {
-        Ok(lock) => lock,
-        Err(_) => return Ok(false),
+    let search = model_search_path(model_dir)?;
+    Ok(required_models
+        .iter()
+        .all(|model| search.iter().any(|paths| has_main_revision(paths, *model))))
+}
+
+fn has_main_revision(paths: &ModelPaths, model: KnownModel) -> bool {
+    let revision = "main";
+    let Ok(lock) = read_lockfile(paths) else {
+        return false;
     };
-    for model in required_models {
-        let revision = "main";
-        let has_entry = lock
-            .models
-            .iter()
-            .any(|entry| entry.model_id == model.model_id() && entry.revision == revision);
-        if !has_entry {
-            return Ok(false);
-        }
-        let rev_dir = model_revision_dir(&paths, *model, revision);
-        if !directory_has_onnx_file(&rev_dir) {
-            return Ok(false);
-        }
-    }
-    Ok(true)
+    let has_entry = lock
+        .models
+        .iter()
+        .any(|entry| entry.model_id == model.model_id() && entry.revision == revision);
+    has_entry && directory_has_onnx_file(&model_revision_dir(paths, model, revision))
 }
 
 fn directory_has_onnx_file(dir: &Path) -> bool {
@@ -531,35 +1556,6 @@ fn directory_has_onnx_file(dir: &Path) -> bool {
         })
 }
 
-fn default_cutout_path(input: &Path) -> Result<PathBuf> {
-    let stem = input
-        .file_stem()
-        .ok_or_else(|| anyhow!("input file must include a valid file name"))?
-        .to_string_lossy();
-    let filename = format!("{}_cutout.png", stem);
-    let out_path = if let Some(parent) = input.parent() {
-        parent.join(filename)
-    } else {
-        PathBuf::from(filename)
-    };
-    Ok(out_path)
-}
-
-fn validate_cutout_extension(path: &Path) -> Result<()> {
-    let ext = path
-        .extension()
-        .and_then(|e| e.to_str())
-        .map(|e| e.to_ascii_lowercase())
-        .unwrap_or_default();
-    if ext != "png" {
-        return Err(anyhow!(
-            "output cutout must be a .png file (received: '{}')",
-            path.display()
-        ));
-    }
-    Ok(())
-}
-
 fn set_ort_dylib_path_if_available() {
     if std::env::var("ORT_DYLIB_PATH").is_ok() {
         return;
@@ -668,6 +1664,27 @@ fn model_kind_label(model: ModelKind) -> &'static str {
     }
 }
 
+/// The custom model's id when one produced the mask, otherwise the RMBG model's label.
+fn model_used_label(result: &InferenceResult) -> &str {
+    result
+        .custom_model_used
+        .as_deref()
+        .unwrap_or_else(|| model_kind_label(result.model_used))
+}
+
+/// Small synthetic image used to load sessions before the server accepts traffic.
+fn serve_warmup_request(base: &InferenceRequest) -> Result<InferenceRequest> {
+    let (width, height) = (64u32, 64u32);
+    let sample = image::RgbImage::from_fn(width, height, |x, y| image::Rgb([(x * 4) as u8, (y * 4) as u8, 128]));
+    let mut bytes = Vec::new();
+    image::DynamicImage::ImageRgb8(sample).write_to(&mut std::io::Cursor::new(&mut bytes), image::ImageFormat::Png)?;
+    let mut request = base.clone();
+    request.input_bytes = Some(bytes);
+    request.width = width;
+    request.height = height;
+    Ok(request)
+}
+
 fn parse_onnx_variant(value: &str) -> Result<OnnxVariant> {
     match value.to_ascii_lowercase().as_str() {
         "fp16" => Ok(OnnxVariant::Fp16),
@@ -693,6 +1710,18 @@ fn parse_execution_provider(value: &str) -> Result<ExecutionProvider> {
     }
 }
 
+fn parse_thread_priority(value: &str) -> Result<ThreadPriority> {
+    match value.to_ascii_lowercase().as_str() {
+        "normal" => Ok(ThreadPriority::Normal),
+        "low" => Ok(ThreadPriority::Low),
+        "lowest" => Ok(ThreadPriority::Lowest),
+        other => Err(anyhow!(
+            "unknown thread priority '{}'; expected one of: normal, low, lowest",
+            other
+        )),
+    }
+}
+
 fn parse_gpu_backend(value: &str) -> Result<GpuBackendPreference> {
     match value.to_ascii_lowercase().as_str() {
         "auto" => Ok(GpuBackendPreference::Auto),
@@ -707,25 +1736,735 @@ fn parse_gpu_backend(value: &str) -> Result<GpuBackendPreference> {
     }
 }
 
-fn write_cutout_png(source_bytes: &[u8], mask_png: &[u8], out_path: &std::path::Path) -> Result<()> {
-    let source = image::load_from_memory(source_bytes)?.to_rgba8();
-    let mask = image::load_from_memory(mask_png)?.to_luma8();
-    let (w, h) = source.dimensions();
-    if mask.dimensions() != (w, h) {
-        return Err(anyhow!("mask dimensions do not match source dimensions"));
+/// Cutouts at or above this many pixels are written row by row from a PNG source instead of
+/// composing the whole image in memory.
+const STREAMING_CUTOUT_MIN_PIXELS: u64 = 16_000_000;
+
+fn write_cutout_png(
+    source_bytes: &[u8],
+    mask_png: &[u8],
+    effects: &[Effect],
+    background: &Backdrop<'_>,
+    metadata: &[(String, String)],
+    encode: &EncodeOptions,
+    out_path: &std::path::Path,
+) -> Result<()> {
+    if let Some(parent) = out_path.parent() {
+        std::fs::create_dir_all(parent)?;
     }
+    // Effects need neighbouring pixels, so only plain cutouts and ones flattened onto an opaque
+    // color can be streamed.
+    let stream_background = match background {
+        Backdrop::Transparent => Some(None),
+        Backdrop::Color([r, g, b, 255]) => Some(Some([*r, *g, *b])),
+        _ => None,
+    };
+    if let Some(background) = stream_background.filter(|_| effects.is_empty() && sniff_format(source_bytes) == SourceFormat::Png) {
+        match CutoutStream::new(std::io::Cursor::new(source_bytes), std::io::Cursor::new(mask_png)) {
+            Ok(stream) => {
+                let (width, height) = stream.dimensions();
+                if width as u64 * height as u64 >= STREAMING_CUTOUT_MIN_PIXELS {
+                    let out = std::io::BufWriter::new(std::fs::File::create(out_path)?);
+                    stream.write_png(background, metadata, encode, out)?;
+                    return Ok(());
+                }
+            }
+            // Mismatched masks are resized by the in-memory path.
+            Err(CutoutStreamError::Unsupported(_) | CutoutStreamError::DimensionMismatch { .. }) => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+    if effects.is_empty() && *background == Backdrop::Transparent && metadata.is_empty() && *encode == EncodeOptions::default() {
+        std::fs::write(out_path, apply_mask_to_rgba(source_bytes, mask_png)?)?;
+        return Ok(());
+    }
+    let (source, mask) = load_source_and_mask(source_bytes, mask_png)?;
+    let cutout = compose_cutout(&source, &mask, effects, background)?;
+    let mut encoded = Vec::new();
+    encode_rgba_png(&cutout, metadata, encode, &mut encoded)?;
+    std::fs::write(out_path, encoded)?;
+    Ok(())
+}
+
+/// Writes a cutout in the format of `out_path`'s extension. Formats without alpha (JPEG) are
+/// flattened onto white unless another background is given; provenance metadata is only
+/// embedded in PNGs.
+fn write_cutout(
+    source_bytes: &[u8],
+    mask_png: &[u8],
+    effects: &[Effect],
+    background: &Backdrop<'_>,
+    metadata: &[(String, String)],
+    encode: &EncodeOptions,
+    out_path: &std::path::Path,
+) -> Result<()> {
+    let format = image::ImageFormat::from_path(out_path)?;
+    let white = Backdrop::Color([255, 255, 255, 255]);
+    let background = match background {
+        Backdrop::Transparent if format == image::ImageFormat::Jpeg => &white,
+        other => other,
+    };
+    if format == image::ImageFormat::Png {
+        return write_cutout_png(source_bytes, mask_png, effects, background, metadata, encode, out_path);
+    }
+    let (source, mask) = load_source_and_mask(source_bytes, mask_png)?;
+    if let Some(parent) = out_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    save_encoded(
+        image::DynamicImage::ImageRgba8(compose_cutout(&source, &mask, effects, background)?),
+        encode,
+        out_path,
+    )
+}
 
+/// PNG text entries describing how a mask was produced, for `--embed-metadata`. The variant and
+/// revision are the ones the backend resolved and are left out when it did not load a model file.
+fn provenance_text(result: &InferenceResult, produced_at: SystemTime) -> Vec<(String, String)> {
+    let mut entries = vec![
+        ("unbg:model".to_string(), model_used_label(result).to_string()),
+        ("unbg:provider".to_string(), result.execution_provider_selected.clone()),
+    ];
+    if let Some(backend) = &result.gpu_backend_selected {
+        entries.push(("unbg:gpu-backend".to_string(), backend.clone()));
+    }
+    if let Some(variant) = result.onnx_variant {
+        let label = serde_json::to_value(variant)
+            .ok()
+            .and_then(|v| v.as_str().map(str::to_string))
+            .unwrap_or_default();
+        entries.push(("unbg:onnx-variant".to_string(), label));
+    }
+    if let Some(revision) = &result.model_revision {
+        entries.push(("unbg:revision".to_string(), revision.clone()));
+    }
+    let seconds = produced_at.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    entries.push(("unbg:created".to_string(), seconds.to_string()));
+    entries
+}
+
+/// Applies `mask` as alpha, then effects, then composites the result over `background`.
+fn compose_cutout(
+    source: &image::RgbaImage,
+    mask: &image::GrayImage,
+    effects: &[Effect],
+    background: &Backdrop<'_>,
+) -> Result<image::RgbaImage> {
+    if effects.is_empty() {
+        return Ok(composite_over_background(source, mask, background)?);
+    }
     let mut cutout = source.clone();
-    for y in 0..h {
-        for x in 0..w {
-            let alpha = mask.get_pixel(x, y)[0];
-            let px = cutout.get_pixel_mut(x, y);
-            px[3] = alpha;
-        }
+    set_alpha_from_mask(&mut cutout, mask);
+    cutout = apply_effects(&cutout, effects);
+    if *background == Backdrop::Transparent {
+        return Ok(cutout);
     }
+    // Effects extend the alpha beyond the mask, so composite with the alpha they produced.
+    let alpha = image::GrayImage::from_fn(cutout.width(), cutout.height(), |x, y| image::Luma([cutout.get_pixel(x, y)[3]]));
+    Ok(composite_over_background(&cutout, &alpha, background)?)
+}
+
+fn write_overlay_png(
+    source_bytes: &[u8],
+    mask_png: &[u8],
+    color: [u8; 3],
+    opacity: f32,
+    encode: &EncodeOptions,
+    out_path: &std::path::Path,
+) -> Result<()> {
+    let (source, mask) = load_source_and_mask(source_bytes, mask_png)?;
+    let overlay = overlay_mask(&source, &mask, color, opacity);
     if let Some(parent) = out_path.parent() {
         std::fs::create_dir_all(parent)?;
     }
-    cutout.save(out_path)?;
+    save_encoded(image::DynamicImage::ImageRgba8(overlay), encode, out_path)
+}
+
+/// Source bytes for inference and the output writers under `--strip-metadata`. Outputs are
+/// encoded from decoded pixels and never carry the input's metadata, so only a rotation has to
+/// be baked in; inputs that already display upright are passed through untouched.
+fn oriented_source(source: Vec<u8>, oriented: &image::DynamicImage) -> Result<Vec<u8>> {
+    if !has_exif_rotation(&source) {
+        return Ok(source);
+    }
+    let stored = EncodeOptions {
+        png_compression: Some(0),
+        ..EncodeOptions::default()
+    };
+    let mut out = Vec::new();
+    encode_rgba_png(&oriented.to_rgba8(), &[], &stored, &mut out)?;
+    Ok(out)
+}
+
+/// Writes `img` in the format implied by `path`'s extension.
+fn save_encoded(img: image::DynamicImage, encode: &EncodeOptions, path: &std::path::Path) -> Result<()> {
+    let format = image::ImageFormat::from_path(path)?;
+    std::fs::write(path, encode_image(&img, format, encode)?)?;
     Ok(())
 }
+
+/// Original, mask and cutout of one input, shrunk to `thumb` pixels, for `--contact-sheet`.
+fn contact_sheet_entry(source_bytes: &[u8], mask_png: &[u8], effects: &[Effect], thumb: u32) -> Result<Vec<image::RgbaImage>> {
+    let (source, mask) = load_source_and_mask(source_bytes, mask_png)?;
+    let cutout = compose_cutout(&source, &mask, effects, &Backdrop::Transparent)?;
+    let shrink = |img: image::DynamicImage| img.thumbnail(thumb, thumb).to_rgba8();
+    Ok(vec![
+        shrink(image::DynamicImage::ImageRgba8(source)),
+        shrink(image::DynamicImage::ImageLuma8(mask)),
+        shrink(image::DynamicImage::ImageRgba8(cutout)),
+    ])
+}
+
+fn load_source_and_mask(source_bytes: &[u8], mask_png: &[u8]) -> Result<(image::RgbaImage, image::GrayImage)> {
+    Ok(decode_source_and_mask(source_bytes, mask_png)?)
+}
+
+/// Tints the source toward `color` in proportion to the mask, leaving background pixels untouched.
+fn overlay_mask(source: &image::RgbaImage, mask: &image::GrayImage, color: [u8; 3], opacity: f32) -> image::RgbaImage {
+    let opacity = opacity.clamp(0.0, 1.0);
+    let mut out = source.clone();
+    for (x, y, px) in out.enumerate_pixels_mut() {
+        let weight = (mask.get_pixel(x, y)[0] as f32 / 255.0) * opacity;
+        for c in 0..3 {
+            let blended = px[c] as f32 * (1.0 - weight) + color[c] as f32 * weight;
+            px[c] = blended.round().clamp(0.0, 255.0) as u8;
+        }
+        px[3] = 255;
+    }
+    out
+}
+
+fn parse_free_dimension(value: &str) -> std::result::Result<(String, i64), String> {
+    let (name, size) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=SIZE (received: '{}')", value))?;
+    let size = size
+        .trim()
+        .parse::<i64>()
+        .map_err(|_| format!("dimension size must be an integer (received: '{}')", size))?;
+    Ok((name.trim().to_string(), size))
+}
+
+fn parse_ensemble(value: &str) -> std::result::Result<EnsembleMode, String> {
+    let lower = value.trim().to_ascii_lowercase();
+    match lower.split_once(':') {
+        Some(("weighted", weight)) => {
+            let weight = weight
+                .trim()
+                .parse::<f32>()
+                .map_err(|_| format!("ensemble weight must be a number between 0.0 and 1.0 (received: '{}')", value))?;
+            if !(0.0..=1.0).contains(&weight) {
+                return Err(format!("ensemble weight must be between 0.0 and 1.0 (received: '{}')", value));
+            }
+            Ok(EnsembleMode::Weighted { weight })
+        }
+        None if lower == "average" => Ok(EnsembleMode::Average),
+        None if lower == "max" => Ok(EnsembleMode::Max),
+        _ => Err(format!("expected weighted:W, average or max (received: '{}')", value)),
+    }
+}
+
+fn parse_mask_activation(value: &str) -> std::result::Result<MaskActivation, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "min-max" | "minmax" => Ok(MaskActivation::MinMax),
+        "sigmoid" => Ok(MaskActivation::Sigmoid),
+        "clamp" => Ok(MaskActivation::Clamp),
+        other => Err(format!("unknown mask activation '{}'; expected one of: sigmoid, clamp, min-max", other)),
+    }
+}
+
+fn parse_graph_opt(value: &str) -> std::result::Result<GraphOptLevel, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "disable" | "none" => Ok(GraphOptLevel::Disable),
+        "basic" => Ok(GraphOptLevel::Basic),
+        "extended" => Ok(GraphOptLevel::Extended),
+        "layout" => Ok(GraphOptLevel::Layout),
+        "all" => Ok(GraphOptLevel::All),
+        other => Err(format!(
+            "unknown graph optimization level '{}'; expected one of: disable, basic, extended, layout, all",
+            other
+        )),
+    }
+}
+
+fn parse_empty_mask_policy(value: &str) -> std::result::Result<EmptyMaskPolicy, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "error" => Ok(EmptyMaskPolicy::Error),
+        "passthrough" => Ok(EmptyMaskPolicy::Passthrough),
+        "flag" => Ok(EmptyMaskPolicy::Flag),
+        other => Err(format!("unknown empty-mask policy '{}'; expected one of: error, passthrough, flag", other)),
+    }
+}
+
+/// Newtype so clap treats the whole point list as one value rather than a `Vec` of them.
+#[derive(Debug, Clone)]
+struct AlphaCurveArg(Vec<(f32, f32)>);
+
+fn parse_alpha_curve(value: &str) -> std::result::Result<AlphaCurveArg, String> {
+    value
+        .split(',')
+        .map(|point| {
+            let (x, y) = point
+                .split_once(':')
+                .ok_or_else(|| format!("expected IN:OUT points, e.g. 0:0,0.4:0.8,1:1 (received: '{}')", value))?;
+            let parse = |raw: &str| {
+                raw.trim()
+                    .parse::<f32>()
+                    .map_err(|_| format!("invalid alpha curve value '{}' in '{}'", raw.trim(), value))
+            };
+            Ok((parse(x)?, parse(y)?))
+        })
+        .collect::<std::result::Result<Vec<_>, String>>()
+        .and_then(|curve| validate_alpha_curve(&curve).map(|()| AlphaCurveArg(curve)).map_err(|err| err.to_string()))
+}
+
+/// A `<n><unit>` age (`s`, `m`, `h` or `d`) in milliseconds.
+fn parse_age(value: &str) -> std::result::Result<u64, String> {
+    let trimmed = value.trim();
+    let split = trimmed.len().saturating_sub(1);
+    let unit_ms = match trimmed.get(split..) {
+        Some("s") => 1_000,
+        Some("m") => 60_000,
+        Some("h") => 3_600_000,
+        Some("d") => 86_400_000,
+        _ => return Err(format!("expected an age like 90s, 30m, 12h or 7d (received: '{}')", value)),
+    };
+    trimmed[..split]
+        .parse::<u64>()
+        .map(|count| count.saturating_mul(unit_ms))
+        .map_err(|_| format!("expected an age like 90s, 30m, 12h or 7d (received: '{}')", value))
+}
+
+fn parse_trimap(value: &str) -> std::result::Result<TrimapThresholds, String> {
+    let (low, high) = value
+        .split_once(':')
+        .ok_or_else(|| format!("expected LOW:HIGH, e.g. 0.1:0.9 (received: '{}')", value))?;
+    let parse = |raw: &str| {
+        raw.trim()
+            .parse::<f32>()
+            .map_err(|_| format!("trimap cutoffs must be numbers between 0.0 and 1.0 (received: '{}')", value))
+    };
+    TrimapThresholds::new(parse(low)?, parse(high)?).map_err(|err| err.to_string())
+}
+
+fn parse_roi(value: &str) -> std::result::Result<RegionOfInterest, String> {
+    let parts: Vec<u32> = value
+        .split(',')
+        .map(|raw| raw.trim().parse::<u32>())
+        .collect::<std::result::Result<_, _>>()
+        .map_err(|_| format!("region must be four whole numbers X,Y,WIDTH,HEIGHT (received: '{}')", value))?;
+    match parts[..] {
+        [x, y, width, height] if width > 0 && height > 0 => Ok(RegionOfInterest { x, y, width, height }),
+        _ => Err(format!("region must be X,Y,WIDTH,HEIGHT with a non-zero size (received: '{}')", value)),
+    }
+}
+
+fn parse_stroke(value: &str) -> std::result::Result<Effect, String> {
+    let mut parts = value.split(':');
+    let width = parts
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .parse::<u32>()
+        .map_err(|_| format!("stroke width must be a whole number of pixels (received: '{}')", value))?;
+    let color = match parts.next() {
+        Some(hex) => parse_hex_color(hex).map_err(|err| err.to_string())?,
+        None => [255, 255, 255],
+    };
+    Ok(Effect::Stroke { width, color })
+}
+
+fn parse_shadow(value: &str) -> std::result::Result<Effect, String> {
+    let parts: Vec<&str> = value.split(':').map(str::trim).collect();
+    if !(3..=5).contains(&parts.len()) {
+        return Err(format!("expected BLUR:DX:DY[:OPACITY[:RRGGBB]] (received: '{}')", value));
+    }
+    let number = |raw: &str| raw.parse::<i32>().map_err(|_| format!("shadow blur and offsets must be integers (received: '{}')", value));
+    let blur = u32::try_from(number(parts[0])?).map_err(|_| format!("shadow blur must not be negative (received: '{}')", value))?;
+    let offset = (number(parts[1])?, number(parts[2])?);
+    let opacity = match parts.get(3) {
+        Some(raw) => raw
+            .parse::<f32>()
+            .map_err(|_| format!("shadow opacity must be a number between 0.0 and 1.0 (received: '{}')", value))?,
+        None => 0.5,
+    };
+    let color = match parts.get(4) {
+        Some(hex) => parse_hex_color(hex).map_err(|err| err.to_string())?,
+        None => [0, 0, 0],
+    };
+    Ok(Effect::Shadow {
+        blur,
+        offset,
+        opacity,
+        color,
+    })
+}
+
+fn parse_hex_color(value: &str) -> Result<[u8; 3]> {
+    let hex = value.trim().trim_start_matches('#');
+    if hex.len() != 6 {
+        return Err(anyhow!("color must be a 6-digit hex RGB value (received: '{}')", value));
+    }
+    let mut rgb = [0u8; 3];
+    for (i, channel) in rgb.iter_mut().enumerate() {
+        *channel = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+            .map_err(|_| anyhow!("color must be a 6-digit hex RGB value (received: '{}')", value))?;
+    }
+    Ok(rgb)
+}
+
+/// Parses `RRGGBB` (opaque) or `RRGGBBAA` hex, with or without a leading `#`.
+fn parse_bg_color(value: &str) -> Result<[u8; 4]> {
+    let invalid = || anyhow!("color must be a 6- or 8-digit hex value (received: '{}')", value);
+    let hex = value.trim().trim_start_matches('#');
+    if !hex.is_ascii() {
+        return Err(invalid());
+    }
+    let (rgb, alpha) = match hex.len() {
+        6 => (hex, 255),
+        8 => (&hex[..6], u8::from_str_radix(&hex[6..], 16).map_err(|_| invalid())?),
+        _ => return Err(invalid()),
+    };
+    let [r, g, b] = parse_hex_color(rgb).map_err(|_| invalid())?;
+    Ok([r, g, b, alpha])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    pub(crate) fn png_bytes(image: &image::DynamicImage) -> Vec<u8> {
+        let mut png = Vec::new();
+        image.write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png).unwrap();
+        png
+    }
+
+    #[test]
+    fn overlay_blends_foreground_toward_color() {
+        let source = image::RgbaImage::from_pixel(2, 1, image::Rgba([0, 0, 200, 255]));
+        let mut mask = image::GrayImage::new(2, 1);
+        mask.put_pixel(0, 0, image::Luma([255]));
+        mask.put_pixel(1, 0, image::Luma([0]));
+
+        let overlay = overlay_mask(&source, &mask, parse_hex_color("#ff0000").unwrap(), 0.5);
+
+        let fg = overlay.get_pixel(0, 0);
+        assert_eq!(fg.0, [128, 0, 100, 255]);
+        let bg = overlay.get_pixel(1, 0);
+        assert_eq!(bg.0, [0, 0, 200, 255]);
+    }
+
+    #[test]
+    fn effect_specs_parse_with_defaults() {
+        assert_eq!(
+            parse_stroke("3").unwrap(),
+            Effect::Stroke {
+                width: 3,
+                color: [255, 255, 255]
+            }
+        );
+        assert_eq!(
+            parse_shadow("8:6:-2:0.4:202020").unwrap(),
+            Effect::Shadow {
+                blur: 8,
+                offset: (6, -2),
+                opacity: 0.4,
+                color: [0x20, 0x20, 0x20]
+            }
+        );
+        assert!(parse_shadow("8:6").is_err());
+    }
+
+    fn exec_args(argv: &[&str]) -> ExecArgs {
+        let cli = Cli::parse_from(["unbg", "exec"].iter().chain(argv));
+        match cli.command {
+            TopLevelCommand::Exec(args) => *args,
+            other => panic!("expected exec command, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn history_ages_parse_into_milliseconds() {
+        assert_eq!(parse_age("90s").unwrap(), 90_000);
+        assert_eq!(parse_age("30m").unwrap(), 1_800_000);
+        assert_eq!(parse_age("7d").unwrap(), 604_800_000);
+        assert!(parse_age("7").is_err());
+        assert!(parse_age("h").is_err());
+        assert!(parse_age("1w").is_err());
+    }
+
+    #[test]
+    fn alpha_curves_are_checked_while_parsing() {
+        assert_eq!(parse_alpha_curve("0:0, 0.4:0.8, 1:1").unwrap().0, vec![(0.0, 0.0), (0.4, 0.8), (1.0, 1.0)]);
+        let err = parse_alpha_curve("0:0,0.6:0.5,0.4:0.7,1:1").unwrap_err();
+        assert!(err.contains("strictly increase"), "{}", err);
+        assert!(parse_alpha_curve("0:0,1:1.5").is_err());
+    }
+
+    #[test]
+    fn preserve_structure_mirrors_input_subdirectories() {
+        let tmp = tempfile::tempdir().unwrap();
+        let input = tmp.path().join("in");
+        for sub in ["a", "b"] {
+            std::fs::create_dir_all(input.join(sub)).unwrap();
+            std::fs::write(input.join(sub).join("1.png"), b"").unwrap();
+        }
+        let out = tmp.path().join("out");
+        let (input_arg, out_arg) = (input.display().to_string(), out.display().to_string());
+        let args = exec_args(&["-i", &input_arg, "--output-dir", &out_arg, "--preserve-structure"]);
+
+        let mut cutouts = Vec::new();
+        for path in resolve_exec_inputs(&args).unwrap() {
+            let (cutout, _, _) = resolve_outputs_for_input(&args, &path, &mut HashSet::new()).unwrap();
+            cutouts.push(cutout.unwrap());
+        }
+        assert_eq!(
+            cutouts,
+            vec![out.join("a").join("1_cutout.png"), out.join("b").join("1_cutout.png")]
+        );
+
+        let root_arg = input.display().to_string();
+        let regex_args = exec_args(&["-i", r"^1\.png$", "-r", &root_arg, "--output-dir", &out_arg, "--preserve-structure"]);
+        let (cutout, _, _) = resolve_outputs_for_input(&regex_args, &input.join("b").join("1.png"), &mut HashSet::new()).unwrap();
+        assert_eq!(cutout.unwrap(), out.join("b").join("1_cutout.png"));
+    }
+
+    #[test]
+    fn collision_policy_skips_or_renames_existing_outputs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let input = tmp.path().join("1.png");
+        std::fs::write(&input, b"").unwrap();
+        let existing = tmp.path().join("1_cutout.png");
+        std::fs::write(&existing, b"keep").unwrap();
+        let input_arg = input.display().to_string();
+
+        let skip = exec_args(&["-i", &input_arg, "--on-collision", "skip"]);
+        let (cutout, mask, _) = resolve_outputs_for_input(&skip, &input, &mut HashSet::new()).unwrap();
+        assert!(cutout.is_none() && mask.is_none());
+        assert_eq!(std::fs::read(&existing).unwrap(), b"keep");
+
+        let rename = exec_args(&["-i", &input_arg, "--on-collision", "rename"]);
+        let mut claimed = HashSet::new();
+        let (cutout, _, _) = resolve_outputs_for_input(&rename, &input, &mut claimed).unwrap();
+        assert_eq!(cutout.unwrap(), tmp.path().join("1_cutout_1.png"));
+        // A second input resolved before anything is written must not get the same free name.
+        let (cutout, _, _) = resolve_outputs_for_input(&rename, &input, &mut claimed).unwrap();
+        assert_eq!(cutout.unwrap(), tmp.path().join("1_cutout_2.png"));
+
+        let overwrite = exec_args(&["-i", &input_arg]);
+        let (cutout, _, _) = resolve_outputs_for_input(&overwrite, &input, &mut HashSet::new()).unwrap();
+        assert_eq!(cutout.unwrap(), existing);
+    }
+
+    #[test]
+    fn effective_config_reflects_env_overrides() {
+        let runtime_cfg = unbg_core::resolve_runtime_config(RuntimeConfig {
+            model: "fast".to_string(),
+            ..RuntimeConfig::default()
+        });
+        let model_path = std::env::join_paths(["/srv/shared-models", "/home/me/models"]).unwrap();
+        let model_path = model_path.to_string_lossy().into_owned();
+        let env = |key: &str| match key {
+            MODEL_PATH_ENV => Some(model_path.clone()),
+            "UNBG_MASK_CACHE_SIZE" => Some("16".to_string()),
+            "UNBG_REMOTE_TOKEN" => Some("secret".to_string()),
+            _ => None,
+        };
+        let config = effective_config(&runtime_cfg, &RuntimePolicy::desktop(), env, None).unwrap();
+
+        assert_eq!(config["runtimeConfig"]["model"], "fast");
+        assert_eq!(config["modelSearchPath"], serde_json::json!(["/srv/shared-models", "/home/me/models"]));
+        assert_eq!(config["env"][MODEL_PATH_ENV], serde_json::json!(model_path));
+        assert_eq!(config["env"]["UNBG_MASK_CACHE_SIZE"], "16");
+        assert!(config["env"].get("UNBG_REMOTE_TOKEN").is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn progress_lines_are_written_to_the_given_fd() {
+        use std::os::unix::io::IntoRawFd;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("progress.jsonl");
+        let fd = std::fs::File::create(&path).unwrap().into_raw_fd();
+
+        let inputs = [PathBuf::from("a.png"), PathBuf::from("b.png")];
+        let mut progress = ProgressReporter::new(open_progress_fd(fd), inputs.len());
+        for input in &inputs {
+            progress.finished(input);
+        }
+        progress.finish();
+        drop(progress);
+
+        let lines: Vec<serde_json::Value> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], serde_json::json!({ "done": 1, "total": 2, "current": "a.png" }));
+        assert_eq!(lines[1], serde_json::json!({ "done": 2, "total": 2, "current": "b.png" }));
+        assert_eq!(lines[2], serde_json::json!({ "done": 2, "total": 2, "current": null }));
+    }
+
+    #[test]
+    fn composite_flattens_existing_mask_onto_background() {
+        let source = image::RgbaImage::from_pixel(3, 1, image::Rgba([0, 0, 200, 255]));
+        let mask = image::GrayImage::from_raw(3, 1, vec![255, 0, 128]).unwrap();
+
+        let transparent = compose_cutout(&source, &mask, &[], &Backdrop::Transparent).unwrap();
+        assert_eq!(transparent.get_pixel(1, 0)[3], 0);
+
+        let flat = compose_cutout(&source, &mask, &[], &Backdrop::Color([255, 0, 0, 255])).unwrap();
+        assert_eq!(flat.get_pixel(0, 0).0, [0, 0, 200, 255]);
+        assert_eq!(flat.get_pixel(1, 0).0, [255, 0, 0, 255]);
+        assert_eq!(flat.get_pixel(2, 0).0, [127, 0, 100, 255]);
+
+        let source_png = png_bytes(&image::DynamicImage::ImageRgba8(source));
+        let small_mask = png_bytes(&image::DynamicImage::new_luma8(2, 1));
+        let (_, resized) = load_source_and_mask(&source_png, &small_mask).unwrap();
+        assert_eq!(resized.dimensions(), (3, 1));
+    }
+
+    #[test]
+    fn bg_color_accepts_rgb_and_rgba_hex() {
+        assert_eq!(parse_bg_color("#ffffff").unwrap(), [255, 255, 255, 255]);
+        assert_eq!(parse_bg_color("0a141e80").unwrap(), [10, 20, 30, 128]);
+        assert!(parse_bg_color("fff").is_err());
+        assert!(parse_bg_color("ffffffzz").is_err());
+    }
+
+    #[test]
+    fn one_run_writes_transparent_png_and_flattened_jpeg() {
+        let source = image::RgbaImage::from_pixel(8, 8, image::Rgba([0, 0, 200, 255]));
+        let mask = image::GrayImage::from_fn(8, 8, |x, _| image::Luma([if x < 4 { 255 } else { 0 }]));
+        let source_png = png_bytes(&image::DynamicImage::ImageRgba8(source));
+        let mask_png = png_bytes(&image::DynamicImage::ImageLuma8(mask));
+
+        let dir = tempfile::tempdir().unwrap();
+        let (png_out, jpeg_out) = (dir.path().join("cat.png"), dir.path().join("cat.jpg"));
+        let args = exec_args(&["-i", "cat.png", "--output", png_out.to_str().unwrap(), "--output", jpeg_out.to_str().unwrap()]);
+        let (cutout, mask_path, outputs) = resolve_outputs_for_input(&args, Path::new("cat.png"), &mut HashSet::new()).unwrap();
+        assert_eq!((cutout, mask_path), (None, None));
+        for path in &outputs {
+            write_cutout(&source_png, &mask_png, &[], &Backdrop::Transparent, &[], &EncodeOptions::default(), path).unwrap();
+        }
+
+        let png = image::open(&outputs[0]).unwrap().to_rgba8();
+        assert_eq!(png.get_pixel(0, 0).0, [0, 0, 200, 255]);
+        assert_eq!(png.get_pixel(7, 0)[3], 0);
+        let jpeg = image::open(&outputs[1]).unwrap();
+        assert!(!jpeg.color().has_alpha());
+        let jpeg = jpeg.to_rgb8();
+        assert!(jpeg.get_pixel(7, 7).0.iter().all(|&c| c > 240), "{:?}", jpeg.get_pixel(7, 7));
+        assert!(jpeg.get_pixel(0, 0)[2] > 150 && jpeg.get_pixel(0, 0)[0] < 60, "{:?}", jpeg.get_pixel(0, 0));
+    }
+
+    #[test]
+    fn stripped_outputs_drop_exif_and_keep_icc_only_on_request() {
+        use image::ImageEncoder;
+
+        let profile = b"unbg-test-icc-profile".to_vec();
+        let mut jpeg = Vec::new();
+        let mut encoder = image::codecs::jpeg::JpegEncoder::new(&mut jpeg);
+        encoder.set_icc_profile(profile.clone()).unwrap();
+        encoder
+            .write_image(&image::RgbImage::from_pixel(4, 2, image::Rgb([200, 100, 50])), 4, 2, image::ExtendedColorType::Rgb8)
+            .unwrap();
+        // Little-endian TIFF: IFD0 holds Orientation = 6 (rotate 90 degrees) and a GPS IFD
+        // pointer; the GPS IFD holds GPSLatitudeRef = "N".
+        let mut exif = b"Exif\0\0II*\0\x08\0\0\0".to_vec();
+        exif.extend_from_slice(&[2, 0]);
+        exif.extend_from_slice(&[0x12, 0x01, 3, 0, 1, 0, 0, 0, 6, 0, 0, 0]);
+        exif.extend_from_slice(&[0x25, 0x88, 4, 0, 1, 0, 0, 0, 38, 0, 0, 0]);
+        exif.extend_from_slice(&[0, 0, 0, 0]);
+        exif.extend_from_slice(&[1, 0, 0x01, 0x00, 2, 0, 2, 0, 0, 0, b'N', 0, 0, 0, 0, 0, 0, 0]);
+        let mut tagged = jpeg[..2].to_vec();
+        tagged.extend_from_slice(&[0xFF, 0xE1]);
+        tagged.extend_from_slice(&((exif.len() + 2) as u16).to_be_bytes());
+        tagged.extend_from_slice(&exif);
+        tagged.extend_from_slice(&jpeg[2..]);
+
+        let hints = DecodeHints {
+            apply_orientation: true,
+            ..DecodeHints::default()
+        };
+        let image = decode_image(&tagged, &hints).unwrap();
+        let icc_profile = read_icc_profile(&tagged);
+        assert_eq!(icc_profile.as_deref(), Some(&profile[..]));
+        let source = oriented_source(tagged, &image).unwrap();
+        let mask = png_bytes(&image::DynamicImage::ImageLuma8(image::GrayImage::from_pixel(2, 4, image::Luma([255]))));
+
+        let dir = tempfile::tempdir().unwrap();
+        let keep = EncodeOptions {
+            icc_profile,
+            ..EncodeOptions::default()
+        };
+        for (name, encode, expect_icc) in [
+            ("plain.png", EncodeOptions::default(), false),
+            ("icc.png", keep.clone(), true),
+            ("icc.jpg", keep, true),
+        ] {
+            let path = dir.path().join(name);
+            write_cutout(&source, &mask, &[], &Backdrop::Transparent, &[], &encode, &path).unwrap();
+            let written = std::fs::read(&path).unwrap();
+            assert_eq!(image::load_from_memory(&written).unwrap().dimensions(), (2, 4), "{}", name);
+            let contains = |needle: &[u8]| written.windows(needle.len()).any(|w| w == needle);
+            for needle in [&b"Exif"[..], b"eXIf", b"tEXt", b"II*\0"] {
+                assert!(!contains(needle), "{} still holds {:?}", name, String::from_utf8_lossy(needle));
+            }
+            let expected = expect_icc.then(|| profile.clone());
+            assert_eq!(read_icc_profile(&written), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn embedded_metadata_reads_back_from_written_cutout() {
+        let mut result = InferenceResult::new(ModelKind::Rmbg20, Vec::new(), 2, 1, "gpu");
+        result.gpu_backend_selected = Some("cuda".to_string());
+        result.onnx_variant = Some(OnnxVariant::Quantized);
+        result.model_revision = Some("1a2b3c".to_string());
+        let produced_at = UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        let metadata = provenance_text(&result, produced_at);
+
+        let source = png_bytes(&image::DynamicImage::new_rgb8(2, 1));
+        let mask = png_bytes(&image::DynamicImage::new_luma8(2, 1));
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("cutout.png");
+        write_cutout_png(&source, &mask, &[], &Backdrop::Transparent, &metadata, &EncodeOptions::default(), &out).unwrap();
+
+        let text: std::collections::HashMap<_, _> = unbg_image::read_png_text(&std::fs::read(&out).unwrap())
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(text["unbg:model"], model_kind_label(ModelKind::Rmbg20));
+        assert_eq!(text["unbg:provider"], "gpu");
+        assert_eq!(text["unbg:gpu-backend"], "cuda");
+        assert_eq!(text["unbg:onnx-variant"], "quantized");
+        assert_eq!(text["unbg:revision"], "1a2b3c");
+        assert_eq!(text["unbg:created"], "1700000000");
+
+        let placeholder = InferenceResult::new(ModelKind::Rmbg14, Vec::new(), 2, 1, "cpu");
+        let keys: Vec<_> = provenance_text(&placeholder, produced_at).into_iter().map(|(key, _)| key).collect();
+        assert!(!keys.iter().any(|key| key == "unbg:revision" || key == "unbg:onnx-variant"), "{:?}", keys);
+    }
+
+    #[test]
+    fn contact_sheet_for_two_inputs_has_one_row_per_input() {
+        let args = exec_args(&["-i", "x.png", "--contact-sheet", "sheet.png", "--contact-sheet-thumb", "32"]);
+        assert_eq!(args.contact_sheet.as_deref(), Some(Path::new("sheet.png")));
+        let entries: Vec<_> = [(120, 60), (40, 80)]
+            .into_iter()
+            .map(|(w, h)| {
+                let source = png_bytes(&image::DynamicImage::new_rgb8(w, h));
+                let mask = png_bytes(&image::DynamicImage::ImageLuma8(image::GrayImage::from_pixel(w, h, image::Luma([255]))));
+                contact_sheet_entry(&source, &mask, &[], args.contact_sheet_thumb).unwrap()
+            })
+            .collect();
+        assert_eq!(entries[0][1].dimensions(), (32, 16));
+
+        let sheet = contact_sheet(&entries, args.contact_sheet_columns, args.contact_sheet_thumb);
+        assert_eq!(sheet.dimensions(), (3 * 32, 2 * 32));
+        let wide = contact_sheet(&entries, 2, args.contact_sheet_thumb);
+        assert_eq!(wide.dimensions(), (6 * 32, 32));
+    }
+}
@@ -0,0 +1,642 @@
+//! Minimal HTTP/1.1 server exposing the v1 remove-background contract.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use unbg_core::{
+    run_inference, v1, BackgroundFill, CancelToken, CoreError, ErrorCode, InferenceBackend, InferenceRequest, RuntimePolicy,
+};
+use unbg_image::{composite_png, image_dimensions, Backdrop, DecodeHints, EncodeOptions};
+
+use super::{model_kind_label, parse_execution_provider, parse_gpu_backend, parse_model_choice, parse_onnx_variant};
+
+const MAX_BODY_BYTES: usize = 256 * 1024 * 1024;
+
+/// Request line plus headers; a client sending more gets a 431 instead of being buffered.
+const MAX_HEADER_BYTES: usize = 64 * 1024;
+const MAX_HEADER_LINES: usize = 100;
+
+/// Accepted connections that may wait for a free worker, per worker; beyond that the server
+/// answers 503 straight away.
+const QUEUED_PER_WORKER: usize = 4;
+
+/// Outcome of the self-test run at startup, reported by `/health`.
+pub(crate) type HealthReport = std::result::Result<serde_json::Value, String>;
+
+pub(crate) struct ServeState {
+    pub backend: Box<dyn InferenceBackend>,
+    /// Server-side defaults; requests may override model and provider options but never `model_dir`.
+    pub base_request: InferenceRequest,
+    pub policy: RuntimePolicy,
+    pub health: HealthReport,
+}
+
+/// Accepts connections until `shutdown` is cancelled and hands them to `workers` threads, then
+/// waits for queued and in-flight requests to finish.
+pub(crate) fn serve(listener: TcpListener, state: Arc<ServeState>, shutdown: CancelToken, workers: usize) -> Result<()> {
+    let workers = workers.max(1);
+    let (queue, connections) = mpsc::sync_channel::<TcpStream>(workers * QUEUED_PER_WORKER);
+    let connections = Arc::new(Mutex::new(connections));
+    let pool: Vec<JoinHandle<()>> = (0..workers)
+        .map(|_| {
+            let (state, connections) = (state.clone(), connections.clone());
+            thread::spawn(move || work(&connections, &state))
+        })
+        .collect();
+
+    listener.set_nonblocking(true)?;
+    let accepted = loop {
+        if shutdown.is_cancelled() {
+            break Ok(());
+        }
+        match listener.accept() {
+            Ok((stream, _)) => {
+                stream.set_nonblocking(false)?;
+                match queue.try_send(stream) {
+                    Ok(()) => {}
+                    Err(TrySendError::Full(stream)) => {
+                        let busy = HttpResponse::json(503, serde_json::json!({ "code": "busy", "message": "server busy" }));
+                        let _ = write_response(stream, &busy);
+                    }
+                    Err(TrySendError::Disconnected(_)) => break Err(anyhow!("serve workers exited")),
+                }
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(25)),
+            Err(err) => break Err(err.into()),
+        }
+    };
+    drop(queue);
+    for worker in pool {
+        let _ = worker.join();
+    }
+    accepted
+}
+
+fn work(connections: &Mutex<Receiver<TcpStream>>, state: &ServeState) {
+    loop {
+        let next = match connections.lock() {
+            Ok(connections) => connections.recv(),
+            Err(_) => return,
+        };
+        match next {
+            Ok(stream) => handle_connection(stream, state),
+            Err(_) => return,
+        }
+    }
+}
+
+/// Cancels `token` on SIGINT/SIGTERM so `serve` can drain before exiting.
+#[cfg(unix)]
+pub(crate) fn cancel_on_signal(token: CancelToken) {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    static SIGNALLED: AtomicBool = AtomicBool::new(false);
+    extern "C" fn on_signal(_: libc::c_int) {
+        SIGNALLED.store(true, Ordering::SeqCst);
+    }
+    let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe.
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+    thread::spawn(move || {
+        while !SIGNALLED.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(100));
+        }
+        token.cancel();
+    });
+}
+
+#[cfg(not(unix))]
+pub(crate) fn cancel_on_signal(_token: CancelToken) {}
+
+struct HttpRequest {
+    method: String,
+    path: String,
+    content_type: String,
+    body: Vec<u8>,
+}
+
+struct HttpResponse {
+    status: u16,
+    body: serde_json::Value,
+}
+
+impl HttpResponse {
+    fn json(status: u16, body: serde_json::Value) -> Self {
+        Self { status, body }
+    }
+
+    fn invalid(message: impl Into<String>) -> Self {
+        Self::json(
+            400,
+            serde_json::json!({ "code": "invalid-argument", "message": message.into() }),
+        )
+    }
+}
+
+fn handle_connection(stream: TcpStream, state: &ServeState) {
+    let response = match read_request(&stream) {
+        Ok(request) => route(request, state),
+        Err(err) if err.is::<HeadersTooLarge>() => HttpResponse::json(
+            431,
+            serde_json::json!({ "code": "headers-too-large", "message": err.to_string() }),
+        ),
+        Err(err) => HttpResponse::invalid(format!("malformed request: {}", err)),
+    };
+    let _ = write_response(stream, &response);
+}
+
+fn route(request: HttpRequest, state: &ServeState) -> HttpResponse {
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/health") => match &state.health {
+            Ok(report) => HttpResponse::json(200, serde_json::json!({ "status": "ok", "selfTest": report })),
+            Err(message) => HttpResponse::json(503, serde_json::json!({ "status": "error", "message": message })),
+        },
+        ("POST", "/v1/remove-background") => {
+            let parsed = if request.content_type.starts_with("multipart/form-data") {
+                parse_multipart(&request.content_type, &request.body)
+            } else {
+                serde_json::from_slice::<v1::RemoveBackgroundRequest>(&request.body)
+                    .map_err(|err| anyhow!("invalid request json: {}", err))
+            };
+            match parsed {
+                Ok(body) => remove_background(body, state),
+                Err(err) => HttpResponse::invalid(err.to_string()),
+            }
+        }
+        (_, "/health") | (_, "/v1/remove-background") => {
+            HttpResponse::json(405, serde_json::json!({ "code": "method-not-allowed", "message": "method not allowed" }))
+        }
+        _ => HttpResponse::json(404, serde_json::json!({ "code": "not-found", "message": "not found" })),
+    }
+}
+
+fn remove_background(mut body: v1::RemoveBackgroundRequest, state: &ServeState) -> HttpResponse {
+    let background = std::mem::take(&mut body.background);
+    // An unreadable backdrop is the client's mistake; catch it before paying for inference.
+    if let BackgroundFill::Image(bytes) = &background {
+        if let Err(err) = image_dimensions(bytes, &DecodeHints::default()) {
+            return HttpResponse::invalid(format!("invalid background image: {}", err));
+        }
+    }
+    let (request, policy) = match to_inference_request(&state.base_request, &state.policy, body) {
+        Ok(pair) => pair,
+        Err(err) => return HttpResponse::invalid(err.to_string()),
+    };
+    let result = match run_inference(state.backend.as_ref(), &request, &policy) {
+        Ok(result) => result,
+        Err(err) => return core_error_response(&err),
+    };
+    let backdrop = match &background {
+        BackgroundFill::Transparent => None,
+        BackgroundFill::Color(color) => Some(Backdrop::Color(*color)),
+        BackgroundFill::Image(bytes) => Some(Backdrop::Image(bytes)),
+    };
+    let composite = match backdrop {
+        None => None,
+        Some(fill) => match composite_png(
+            request.input_bytes.as_deref().unwrap_or_default(),
+            &result.mask_png,
+            &fill,
+            &EncodeOptions {
+                png_compression: request.png_compression,
+                ..EncodeOptions::default()
+            },
+        ) {
+            Ok(png) => Some(png),
+            Err(err) => return core_error_response(&CoreError::Backend(format!("failed to composite the result: {}", err))),
+        },
+    };
+    HttpResponse::json(
+        200,
+        serde_json::to_value(v1::RemoveBackgroundResponse {
+            model_used: model_kind_label(result.model_used).to_string(),
+            width: result.width,
+            height: result.height,
+            mask_png: result.mask_png,
+            provider_selected: result.execution_provider_selected,
+            backend_selected: result.gpu_backend_selected,
+            fallback_used: result.fallback_used,
+            composite_png: composite,
+            timings: result.timings,
+            mask_rle: result.mask_rle,
+        })
+        .unwrap_or_default(),
+    )
+}
+
+fn core_error_response(err: &CoreError) -> HttpResponse {
+    let info = err.as_error_info();
+    let status = match info.code {
+        ErrorCode::MissingInput | ErrorCode::InvalidArgument => 400,
+        ErrorCode::Rmbg20Disabled => 422,
+        ErrorCode::BackendError => 500,
+        ErrorCode::Cancelled => 499,
+        ErrorCode::ModelNotInstalled | ErrorCode::OnnxFileMissing => 503,
+    };
+    HttpResponse::json(status, serde_json::to_value(info).unwrap_or_default())
+}
+
+fn to_inference_request(
+    base: &InferenceRequest,
+    base_policy: &RuntimePolicy,
+    body: v1::RemoveBackgroundRequest,
+) -> Result<(InferenceRequest, RuntimePolicy)> {
+    if body.image_bytes.is_empty() {
+        return Err(anyhow!("imageBytes must not be empty"));
+    }
+    let mut request = base.clone();
+    if !body.model.trim().is_empty() {
+        request.requested_model = parse_model_choice(body.model.trim())?;
+    }
+    if let Some(value) = body.onnx_variant.as_deref() {
+        request.onnx_variant = parse_onnx_variant(value)?;
+    }
+    if let Some(value) = body.execution_provider.as_deref() {
+        request.execution_provider = parse_execution_provider(value)?;
+    }
+    if let Some(value) = body.gpu_backend.as_deref() {
+        request.gpu_backend = parse_gpu_backend(value)?;
+    }
+    if let Some(enabled) = body.benchmark_provider {
+        request.benchmark_provider = enabled;
+    }
+    if body.mask_threshold.is_some() {
+        request.mask_threshold = body.mask_threshold;
+    }
+    request.collect_timings = body.collect_timings;
+    request.emit_mask_rle = body.emit_mask_rle;
+    // The pixel policy is checked against these, so they come from the upload's header and any
+    // `width`/`height` the client sent is ignored.
+    let (width, height) = image_dimensions(&body.image_bytes, &DecodeHints::default())?;
+    request.width = width;
+    request.height = height;
+    request.input_bytes = Some(body.image_bytes);
+    let mut policy = base_policy.clone();
+    if let Some(max) = body.max_inference_pixels {
+        policy.max_inference_pixels = max;
+    }
+    Ok((request, policy))
+}
+
+fn read_request(stream: &TcpStream) -> Result<HttpRequest> {
+    stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+    let mut reader = BufReader::new(stream);
+    let mut budget = MAX_HEADER_BYTES;
+    let mut line = String::new();
+    read_header_line(&mut reader, &mut line, &mut budget)?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().ok_or_else(|| anyhow!("missing method"))?.to_string();
+    let target = parts.next().ok_or_else(|| anyhow!("missing path"))?;
+    let path = target.split('?').next().unwrap_or(target).to_string();
+
+    let mut content_length = 0usize;
+    let mut content_type = String::new();
+    let mut header_lines = 0;
+    loop {
+        line.clear();
+        if read_header_line(&mut reader, &mut line, &mut budget)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        header_lines += 1;
+        if header_lines > MAX_HEADER_LINES {
+            return Err(HeadersTooLarge.into());
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            match name.trim().to_ascii_lowercase().as_str() {
+                "content-length" => content_length = value.trim().parse()?,
+                "content-type" => content_type = value.trim().to_string(),
+                _ => {}
+            }
+        }
+    }
+    if content_length > MAX_BODY_BYTES {
+        return Err(anyhow!("request body exceeds {} bytes", MAX_BODY_BYTES));
+    }
+    // Grow with the bytes that actually arrive instead of trusting Content-Length up front.
+    let mut body = Vec::with_capacity(content_length.min(64 * 1024));
+    reader.take(content_length as u64).read_to_end(&mut body)?;
+    if body.len() != content_length {
+        return Err(anyhow!("request body ended after {} of {} bytes", body.len(), content_length));
+    }
+    Ok(HttpRequest {
+        method,
+        path,
+        content_type,
+        body,
+    })
+}
+
+/// The request line and headers went past [`MAX_HEADER_BYTES`] or [`MAX_HEADER_LINES`].
+#[derive(Debug)]
+struct HeadersTooLarge;
+
+impl std::fmt::Display for HeadersTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "request headers exceed {} bytes or {} lines",
+            MAX_HEADER_BYTES, MAX_HEADER_LINES
+        )
+    }
+}
+
+impl std::error::Error for HeadersTooLarge {}
+
+/// `read_line` that stops after the `budget` bytes left for the headers rather than buffering
+/// a line that never ends.
+fn read_header_line(reader: &mut impl BufRead, line: &mut String, budget: &mut usize) -> Result<usize> {
+    let read = reader.by_ref().take(*budget as u64 + 1).read_line(line)?;
+    if read > *budget {
+        return Err(HeadersTooLarge.into());
+    }
+    *budget -= read;
+    Ok(read)
+}
+
+fn write_response(mut stream: TcpStream, response: &HttpResponse) -> std::io::Result<()> {
+    let body = response.body.to_string();
+    let reason = match response.status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        422 => "Unprocessable Entity",
+        431 => "Request Header Fields Too Large",
+        499 => "Client Closed Request",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        reason,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+/// Reads an `image` file part plus optional text fields named like the v1 JSON keys.
+fn parse_multipart(content_type: &str, body: &[u8]) -> Result<v1::RemoveBackgroundRequest> {
+    let boundary = content_type
+        .split(';')
+        .find_map(|param| param.trim().strip_prefix("boundary="))
+        .map(|b| b.trim_matches('"'))
+        .ok_or_else(|| anyhow!("multipart request is missing a boundary"))?;
+    let delimiter = format!("--{}", boundary).into_bytes();
+
+    let mut out = v1::RemoveBackgroundRequest {
+        image_bytes: Vec::new(),
+        width: 0,
+        height: 0,
+        model: String::new(),
+        onnx_variant: None,
+        execution_provider: None,
+        gpu_backend: None,
+        benchmark_provider: None,
+        model_dir: None,
+        max_inference_pixels: None,
+        mask_threshold: None,
+        background: BackgroundFill::Transparent,
+        collect_timings: false,
+        emit_mask_rle: false,
+    };
+    for part in split_bytes(body, &delimiter).into_iter().skip(1) {
+        if part.starts_with(b"--") {
+            break;
+        }
+        let part = part.strip_prefix(b"\r\n").unwrap_or(part);
+        let Some(header_end) = find_bytes(part, b"\r\n\r\n") else {
+            continue;
+        };
+        let headers = String::from_utf8_lossy(&part[..header_end]);
+        let content = &part[header_end + 4..];
+        let content = content.strip_suffix(b"\r\n").unwrap_or(content);
+        let Some(name) = headers
+            .split(';')
+            .find_map(|param| param.trim().strip_prefix("name="))
+            .map(|n| n.trim_matches('"').to_string())
+        else {
+            continue;
+        };
+        let text = || String::from_utf8_lossy(content).trim().to_string();
+        match name.as_str() {
+            "image" | "imageBytes" => out.image_bytes = content.to_vec(),
+            "model" => out.model = text(),
+            "width" => out.width = text().parse()?,
+            "height" => out.height = text().parse()?,
+            "onnxVariant" => out.onnx_variant = Some(text()),
+            "executionProvider" => out.execution_provider = Some(text()),
+            "gpuBackend" => out.gpu_backend = Some(text()),
+            "benchmarkProvider" => out.benchmark_provider = Some(text().parse()?),
+            "maxInferencePixels" => out.max_inference_pixels = Some(text().parse()?),
+            "maskThreshold" => out.mask_threshold = Some(text().parse()?),
+            "background" => out.background = BackgroundFill::Image(content.to_vec()),
+            "collectTimings" => out.collect_timings = text().parse()?,
+            "emitMaskRle" => out.emit_mask_rle = text().parse()?,
+            _ => {}
+        }
+    }
+    if out.image_bytes.is_empty() {
+        return Err(anyhow!("multipart request has no 'image' part"));
+    }
+    Ok(out)
+}
+
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+fn split_bytes<'a>(mut data: &'a [u8], delimiter: &[u8]) -> Vec<&'a [u8]> {
+    let mut parts = Vec::new();
+    while let Some(pos) = find_bytes(data, delimiter) {
+        parts.push(&data[..pos]);
+        data = &data[pos + delimiter.len()..];
+    }
+    parts.push(data);
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::png_bytes;
+    use unbg_core::{InferenceResult, ModelKind};
+
+    struct EchoBackend;
+
+    impl InferenceBackend for EchoBackend {
+        fn infer(&self, request: &InferenceRequest, selected_model: ModelKind) -> std::result::Result<InferenceResult, CoreError> {
+            Ok(InferenceResult::new(
+                selected_model,
+                request.input_bytes.clone().unwrap_or_default(),
+                request.width,
+                request.height,
+                "cpu",
+            ))
+        }
+    }
+
+    fn send(addr: std::net::SocketAddr, raw: &[u8]) -> (u16, serde_json::Value) {
+        let mut stream = TcpStream::connect(addr).expect("connect");
+        stream.write_all(raw).expect("send request");
+        let mut response = String::new();
+        stream.read_to_string(&mut response).expect("read response");
+        let status = response[9..12].parse().expect("status code");
+        let body = response.split_once("\r\n\r\n").map(|(_, b)| b).unwrap_or_default();
+        (status, serde_json::from_str(body).expect("json body"))
+    }
+
+    fn post(path: &str, content_type: &str, body: &[u8]) -> Vec<u8> {
+        let mut raw = format!(
+            "POST {} HTTP/1.1\r\nHost: test\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+            path,
+            content_type,
+            body.len()
+        )
+        .into_bytes();
+        raw.extend_from_slice(body);
+        raw
+    }
+
+    struct UnreadableMaskBackend;
+
+    impl InferenceBackend for UnreadableMaskBackend {
+        fn infer(&self, request: &InferenceRequest, selected_model: ModelKind) -> std::result::Result<InferenceResult, CoreError> {
+            Ok(InferenceResult::new(selected_model, vec![0], request.width, request.height, "cpu"))
+        }
+    }
+
+    #[test]
+    fn compositing_after_a_successful_run_fails_as_a_server_error() {
+        let state = ServeState {
+            backend: Box::new(UnreadableMaskBackend),
+            base_request: InferenceRequest::builder().requested_model(ModelKind::Rmbg14).build(),
+            policy: RuntimePolicy::default(),
+            health: Ok(serde_json::json!({})),
+        };
+        let body = serde_json::from_value(serde_json::json!({
+            "imageBytes": png_bytes(&image::DynamicImage::new_rgb8(2, 2)),
+            "width": 0,
+            "height": 0,
+            "model": "",
+            "background": { "color": [0, 0, 0, 255] },
+        }))
+        .unwrap();
+        let response = remove_background(body, &state);
+        assert_eq!(response.status, 500, "{}", response.body);
+        assert_eq!(response.body["code"], "backend-error");
+    }
+
+    #[test]
+    fn server_handles_json_multipart_and_health_then_shuts_down() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let addr = listener.local_addr().unwrap();
+        let state = Arc::new(ServeState {
+            backend: Box::new(EchoBackend),
+            base_request: InferenceRequest::builder().requested_model(ModelKind::Rmbg14).build(),
+            policy: RuntimePolicy::default(),
+            health: Ok(serde_json::json!({ "modelUsed": "rmbg14" })),
+        });
+        let shutdown = CancelToken::new();
+        let server = {
+            let shutdown = shutdown.clone();
+            thread::spawn(move || serve(listener, state, shutdown, 2))
+        };
+
+        let image = png_bytes(&image::DynamicImage::new_rgb8(4, 2));
+        // The claimed size is ignored in favour of the image's own header.
+        let json = serde_json::json!({
+            "imageBytes": image,
+            "width": 1,
+            "height": 1,
+            "model": "quality",
+        });
+        let (status, body) = send(
+            addr,
+            &post("/v1/remove-background", "application/json", json.to_string().as_bytes()),
+        );
+        assert_eq!(status, 200, "{}", body);
+        let response: v1::RemoveBackgroundResponse = serde_json::from_value(body).unwrap();
+        assert_eq!(response.model_used, "rmbg-2.0");
+        assert_eq!(response.mask_png, image);
+        assert_eq!((response.width, response.height), (4, 2));
+
+        let image = png_bytes(&image::DynamicImage::new_rgb8(5, 6));
+        let mut multipart = b"--xyz\r\nContent-Disposition: form-data; name=\"image\"; filename=\"a.png\"\r\n\r\n".to_vec();
+        multipart.extend_from_slice(&image);
+        multipart.extend_from_slice(b"\r\n--xyz--\r\n");
+        let (status, body) = send(
+            addr,
+            &post("/v1/remove-background", "multipart/form-data; boundary=xyz", &multipart),
+        );
+        assert_eq!(status, 200, "{}", body);
+        assert_eq!(body["maskPng"], serde_json::json!(image));
+        assert_eq!((&body["width"], &body["height"]), (&serde_json::json!(5), &serde_json::json!(6)));
+        assert_eq!(body["modelUsed"], "rmbg-1.4");
+
+        let json = serde_json::json!({
+            "imageBytes": png_bytes(&image::DynamicImage::new_rgb8(2, 2)),
+            "width": 0,
+            "height": 0,
+            "model": "",
+            "background": { "image": [1, 2, 3] },
+        });
+        let (status, body) = send(
+            addr,
+            &post("/v1/remove-background", "application/json", json.to_string().as_bytes()),
+        );
+        assert_eq!(status, 400, "{}", body);
+        assert!(body["message"].as_str().unwrap().contains("background"), "{}", body);
+
+        let (status, body) = send(addr, &post("/v1/remove-background", "application/json", b"{"));
+        assert_eq!(status, 400);
+        assert_eq!(body["code"], "invalid-argument");
+
+        let (status, body) = send(addr, b"GET /health HTTP/1.1\r\nHost: test\r\n\r\n");
+        assert_eq!(status, 200);
+        assert_eq!(body["status"], "ok");
+
+        // A body shorter than its Content-Length is rejected once the client stops sending.
+        let mut stream = TcpStream::connect(addr).expect("connect");
+        let header = format!(
+            "POST /v1/remove-background HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{{}}",
+            MAX_BODY_BYTES
+        );
+        stream.write_all(header.as_bytes()).expect("send header");
+        stream.shutdown(std::net::Shutdown::Write).expect("half close");
+        let mut response = String::new();
+        stream.read_to_string(&mut response).expect("read response");
+        assert!(response.starts_with("HTTP/1.1 400"), "{}", response);
+        assert!(response.contains("ended after 2 of"), "{}", response);
+
+        // A header line that never ends, and too many short ones, are cut off at the cap.
+        let mut endless = b"GET /health HTTP/1.1\r\nX-Padding: ".to_vec();
+        endless.resize(MAX_HEADER_BYTES + 1, b'a');
+        let mut many = b"GET /health HTTP/1.1\r\n".to_vec();
+        for i in 0..=MAX_HEADER_LINES {
+            many.extend_from_slice(format!("X-Header-{}: 1\r\n", i).as_bytes());
+        }
+        for raw in [endless, many] {
+            let mut stream = TcpStream::connect(addr).expect("connect");
+            stream.write_all(&raw).expect("send headers");
+            stream.shutdown(std::net::Shutdown::Write).expect("half close");
+            let mut response = String::new();
+            stream.read_to_string(&mut response).expect("read response");
+            assert!(response.starts_with("HTTP/1.1 431"), "{}", response);
+            assert!(response.contains("headers-too-large"), "{}", response);
+        }
+
+        shutdown.cancel();
+        server.join().unwrap().expect("server exits cleanly");
+    }
+}
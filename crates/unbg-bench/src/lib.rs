@@ -1,3 +1,9 @@
+use std::time::Instant;
+
+use image::{DynamicImage, ImageFormat, Rgb, RgbImage};
+use serde::Serialize;
+use unbg_core::{run_inference, CoreError, InferenceBackend, InferenceRequest, ModelKind, RuntimePolicy};
+
 #[derive(Debug, Clone)]
 pub struct BenchmarkCase {
     pub name: String,
@@ -32,3 +38,110 @@ pub fn describe(cases: &[BenchmarkCase]) -> String {
         .collect::<Vec<_>>()
         .join(", ")
 }
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BenchmarkResult {
+    pub case: String,
+    pub model_used: ModelKind,
+    pub provider: String,
+    pub runs: usize,
+    pub min_ms: f64,
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+impl BenchmarkResult {
+    pub fn from_samples(case: &str, model_used: ModelKind, provider: &str, samples_ms: &[f64]) -> Self {
+        let mut sorted = samples_ms.to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        Self {
+            case: case.to_string(),
+            model_used,
+            provider: provider.to_string(),
+            runs: sorted.len(),
+            min_ms: sorted.first().copied().unwrap_or_default(),
+            p50_ms: percentile(&sorted, 50.0),
+            p90_ms: percentile(&sorted, 90.0),
+            p99_ms: percentile(&sorted, 99.0),
+            max_ms: sorted.last().copied().unwrap_or_default(),
+        }
+    }
+}
+
+/// Nearest-rank percentile of already sorted samples.
+pub fn percentile(sorted_ms: &[f64], pct: f64) -> f64 {
+    if sorted_ms.is_empty() {
+        return 0.0;
+    }
+    let rank = ((pct / 100.0) * sorted_ms.len() as f64).ceil() as usize;
+    sorted_ms[rank.clamp(1, sorted_ms.len()) - 1]
+}
+
+/// Runs `base` against a synthetic image of the case size `runs` times and collects every sample.
+pub fn run_case(
+    backend: &dyn InferenceBackend,
+    base: &InferenceRequest,
+    case: &BenchmarkCase,
+    runs: u32,
+) -> Result<BenchmarkResult, CoreError> {
+    let mut request = base.clone();
+    request.input_bytes = Some(synthetic_png(case.width, case.height)?);
+    request.input_path = None;
+    request.width = case.width;
+    request.height = case.height;
+    let policy = RuntimePolicy {
+        max_inference_pixels: u32::MAX,
+        ..RuntimePolicy::default()
+    };
+
+    let mut samples = Vec::with_capacity(runs.max(1) as usize);
+    let mut last = None;
+    for _ in 0..runs.max(1) {
+        let start = Instant::now();
+        let result = run_inference(backend, &request, &policy)?;
+        samples.push(start.elapsed().as_secs_f64() * 1000.0);
+        last = Some(result);
+    }
+    let last = last.expect("at least one run");
+    Ok(BenchmarkResult::from_samples(
+        &case.name,
+        last.model_used,
+        &last.execution_provider_selected,
+        &samples,
+    ))
+}
+
+fn synthetic_png(width: u32, height: u32) -> Result<Vec<u8>, CoreError> {
+    let img = RgbImage::from_fn(width, height, |x, y| {
+        Rgb([(x % 256) as u8, (y % 256) as u8, ((x + y) % 256) as u8])
+    });
+    let mut bytes = Vec::new();
+    DynamicImage::ImageRgb8(img)
+        .write_to(&mut std::io::Cursor::new(&mut bytes), ImageFormat::Png)
+        .map_err(|e| CoreError::Backend(e.to_string()))?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_follow_nearest_rank() {
+        let samples: Vec<f64> = (1..=100).rev().map(f64::from).collect();
+        let result = BenchmarkResult::from_samples("synthetic", ModelKind::Rmbg14, "cpu", &samples);
+        assert_eq!(result.runs, 100);
+        assert_eq!(result.min_ms, 1.0);
+        assert_eq!(result.p50_ms, 50.0);
+        assert_eq!(result.p90_ms, 90.0);
+        assert_eq!(result.p99_ms, 99.0);
+        assert_eq!(result.max_ms, 100.0);
+
+        assert_eq!(percentile(&[4.0, 8.0, 15.0], 50.0), 8.0);
+        assert_eq!(percentile(&[4.0, 8.0, 15.0], 99.0), 15.0);
+        assert_eq!(percentile(&[], 90.0), 0.0);
+    }
+}
@@ -1,4 +1,6 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::Instant;
 
 use serde::{Deserialize, Serialize};
@@ -39,6 +41,50 @@ pub enum GpuBackendPreference {
     Metal,
 }
 
+/// OS scheduling priority for the thread that runs inference, so batch work does not starve a UI.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ThreadPriority {
+    #[default]
+    Normal,
+    /// Below normal: nice 10, `THREAD_PRIORITY_BELOW_NORMAL`, or the Apple background band.
+    Low,
+    /// Idle-ish: nice 19, `THREAD_PRIORITY_LOWEST`, or the Apple background band.
+    Lowest,
+}
+
+/// ONNX Runtime graph optimization level for new sessions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum GraphOptLevel {
+    /// No graph rewrites; useful when debugging a model export.
+    Disable,
+    /// Constant folding and redundant node elimination.
+    Basic,
+    /// Basic plus complex node fusions.
+    Extended,
+    /// Extended plus layout optimizations.
+    Layout,
+    /// Every optimization; ONNX Runtime's default.
+    #[default]
+    All,
+}
+
+/// Session options that trade CPU use against latency. The default leaves ONNX Runtime's own
+/// settings untouched.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionTuning {
+    /// Threads used within an operator; `None` lets ONNX Runtime pick (one per physical core).
+    #[serde(default)]
+    pub intra_threads: Option<usize>,
+    /// Threads used across independent graph branches; `None` lets ONNX Runtime pick.
+    #[serde(default)]
+    pub inter_threads: Option<usize>,
+    #[serde(default)]
+    pub graph_opt: GraphOptLevel,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum PlatformTarget {
@@ -55,17 +101,177 @@ pub struct RuntimePolicy {
     pub allow_rmbg20: bool,
 }
 
-impl Default for RuntimePolicy {
-    fn default() -> Self {
+impl RuntimePolicy {
+    /// Interactive desktop apps and the CLI.
+    pub fn desktop() -> Self {
         Self {
             max_inference_pixels: 2_000_000,
             max_latency_ms: 1_500,
             allow_rmbg20: true,
         }
     }
+
+    /// Phones and tablets, where memory is tighter than on desktop.
+    pub fn mobile() -> Self {
+        Self {
+            max_inference_pixels: 1_500_000,
+            ..Self::desktop()
+        }
+    }
+
+    /// Batch or request/response servers that trade latency for resolution.
+    pub fn server() -> Self {
+        Self {
+            max_inference_pixels: 4_000_000,
+            max_latency_ms: 5_000,
+            allow_rmbg20: true,
+        }
+    }
+}
+
+impl Default for RuntimePolicy {
+    fn default() -> Self {
+        Self::desktop()
+    }
+}
+
+/// Cutoffs for a trimap: alpha at or below `low` is background, at or above `high` is foreground,
+/// anything between is unknown. Both are fractions of full opacity.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TrimapThresholds {
+    pub low: f32,
+    pub high: f32,
+}
+
+impl TrimapThresholds {
+    pub fn new(low: f32, high: f32) -> Result<Self, CoreError> {
+        let thresholds = Self { low, high };
+        thresholds.validate()?;
+        Ok(thresholds)
+    }
+
+    pub fn validate(&self) -> Result<(), CoreError> {
+        if !(0.0..=1.0).contains(&self.low) || !(0.0..=1.0).contains(&self.high) || self.low >= self.high {
+            return Err(CoreError::InvalidArgument(format!(
+                "trimap cutoffs must satisfy 0 <= low < high <= 1, got {}:{}",
+                self.low, self.high
+            )));
+        }
+        Ok(())
+    }
+
+    /// Maps an 8-bit alpha to 0 (background), 128 (unknown) or 255 (foreground).
+    pub fn classify(&self, alpha: u8) -> u8 {
+        let value = alpha as f32 / 255.0;
+        if value <= self.low {
+            0
+        } else if value >= self.high {
+            255
+        } else {
+            128
+        }
+    }
+}
+
+/// Sub-rectangle of the input, in source pixels, that inference is restricted to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegionOfInterest {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl RegionOfInterest {
+    /// Fails unless the region is non-empty and lies entirely inside a `width`x`height` image.
+    pub fn validate_within(&self, width: u32, height: u32) -> Result<(), CoreError> {
+        let fits = |start: u32, len: u32, bound: u32| len > 0 && start.checked_add(len).is_some_and(|end| end <= bound);
+        if !fits(self.x, self.width, width) || !fits(self.y, self.height, height) {
+            return Err(CoreError::InvalidArgument(format!(
+                "region of interest {}x{} at {},{} does not fit inside the {}x{} image",
+                self.width, self.height, self.x, self.y, width, height
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// How the RMBG-1.4 and RMBG-2.0 masks are combined for an ensemble request.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EnsembleMode {
+    /// `weight * rmbg-2.0 + (1 - weight) * rmbg-1.4`.
+    Weighted { weight: f32 },
+    Average,
+    Max,
+}
+
+/// How raw model output becomes alpha in `0.0..=1.0`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MaskActivation {
+    /// Stretch each image's output so its smallest value is 0 and its largest 1.
+    MinMax,
+    /// Sigmoid for logits; output already in `0.0..=1.0` is taken as probabilities.
+    Sigmoid,
+    /// Clamp to `0.0..=1.0`, for heads that already end in a sigmoid.
+    Clamp,
+}
+
+/// What inference does with a mask that is entirely background or entirely foreground, which
+/// usually means the model found no subject (logos, solid images).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EmptyMaskPolicy {
+    /// Fail the request.
+    Error,
+    /// Replace the mask with a fully opaque one so the cutout is the unchanged source.
+    Passthrough,
+    /// Keep the mask and report it in [`InferenceResult::mask_coverage`].
+    #[default]
+    Flag,
+}
+
+/// A mask whose [`foreground_ratio`] sits at one of the extremes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MaskCoverage {
+    /// No pixel, or almost none, is foreground.
+    Empty,
+    /// Every pixel, or almost every one, is foreground.
+    Full,
+}
+
+impl MaskCoverage {
+    /// Share of pixels allowed on the other side before a mask stops counting as empty or full.
+    pub const TOLERANCE: f32 = 0.001;
+
+    /// `Empty` or `Full` when `ratio` is within [`Self::TOLERANCE`] of 0 or 1.
+    pub fn from_foreground_ratio(ratio: f32) -> Option<Self> {
+        if ratio <= Self::TOLERANCE {
+            Some(Self::Empty)
+        } else if ratio >= 1.0 - Self::TOLERANCE {
+            Some(Self::Full)
+        } else {
+            None
+        }
+    }
+}
+
+/// What shows through where the mask is transparent.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BackgroundFill {
+    #[default]
+    Transparent,
+    /// Straight RGBA; an alpha below 255 leaves the result partly transparent.
+    Color([u8; 4]),
+    /// Encoded image bytes, resized to the source dimensions.
+    Image(Vec<u8>),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[non_exhaustive]
 pub struct InferenceRequest {
     pub requested_model: ModelKind,
     pub onnx_variant: OnnxVariant,
@@ -78,9 +284,312 @@ pub struct InferenceRequest {
     pub model_dir: Option<PathBuf>,
     pub width: u32,
     pub height: u32,
+    pub source_max_value: Option<f32>,
+    /// Skip the min/max stretch when the raw output spans less than this range.
+    pub low_confidence_range: Option<f32>,
+    /// Installed revision to load instead of the one recorded in the lockfile.
+    pub revision: Option<String>,
+    /// Concrete sizes for symbolic model input dimensions, applied when the session is built.
+    pub free_dimension_overrides: Vec<(String, i64)>,
+    /// Run both models and blend their masks; requires both installed and RMBG-2.0 allowed.
+    pub ensemble: Option<EnsembleMode>,
+    /// Output channels unioned into the alpha for multi-class models; empty means channel 0.
+    pub foreground_channels: Vec<usize>,
+    /// Extra attempts on the same provider after a failed run before falling back to the next one.
+    pub inference_retries: u32,
+    /// Emit a three-level trimap (0/128/255) instead of a soft mask.
+    pub trimap: Option<TrimapThresholds>,
+    /// Downscale the input so its longer side is at most this many pixels before inference;
+    /// the mask is scaled back to the original size.
+    pub max_dimension: Option<u32>,
+    /// Run inference on this region only; the mask stays full size with zeros outside it.
+    pub roi: Option<RegionOfInterest>,
+    /// Encoded grayscale hint the size of the input: 255 forces foreground, 0 forces background,
+    /// any other value (conventionally 128) keeps the model's prediction.
+    pub seed_mask: Option<Vec<u8>>,
+    /// zlib level (0-9) for the mask PNG; `None` keeps the fast default.
+    pub png_compression: Option<u8>,
+    /// Binarize the mask: alpha below this cutoff (0.0-1.0) becomes 0, at or above it 255.
+    /// `None` keeps the soft mask.
+    pub mask_threshold: Option<f32>,
+    /// Activation applied by the default postprocess chain. `None` uses the model's own:
+    /// sigmoid for RMBG-2.0, min/max stretch for RMBG-1.4.
+    pub mask_activation: Option<MaskActivation>,
+    /// Also return the activated float mask at model-native resolution in
+    /// [`InferenceResult::mask_raw`].
+    pub emit_mask_raw: bool,
+    /// Square model input edge in pixels, a multiple of 32; `None` keeps 1024.
+    pub input_size: Option<u32>,
+    /// Also return the final mask run-length encoded in [`InferenceResult::mask_rle`].
+    /// Only filled when the PNG mask is produced too (`emit_mask_png`).
+    pub emit_mask_rle: bool,
+    /// Downscale inputs above this many pixels before inference; the mask is scaled back up.
+    /// [`run_inference`] fills it from [`RuntimePolicy::max_inference_pixels`] when unset.
+    pub max_inference_pixels: Option<u32>,
+    /// Gaussian feather, in pixels of standard deviation and at most the longer image side,
+    /// applied to the full-size mask after `mask_threshold` and before `trimap`. `None` keeps the
+    /// resized mask as is.
+    pub feather_radius: Option<f32>,
+    /// Measure per-stage wall time into [`InferenceResult::timings`].
+    pub collect_timings: bool,
+    /// Fail when the model picked by `Auto` or an ensemble member cannot be loaded or run,
+    /// instead of falling back to the other model.
+    pub strict_model: bool,
+    /// Hugging Face id of an installed custom model to run in place of the RMBG model; its
+    /// registered input size and normalization apply. The result names it in
+    /// [`InferenceResult::custom_model_used`].
+    pub custom_model: Option<String>,
+    /// Handling of a mask that came out entirely background or foreground.
+    pub on_empty_mask: EmptyMaskPolicy,
+    /// Thread counts and graph optimization for the ONNX Runtime session; sessions with different
+    /// tuning are cached separately.
+    pub session_tuning: SessionTuning,
+    /// Piecewise-linear alpha transfer function as `(input, output)` points, applied to the
+    /// activated mask before it is quantized. x must increase strictly from 0 to 1 and every y
+    /// lie in `0.0..=1.0` (see [`validate_alpha_curve`]). `None` keeps alpha unchanged.
+    pub alpha_curve: Option<Vec<(f32, f32)>>,
+}
+
+impl Default for InferenceRequest {
+    fn default() -> Self {
+        Self {
+            requested_model: ModelKind::Auto,
+            onnx_variant: OnnxVariant::Fp16,
+            execution_provider: ExecutionProvider::Auto,
+            gpu_backend: GpuBackendPreference::Auto,
+            benchmark_provider: false,
+            emit_mask_png: true,
+            input_path: None,
+            input_bytes: None,
+            model_dir: None,
+            width: 0,
+            height: 0,
+            source_max_value: None,
+            low_confidence_range: None,
+            revision: None,
+            free_dimension_overrides: Vec::new(),
+            ensemble: None,
+            foreground_channels: Vec::new(),
+            inference_retries: 0,
+            trimap: None,
+            max_dimension: None,
+            roi: None,
+            seed_mask: None,
+            png_compression: None,
+            mask_threshold: None,
+            mask_activation: None,
+            emit_mask_raw: false,
+            input_size: None,
+            emit_mask_rle: false,
+            max_inference_pixels: None,
+            feather_radius: None,
+            collect_timings: false,
+            strict_model: false,
+            custom_model: None,
+            on_empty_mask: EmptyMaskPolicy::default(),
+            session_tuning: SessionTuning::default(),
+            alpha_curve: None,
+        }
+    }
+}
+
+impl InferenceRequest {
+    pub fn builder() -> InferenceRequestBuilder {
+        InferenceRequestBuilder::default()
+    }
+}
+
+/// Builds an [`InferenceRequest`] starting from its defaults: auto model, fp16, auto providers,
+/// mask PNG emitted, no provider benchmark.
+#[derive(Debug, Clone, Default)]
+pub struct InferenceRequestBuilder {
+    request: InferenceRequest,
+}
+
+impl InferenceRequestBuilder {
+    pub fn requested_model(mut self, model: ModelKind) -> Self {
+        self.request.requested_model = model;
+        self
+    }
+
+    pub fn onnx_variant(mut self, variant: OnnxVariant) -> Self {
+        self.request.onnx_variant = variant;
+        self
+    }
+
+    pub fn execution_provider(mut self, provider: ExecutionProvider) -> Self {
+        self.request.execution_provider = provider;
+        self
+    }
+
+    pub fn gpu_backend(mut self, backend: GpuBackendPreference) -> Self {
+        self.request.gpu_backend = backend;
+        self
+    }
+
+    pub fn benchmark_provider(mut self, enabled: bool) -> Self {
+        self.request.benchmark_provider = enabled;
+        self
+    }
+
+    pub fn emit_mask_png(mut self, enabled: bool) -> Self {
+        self.request.emit_mask_png = enabled;
+        self
+    }
+
+    pub fn input_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.request.input_path = Some(path.into());
+        self
+    }
+
+    pub fn input_bytes(mut self, bytes: Vec<u8>) -> Self {
+        self.request.input_bytes = Some(bytes);
+        self
+    }
+
+    pub fn model_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.request.model_dir = dir;
+        self
+    }
+
+    pub fn dimensions(mut self, width: u32, height: u32) -> Self {
+        self.request.width = width;
+        self.request.height = height;
+        self
+    }
+
+    pub fn source_max_value(mut self, max: Option<f32>) -> Self {
+        self.request.source_max_value = max;
+        self
+    }
+
+    pub fn low_confidence_range(mut self, range: Option<f32>) -> Self {
+        self.request.low_confidence_range = range;
+        self
+    }
+
+    pub fn revision(mut self, revision: Option<String>) -> Self {
+        self.request.revision = revision;
+        self
+    }
+
+    pub fn free_dimension_override(mut self, name: impl Into<String>, size: i64) -> Self {
+        self.request.free_dimension_overrides.push((name.into(), size));
+        self
+    }
+
+    pub fn ensemble(mut self, mode: Option<EnsembleMode>) -> Self {
+        self.request.ensemble = mode;
+        self
+    }
+
+    pub fn foreground_channels(mut self, channels: Vec<usize>) -> Self {
+        self.request.foreground_channels = channels;
+        self
+    }
+
+    pub fn inference_retries(mut self, retries: u32) -> Self {
+        self.request.inference_retries = retries;
+        self
+    }
+
+    pub fn trimap(mut self, thresholds: Option<TrimapThresholds>) -> Self {
+        self.request.trimap = thresholds;
+        self
+    }
+
+    pub fn max_dimension(mut self, max_dimension: Option<u32>) -> Self {
+        self.request.max_dimension = max_dimension;
+        self
+    }
+
+    pub fn roi(mut self, roi: Option<RegionOfInterest>) -> Self {
+        self.request.roi = roi;
+        self
+    }
+
+    pub fn seed_mask(mut self, seed: Option<Vec<u8>>) -> Self {
+        self.request.seed_mask = seed;
+        self
+    }
+
+    pub fn png_compression(mut self, level: Option<u8>) -> Self {
+        self.request.png_compression = level;
+        self
+    }
+
+    pub fn mask_threshold(mut self, threshold: Option<f32>) -> Self {
+        self.request.mask_threshold = threshold;
+        self
+    }
+
+    pub fn mask_activation(mut self, activation: Option<MaskActivation>) -> Self {
+        self.request.mask_activation = activation;
+        self
+    }
+
+    pub fn emit_mask_raw(mut self, emit: bool) -> Self {
+        self.request.emit_mask_raw = emit;
+        self
+    }
+
+    pub fn input_size(mut self, size: Option<u32>) -> Self {
+        self.request.input_size = size;
+        self
+    }
+
+    pub fn emit_mask_rle(mut self, emit: bool) -> Self {
+        self.request.emit_mask_rle = emit;
+        self
+    }
+
+    pub fn max_inference_pixels(mut self, max_pixels: Option<u32>) -> Self {
+        self.request.max_inference_pixels = max_pixels;
+        self
+    }
+
+    pub fn feather_radius(mut self, radius: Option<f32>) -> Self {
+        self.request.feather_radius = radius;
+        self
+    }
+
+    pub fn collect_timings(mut self, enabled: bool) -> Self {
+        self.request.collect_timings = enabled;
+        self
+    }
+
+    pub fn strict_model(mut self, strict: bool) -> Self {
+        self.request.strict_model = strict;
+        self
+    }
+
+    pub fn custom_model(mut self, id: Option<String>) -> Self {
+        self.request.custom_model = id;
+        self
+    }
+
+    pub fn on_empty_mask(mut self, policy: EmptyMaskPolicy) -> Self {
+        self.request.on_empty_mask = policy;
+        self
+    }
+
+    pub fn session_tuning(mut self, tuning: SessionTuning) -> Self {
+        self.request.session_tuning = tuning;
+        self
+    }
+
+    pub fn alpha_curve(mut self, curve: Option<Vec<(f32, f32)>>) -> Self {
+        self.request.alpha_curve = curve;
+        self
+    }
+
+    pub fn build(self) -> InferenceRequest {
+        self.request
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[non_exhaustive]
 pub struct InferenceResult {
     pub model_used: ModelKind,
     pub mask_png: Vec<u8>,
@@ -89,6 +598,164 @@ pub struct InferenceResult {
     pub execution_provider_selected: String,
     pub gpu_backend_selected: Option<String>,
     pub fallback_used: bool,
+    /// Set when the mask is a blend of both models' outputs.
+    #[serde(default)]
+    pub ensemble_used: bool,
+    /// Set when the selected model could not be loaded or run and the mask came from the other
+    /// model instead; see [`InferenceRequest::strict_model`].
+    #[serde(default)]
+    pub model_fallback_used: bool,
+    /// Errors from the preferred provider(s) when `fallback_used` is set.
+    #[serde(default)]
+    pub fallback_reason: Option<String>,
+    /// Activated mask in 0.0-1.0 before resizing to the input, row-major
+    /// `mask_raw_width * mask_raw_height`; set when `emit_mask_raw` was requested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mask_raw: Option<Vec<f32>>,
+    #[serde(default)]
+    pub mask_raw_width: u32,
+    #[serde(default)]
+    pub mask_raw_height: u32,
+    /// Final mask as `(alpha, run length)` pairs in row-major order, a compact alternative to
+    /// `mask_png` for masks that are mostly 0 or 255; set when `emit_mask_rle` was requested.
+    /// Expand with [`decode_mask_rle`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mask_rle: Option<Vec<(u8, u32)>>,
+    /// `(provider, elapsed ms)` for every provider tried by the auto benchmark, in the order they
+    /// ran; failed providers report [`PROVIDER_BENCHMARK_FAILED`]. Only set on that path.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider_benchmarks: Option<Vec<(String, u128)>>,
+    /// Per-stage wall time of the run that produced the mask; set when `collect_timings` was
+    /// requested and a single model ran, and `None` when the mask came from the mask cache.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timings: Option<InferenceTimings>,
+    /// Set when the mask came out entirely background or foreground and the request's
+    /// [`EmptyMaskPolicy`] did not turn that into an error.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mask_coverage: Option<MaskCoverage>,
+    /// [`foreground_ratio`] of the mask the model produced, kept as it was when
+    /// [`EmptyMaskPolicy::Passthrough`] replaces the mask; `None` when no mask was emitted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub foreground_ratio: Option<f32>,
+    /// Revision the `model_used` weights were loaded from; `None` when no model file was loaded,
+    /// as for placeholder masks, or the backend does not report it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model_revision: Option<String>,
+    /// Variant of the `.onnx` file that ran, which [`OnnxVariant::Auto`] requests resolve to a
+    /// concrete one; `None` under the same conditions as `model_revision`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub onnx_variant: Option<OnnxVariant>,
+    /// Id of the custom model that produced the mask. When set, `model_used` is only the kind the
+    /// policy resolved; the RMBG weights did not run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub custom_model_used: Option<String>,
+    /// SHA-256 of the encoded input, taken when the backend read it for a run-history entry.
+    /// Never serialized.
+    #[serde(skip)]
+    pub input_sha256: Option<String>,
+}
+
+impl InferenceResult {
+    /// A result with no fallback, ensemble or optional outputs set; backends fill in the rest
+    /// field by field.
+    pub fn new(
+        model_used: ModelKind,
+        mask_png: Vec<u8>,
+        width: u32,
+        height: u32,
+        execution_provider_selected: impl Into<String>,
+    ) -> Self {
+        Self {
+            model_used,
+            mask_png,
+            width,
+            height,
+            execution_provider_selected: execution_provider_selected.into(),
+            gpu_backend_selected: None,
+            fallback_used: false,
+            ensemble_used: false,
+            model_fallback_used: false,
+            fallback_reason: None,
+            mask_raw: None,
+            mask_raw_width: 0,
+            mask_raw_height: 0,
+            mask_rle: None,
+            provider_benchmarks: None,
+            timings: None,
+            mask_coverage: None,
+            foreground_ratio: None,
+            model_revision: None,
+            onnx_variant: None,
+            custom_model_used: None,
+            input_sha256: None,
+        }
+    }
+}
+
+/// Wall time, in milliseconds, spent in each stage of one inference.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InferenceTimings {
+    /// Resizing and normalizing the already decoded input into the model tensor.
+    pub preprocess_ms: u64,
+    /// The ONNX Runtime session run.
+    pub session_run_ms: u64,
+    /// Activating, resizing and encoding the mask.
+    pub postprocess_ms: u64,
+    /// Everything above plus session setup, measured around the whole provider attempt.
+    pub total_ms: u64,
+}
+
+/// Elapsed-ms sentinel in [`InferenceResult::provider_benchmarks`] for a provider that failed.
+pub const PROVIDER_BENCHMARK_FAILED: u128 = u128::MAX;
+
+/// Run-length encodes a row-major alpha mask as `(alpha, run length)` pairs.
+pub fn encode_mask_rle(alpha: &[u8]) -> Vec<(u8, u32)> {
+    let mut runs: Vec<(u8, u32)> = Vec::new();
+    for &value in alpha {
+        match runs.last_mut() {
+            Some((last, len)) if *last == value && *len < u32::MAX => *len += 1,
+            _ => runs.push((value, 1)),
+        }
+    }
+    runs
+}
+
+/// Share of pixels in a row-major alpha mask that are at least half opaque; 0 for an empty slice.
+pub fn foreground_ratio(alpha: &[u8]) -> f32 {
+    if alpha.is_empty() {
+        return 0.0;
+    }
+    alpha.iter().filter(|&&value| value >= 128).count() as f32 / alpha.len() as f32
+}
+
+/// Fails unless `curve` has at least two points, its x values strictly increase from 0 to 1 and
+/// every y lies in `0.0..=1.0`.
+pub fn validate_alpha_curve(curve: &[(f32, f32)]) -> Result<(), CoreError> {
+    let covers_unit_range = curve.first().is_some_and(|&(x, _)| x == 0.0) && curve.last().is_some_and(|&(x, _)| x == 1.0);
+    if curve.len() < 2 || !covers_unit_range {
+        return Err(CoreError::InvalidArgument(format!(
+            "alpha curve must have at least two points and run from x = 0 to x = 1, got {:?}",
+            curve
+        )));
+    }
+    let increasing = curve.windows(2).all(|pair| pair[0].0 < pair[1].0);
+    if !increasing || curve.iter().any(|&(_, y)| !(0.0..=1.0).contains(&y)) {
+        return Err(CoreError::InvalidArgument(format!(
+            "alpha curve x values must strictly increase and y values lie between 0 and 1, got {:?}",
+            curve
+        )));
+    }
+    Ok(())
+}
+
+/// Expands the output of [`encode_mask_rle`] back into one alpha byte per pixel.
+pub fn decode_mask_rle(runs: &[(u8, u32)]) -> Vec<u8> {
+    let mut alpha = Vec::with_capacity(runs.iter().map(|&(_, len)| len as usize).sum());
+    for &(value, len) in runs {
+        alpha.resize(alpha.len() + len as usize, value);
+    }
+    alpha
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -99,6 +766,13 @@ pub enum TelemetryEventType {
     InferenceStart,
     InferenceSuccess,
     InferenceError,
+    /// The caller's [`CancelToken`] stopped the run; reported apart from `InferenceError` so
+    /// cancels don't count as failures.
+    InferenceCancelled,
+    /// Inference succeeded only after the preferred GPU provider failed.
+    ProviderFallback,
+    /// Session and provider cache hit/miss counters, carried in `fields`.
+    CacheStats,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -108,14 +782,138 @@ pub struct TelemetryEvent {
     pub platform: PlatformTarget,
     pub duration_ms: Option<u64>,
     pub detail: Option<String>,
+    /// Typed counterpart of `detail` (provider, backend, fallback, model, onnx_variant) so
+    /// collectors can query events without parsing the free-text string.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fields: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// `Err(CoreError::Cancelled)` once [`CancelToken::cancel`] has been called.
+    pub fn check(&self) -> Result<(), CoreError> {
+        if self.is_cancelled() {
+            Err(CoreError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
 }
 
 pub trait TelemetrySink: Send + Sync {
     fn emit(&self, event: TelemetryEvent);
 }
 
+/// Adapts a closure into a [`TelemetrySink`], for in-process subscribers that just want each event.
+pub struct FnSink<F>(pub F);
+
+impl<F> TelemetrySink for FnSink<F>
+where
+    F: Fn(TelemetryEvent) + Send + Sync,
+{
+    fn emit(&self, event: TelemetryEvent) {
+        (self.0)(event)
+    }
+}
+
 pub trait InferenceBackend: Send + Sync {
     fn infer(&self, request: &InferenceRequest, selected_model: ModelKind) -> Result<InferenceResult, CoreError>;
+
+    /// [`InferenceBackend::infer`] that gives up with [`CoreError::Cancelled`] once `cancel` fires.
+    /// Backends check the token at stage boundaries (decode and preprocessing, the model run,
+    /// postprocessing): ONNX Runtime cannot interrupt a `run` that has started, so a cancel that
+    /// arrives during it takes effect when it returns. The default checks before and after `infer`.
+    fn infer_cancellable(
+        &self,
+        request: &InferenceRequest,
+        selected_model: ModelKind,
+        cancel: &CancelToken,
+    ) -> Result<InferenceResult, CoreError> {
+        cancel.check()?;
+        let result = self.infer(request, selected_model)?;
+        cancel.check()?;
+        Ok(result)
+    }
+
+    /// Runs several requests on `selected_model` and returns the results in request order.
+    /// The default calls [`InferenceBackend::infer`] for each; backends that can stack inputs
+    /// into one model run override it.
+    fn infer_batch(&self, requests: &[InferenceRequest], selected_model: ModelKind) -> Result<Vec<InferenceResult>, CoreError> {
+        requests.iter().map(|request| self.infer(request, selected_model)).collect()
+    }
+
+    /// [`InferenceBackend::infer_batch`] that gives up with [`CoreError::Cancelled`] once `cancel`
+    /// fires. The default calls [`InferenceBackend::infer_cancellable`] for each request.
+    fn infer_batch_cancellable(
+        &self,
+        requests: &[InferenceRequest],
+        selected_model: ModelKind,
+        cancel: &CancelToken,
+    ) -> Result<Vec<InferenceResult>, CoreError> {
+        requests
+            .iter()
+            .map(|request| self.infer_cancellable(request, selected_model, cancel))
+            .collect()
+    }
+}
+
+/// Backend id used when a `RuntimeConfig` does not name one.
+pub const DEFAULT_BACKEND_ID: &str = "ort";
+
+pub type BackendFactory = Box<dyn Fn() -> Box<dyn InferenceBackend> + Send + Sync>;
+
+/// Named backend factories, so callers can pick an implementation from configuration.
+#[derive(Default)]
+pub struct BackendRegistry {
+    factories: Vec<(String, BackendFactory)>,
+}
+
+impl BackendRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `factory` under `id`, replacing any earlier registration with the same id.
+    pub fn register<F>(&mut self, id: impl Into<String>, factory: F)
+    where
+        F: Fn() -> Box<dyn InferenceBackend> + Send + Sync + 'static,
+    {
+        let id = id.into();
+        self.factories.retain(|(existing, _)| *existing != id);
+        self.factories.push((id, Box::new(factory)));
+    }
+
+    pub fn ids(&self) -> Vec<&str> {
+        self.factories.iter().map(|(id, _)| id.as_str()).collect()
+    }
+
+    pub fn create(&self, id: &str) -> Result<Box<dyn InferenceBackend>, CoreError> {
+        self.factories
+            .iter()
+            .find(|(existing, _)| existing.eq_ignore_ascii_case(id.trim()))
+            .map(|(_, factory)| factory())
+            .ok_or_else(|| {
+                CoreError::Backend(format!(
+                    "unknown backend '{}'; registered: {}",
+                    id,
+                    self.ids().join(", ")
+                ))
+            })
+    }
 }
 
 #[derive(Debug, Error)]
@@ -126,6 +924,17 @@ pub enum CoreError {
     MissingInput,
     #[error("backend error: {0}")]
     Backend(String),
+    #[error("inference cancelled")]
+    Cancelled,
+    /// No installed revision of the model was found in any model directory.
+    #[error("model not found in lockfile: {model_id}")]
+    ModelNotInstalled { model_id: String },
+    /// The model's revision directory is missing or holds no loadable `.onnx` file.
+    #[error("no .onnx file found for {model_id} revision {revision}")]
+    OnnxFileMissing { model_id: String, revision: String },
+    /// The request asks for something this backend cannot do or holds an out-of-range value.
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -134,6 +943,10 @@ pub enum ErrorCode {
     Rmbg20Disabled,
     MissingInput,
     BackendError,
+    Cancelled,
+    ModelNotInstalled,
+    OnnxFileMissing,
+    InvalidArgument,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -158,6 +971,22 @@ impl CoreError {
                 code: ErrorCode::BackendError,
                 message: message.clone(),
             },
+            Self::Cancelled => ErrorInfo {
+                code: ErrorCode::Cancelled,
+                message: self.to_string(),
+            },
+            Self::ModelNotInstalled { .. } => ErrorInfo {
+                code: ErrorCode::ModelNotInstalled,
+                message: self.to_string(),
+            },
+            Self::OnnxFileMissing { .. } => ErrorInfo {
+                code: ErrorCode::OnnxFileMissing,
+                message: self.to_string(),
+            },
+            Self::InvalidArgument(message) => ErrorInfo {
+                code: ErrorCode::InvalidArgument,
+                message: message.clone(),
+            },
         }
     }
 }
@@ -171,6 +1000,20 @@ pub struct RuntimeConfig {
     pub gpu_backend: String,
     pub benchmark_provider: bool,
     pub model_dir: Option<String>,
+    /// Registry id of the backend to run inference with.
+    #[serde(default = "default_backend_id")]
+    pub backend: String,
+    /// `normal`, `low` or `lowest`; see [`ThreadPriority`].
+    #[serde(default = "default_thread_priority")]
+    pub thread_priority: String,
+}
+
+fn default_backend_id() -> String {
+    DEFAULT_BACKEND_ID.to_string()
+}
+
+fn default_thread_priority() -> String {
+    "normal".to_string()
 }
 
 impl Default for RuntimeConfig {
@@ -182,6 +1025,8 @@ impl Default for RuntimeConfig {
             gpu_backend: "auto".to_string(),
             benchmark_provider: true,
             model_dir: None,
+            backend: default_backend_id(),
+            thread_priority: default_thread_priority(),
         }
     }
 }
@@ -200,11 +1045,180 @@ pub fn resolve_runtime_config(overrides: RuntimeConfig) -> RuntimeConfig {
     if !overrides.gpu_backend.trim().is_empty() {
         cfg.gpu_backend = overrides.gpu_backend;
     }
+    if !overrides.backend.trim().is_empty() {
+        cfg.backend = overrides.backend;
+    }
+    if !overrides.thread_priority.trim().is_empty() {
+        cfg.thread_priority = overrides.thread_priority;
+    }
     cfg.benchmark_provider = overrides.benchmark_provider;
     cfg.model_dir = overrides.model_dir;
     cfg
 }
 
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum OutputNamingError {
+    #[error("input file must include a valid file name")]
+    MissingFileName,
+    #[error("output cutout must be a .png file (received: '{}')", .0.display())]
+    NotPng(PathBuf),
+    #[error("output must be a .png, .jpg, .jpeg or .webp file (received: '{}')", .0.display())]
+    UnsupportedFormat(PathBuf),
+    #[error("two outputs resolve to the same file '{}'; give them different directories or extensions", .0.display())]
+    Collision(PathBuf),
+}
+
+/// Where the outputs for one input go, as resolved by [`OutputNaming::resolve`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OutputPaths {
+    pub cutout: Option<PathBuf>,
+    pub mask: Option<PathBuf>,
+    /// One path per [`OutputNaming::outputs`] spec, in the same order.
+    pub outputs: Vec<PathBuf>,
+}
+
+/// The CLI's output naming rules, so embedders write the same files the CLI would.
+///
+/// For a single input `-o`/`-m` are file paths; for a directory or pattern run they are
+/// directories (or `output_dir` overrides them) and each input gets `<stem>_cutout.png` /
+/// `<stem>_mask.png` inside. With no outputs requested at all, the cutout lands next to the input.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OutputNaming {
+    /// The run covers several inputs (a directory or a pattern) rather than one file.
+    pub multi_input: bool,
+    pub output_cutout: Option<PathBuf>,
+    pub output_mask: Option<PathBuf>,
+    pub output_dir: Option<PathBuf>,
+    /// Mirror each input's subdirectory below this root into the output directory.
+    pub structure_root: Option<PathBuf>,
+    /// Extra cutouts, each in the format of its extension. In multi-input runs only the
+    /// directory and extension count: each input gets `<stem>_cutout.<ext>` in `output_dir`,
+    /// or next to the spec when `output_dir` is unset. Specs that land on the same file as each
+    /// other or as the cutout or mask are rejected with [`OutputNamingError::Collision`].
+    pub outputs: Vec<PathBuf>,
+}
+
+impl OutputNaming {
+    pub fn resolve(&self, input: &Path) -> Result<OutputPaths, OutputNamingError> {
+        // When multi-input, prefer explicit output_dir, otherwise interpret -o/-m as directories.
+        let bulk_out_dir = if self.multi_input { self.output_dir.as_deref() } else { None };
+
+        let cutout = if let Some(spec) = &self.output_cutout {
+            if self.multi_input {
+                let dir = bulk_out_dir.unwrap_or(spec);
+                Some(self.bulk_dir(dir, input).join(default_cutout_filename(input)?))
+            } else {
+                validate_cutout_extension(spec)?;
+                Some(spec.clone())
+            }
+        } else if self.output_mask.is_none() && self.outputs.is_empty() {
+            match bulk_out_dir {
+                Some(dir) => Some(self.bulk_dir(dir, input).join(default_cutout_filename(input)?)),
+                None => Some(default_cutout_path(input)?),
+            }
+        } else {
+            None
+        };
+
+        let mask = match &self.output_mask {
+            Some(spec) if self.multi_input => {
+                let dir = self.output_dir.as_deref().unwrap_or(spec);
+                Some(self.bulk_dir(dir, input).join(default_mask_filename(input)?))
+            }
+            Some(spec) => Some(spec.clone()),
+            None => None,
+        };
+
+        let outputs = self
+            .outputs
+            .iter()
+            .map(|spec| {
+                validate_output_extension(spec)?;
+                if !self.multi_input {
+                    return Ok(spec.clone());
+                }
+                let dir = bulk_out_dir.or(spec.parent()).unwrap_or(Path::new(""));
+                let extension = spec.extension().unwrap_or_default().to_string_lossy();
+                Ok(self
+                    .bulk_dir(dir, input)
+                    .join(format!("{}_cutout.{}", file_stem(input)?, extension)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut seen: Vec<&PathBuf> = Vec::new();
+        for path in cutout.iter().chain(mask.iter()).chain(outputs.iter()) {
+            if seen.contains(&path) {
+                return Err(OutputNamingError::Collision(path.clone()));
+            }
+            seen.push(path);
+        }
+        Ok(OutputPaths { cutout, mask, outputs })
+    }
+
+    /// Output directory for one input of a multi-input run below `out_dir`.
+    pub fn bulk_dir(&self, out_dir: &Path, input: &Path) -> PathBuf {
+        let Some(root) = &self.structure_root else {
+            return out_dir.to_path_buf();
+        };
+        let relative_parent = input
+            .strip_prefix(root)
+            .ok()
+            .and_then(Path::parent)
+            .unwrap_or_else(|| Path::new(""));
+        out_dir.join(relative_parent)
+    }
+}
+
+pub fn default_cutout_filename(input: &Path) -> Result<String, OutputNamingError> {
+    Ok(format!("{}_cutout.png", file_stem(input)?))
+}
+
+pub fn default_mask_filename(input: &Path) -> Result<String, OutputNamingError> {
+    Ok(format!("{}_mask.png", file_stem(input)?))
+}
+
+pub fn default_overlay_filename(input: &Path) -> Result<String, OutputNamingError> {
+    Ok(format!("{}_overlay.png", file_stem(input)?))
+}
+
+/// `<stem>_cutout.png` next to the input.
+pub fn default_cutout_path(input: &Path) -> Result<PathBuf, OutputNamingError> {
+    let filename = default_cutout_filename(input)?;
+    Ok(match input.parent() {
+        Some(parent) => parent.join(filename),
+        None => PathBuf::from(filename),
+    })
+}
+
+pub fn validate_cutout_extension(path: &Path) -> Result<(), OutputNamingError> {
+    let is_png = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("png"));
+    if !is_png {
+        return Err(OutputNamingError::NotPng(path.to_path_buf()));
+    }
+    Ok(())
+}
+
+/// Accepts the cutout formats the CLI can write: PNG, JPEG and WebP.
+pub fn validate_output_extension(path: &Path) -> Result<(), OutputNamingError> {
+    let supported = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| ["png", "jpg", "jpeg", "webp"].iter().any(|ok| e.eq_ignore_ascii_case(ok)));
+    if !supported {
+        return Err(OutputNamingError::UnsupportedFormat(path.to_path_buf()));
+    }
+    Ok(())
+}
+
+fn file_stem(input: &Path) -> Result<std::borrow::Cow<'_, str>, OutputNamingError> {
+    input
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .ok_or(OutputNamingError::MissingFileName)
+}
+
 pub mod v1 {
     use super::*;
 
@@ -221,6 +1235,15 @@ pub mod v1 {
         pub benchmark_provider: Option<bool>,
         pub model_dir: Option<String>,
         pub max_inference_pixels: Option<u32>,
+        pub mask_threshold: Option<f32>,
+        #[serde(default)]
+        pub background: BackgroundFill,
+        /// Return per-stage timings in [`RemoveBackgroundResponse::timings`].
+        #[serde(default)]
+        pub collect_timings: bool,
+        /// Also return the mask run-length encoded in [`RemoveBackgroundResponse::mask_rle`].
+        #[serde(default)]
+        pub emit_mask_rle: bool,
     }
 
     #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -233,11 +1256,27 @@ pub mod v1 {
         pub provider_selected: String,
         pub backend_selected: Option<String>,
         pub fallback_used: bool,
+        /// The source composited over the request's background; absent for `Transparent`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub composite_png: Option<Vec<u8>>,
+        /// Present when the request set `collectTimings`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub timings: Option<InferenceTimings>,
+        /// `maskPng` as `[value, run]` pairs; present when the request set `emitMaskRle`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub mask_rle: Option<Vec<(u8, u32)>>,
     }
 }
 
 pub fn resolve_model(request: &InferenceRequest, policy: &RuntimePolicy) -> Result<ModelKind, CoreError> {
     let pixels = request.width.saturating_mul(request.height);
+    if request.ensemble.is_some() {
+        return if policy.allow_rmbg20 {
+            Ok(ModelKind::Rmbg20)
+        } else {
+            Err(CoreError::Rmbg20Disabled)
+        };
+    }
     match request.requested_model {
         ModelKind::Rmbg20 if !policy.allow_rmbg20 => Err(CoreError::Rmbg20Disabled),
         ModelKind::Rmbg20 => Ok(ModelKind::Rmbg20),
@@ -257,59 +1296,193 @@ pub fn run_inference(
     request: &InferenceRequest,
     policy: &RuntimePolicy,
 ) -> Result<InferenceResult, CoreError> {
-    run_inference_with_telemetry(backend, request, policy, PlatformTarget::Cli, None)
+    run_inference_with_telemetry(backend, request, policy, PlatformTarget::Cli, None, None)
 }
 
+/// Resolves `config.backend` through `registry` and runs the request on it.
+pub fn run_inference_with_registry(
+    registry: &BackendRegistry,
+    config: &RuntimeConfig,
+    request: &InferenceRequest,
+    policy: &RuntimePolicy,
+    platform: PlatformTarget,
+    telemetry: Option<&dyn TelemetrySink>,
+) -> Result<InferenceResult, CoreError> {
+    let backend = registry.create(&config.backend)?;
+    run_inference_with_telemetry(backend.as_ref(), request, policy, platform, telemetry, None)
+}
+
+/// Runs the request on `backend`, reporting to `telemetry`. With `cancel`, the run goes through
+/// [`InferenceBackend::infer_cancellable`] and ends with [`CoreError::Cancelled`] at the next stage
+/// boundary after the token fires.
 pub fn run_inference_with_telemetry(
     backend: &dyn InferenceBackend,
     request: &InferenceRequest,
     policy: &RuntimePolicy,
     platform: PlatformTarget,
     telemetry: Option<&dyn TelemetrySink>,
+    cancel: Option<&CancelToken>,
 ) -> Result<InferenceResult, CoreError> {
     if request.input_bytes.is_none() && request.input_path.is_none() {
         return Err(CoreError::MissingInput);
     }
     let start = Instant::now();
     if let Some(sink) = telemetry {
-        sink.emit(TelemetryEvent {
-            event_type: TelemetryEventType::InferenceStart,
-            model: request.requested_model,
-            platform,
-            duration_ms: None,
-            detail: None,
-        });
+        emit_inference_start(sink, request, platform);
     }
     let selected_model = resolve_model(request, policy)?;
-    match backend.infer(request, selected_model) {
+    let request = with_pixel_budget(request, policy);
+    let outcome = match cancel {
+        Some(cancel) => backend.infer_cancellable(&request, selected_model, cancel),
+        None => backend.infer(&request, selected_model),
+    };
+    if let Some(sink) = telemetry {
+        emit_inference_outcome(sink, &request, selected_model, platform, start, outcome.as_ref());
+    }
+    outcome
+}
+
+/// Runs `requests` on `backend` like [`run_inference_with_telemetry`] does one request, but hands
+/// each run of consecutive requests that resolve to the same model to
+/// [`InferenceBackend::infer_batch`] (or [`InferenceBackend::infer_batch_cancellable`]) at once.
+/// Results come back in request order. When a batch fails, every request in it reports the error.
+pub fn run_inference_batch_with_telemetry(
+    backend: &dyn InferenceBackend,
+    requests: &[InferenceRequest],
+    policy: &RuntimePolicy,
+    platform: PlatformTarget,
+    telemetry: Option<&dyn TelemetrySink>,
+    cancel: Option<&CancelToken>,
+) -> Result<Vec<InferenceResult>, CoreError> {
+    if requests.iter().any(|r| r.input_bytes.is_none() && r.input_path.is_none()) {
+        return Err(CoreError::MissingInput);
+    }
+    let mut runs: Vec<(ModelKind, Vec<InferenceRequest>)> = Vec::new();
+    for request in requests {
+        let selected_model = resolve_model(request, policy)?;
+        let request = with_pixel_budget(request, policy).into_owned();
+        match runs.last_mut() {
+            Some((model, run)) if *model == selected_model => run.push(request),
+            _ => runs.push((selected_model, vec![request])),
+        }
+    }
+    let mut results = Vec::with_capacity(requests.len());
+    for (selected_model, run) in runs {
+        let start = Instant::now();
+        if let Some(sink) = telemetry {
+            for request in &run {
+                emit_inference_start(sink, request, platform);
+            }
+        }
+        let outcome = match cancel {
+            Some(cancel) => backend.infer_batch_cancellable(&run, selected_model, cancel),
+            None => backend.infer_batch(&run, selected_model),
+        };
+        if let Some(sink) = telemetry {
+            match &outcome {
+                Ok(batch) => {
+                    for (request, result) in run.iter().zip(batch) {
+                        emit_inference_outcome(sink, request, selected_model, platform, start, Ok(result));
+                    }
+                }
+                Err(err) => {
+                    for request in &run {
+                        emit_inference_outcome(sink, request, selected_model, platform, start, Err(err));
+                    }
+                }
+            }
+        }
+        results.extend(outcome?);
+    }
+    Ok(results)
+}
+
+/// `request` with `max_inference_pixels` filled from `policy` when the caller left it unset.
+fn with_pixel_budget<'a>(request: &'a InferenceRequest, policy: &RuntimePolicy) -> std::borrow::Cow<'a, InferenceRequest> {
+    if request.max_inference_pixels.is_some() {
+        return std::borrow::Cow::Borrowed(request);
+    }
+    std::borrow::Cow::Owned(InferenceRequest {
+        max_inference_pixels: Some(policy.max_inference_pixels),
+        ..request.clone()
+    })
+}
+
+fn emit_inference_start(sink: &dyn TelemetrySink, request: &InferenceRequest, platform: PlatformTarget) {
+    sink.emit(TelemetryEvent {
+        event_type: TelemetryEventType::InferenceStart,
+        model: request.requested_model,
+        platform,
+        duration_ms: None,
+        detail: None,
+        fields: None,
+    });
+}
+
+fn emit_inference_outcome(
+    sink: &dyn TelemetrySink,
+    request: &InferenceRequest,
+    selected_model: ModelKind,
+    platform: PlatformTarget,
+    start: Instant,
+    outcome: Result<&InferenceResult, &CoreError>,
+) {
+    match outcome {
         Ok(result) => {
-            if let Some(sink) = telemetry {
+            let fields = serde_json::json!({
+                "provider": result.execution_provider_selected,
+                "backend": result.gpu_backend_selected,
+                "fallback": result.fallback_used,
+                "model": result.model_used,
+                "onnx_variant": request.onnx_variant,
+            });
+            if result.fallback_used {
                 sink.emit(TelemetryEvent {
-                    event_type: TelemetryEventType::InferenceSuccess,
+                    event_type: TelemetryEventType::ProviderFallback,
                     model: result.model_used,
                     platform,
-                    duration_ms: Some(start.elapsed().as_millis() as u64),
+                    duration_ms: None,
                     detail: Some(format!(
-                        "provider={},backend={},fallback={}",
+                        "provider={},reason={}",
                         result.execution_provider_selected,
-                        result.gpu_backend_selected.clone().unwrap_or_else(|| "none".to_string()),
-                        result.fallback_used
+                        result.fallback_reason.as_deref().unwrap_or("unknown")
                     )),
+                    fields: Some(serde_json::json!({
+                        "provider": result.execution_provider_selected,
+                        "reason": result.fallback_reason,
+                    })),
                 });
             }
-            Ok(result)
+            sink.emit(TelemetryEvent {
+                event_type: TelemetryEventType::InferenceSuccess,
+                model: result.model_used,
+                platform,
+                duration_ms: Some(start.elapsed().as_millis() as u64),
+                detail: Some(format!(
+                    "provider={},backend={},fallback={}",
+                    result.execution_provider_selected,
+                    result.gpu_backend_selected.clone().unwrap_or_else(|| "none".to_string()),
+                    result.fallback_used
+                )),
+                fields: Some(fields),
+            });
         }
         Err(err) => {
-            if let Some(sink) = telemetry {
-                sink.emit(TelemetryEvent {
-                    event_type: TelemetryEventType::InferenceError,
-                    model: selected_model,
-                    platform,
-                    duration_ms: Some(start.elapsed().as_millis() as u64),
-                    detail: Some(err.to_string()),
-                });
-            }
-            Err(err)
+            let event_type = match err {
+                CoreError::Cancelled => TelemetryEventType::InferenceCancelled,
+                _ => TelemetryEventType::InferenceError,
+            };
+            sink.emit(TelemetryEvent {
+                event_type,
+                model: selected_model,
+                platform,
+                duration_ms: Some(start.elapsed().as_millis() as u64),
+                detail: Some(err.to_string()),
+                fields: Some(serde_json::json!({
+                    "model": selected_model,
+                    "onnx_variant": request.onnx_variant,
+                })),
+            });
         }
     }
 }
@@ -322,33 +1495,76 @@ mod tests {
 
     impl InferenceBackend for StubBackend {
         fn infer(&self, request: &InferenceRequest, selected_model: ModelKind) -> Result<InferenceResult, CoreError> {
-            Ok(InferenceResult {
-                model_used: selected_model,
-                mask_png: vec![0, 1, 2],
-                width: request.width,
-                height: request.height,
-                execution_provider_selected: "cpu".to_string(),
-                gpu_backend_selected: None,
-                fallback_used: false,
-            })
+            Ok(InferenceResult::new(selected_model, vec![0, 1, 2], request.width, request.height, "cpu"))
         }
     }
 
     #[test]
-    fn auto_falls_back_to_rmbg14_when_pixel_budget_exceeded() {
-        let request = InferenceRequest {
-            requested_model: ModelKind::Auto,
-            onnx_variant: OnnxVariant::Fp16,
-            execution_provider: ExecutionProvider::Auto,
-            gpu_backend: GpuBackendPreference::Auto,
-            benchmark_provider: true,
-            emit_mask_png: true,
-            input_path: Some(PathBuf::from("input.png")),
-            input_bytes: None,
-            model_dir: None,
-            width: 4096,
-            height: 4096,
+    fn default_infer_batch_runs_each_request_in_order() {
+        let requests: Vec<InferenceRequest> = [(10, 20), (30, 40)]
+            .into_iter()
+            .map(|(w, h)| InferenceRequest::builder().input_path("input.png").dimensions(w, h).build())
+            .collect();
+        let results = StubBackend.infer_batch(&requests, ModelKind::Rmbg14).expect("batch");
+        let sizes: Vec<_> = results.iter().map(|r| (r.width, r.height)).collect();
+        assert_eq!(sizes, vec![(10, 20), (30, 40)]);
+        assert!(StubBackend.infer_batch(&[], ModelKind::Rmbg14).expect("empty batch").is_empty());
+    }
+
+    #[test]
+    fn batch_runs_group_consecutive_requests_by_resolved_model() {
+        struct CountingBackend(std::sync::Mutex<Vec<(ModelKind, usize)>>);
+
+        impl InferenceBackend for CountingBackend {
+            fn infer(&self, request: &InferenceRequest, selected_model: ModelKind) -> Result<InferenceResult, CoreError> {
+                StubBackend.infer(request, selected_model)
+            }
+
+            fn infer_batch(&self, requests: &[InferenceRequest], selected_model: ModelKind) -> Result<Vec<InferenceResult>, CoreError> {
+                self.0.lock().unwrap().push((selected_model, requests.len()));
+                StubBackend.infer_batch(requests, selected_model)
+            }
+        }
+
+        let policy = RuntimePolicy {
+            max_inference_pixels: 1_000,
+            ..RuntimePolicy::desktop()
         };
+        let requests: Vec<InferenceRequest> = [(10, 10), (20, 20), (100, 100), (5, 5)]
+            .into_iter()
+            .map(|(w, h)| InferenceRequest::builder().input_path("input.png").dimensions(w, h).build())
+            .collect();
+        let backend = CountingBackend(std::sync::Mutex::new(Vec::new()));
+        let events = std::sync::Mutex::new(Vec::new());
+        let sink = FnSink(|event: TelemetryEvent| events.lock().unwrap().push(event.event_type));
+
+        let results = run_inference_batch_with_telemetry(&backend, &requests, &policy, PlatformTarget::Cli, Some(&sink), None).unwrap();
+        let sizes: Vec<_> = results.iter().map(|r| (r.width, r.model_used)).collect();
+        assert_eq!(
+            sizes,
+            vec![(10, ModelKind::Rmbg20), (20, ModelKind::Rmbg20), (100, ModelKind::Rmbg14), (5, ModelKind::Rmbg20)]
+        );
+        assert_eq!(
+            *backend.0.lock().unwrap(),
+            vec![(ModelKind::Rmbg20, 2), (ModelKind::Rmbg14, 1), (ModelKind::Rmbg20, 1)]
+        );
+        let events = events.into_inner().unwrap();
+        assert_eq!(events.iter().filter(|e| matches!(e, TelemetryEventType::InferenceSuccess)).count(), 4);
+
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let err = run_inference_batch_with_telemetry(&StubBackend, &requests, &policy, PlatformTarget::Cli, None, Some(&cancel)).unwrap_err();
+        assert!(matches!(err, CoreError::Cancelled));
+    }
+
+    #[test]
+    fn auto_falls_back_to_rmbg14_when_pixel_budget_exceeded() {
+        let request = InferenceRequest::builder()
+            .requested_model(ModelKind::Auto)
+            .benchmark_provider(true)
+            .input_path("input.png")
+            .dimensions(4096, 4096)
+            .build();
         let policy = RuntimePolicy {
             max_inference_pixels: 1_000_000,
             max_latency_ms: 1500,
@@ -360,21 +1576,433 @@ mod tests {
 
     #[test]
     fn inference_uses_selected_model() {
-        let request = InferenceRequest {
-            requested_model: ModelKind::Rmbg20,
-            onnx_variant: OnnxVariant::Fp16,
-            execution_provider: ExecutionProvider::Auto,
-            gpu_backend: GpuBackendPreference::Auto,
-            benchmark_provider: true,
-            emit_mask_png: true,
-            input_path: Some(PathBuf::from("input.png")),
-            input_bytes: None,
-            model_dir: None,
-            width: 100,
-            height: 100,
-        };
+        let request = InferenceRequest::builder()
+            .requested_model(ModelKind::Rmbg20)
+            .benchmark_provider(true)
+            .input_path("input.png")
+            .dimensions(100, 100)
+            .build();
         let policy = RuntimePolicy::default();
         let result = run_inference(&StubBackend, &request, &policy).expect("inference should succeed");
         assert_eq!(result.model_used, ModelKind::Rmbg20);
     }
+
+    #[test]
+    fn builder_fills_defaults_for_minimal_request() {
+        let request = InferenceRequest::builder()
+            .input_bytes(vec![1, 2, 3])
+            .requested_model(ModelKind::Rmbg14)
+            .build();
+        assert_eq!(request.input_bytes.as_deref(), Some(&[1u8, 2, 3][..]));
+        assert_eq!(request.requested_model, ModelKind::Rmbg14);
+        assert_eq!(request.onnx_variant, OnnxVariant::Fp16);
+        assert_eq!(request.execution_provider, ExecutionProvider::Auto);
+        assert_eq!(request.gpu_backend, GpuBackendPreference::Auto);
+        assert!(!request.benchmark_provider);
+        assert!(request.emit_mask_png);
+        assert!(request.input_path.is_none());
+        assert!(request.model_dir.is_none());
+        assert_eq!((request.width, request.height), (0, 0));
+        assert!(request.source_max_value.is_none());
+        assert!(request.low_confidence_range.is_none());
+        assert!(request.revision.is_none());
+        assert!(request.free_dimension_overrides.is_empty());
+        assert!(request.ensemble.is_none());
+        assert!(request.foreground_channels.is_empty());
+        assert_eq!(request.inference_retries, 0);
+        assert!(request.trimap.is_none());
+        assert!(request.max_dimension.is_none());
+        assert!(request.roi.is_none());
+    }
+
+    #[test]
+    fn registry_resolves_backend_named_in_config() {
+        let mut registry = BackendRegistry::new();
+        registry.register("stub", || Box::new(StubBackend));
+        let config = resolve_runtime_config(RuntimeConfig {
+            backend: "stub".to_string(),
+            ..RuntimeConfig::default()
+        });
+        let request = InferenceRequest::builder()
+            .requested_model(ModelKind::Rmbg14)
+            .input_path("input.png")
+            .dimensions(8, 8)
+            .build();
+        let result = run_inference_with_registry(
+            &registry,
+            &config,
+            &request,
+            &RuntimePolicy::default(),
+            PlatformTarget::Cli,
+            None,
+        )
+        .expect("stub backend should be resolved");
+        assert_eq!(result.mask_png, vec![0, 1, 2]);
+
+        let missing = RuntimeConfig {
+            backend: "tract".to_string(),
+            ..RuntimeConfig::default()
+        };
+        let err = run_inference_with_registry(&registry, &missing, &request, &RuntimePolicy::default(), PlatformTarget::Cli, None)
+            .expect_err("unknown backend should fail");
+        assert!(err.to_string().contains("unknown backend 'tract'"));
+    }
+
+    struct GpuFailingBackend;
+
+    impl InferenceBackend for GpuFailingBackend {
+        fn infer(&self, request: &InferenceRequest, selected_model: ModelKind) -> Result<InferenceResult, CoreError> {
+            Ok(InferenceResult {
+                fallback_used: true,
+                fallback_reason: Some("cuda: CUDA driver version is insufficient".to_string()),
+                ..StubBackend.infer(request, selected_model)?
+            })
+        }
+    }
+
+    #[derive(Default)]
+    struct RecordingSink(std::sync::Mutex<Vec<TelemetryEvent>>);
+
+    impl TelemetrySink for RecordingSink {
+        fn emit(&self, event: TelemetryEvent) {
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    #[test]
+    fn gpu_fallback_emits_telemetry_with_provider_error() {
+        let request = InferenceRequest::builder()
+            .requested_model(ModelKind::Rmbg14)
+            .input_path("input.png")
+            .dimensions(8, 8)
+            .build();
+        let sink = RecordingSink::default();
+        run_inference_with_telemetry(
+            &GpuFailingBackend,
+            &request,
+            &RuntimePolicy::default(),
+            PlatformTarget::Cli,
+            Some(&sink),
+            None,
+        )
+        .expect("fallback still succeeds");
+
+        let events = sink.0.lock().unwrap();
+        let fallback = events
+            .iter()
+            .find(|e| matches!(e.event_type, TelemetryEventType::ProviderFallback))
+            .expect("fallback event should be emitted");
+        let detail = fallback.detail.as_deref().unwrap();
+        assert!(detail.contains("provider=cpu"), "{}", detail);
+        assert!(detail.contains("CUDA driver version is insufficient"), "{}", detail);
+
+        drop(events);
+        sink.0.lock().unwrap().clear();
+        run_inference_with_telemetry(&StubBackend, &request, &RuntimePolicy::default(), PlatformTarget::Cli, Some(&sink), None)
+            .unwrap();
+        assert!(!sink
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|e| matches!(e.event_type, TelemetryEventType::ProviderFallback)));
+    }
+
+    #[test]
+    fn success_event_carries_structured_fields() {
+        let request = InferenceRequest::builder()
+            .requested_model(ModelKind::Rmbg14)
+            .input_path("input.png")
+            .dimensions(8, 8)
+            .build();
+        let sink = RecordingSink::default();
+        run_inference_with_telemetry(&StubBackend, &request, &RuntimePolicy::default(), PlatformTarget::Cli, Some(&sink), None)
+            .unwrap();
+
+        let events = sink.0.lock().unwrap();
+        let success = events
+            .iter()
+            .find(|e| matches!(e.event_type, TelemetryEventType::InferenceSuccess))
+            .expect("success event should be emitted");
+        let fields = success.fields.as_ref().expect("structured fields");
+        assert_eq!(fields["provider"], "cpu");
+        assert_eq!(fields["fallback"], false);
+        assert_eq!(fields["model"], "rmbg14");
+        assert_eq!(fields["onnx_variant"], "fp16");
+        assert!(fields["backend"].is_null());
+        assert!(success.detail.as_deref().unwrap().starts_with("provider=cpu"));
+    }
+
+    #[test]
+    fn closure_sink_receives_each_event() {
+        let request = InferenceRequest::builder()
+            .requested_model(ModelKind::Rmbg14)
+            .input_path("input.png")
+            .dimensions(8, 8)
+            .build();
+        let seen = std::sync::Mutex::new(Vec::new());
+        let sink = FnSink(|event: TelemetryEvent| seen.lock().unwrap().push(event.event_type));
+        run_inference_with_telemetry(&StubBackend, &request, &RuntimePolicy::default(), PlatformTarget::Cli, Some(&sink), None)
+            .unwrap();
+
+        let seen = seen.into_inner().unwrap();
+        assert_eq!(seen.len(), 2, "{:?}", seen);
+        assert!(matches!(seen[0], TelemetryEventType::InferenceStart));
+        assert!(matches!(seen[1], TelemetryEventType::InferenceSuccess));
+    }
+
+    /// Simulates the user cancelling while the model runs.
+    struct CancelDuringRun(CancelToken);
+
+    impl InferenceBackend for CancelDuringRun {
+        fn infer(&self, request: &InferenceRequest, selected_model: ModelKind) -> Result<InferenceResult, CoreError> {
+            self.0.cancel();
+            StubBackend.infer(request, selected_model)
+        }
+    }
+
+    #[test]
+    fn cancel_token_stops_inference_at_a_stage_boundary() {
+        let request = InferenceRequest::builder()
+            .requested_model(ModelKind::Rmbg14)
+            .input_path("input.png")
+            .dimensions(8, 8)
+            .build();
+        let policy = RuntimePolicy::default();
+        let cancel = CancelToken::new();
+        let sink = RecordingSink::default();
+        let backend = CancelDuringRun(cancel.clone());
+        let err = run_inference_with_telemetry(&backend, &request, &policy, PlatformTarget::Cli, Some(&sink), Some(&cancel))
+            .expect_err("cancelled mid-run");
+        assert!(matches!(err, CoreError::Cancelled));
+        assert_eq!(err.as_error_info().code, ErrorCode::Cancelled);
+        assert!(matches!(
+            sink.0.lock().unwrap().last().map(|e| &e.event_type),
+            Some(TelemetryEventType::InferenceCancelled)
+        ));
+
+        let untouched = CancelToken::new();
+        run_inference_with_telemetry(&StubBackend, &request, &policy, PlatformTarget::Cli, None, Some(&untouched))
+            .expect("token that never fires");
+        untouched.cancel();
+        let err = run_inference_with_telemetry(&StubBackend, &request, &policy, PlatformTarget::Cli, None, Some(&untouched))
+            .expect_err("already cancelled");
+        assert!(matches!(err, CoreError::Cancelled));
+    }
+
+    #[test]
+    fn alpha_curves_must_span_the_unit_range_and_increase() {
+        assert!(validate_alpha_curve(&[(0.0, 0.0), (0.4, 0.8), (1.0, 1.0)]).is_ok());
+        for curve in [
+            vec![(0.0, 0.0)],
+            vec![(0.1, 0.0), (1.0, 1.0)],
+            vec![(0.0, 0.0), (0.6, 0.5), (0.4, 0.7), (1.0, 1.0)],
+            vec![(0.0, 0.0), (0.5, f32::NAN), (1.0, 1.0)],
+            vec![(0.0, 0.0), (1.0, 1.5)],
+        ] {
+            let err = validate_alpha_curve(&curve).unwrap_err();
+            assert!(matches!(err, CoreError::InvalidArgument(_)), "{:?}: {:?}", curve, err);
+        }
+    }
+
+    #[test]
+    fn mask_rle_round_trips_to_identical_bytes() {
+        let mut mask = vec![0u8; 1000];
+        mask[300..700].fill(255);
+        mask[450] = 128;
+        mask[999] = 17;
+
+        let runs = encode_mask_rle(&mask);
+        assert_eq!(runs, vec![(0, 300), (255, 150), (128, 1), (255, 249), (0, 299), (17, 1)]);
+        assert_eq!(decode_mask_rle(&runs), mask);
+        assert!(encode_mask_rle(&[]).is_empty());
+    }
+
+    #[test]
+    fn v1_timings_are_opt_in_and_omitted_when_absent() {
+        let request: v1::RemoveBackgroundRequest = serde_json::from_value(serde_json::json!({
+            "imageBytes": [1, 2, 3],
+            "width": 4,
+            "height": 3,
+            "model": "auto",
+        }))
+        .unwrap();
+        assert!(!request.collect_timings);
+
+        let mut response = v1::RemoveBackgroundResponse {
+            model_used: "rmbg-1.4".to_string(),
+            width: 4,
+            height: 3,
+            mask_png: Vec::new(),
+            provider_selected: "cpu".to_string(),
+            backend_selected: None,
+            fallback_used: false,
+            composite_png: None,
+            timings: None,
+            mask_rle: None,
+        };
+        assert!(serde_json::to_value(&response).unwrap().get("timings").is_none());
+
+        response.timings = Some(InferenceTimings {
+            preprocess_ms: 3,
+            session_run_ms: 40,
+            postprocess_ms: 5,
+            total_ms: 50,
+        });
+        assert_eq!(
+            serde_json::to_value(&response).unwrap()["timings"],
+            serde_json::json!({ "preprocessMs": 3, "sessionRunMs": 40, "postprocessMs": 5, "totalMs": 50 })
+        );
+    }
+
+    #[test]
+    fn v1_mask_rle_is_opt_in_and_serialized_as_pairs() {
+        let request: v1::RemoveBackgroundRequest = serde_json::from_value(serde_json::json!({
+            "imageBytes": [1, 2, 3],
+            "width": 4,
+            "height": 1,
+            "model": "auto",
+            "emitMaskRle": true,
+        }))
+        .unwrap();
+        assert!(request.emit_mask_rle);
+
+        let response = v1::RemoveBackgroundResponse {
+            model_used: "rmbg-1.4".to_string(),
+            width: 4,
+            height: 1,
+            mask_png: Vec::new(),
+            provider_selected: "cpu".to_string(),
+            backend_selected: None,
+            fallback_used: false,
+            composite_png: None,
+            timings: None,
+            mask_rle: Some(encode_mask_rle(&[0, 0, 255, 255])),
+        };
+        assert_eq!(serde_json::to_value(&response).unwrap()["maskRle"], serde_json::json!([[0, 2], [255, 2]]));
+    }
+
+    #[test]
+    fn output_naming_matches_cli_for_single_directory_and_pattern_inputs() {
+        let single = OutputNaming::default();
+        assert_eq!(
+            single.resolve(Path::new("photos/cat.jpg")).unwrap(),
+            OutputPaths {
+                cutout: Some(PathBuf::from("photos/cat_cutout.png")),
+                mask: None,
+                outputs: Vec::new(),
+            }
+        );
+        let explicit = OutputNaming {
+            output_cutout: Some(PathBuf::from("out/cutout.jpg")),
+            ..OutputNaming::default()
+        };
+        assert_eq!(
+            explicit.resolve(Path::new("cat.jpg")).unwrap_err(),
+            OutputNamingError::NotPng(PathBuf::from("out/cutout.jpg"))
+        );
+
+        let directory = OutputNaming {
+            multi_input: true,
+            output_cutout: Some(PathBuf::from("out")),
+            output_mask: Some(PathBuf::from("masks")),
+            ..OutputNaming::default()
+        };
+        assert_eq!(
+            directory.resolve(Path::new("photos/a/cat.jpg")).unwrap(),
+            OutputPaths {
+                cutout: Some(PathBuf::from("out/cat_cutout.png")),
+                mask: Some(PathBuf::from("masks/cat_mask.png")),
+                outputs: Vec::new(),
+            }
+        );
+
+        let pattern = OutputNaming {
+            multi_input: true,
+            output_dir: Some(PathBuf::from("out")),
+            structure_root: Some(PathBuf::from("photos")),
+            ..OutputNaming::default()
+        };
+        assert_eq!(
+            pattern.resolve(Path::new("photos/a/cat.jpg")).unwrap(),
+            OutputPaths {
+                cutout: Some(PathBuf::from("out/a/cat_cutout.png")),
+                mask: None,
+                outputs: Vec::new(),
+            }
+        );
+        assert_eq!(default_mask_filename(Path::new("")), Err(OutputNamingError::MissingFileName));
+
+        let formats = OutputNaming {
+            outputs: vec![PathBuf::from("out/cat.png"), PathBuf::from("out/cat.jpg")],
+            ..OutputNaming::default()
+        };
+        assert_eq!(
+            formats.resolve(Path::new("cat.jpg")).unwrap(),
+            OutputPaths {
+                cutout: None,
+                mask: None,
+                outputs: vec![PathBuf::from("out/cat.png"), PathBuf::from("out/cat.jpg")],
+            }
+        );
+        let bulk_formats = OutputNaming {
+            multi_input: true,
+            outputs: vec![PathBuf::from("png/x.png"), PathBuf::from("jpeg/x.JPG")],
+            ..OutputNaming::default()
+        };
+        assert_eq!(
+            bulk_formats.resolve(Path::new("photos/cat.heic")).unwrap().outputs,
+            vec![PathBuf::from("png/cat_cutout.png"), PathBuf::from("jpeg/cat_cutout.JPG")]
+        );
+        let tiff = OutputNaming {
+            outputs: vec![PathBuf::from("cat.tiff")],
+            ..OutputNaming::default()
+        };
+        assert_eq!(
+            tiff.resolve(Path::new("cat.jpg")).unwrap_err(),
+            OutputNamingError::UnsupportedFormat(PathBuf::from("cat.tiff"))
+        );
+    }
+
+    #[test]
+    fn output_naming_rejects_outputs_that_resolve_to_the_same_file() {
+        let same_extension = OutputNaming {
+            multi_input: true,
+            outputs: vec![PathBuf::from("out/a.png"), PathBuf::from("out/b.png")],
+            ..OutputNaming::default()
+        };
+        assert_eq!(
+            same_extension.resolve(Path::new("photos/cat.jpg")).unwrap_err(),
+            OutputNamingError::Collision(PathBuf::from("out/cat_cutout.png"))
+        );
+        let matches_cutout = OutputNaming {
+            multi_input: true,
+            output_cutout: Some(PathBuf::from("out")),
+            outputs: vec![PathBuf::from("out/x.png")],
+            ..OutputNaming::default()
+        };
+        assert_eq!(
+            matches_cutout.resolve(Path::new("photos/cat.jpg")).unwrap_err(),
+            OutputNamingError::Collision(PathBuf::from("out/cat_cutout.png"))
+        );
+        let distinct = OutputNaming {
+            multi_input: true,
+            output_cutout: Some(PathBuf::from("out")),
+            outputs: vec![PathBuf::from("out/x.webp"), PathBuf::from("jpeg/x.jpg")],
+            ..OutputNaming::default()
+        };
+        assert!(distinct.resolve(Path::new("photos/cat.jpg")).is_ok());
+    }
+
+    #[test]
+    fn runtime_policy_presets_carry_platform_values() {
+        let desktop = RuntimePolicy::desktop();
+        assert_eq!((desktop.max_inference_pixels, desktop.max_latency_ms), (2_000_000, 1_500));
+        let mobile = RuntimePolicy::mobile();
+        assert_eq!((mobile.max_inference_pixels, mobile.max_latency_ms), (1_500_000, 1_500));
+        let server = RuntimePolicy::server();
+        assert_eq!((server.max_inference_pixels, server.max_latency_ms), (4_000_000, 5_000));
+        assert!(desktop.allow_rmbg20 && mobile.allow_rmbg20 && server.allow_rmbg20);
+        assert_eq!(RuntimePolicy::default().max_inference_pixels, desktop.max_inference_pixels);
+    }
 }
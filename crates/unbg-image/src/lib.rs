@@ -1,3 +1,14 @@
+use std::io::{BufRead, Seek, Write};
+
+use image::{DynamicImage, GrayImage, ImageFormat, Rgba, RgbaImage};
+use thiserror::Error;
+
+mod resize;
+
+#[cfg(feature = "fast-resize")]
+pub use resize::FastResizer;
+pub use resize::{active_resizer, resizer, ImageResizer, ResizeBackend, Resizer};
+
 #[derive(Debug, Clone, Copy)]
 pub struct ImageSize {
     pub width: u32,
@@ -24,3 +35,1738 @@ pub fn clamp_to_max_pixels(size: ImageSize, max_pixels: u32) -> ImageSize {
         height: new_height,
     }
 }
+
+/// Shrinks `size` so its longer side is at most `max_dimension`, keeping the aspect ratio.
+pub fn clamp_to_max_dimension(size: ImageSize, max_dimension: u32) -> ImageSize {
+    let long_edge = size.width.max(size.height);
+    if long_edge <= max_dimension || max_dimension == 0 {
+        return size;
+    }
+    let scale = |side: u32| ((side as u64 * max_dimension as u64 / long_edge as u64) as u32).max(1);
+    ImageSize {
+        width: scale(size.width),
+        height: scale(size.height),
+    }
+}
+
+/// Container/codec detected from the leading bytes of an encoded image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceFormat {
+    Png,
+    Jpeg,
+    Webp,
+    Gif,
+    Bmp,
+    Tiff,
+    Avif,
+    Heic,
+    Jxl,
+    Unknown,
+}
+
+impl SourceFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Png => "PNG",
+            Self::Jpeg => "JPEG",
+            Self::Webp => "WebP",
+            Self::Gif => "GIF",
+            Self::Bmp => "BMP",
+            Self::Tiff => "TIFF",
+            Self::Avif => "AVIF",
+            Self::Heic => "HEIC",
+            Self::Jxl => "JPEG XL",
+            Self::Unknown => "unknown",
+        }
+    }
+
+    /// Cargo feature of `unbg-image` that enables decoding, if the format is optional.
+    pub fn feature(self) -> Option<&'static str> {
+        match self {
+            Self::Webp => Some("webp"),
+            Self::Gif => Some("gif"),
+            Self::Bmp => Some("bmp"),
+            Self::Tiff => Some("tiff"),
+            Self::Avif => Some("avif"),
+            Self::Heic => Some("heic"),
+            Self::Jxl => Some("jxl"),
+            Self::Png | Self::Jpeg | Self::Unknown => None,
+        }
+    }
+
+    pub fn is_enabled(self) -> bool {
+        match self {
+            Self::Png | Self::Jpeg => true,
+            Self::Webp => cfg!(feature = "webp"),
+            Self::Gif => cfg!(feature = "gif"),
+            Self::Bmp => cfg!(feature = "bmp"),
+            Self::Tiff => cfg!(feature = "tiff"),
+            Self::Avif => cfg!(feature = "avif"),
+            Self::Heic => cfg!(feature = "heic"),
+            Self::Jxl => cfg!(feature = "jxl"),
+            Self::Unknown => false,
+        }
+    }
+
+    pub fn from_extension(ext: &str) -> Self {
+        match ext.trim_start_matches('.').to_ascii_lowercase().as_str() {
+            "png" => Self::Png,
+            "jpg" | "jpeg" => Self::Jpeg,
+            "webp" => Self::Webp,
+            "gif" => Self::Gif,
+            "bmp" => Self::Bmp,
+            "tif" | "tiff" => Self::Tiff,
+            "avif" => Self::Avif,
+            "heic" | "heif" => Self::Heic,
+            "jxl" => Self::Jxl,
+            _ => Self::Unknown,
+        }
+    }
+
+    fn image_format(self) -> Option<ImageFormat> {
+        match self {
+            Self::Png => Some(ImageFormat::Png),
+            Self::Jpeg => Some(ImageFormat::Jpeg),
+            Self::Webp => Some(ImageFormat::WebP),
+            Self::Gif => Some(ImageFormat::Gif),
+            Self::Bmp => Some(ImageFormat::Bmp),
+            Self::Tiff => Some(ImageFormat::Tiff),
+            Self::Avif => Some(ImageFormat::Avif),
+            Self::Heic | Self::Jxl | Self::Unknown => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DecodeHints {
+    /// File extension to fall back on when the leading bytes are not recognized.
+    pub extension: Option<String>,
+    /// Rotate and flip the decoded pixels as the EXIF orientation tag asks, for callers that
+    /// drop the tag on output.
+    pub apply_orientation: bool,
+}
+
+impl DecodeHints {
+    pub fn from_path(path: &std::path::Path) -> Self {
+        Self {
+            extension: path.extension().and_then(|e| e.to_str()).map(str::to_string),
+            apply_orientation: false,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum DecodeError {
+    #[error("{format} images are not supported by this build; enable the `{feature}` feature of unbg-image")]
+    MissingFeature { format: &'static str, feature: &'static str },
+    #[error("unrecognized image format")]
+    UnknownFormat,
+    #[error("failed to decode {format} image: {message}")]
+    Decode { format: &'static str, message: String },
+    #[error("{format} image is {width}x{height} ({pixels} pixels), over the limit of {max_pixels} pixels")]
+    TooManyPixels {
+        format: &'static str,
+        width: u32,
+        height: u32,
+        pixels: u64,
+        max_pixels: u64,
+    },
+    #[error("decoding {format} image would allocate more than {max_alloc} bytes")]
+    AllocationLimit { format: &'static str, max_alloc: u64 },
+}
+
+/// Caps applied by [`safe_decode`] before and while decoding untrusted input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// Largest `width * height` accepted, checked against the header before any pixel data is decoded.
+    pub max_pixels: u64,
+    /// Upper bound on decoder allocations, including the output buffer.
+    pub max_alloc: u64,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self {
+            max_pixels: 200_000_000,
+            max_alloc: 1024 * 1024 * 1024,
+        }
+    }
+}
+
+impl DecodeLimits {
+    fn check_dimensions(&self, format: SourceFormat, width: u32, height: u32) -> Result<(), DecodeError> {
+        let pixels = width as u64 * height as u64;
+        if pixels > self.max_pixels {
+            return Err(DecodeError::TooManyPixels {
+                format: format.name(),
+                width,
+                height,
+                pixels,
+                max_pixels: self.max_pixels,
+            });
+        }
+        let decoded_bytes = pixels.saturating_mul(4);
+        if decoded_bytes > self.max_alloc {
+            return Err(DecodeError::AllocationLimit {
+                format: format.name(),
+                max_alloc: self.max_alloc,
+            });
+        }
+        Ok(())
+    }
+}
+
+pub fn sniff_format(bytes: &[u8]) -> SourceFormat {
+    if bytes.starts_with(&[0x89, b'P', b'N', b'G']) {
+        SourceFormat::Png
+    } else if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        SourceFormat::Jpeg
+    } else if bytes.len() >= 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        SourceFormat::Webp
+    } else if bytes.starts_with(b"GIF8") {
+        SourceFormat::Gif
+    } else if bytes.starts_with(b"BM") {
+        SourceFormat::Bmp
+    } else if bytes.starts_with(b"II*\0") || bytes.starts_with(b"MM\0*") {
+        SourceFormat::Tiff
+    } else if bytes.starts_with(&[0xFF, 0x0A]) || bytes.starts_with(b"\0\0\0\x0cJXL \r\n\x87\n") {
+        SourceFormat::Jxl
+    } else if bytes.len() >= 12 && &bytes[4..8] == b"ftyp" {
+        match &bytes[8..12] {
+            b"avif" | b"avis" => SourceFormat::Avif,
+            b"heic" | b"heix" | b"hevc" | b"hevx" | b"heim" | b"heis" | b"mif1" | b"msf1" => SourceFormat::Heic,
+            _ => SourceFormat::Unknown,
+        }
+    } else {
+        SourceFormat::Unknown
+    }
+}
+
+/// Decodes any format this build supports, picking the decoder from the sniffed format.
+pub fn decode_image(bytes: &[u8], hints: &DecodeHints) -> Result<DynamicImage, DecodeError> {
+    decode_with_limits(bytes, hints, None)
+}
+
+/// Like [`decode_image`], but rejects images over `limits` from their header and caps decoder memory.
+///
+/// Use this for untrusted uploads so a small file claiming huge dimensions cannot exhaust memory.
+pub fn safe_decode(bytes: &[u8], hints: &DecodeHints, limits: &DecodeLimits) -> Result<DynamicImage, DecodeError> {
+    decode_with_limits(bytes, hints, Some(limits))
+}
+
+fn decode_with_limits(bytes: &[u8], hints: &DecodeHints, limits: Option<&DecodeLimits>) -> Result<DynamicImage, DecodeError> {
+    let mut image = decode_pixels(bytes, hints, limits)?;
+    if hints.apply_orientation {
+        if let Some(orientation) = sniff_format(bytes).image_format().and_then(|format| exif_orientation(bytes, format)) {
+            image.apply_orientation(orientation);
+        }
+    }
+    Ok(image)
+}
+
+fn exif_orientation(bytes: &[u8], format: ImageFormat) -> Option<image::metadata::Orientation> {
+    use image::ImageDecoder;
+    let mut decoder = image::ImageReader::with_format(std::io::Cursor::new(bytes), format).into_decoder().ok()?;
+    decoder.orientation().ok()
+}
+
+/// The sniffed format of `bytes`, or the one the hinted extension names, if this build decodes it.
+fn decodable_format(bytes: &[u8], hints: &DecodeHints) -> Result<SourceFormat, DecodeError> {
+    let format = match sniff_format(bytes) {
+        SourceFormat::Unknown => hints
+            .extension
+            .as_deref()
+            .map(SourceFormat::from_extension)
+            .unwrap_or(SourceFormat::Unknown),
+        sniffed => sniffed,
+    };
+    if format == SourceFormat::Unknown {
+        return Err(DecodeError::UnknownFormat);
+    }
+    if !format.is_enabled() {
+        return Err(DecodeError::MissingFeature {
+            format: format.name(),
+            feature: format.feature().unwrap_or_default(),
+        });
+    }
+    Ok(format)
+}
+
+/// Width and height of the image in `bytes`, read from its header without decoding any pixels.
+/// With `hints.apply_orientation` they are the dimensions after the EXIF rotation.
+pub fn image_dimensions(bytes: &[u8], hints: &DecodeHints) -> Result<(u32, u32), DecodeError> {
+    let format = decodable_format(bytes, hints)?;
+    let decode_error = |message: String| DecodeError::Decode {
+        format: format.name(),
+        message,
+    };
+    let (width, height) = match format {
+        SourceFormat::Jxl => jxl_dimensions(bytes).map_err(decode_error)?,
+        SourceFormat::Heic => heic_dimensions(bytes).map_err(decode_error)?,
+        other => {
+            let image_format = other.image_format().expect("image crate format");
+            image::ImageReader::with_format(std::io::Cursor::new(bytes), image_format)
+                .into_dimensions()
+                .map_err(|e| decode_error(e.to_string()))?
+        }
+    };
+    use image::metadata::Orientation;
+    let swapped = hints.apply_orientation
+        && format.image_format().and_then(|image_format| exif_orientation(bytes, image_format)).is_some_and(|orientation| {
+            matches!(
+                orientation,
+                Orientation::Rotate90 | Orientation::Rotate270 | Orientation::Rotate90FlipH | Orientation::Rotate270FlipH
+            )
+        });
+    Ok(if swapped { (height, width) } else { (width, height) })
+}
+
+fn decode_pixels(bytes: &[u8], hints: &DecodeHints, limits: Option<&DecodeLimits>) -> Result<DynamicImage, DecodeError> {
+    let format = decodable_format(bytes, hints)?;
+    let decode_error = |message: String| DecodeError::Decode {
+        format: format.name(),
+        message,
+    };
+    match format {
+        SourceFormat::Jxl => decode_jxl(bytes, format, limits),
+        SourceFormat::Heic => decode_heic(bytes, format, limits),
+        other => {
+            let image_format = other.image_format().expect("image crate format");
+            let Some(limits) = limits else {
+                return image::load_from_memory_with_format(bytes, image_format).map_err(|e| decode_error(e.to_string()));
+            };
+            let reader = |max_alloc: u64| {
+                let mut reader = image::ImageReader::with_format(std::io::Cursor::new(bytes), image_format);
+                let mut image_limits = image::Limits::default();
+                image_limits.max_alloc = Some(max_alloc);
+                reader.limits(image_limits);
+                reader
+            };
+            let map_error = |err: image::ImageError| match err {
+                image::ImageError::Limits(_) => DecodeError::AllocationLimit {
+                    format: format.name(),
+                    max_alloc: limits.max_alloc,
+                },
+                other => decode_error(other.to_string()),
+            };
+            let (width, height) = reader(limits.max_alloc).into_dimensions().map_err(map_error)?;
+            limits.check_dimensions(format, width, height)?;
+            reader(limits.max_alloc).decode().map_err(map_error)
+        }
+    }
+}
+
+/// Lowercase file extensions [`decode_image`] can handle in this build.
+pub fn supported_extensions() -> Vec<&'static str> {
+    [
+        "png", "jpg", "jpeg", "webp", "gif", "bmp", "tif", "tiff", "avif", "heic", "heif", "jxl",
+    ]
+    .into_iter()
+    .filter(|ext| SourceFormat::from_extension(ext).is_enabled())
+    .collect()
+}
+
+#[cfg(feature = "jxl")]
+fn decode_jxl(bytes: &[u8], format: SourceFormat, limits: Option<&DecodeLimits>) -> Result<DynamicImage, DecodeError> {
+    use image::ImageDecoder;
+
+    let decode_error = |message: String| DecodeError::Decode {
+        format: format.name(),
+        message,
+    };
+    let decoder = jxl_oxide::integration::JxlDecoder::new(std::io::Cursor::new(bytes)).map_err(|e| decode_error(e.to_string()))?;
+    if let Some(limits) = limits {
+        let (width, height) = decoder.dimensions();
+        limits.check_dimensions(format, width, height)?;
+    }
+    DynamicImage::from_decoder(decoder).map_err(|e| decode_error(e.to_string()))
+}
+
+#[cfg(feature = "jxl")]
+fn jxl_dimensions(bytes: &[u8]) -> Result<(u32, u32), String> {
+    use image::ImageDecoder;
+
+    let decoder = jxl_oxide::integration::JxlDecoder::new(std::io::Cursor::new(bytes)).map_err(|e| e.to_string())?;
+    Ok(decoder.dimensions())
+}
+
+#[cfg(not(feature = "jxl"))]
+fn jxl_dimensions(_bytes: &[u8]) -> Result<(u32, u32), String> {
+    Err("jxl feature not enabled".to_string())
+}
+
+#[cfg(not(feature = "jxl"))]
+fn decode_jxl(_bytes: &[u8], format: SourceFormat, _limits: Option<&DecodeLimits>) -> Result<DynamicImage, DecodeError> {
+    Err(DecodeError::Decode {
+        format: format.name(),
+        message: "jxl feature not enabled".to_string(),
+    })
+}
+
+#[cfg(feature = "heic")]
+fn decode_heic(bytes: &[u8], format: SourceFormat, limits: Option<&DecodeLimits>) -> Result<DynamicImage, DecodeError> {
+    if let Some(limits) = limits {
+        let ctx = libheif_rs::HeifContext::read_from_bytes(bytes).map_err(|e| DecodeError::Decode {
+            format: format.name(),
+            message: e.to_string(),
+        })?;
+        if let Ok(handle) = ctx.primary_image_handle() {
+            limits.check_dimensions(format, handle.width(), handle.height())?;
+        }
+    }
+    decode_heic_unchecked(bytes).map_err(|message| DecodeError::Decode {
+        format: format.name(),
+        message,
+    })
+}
+
+#[cfg(feature = "heic")]
+fn decode_heic_unchecked(bytes: &[u8]) -> Result<DynamicImage, String> {
+    use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+    let lib = LibHeif::new();
+    let ctx = HeifContext::read_from_bytes(bytes).map_err(|e| e.to_string())?;
+    let handle = ctx.primary_image_handle().map_err(|e| e.to_string())?;
+    let decoded = lib
+        .decode(&handle, ColorSpace::Rgb(RgbChroma::Rgba), None)
+        .map_err(|e| e.to_string())?;
+    let plane = decoded
+        .planes()
+        .interleaved
+        .ok_or_else(|| "decoded HEIC image has no interleaved plane".to_string())?;
+    let row_bytes = plane.width as usize * 4;
+    let mut rgba = Vec::with_capacity(row_bytes * plane.height as usize);
+    for row in plane.data.chunks(plane.stride).take(plane.height as usize) {
+        rgba.extend_from_slice(&row[..row_bytes]);
+    }
+    image::RgbaImage::from_raw(plane.width, plane.height, rgba)
+        .map(DynamicImage::ImageRgba8)
+        .ok_or_else(|| "decoded HEIC buffer has unexpected size".to_string())
+}
+
+#[cfg(feature = "heic")]
+fn heic_dimensions(bytes: &[u8]) -> Result<(u32, u32), String> {
+    let ctx = libheif_rs::HeifContext::read_from_bytes(bytes).map_err(|e| e.to_string())?;
+    let handle = ctx.primary_image_handle().map_err(|e| e.to_string())?;
+    Ok((handle.width(), handle.height()))
+}
+
+#[cfg(not(feature = "heic"))]
+fn heic_dimensions(_bytes: &[u8]) -> Result<(u32, u32), String> {
+    Err("heic feature not enabled".to_string())
+}
+
+#[cfg(not(feature = "heic"))]
+fn decode_heic(_bytes: &[u8], format: SourceFormat, _limits: Option<&DecodeLimits>) -> Result<DynamicImage, DecodeError> {
+    Err(DecodeError::Decode {
+        format: format.name(),
+        message: "heic feature not enabled".to_string(),
+    })
+}
+
+/// Finishing touches drawn underneath a cutout, derived from its alpha channel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Effect {
+    /// Blurred copy of the silhouette, shifted by `offset` and tinted with `color`.
+    Shadow {
+        blur: u32,
+        offset: (i32, i32),
+        opacity: f32,
+        color: [u8; 3],
+    },
+    /// Solid outline `width` pixels wide around the silhouette.
+    Stroke { width: u32, color: [u8; 3] },
+}
+
+/// Applies `effects` in order, each composited beneath the image built so far.
+pub fn apply_effects(cutout: &RgbaImage, effects: &[Effect]) -> RgbaImage {
+    let mut out = cutout.clone();
+    for effect in effects {
+        let layer = match *effect {
+            Effect::Shadow {
+                blur,
+                offset,
+                opacity,
+                color,
+            } => shadow_layer(&out, blur, offset, opacity, color),
+            Effect::Stroke { width, color } => stroke_layer(&out, width, color),
+        };
+        out = composite_over(&out, &layer);
+    }
+    out
+}
+
+fn alpha_plane(img: &RgbaImage) -> Vec<f32> {
+    img.pixels().map(|p| p[3] as f32 / 255.0).collect()
+}
+
+fn layer_from_alpha(width: u32, height: u32, alpha: &[f32], color: [u8; 3]) -> RgbaImage {
+    RgbaImage::from_fn(width, height, |x, y| {
+        let a = alpha[(y * width + x) as usize].clamp(0.0, 1.0);
+        Rgba([color[0], color[1], color[2], (a * 255.0).round() as u8])
+    })
+}
+
+fn shadow_layer(img: &RgbaImage, blur: u32, offset: (i32, i32), opacity: f32, color: [u8; 3]) -> RgbaImage {
+    let (w, h) = img.dimensions();
+    let alpha = alpha_plane(img);
+    let mut shifted = vec![0f32; alpha.len()];
+    for y in 0..h as i32 {
+        for x in 0..w as i32 {
+            let (sx, sy) = (x - offset.0, y - offset.1);
+            if sx >= 0 && sy >= 0 && sx < w as i32 && sy < h as i32 {
+                shifted[(y as u32 * w + x as u32) as usize] = alpha[(sy as u32 * w + sx as u32) as usize];
+            }
+        }
+    }
+    let blurred = box_blur(&shifted, w as usize, h as usize, blur as usize);
+    let scaled: Vec<f32> = blurred.iter().map(|a| a * opacity.clamp(0.0, 1.0)).collect();
+    layer_from_alpha(w, h, &scaled, color)
+}
+
+/// Outline `width` pixels wide around the silhouette (alpha of at least one half). Coverage comes
+/// from the exact distance to the silhouette, so the cost does not grow with `width`, and the
+/// outer edge is antialiased over the last pixel. The subject's own alpha is kept underneath.
+fn stroke_layer(img: &RgbaImage, width: u32, color: [u8; 3]) -> RgbaImage {
+    let (w, h) = img.dimensions();
+    let alpha = alpha_plane(img);
+    let inside: Vec<bool> = alpha.iter().map(|&a| a >= 0.5).collect();
+    let distance = squared_distance_to(&inside, w as usize, h as usize);
+    let reach = width as f64 + 1.0;
+    let stroke: Vec<f32> = alpha
+        .iter()
+        .zip(&distance)
+        .map(|(&a, &d2)| a.max((reach - d2.sqrt()).clamp(0.0, 1.0) as f32))
+        .collect();
+    layer_from_alpha(w, h, &stroke, color)
+}
+
+/// Squared Euclidean distance from every pixel to the nearest `inside` one, computed a column
+/// and then a row at a time (Felzenszwalb and Huttenlocher), in time linear in the pixel count.
+/// Pixels with nothing inside the image to measure to get a huge distance.
+fn squared_distance_to(inside: &[bool], w: usize, h: usize) -> Vec<f64> {
+    const FAR: f64 = 1e18;
+    let mut grid: Vec<f64> = inside.iter().map(|&i| if i { 0.0 } else { FAR }).collect();
+    let mut line = vec![0f64; w.max(h)];
+    let mut out = vec![0f64; w.max(h)];
+    for x in 0..w {
+        for y in 0..h {
+            line[y] = grid[y * w + x];
+        }
+        squared_distance_1d(&line[..h], &mut out[..h]);
+        for y in 0..h {
+            grid[y * w + x] = out[y];
+        }
+    }
+    for y in 0..h {
+        squared_distance_1d(&grid[y * w..(y + 1) * w], &mut out[..w]);
+        grid[y * w..(y + 1) * w].copy_from_slice(&out[..w]);
+    }
+    grid
+}
+
+/// One-dimensional pass of [`squared_distance_to`]: `out[q] = min_p (q - p)^2 + f[p]`, via the
+/// lower envelope of the parabolas rooted at each `p`.
+fn squared_distance_1d(f: &[f64], out: &mut [f64]) {
+    let n = f.len();
+    if n == 0 {
+        return;
+    }
+    let mut roots = vec![0usize; n];
+    let mut bounds = vec![0f64; n + 1];
+    let intersect = |q: usize, p: usize| ((f[q] + (q * q) as f64) - (f[p] + (p * p) as f64)) / (2.0 * (q as f64 - p as f64));
+    let mut k = 0;
+    bounds[0] = f64::NEG_INFINITY;
+    bounds[1] = f64::INFINITY;
+    for q in 1..n {
+        let mut s = intersect(q, roots[k]);
+        while s <= bounds[k] {
+            k -= 1;
+            s = intersect(q, roots[k]);
+        }
+        k += 1;
+        roots[k] = q;
+        bounds[k] = s;
+        bounds[k + 1] = f64::INFINITY;
+    }
+    k = 0;
+    for (q, value) in out.iter_mut().enumerate() {
+        while bounds[k + 1] < q as f64 {
+            k += 1;
+        }
+        let offset = q as f64 - roots[k] as f64;
+        *value = offset * offset + f[roots[k]];
+    }
+}
+
+/// Separable box blur with edge clamping. Each pass keeps a running window sum, so the cost does
+/// not grow with `radius`.
+fn box_blur(values: &[f32], w: usize, h: usize, radius: usize) -> Vec<f32> {
+    if radius == 0 || w == 0 || h == 0 {
+        return values.to_vec();
+    }
+    let mut horizontal = vec![0f32; values.len()];
+    for y in 0..h {
+        box_blur_line(&values[y * w..(y + 1) * w], radius, &mut horizontal[y * w..(y + 1) * w]);
+    }
+    let mut out = vec![0f32; values.len()];
+    let (mut column, mut blurred) = (vec![0f32; h], vec![0f32; h]);
+    for x in 0..w {
+        for y in 0..h {
+            column[y] = horizontal[y * w + x];
+        }
+        box_blur_line(&column, radius, &mut blurred);
+        for y in 0..h {
+            out[y * w + x] = blurred[y];
+        }
+    }
+    out
+}
+
+/// Mean of the `2 * radius + 1` samples centred on each index, repeating the end samples past
+/// either edge.
+fn box_blur_line(line: &[f32], radius: usize, out: &mut [f32]) {
+    let last = line.len() - 1;
+    let at = |i: usize| line[i.min(last)] as f64;
+    let window = (2 * radius + 1) as f64;
+    let mut sum = at(0) * (radius + 1) as f64 + (1..=radius).map(at).sum::<f64>();
+    for (x, value) in out.iter_mut().enumerate() {
+        *value = (sum / window) as f32;
+        sum += at(x + radius + 1) - at(x.saturating_sub(radius));
+    }
+}
+
+/// Softens a mask with a separable Gaussian blur of standard deviation `radius` pixels. Samples
+/// past the border repeat the edge pixel, so opposite edges never bleed into each other. The
+/// radius is capped at the mask's longer side, which keeps the kernel bounded.
+pub fn feather_mask(mask: &GrayImage, radius: f32) -> GrayImage {
+    let (w, h) = (mask.width() as usize, mask.height() as usize);
+    if radius.is_nan() || radius <= 0.0 || w == 0 || h == 0 {
+        return mask.clone();
+    }
+    let radius = radius.min(w.max(h) as f32);
+    let reach = (radius * 3.0).ceil() as usize;
+    let mut kernel: Vec<f32> = (0..=2 * reach)
+        .map(|i| {
+            let d = i as f32 - reach as f32;
+            (-d * d / (2.0 * radius * radius)).exp()
+        })
+        .collect();
+    let total: f32 = kernel.iter().sum();
+    kernel.iter_mut().for_each(|k| *k /= total);
+
+    let values: Vec<f32> = mask.as_raw().iter().map(|&v| v as f32).collect();
+    let mut horizontal = vec![0f32; values.len()];
+    for y in 0..h {
+        for x in 0..w {
+            horizontal[y * w + x] = kernel
+                .iter()
+                .enumerate()
+                .map(|(i, k)| k * values[y * w + (x + i).saturating_sub(reach).min(w - 1)])
+                .sum();
+        }
+    }
+    GrayImage::from_fn(w as u32, h as u32, |x, y| {
+        let (x, y) = (x as usize, y as usize);
+        let v: f32 = kernel
+            .iter()
+            .enumerate()
+            .map(|(i, k)| k * horizontal[(y + i).saturating_sub(reach).min(h - 1) * w + x])
+            .sum();
+        image::Luma([v.round().clamp(0.0, 255.0) as u8])
+    })
+}
+
+/// Straight-alpha "over": `top` drawn on `bottom`.
+fn composite_over(top: &RgbaImage, bottom: &RgbaImage) -> RgbaImage {
+    RgbaImage::from_fn(top.width(), top.height(), |x, y| {
+        let t = top.get_pixel(x, y);
+        let b = bottom.get_pixel(x, y);
+        let ta = t[3] as f32 / 255.0;
+        let ba = b[3] as f32 / 255.0;
+        let out_a = ta + ba * (1.0 - ta);
+        if out_a <= 0.0 {
+            return Rgba([0, 0, 0, 0]);
+        }
+        let mut px = [0u8; 4];
+        for c in 0..3 {
+            let v = (t[c] as f32 * ta + b[c] as f32 * ba * (1.0 - ta)) / out_a;
+            px[c] = v.round().clamp(0.0, 255.0) as u8;
+        }
+        px[3] = (out_a * 255.0).round() as u8;
+        Rgba(px)
+    })
+}
+
+/// A tile of a larger image, in full-resolution pixel coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TileRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Splits `width` x `height` into `tile_size` tiles in row-major order; edge tiles are clipped.
+pub fn tile_grid(width: u32, height: u32, tile_size: u32) -> Vec<TileRect> {
+    let tile_size = tile_size.max(1);
+    let mut tiles = Vec::new();
+    for y in (0..height).step_by(tile_size as usize) {
+        for x in (0..width).step_by(tile_size as usize) {
+            tiles.push(TileRect {
+                x,
+                y,
+                width: tile_size.min(width - x),
+                height: tile_size.min(height - y),
+            });
+        }
+    }
+    tiles
+}
+
+/// Hands each tile of the mask produced by `alpha_for` to `sink` as soon as it is computed,
+/// so callers can write or composite progressively instead of holding the whole mask.
+///
+/// Tiles are delivered in [`tile_grid`] order; the first error from either callback stops the stream.
+pub fn stream_mask_tiles<E>(
+    width: u32,
+    height: u32,
+    tile_size: u32,
+    mut alpha_for: impl FnMut(TileRect) -> Result<GrayImage, E>,
+    mut sink: impl FnMut(TileRect, &GrayImage) -> Result<(), E>,
+) -> Result<(), E> {
+    for tile in tile_grid(width, height, tile_size) {
+        let alpha = alpha_for(tile)?;
+        sink(tile, &alpha)?;
+    }
+    Ok(())
+}
+
+/// The `tile` of `mask` bilinearly scaled to `width` x `height`, computed without
+/// materializing the rest of the scaled mask. Tiles of one grid join without seams.
+pub fn upscale_mask_tile(mask: &GrayImage, width: u32, height: u32, tile: TileRect) -> GrayImage {
+    let (src_w, src_h) = mask.dimensions();
+    let scale_x = src_w as f32 / width.max(1) as f32;
+    let scale_y = src_h as f32 / height.max(1) as f32;
+    // Pixel centers map onto each other; edges clamp to the nearest source pixel.
+    let sample = |pos: u32, scale: f32, len: u32| {
+        let at = ((pos as f32 + 0.5) * scale - 0.5).clamp(0.0, len.saturating_sub(1) as f32);
+        let lo = at.floor() as u32;
+        (lo, (lo + 1).min(len.saturating_sub(1)), at - lo as f32)
+    };
+    GrayImage::from_fn(tile.width, tile.height, |x, y| {
+        let (x0, x1, fx) = sample(tile.x + x, scale_x, src_w);
+        let (y0, y1, fy) = sample(tile.y + y, scale_y, src_h);
+        let at = |x, y| mask.get_pixel(x, y)[0] as f32;
+        let top = at(x0, y0) * (1.0 - fx) + at(x1, y0) * fx;
+        let bottom = at(x0, y1) * (1.0 - fx) + at(x1, y1) * fx;
+        image::Luma([(top * (1.0 - fy) + bottom * fy).round() as u8])
+    })
+}
+
+/// Tiles groups of images (e.g. original, mask, cutout) into one review sheet.
+///
+/// Each group fills consecutive `cell_size` square cells, and `columns` groups share a row.
+/// Images are shrunk to fit their cell, keeping their aspect ratio, and centered; the rest of
+/// the sheet is transparent.
+pub fn contact_sheet(groups: &[Vec<RgbaImage>], columns: u32, cell_size: u32) -> RgbaImage {
+    let columns = columns.max(1);
+    let cell_size = cell_size.max(1);
+    let cells_per_group = groups.iter().map(|group| group.len() as u32).max().unwrap_or(0);
+    let rows = (groups.len() as u32).div_ceil(columns);
+    let mut sheet = RgbaImage::new(columns * cells_per_group * cell_size, rows * cell_size);
+    for (index, group) in groups.iter().enumerate() {
+        let (row, column) = (index as u32 / columns, index as u32 % columns);
+        for (cell, img) in group.iter().enumerate() {
+            let fitted = clamp_to_max_dimension(
+                ImageSize {
+                    width: img.width(),
+                    height: img.height(),
+                },
+                cell_size,
+            );
+            let thumb = image::imageops::resize(img, fitted.width, fitted.height, image::imageops::FilterType::Triangle);
+            let x = (column * cells_per_group + cell as u32) * cell_size + (cell_size - thumb.width()) / 2;
+            let y = row * cell_size + (cell_size - thumb.height()) / 2;
+            image::imageops::replace(&mut sheet, &thumb, x as i64, y as i64);
+        }
+    }
+    sheet
+}
+
+const PNG_SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum PngTextError {
+    #[error("not a PNG file")]
+    NotPng,
+    #[error("truncated PNG chunk")]
+    Truncated,
+    #[error("PNG text keyword must be 1-79 printable Latin-1 characters (received: '{0}')")]
+    InvalidKeyword(String),
+    #[error("failed to re-encode PNG: {0}")]
+    Codec(String),
+}
+
+/// Re-encodes `png` with `entries` as text chunks: `tEXt` for ASCII values, uncompressed `iTXt`
+/// otherwise. Color type, bit depth, palette and pixels are carried over unchanged; only the
+/// compression follows `options`.
+pub fn embed_png_text(png: &[u8], entries: &[(String, String)], options: &EncodeOptions) -> Result<Vec<u8>, PngTextError> {
+    if !png.starts_with(PNG_SIGNATURE) {
+        return Err(PngTextError::NotPng);
+    }
+    let codec = |err: &dyn std::fmt::Display| PngTextError::Codec(err.to_string());
+    let mut reader = png::Decoder::new(std::io::Cursor::new(png)).read_info().map_err(|e| codec(&e))?;
+    let mut pixels = vec![0; reader.output_buffer_size().ok_or_else(|| codec(&"image too large"))?];
+    reader.next_frame(&mut pixels).map_err(|e| codec(&e))?;
+    let info = reader.info();
+
+    let mut out = Vec::with_capacity(png.len() + entries.len() * 64);
+    let mut encoder = new_png_encoder(&mut out, info.width, info.height, options).map_err(|e| codec(&e))?;
+    encoder.set_color(info.color_type);
+    encoder.set_depth(info.bit_depth);
+    if let Some(palette) = &info.palette {
+        encoder.set_palette(palette.to_vec());
+    }
+    if let Some(trns) = &info.trns {
+        encoder.set_trns(trns.to_vec());
+    }
+    configure_png_encoder(&mut encoder, entries, options)?;
+    let mut writer = encoder.write_header().map_err(|e| codec(&e))?;
+    writer.write_image_data(&pixels).map_err(|e| codec(&e))?;
+    writer.finish().map_err(|e| codec(&e))?;
+    Ok(out)
+}
+
+/// Creates a PNG encoder for a `width`x`height` image carrying `options`' ICC profile;
+/// compression and text are set afterwards by [`configure_png_encoder`].
+fn new_png_encoder<W: Write>(out: W, width: u32, height: u32, options: &EncodeOptions) -> Result<png::Encoder<'static, W>, png::EncodingError> {
+    let mut info = png::Info::with_size(width, height);
+    info.icc_profile = options.icc_profile.clone().map(std::borrow::Cow::Owned);
+    png::Encoder::with_info(out, info)
+}
+
+/// Applies `options`' compression and adds `text` to a PNG encoder before its header is written.
+fn configure_png_encoder<W: Write>(
+    encoder: &mut png::Encoder<'_, W>,
+    text: &[(String, String)],
+    options: &EncodeOptions,
+) -> Result<(), PngTextError> {
+    match options.png_compression {
+        Some(0) => encoder.set_compression(png::Compression::NoCompression),
+        Some(level) => encoder.set_deflate_compression(png::DeflateCompression::Level(level.min(9))),
+        None => encoder.set_compression(png::Compression::Fast),
+    }
+    encoder.set_filter(png::Filter::Adaptive);
+    for (keyword, value) in text {
+        validate_png_keyword(keyword)?;
+        let added = if value.is_ascii() {
+            encoder.add_text_chunk(keyword.clone(), value.clone())
+        } else {
+            encoder.add_itxt_chunk(keyword.clone(), value.clone())
+        };
+        added.map_err(|e| PngTextError::Codec(e.to_string()))?;
+    }
+    Ok(())
+}
+
+fn validate_png_keyword(keyword: &str) -> Result<(), PngTextError> {
+    if keyword.is_empty() || keyword.len() > 79 || !keyword.bytes().all(|b| (0x20..=0x7e).contains(&b)) {
+        return Err(PngTextError::InvalidKeyword(keyword.to_string()));
+    }
+    Ok(())
+}
+
+/// Reads `tEXt` and uncompressed `iTXt` chunks as `(keyword, text)` pairs, in file order.
+pub fn read_png_text(png: &[u8]) -> Result<Vec<(String, String)>, PngTextError> {
+    let mut entries = Vec::new();
+    for chunk in png_chunks(png)? {
+        let data = &png[chunk.data.clone()];
+        let Some(split) = data.iter().position(|&b| b == 0) else {
+            continue;
+        };
+        let keyword = data[..split].iter().map(|&b| b as char).collect::<String>();
+        let rest = &data[split + 1..];
+        match &chunk.kind {
+            b"tEXt" => entries.push((keyword, rest.iter().map(|&b| b as char).collect())),
+            b"iTXt" if rest.len() >= 2 && rest[0] == 0 => {
+                let mut fields = rest[2..].splitn(3, |&b| b == 0);
+                let (_language, _translated) = (fields.next(), fields.next());
+                if let Some(text) = fields.next() {
+                    entries.push((keyword, String::from_utf8_lossy(text).into_owned()));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(entries)
+}
+
+struct PngChunk {
+    kind: [u8; 4],
+    data: std::ops::Range<usize>,
+}
+
+fn png_chunks(png: &[u8]) -> Result<Vec<PngChunk>, PngTextError> {
+    if !png.starts_with(PNG_SIGNATURE) {
+        return Err(PngTextError::NotPng);
+    }
+    let mut chunks = Vec::new();
+    let mut offset = PNG_SIGNATURE.len();
+    while offset < png.len() {
+        let header = png.get(offset..offset + 8).ok_or(PngTextError::Truncated)?;
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let kind = [header[4], header[5], header[6], header[7]];
+        let data = offset + 8..offset + 8 + len;
+        let end = data.end + 4;
+        if end > png.len() {
+            return Err(PngTextError::Truncated);
+        }
+        chunks.push(PngChunk { kind, data });
+        offset = end;
+        if &kind == b"IEND" {
+            break;
+        }
+    }
+    Ok(chunks)
+}
+
+/// Output encoder settings; the default reproduces the `image` crate's own defaults.
+///
+/// Encoders here write pixels only, so no EXIF, GPS, camera or text metadata from a source
+/// ever reaches an output; the ICC profile is the one thing a caller can opt back in.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EncodeOptions {
+    /// zlib level for PNG output, 0 (stored) to 9 (smallest); `None` uses the fast default.
+    pub png_compression: Option<u8>,
+    /// JPEG quality, 1 to 100; `None` uses the encoder default of 75. WebP output is always
+    /// lossless with the bundled encoder, so it ignores this.
+    pub quality: Option<u8>,
+    /// ICC color profile to embed, e.g. the source's from [`read_icc_profile`]; `None` writes none.
+    pub icc_profile: Option<Vec<u8>>,
+}
+
+/// The ICC color profile embedded in an encoded image, if its format carries one.
+pub fn read_icc_profile(bytes: &[u8]) -> Option<Vec<u8>> {
+    use image::ImageDecoder;
+    let format = sniff_format(bytes).image_format()?;
+    let mut decoder = image::ImageReader::with_format(std::io::Cursor::new(bytes), format).into_decoder().ok()?;
+    decoder.icc_profile().ok().flatten()
+}
+
+/// Whether the EXIF orientation tag asks for the pixels to be rotated or flipped, i.e. whether
+/// dropping the tag on output would change how the image displays.
+pub fn has_exif_rotation(bytes: &[u8]) -> bool {
+    sniff_format(bytes)
+        .image_format()
+        .and_then(|format| exif_orientation(bytes, format))
+        .is_some_and(|orientation| orientation != image::metadata::Orientation::NoTransforms)
+}
+
+/// Encodes `img` as `format` with `options`. JPEG output drops the alpha channel.
+pub fn encode_image(img: &DynamicImage, format: ImageFormat, options: &EncodeOptions) -> Result<Vec<u8>, image::ImageError> {
+    use image::codecs::jpeg::JpegEncoder;
+    use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+
+    let mut out = Vec::new();
+    match format {
+        ImageFormat::Png => {
+            let compression = match options.png_compression {
+                Some(0) => CompressionType::Uncompressed,
+                Some(level) => CompressionType::Level(level.min(9)),
+                None => CompressionType::default(),
+            };
+            let mut encoder = PngEncoder::new_with_quality(&mut out, compression, FilterType::default());
+            set_icc_profile(&mut encoder, options)?;
+            img.write_with_encoder(encoder)?;
+        }
+        ImageFormat::Jpeg => {
+            let quality = options.quality.unwrap_or(75).clamp(1, 100);
+            let mut encoder = JpegEncoder::new_with_quality(&mut out, quality);
+            set_icc_profile(&mut encoder, options)?;
+            DynamicImage::ImageRgb8(img.to_rgb8()).write_with_encoder(encoder)?;
+        }
+        #[cfg(feature = "webp")]
+        ImageFormat::WebP => {
+            let mut encoder = image::codecs::webp::WebPEncoder::new_lossless(&mut out);
+            set_icc_profile(&mut encoder, options)?;
+            img.write_with_encoder(encoder)?;
+        }
+        other => img.write_to(&mut std::io::Cursor::new(&mut out), other)?,
+    }
+    Ok(out)
+}
+
+fn set_icc_profile(encoder: &mut impl image::ImageEncoder, options: &EncodeOptions) -> Result<(), image::ImageError> {
+    if let Some(profile) = &options.icc_profile {
+        encoder.set_icc_profile(profile.clone()).map_err(image::ImageError::Unsupported)?;
+    }
+    Ok(())
+}
+
+/// Flattens a straight-alpha pixel onto an opaque `background`.
+pub fn flatten_onto(px: &mut Rgba<u8>, background: [u8; 3]) {
+    let alpha = px[3] as f32 / 255.0;
+    for c in 0..3 {
+        px[c] = (px[c] as f32 * alpha + background[c] as f32 * (1.0 - alpha)).round() as u8;
+    }
+    px[3] = 255;
+}
+
+#[derive(Debug, Error)]
+pub enum CutoutStreamError {
+    #[error("streaming cutouts are not supported for this input: {0}")]
+    Unsupported(String),
+    #[error("mask is {mask_width}x{mask_height} but the source is {width}x{height}")]
+    DimensionMismatch {
+        width: u32,
+        height: u32,
+        mask_width: u32,
+        mask_height: u32,
+    },
+    #[error("failed to decode PNG: {0}")]
+    Decode(String),
+    #[error("failed to encode PNG: {0}")]
+    Encode(String),
+    #[error(transparent)]
+    Text(#[from] PngTextError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Writes a cutout row by row from a PNG source and mask, so peak memory stays at a few rows
+/// regardless of image size. Output bytes match [`encode_rgba_png`] on the in-memory cutout.
+///
+/// Only 8-bit, non-interlaced sources and grayscale masks are streamable; anything else is
+/// reported as [`CutoutStreamError::Unsupported`] before any output is written.
+pub struct CutoutStream<R: BufRead + Seek, M: BufRead + Seek> {
+    source: png::Reader<R>,
+    mask: png::Reader<M>,
+    width: u32,
+    height: u32,
+}
+
+impl<R: BufRead + Seek, M: BufRead + Seek> CutoutStream<R, M> {
+    pub fn new(source: R, mask: M) -> Result<Self, CutoutStreamError> {
+        let source = open_png_rows(source, "source")?;
+        let mask = open_png_rows(mask, "mask")?;
+        let (width, height) = (source.info().width, source.info().height);
+        let (mask_width, mask_height) = (mask.info().width, mask.info().height);
+        if (width, height) != (mask_width, mask_height) {
+            return Err(CutoutStreamError::DimensionMismatch {
+                width,
+                height,
+                mask_width,
+                mask_height,
+            });
+        }
+        if !matches!(mask.output_color_type().0, png::ColorType::Grayscale | png::ColorType::GrayscaleAlpha) {
+            return Err(CutoutStreamError::Unsupported("mask is not grayscale".to_string()));
+        }
+        Ok(Self {
+            source,
+            mask,
+            width,
+            height,
+        })
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Applies the mask as alpha to each source row, optionally flattens onto `background`,
+    /// and writes the encoded PNG with `text` chunks to `out`.
+    pub fn write_png<W: Write>(
+        mut self,
+        background: Option<[u8; 3]>,
+        text: &[(String, String)],
+        options: &EncodeOptions,
+        out: W,
+    ) -> Result<(), CutoutStreamError> {
+        let source_channels = self.source.output_color_type().0.samples();
+        let mask_channels = self.mask.output_color_type().0.samples();
+        let mut line = vec![0u8; self.width as usize * 4];
+        write_rgba_png(out, self.width, self.height, text, options, |writer| {
+            for _ in 0..self.height {
+                let source_row = self.source.next_row().map_err(decode_error)?.ok_or_else(truncated_rows)?;
+                let mask_row = self.mask.next_row().map_err(decode_error)?.ok_or_else(truncated_rows)?;
+                for (x, px) in line.chunks_exact_mut(4).enumerate() {
+                    let src = &source_row.data()[x * source_channels..][..source_channels];
+                    let mut rgba = match src {
+                        [l] => Rgba([*l, *l, *l, 255]),
+                        [l, a] => Rgba([*l, *l, *l, *a]),
+                        [r, g, b] => Rgba([*r, *g, *b, 255]),
+                        _ => Rgba([src[0], src[1], src[2], src[3]]),
+                    };
+                    rgba[3] = mask_row.data()[x * mask_channels];
+                    if let Some(color) = background {
+                        flatten_onto(&mut rgba, color);
+                    }
+                    px.copy_from_slice(&rgba.0);
+                }
+                writer.write_all(&line)?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Encodes `img` with the PNG settings [`CutoutStream`] uses, so both cutout paths produce the
+/// same bytes for the same pixels.
+pub fn encode_rgba_png<W: Write>(
+    img: &RgbaImage,
+    text: &[(String, String)],
+    options: &EncodeOptions,
+    out: W,
+) -> Result<(), CutoutStreamError> {
+    write_rgba_png(out, img.width(), img.height(), text, options, |writer| {
+        for row in img.as_raw().chunks_exact(img.width().max(1) as usize * 4) {
+            writer.write_all(row)?;
+        }
+        Ok(())
+    })
+}
+
+#[derive(Debug, Error)]
+pub enum CutoutError {
+    #[error("failed to decode source image: {0}")]
+    Source(#[from] DecodeError),
+    #[error("failed to decode mask: {0}")]
+    Mask(#[from] image::ImageError),
+    #[error("failed to decode background image: {0}")]
+    Background(DecodeError),
+    #[error(transparent)]
+    Encode(#[from] CutoutStreamError),
+}
+
+/// Decodes a source image, within the default [`DecodeLimits`], and its mask PNG. A mask of a
+/// different size is resized to the source dimensions rather than rejected.
+pub fn decode_source_and_mask(source_bytes: &[u8], mask_png: &[u8]) -> Result<(RgbaImage, GrayImage), CutoutError> {
+    let source = safe_decode(source_bytes, &DecodeHints::default(), &DecodeLimits::default())?.to_rgba8();
+    let mut mask = image::load_from_memory(mask_png)?.to_luma8();
+    if mask.dimensions() != source.dimensions() {
+        mask = image::imageops::resize(&mask, source.width(), source.height(), image::imageops::FilterType::Triangle);
+    }
+    Ok((source, mask))
+}
+
+/// Copies `mask` into the alpha channel of `img`; both must have the same dimensions.
+pub fn set_alpha_from_mask(img: &mut RgbaImage, mask: &GrayImage) {
+    for (x, y, px) in img.enumerate_pixels_mut() {
+        px[3] = mask.get_pixel(x, y)[0];
+    }
+}
+
+/// Applies `mask_png` as the alpha channel of the decoded source and returns the cutout as a
+/// PNG, encoded the same way as [`encode_rgba_png`] with default options.
+pub fn apply_mask_to_rgba(source_bytes: &[u8], mask_png: &[u8]) -> Result<Vec<u8>, CutoutError> {
+    composite_png(source_bytes, mask_png, &Backdrop::Transparent, &EncodeOptions::default())
+}
+
+/// Like [`apply_mask_to_rgba`], but composites the cutout over `fill` and encodes it with `options`.
+pub fn composite_png(
+    source_bytes: &[u8],
+    mask_png: &[u8],
+    fill: &Backdrop<'_>,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, CutoutError> {
+    let (source, mask) = decode_source_and_mask(source_bytes, mask_png)?;
+    let composite = composite_over_background(&source, &mask, fill)?;
+    let mut out = Vec::new();
+    encode_rgba_png(&composite, &[], options, &mut out)?;
+    Ok(out)
+}
+
+/// What shows through where the mask is transparent, for [`composite_over_background`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backdrop<'a> {
+    #[default]
+    Transparent,
+    /// Straight RGBA; an alpha below 255 leaves the result partly transparent.
+    Color([u8; 4]),
+    /// Encoded image bytes, resized to the source dimensions.
+    Image(&'a [u8]),
+}
+
+/// Blends `source` over `fill` using `mask` as the foreground alpha (source-over with straight
+/// alpha). An image fill is decoded under [`DecodeLimits::default`], like untrusted inputs, and
+/// resized to the source dimensions first. Over an opaque color this matches [`flatten_onto`] exactly.
+pub fn composite_over_background(source: &RgbaImage, mask: &GrayImage, fill: &Backdrop<'_>) -> Result<RgbaImage, CutoutError> {
+    let mut out = source.clone();
+    set_alpha_from_mask(&mut out, mask);
+    match fill {
+        Backdrop::Transparent => {}
+        Backdrop::Color(color) => {
+            for px in out.pixels_mut() {
+                blend_over(px, *color);
+            }
+        }
+        Backdrop::Image(bytes) => {
+            let mut background = safe_decode(bytes, &DecodeHints::default(), &DecodeLimits::default())
+                .map_err(CutoutError::Background)?
+                .to_rgba8();
+            if background.dimensions() != out.dimensions() {
+                background = image::imageops::resize(&background, out.width(), out.height(), image::imageops::FilterType::Triangle);
+            }
+            for (px, behind) in out.pixels_mut().zip(background.pixels()) {
+                blend_over(px, behind.0);
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn blend_over(px: &mut Rgba<u8>, background: [u8; 4]) {
+    if background[3] == 255 {
+        return flatten_onto(px, [background[0], background[1], background[2]]);
+    }
+    let alpha = px[3] as f32 / 255.0;
+    let behind = background[3] as f32 / 255.0 * (1.0 - alpha);
+    let out_alpha = alpha + behind;
+    for c in 0..3 {
+        px[c] = if out_alpha > 0.0 {
+            ((px[c] as f32 * alpha + background[c] as f32 * behind) / out_alpha).round() as u8
+        } else {
+            0
+        };
+    }
+    px[3] = (out_alpha * 255.0).round() as u8;
+}
+
+/// Re-encodes an image as a PNG holding only its pixels: EXIF orientation is applied first, and
+/// no EXIF, GPS, camera, ICC or text metadata is carried over.
+pub fn strip_metadata(bytes: &[u8], hints: &DecodeHints) -> Result<Vec<u8>, CutoutError> {
+    let hints = DecodeHints {
+        apply_orientation: true,
+        ..hints.clone()
+    };
+    let image = decode_image(bytes, &hints)?.to_rgba8();
+    let mut out = Vec::new();
+    encode_rgba_png(&image, &[], &EncodeOptions::default(), &mut out)?;
+    Ok(out)
+}
+
+fn open_png_rows<T: BufRead + Seek>(input: T, role: &str) -> Result<png::Reader<T>, CutoutStreamError> {
+    let mut decoder = png::Decoder::new(input);
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let reader = decoder.read_info().map_err(decode_error)?;
+    let info = reader.info();
+    if info.interlaced {
+        return Err(CutoutStreamError::Unsupported(format!("{role} PNG is interlaced")));
+    }
+    if reader.output_color_type().1 != png::BitDepth::Eight {
+        return Err(CutoutStreamError::Unsupported(format!("{role} PNG is not 8 bits per channel")));
+    }
+    Ok(reader)
+}
+
+fn write_rgba_png<W: Write>(
+    out: W,
+    width: u32,
+    height: u32,
+    text: &[(String, String)],
+    options: &EncodeOptions,
+    write_rows: impl FnOnce(&mut png::StreamWriter<'_, W>) -> Result<(), CutoutStreamError>,
+) -> Result<(), CutoutStreamError> {
+    let mut encoder = new_png_encoder(out, width, height, options).map_err(encode_error)?;
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    configure_png_encoder(&mut encoder, text, options)?;
+    let mut writer = encoder.write_header().map_err(encode_error)?;
+    let mut rows = writer.stream_writer().map_err(encode_error)?;
+    write_rows(&mut rows)?;
+    rows.finish().map_err(encode_error)?;
+    writer.finish().map_err(encode_error)
+}
+
+fn decode_error(err: png::DecodingError) -> CutoutStreamError {
+    CutoutStreamError::Decode(err.to_string())
+}
+
+fn encode_error(err: png::EncodingError) -> CutoutStreamError {
+    CutoutStreamError::Encode(err.to_string())
+}
+
+fn truncated_rows() -> CutoutStreamError {
+    CutoutStreamError::Decode("image data ended before the last row".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png_bytes(image: &DynamicImage) -> Vec<u8> {
+        let mut png = Vec::new();
+        image.write_to(&mut std::io::Cursor::new(&mut png), ImageFormat::Png).unwrap();
+        png
+    }
+
+    #[test]
+    fn clamped_size_preserves_aspect_ratio() {
+        let size = ImageSize {
+            width: 6000,
+            height: 4000,
+        };
+        let clamped = clamp_to_max_pixels(size, 2_000_000);
+        assert!(clamped.width.saturating_mul(clamped.height) <= 2_000_000);
+        let original = size.width as f32 / size.height as f32;
+        let result = clamped.width as f32 / clamped.height as f32;
+        assert!((original - result).abs() < 0.01, "aspect {} vs {}", original, result);
+        assert_eq!(estimate_rgba_bytes(size), 6000 * 4000 * 4);
+
+        let capped = clamp_to_max_dimension(size, 1500);
+        assert_eq!((capped.width, capped.height), (1500, 1000));
+        let small = clamp_to_max_dimension(size, 8000);
+        assert_eq!((small.width, small.height), (6000, 4000));
+    }
+
+    #[test]
+    fn decodes_png_and_sniffs_modern_formats() {
+        let png = png_bytes(&DynamicImage::new_rgb8(3, 2));
+        let decoded = decode_image(&png, &DecodeHints::default()).expect("decode png");
+        assert_eq!((decoded.width(), decoded.height()), (3, 2));
+
+        assert_eq!(sniff_format(b"\0\0\0\x1cftypavif\0\0\0\0"), SourceFormat::Avif);
+        assert_eq!(sniff_format(b"\0\0\0\x18ftypheic\0\0\0\0"), SourceFormat::Heic);
+        assert!(matches!(
+            decode_image(b"not an image", &DecodeHints::default()),
+            Err(DecodeError::UnknownFormat)
+        ));
+    }
+
+    #[cfg(not(feature = "jxl"))]
+    #[test]
+    fn unsupported_format_error_names_enabling_feature() {
+        let err = decode_image(&[0xFF, 0x0A, 0x00, 0x00], &DecodeHints::default()).unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("JPEG XL"), "{}", message);
+        assert!(message.contains("`jxl` feature"), "{}", message);
+    }
+
+    fn square_cutout() -> RgbaImage {
+        RgbaImage::from_fn(20, 20, |x, y| {
+            if (5..15).contains(&x) && (5..15).contains(&y) {
+                Rgba([10, 200, 10, 255])
+            } else {
+                Rgba([0, 0, 0, 0])
+            }
+        })
+    }
+
+    #[test]
+    fn stroke_colors_pixels_just_outside_the_subject() {
+        let out = apply_effects(
+            &square_cutout(),
+            &[Effect::Stroke {
+                width: 2,
+                color: [255, 0, 0],
+            }],
+        );
+        assert_eq!(out.get_pixel(4, 10).0, [255, 0, 0, 255]);
+        assert_eq!(out.get_pixel(3, 10).0, [255, 0, 0, 255]);
+        assert_eq!(out.get_pixel(1, 10)[3], 0);
+        assert_eq!(out.get_pixel(10, 10).0, [10, 200, 10, 255]);
+    }
+
+    #[test]
+    fn blur_and_stroke_handle_wide_radii() {
+        let values: Vec<f32> = (0..7 * 5).map(|i| ((i * 37) % 11) as f32 / 10.0).collect();
+        for radius in [1, 2, 9] {
+            let blurred = box_blur(&values, 7, 5, radius);
+            // Reference: the clamped window mean taken directly.
+            let clamp = |v: isize, n: usize| v.clamp(0, n as isize - 1) as usize;
+            let r = radius as isize;
+            let horizontal: Vec<f32> = (0..7 * 5)
+                .map(|i| (-r..=r).map(|d| values[i / 7 * 7 + clamp((i % 7) as isize + d, 7)]).sum::<f32>() / (2 * r + 1) as f32)
+                .collect();
+            for (i, &got) in blurred.iter().enumerate() {
+                let want = (-r..=r).map(|d| horizontal[clamp((i / 7) as isize + d, 5) * 7 + i % 7]).sum::<f32>() / (2 * r + 1) as f32;
+                assert!((got - want).abs() < 1e-5, "radius {} index {}: {} vs {}", radius, i, got, want);
+            }
+        }
+
+        // A dot stroked 300 pixels wide: a disk, reached without a per-pixel window scan.
+        let mut dot = RgbaImage::new(1000, 1000);
+        dot.put_pixel(500, 500, Rgba([0, 0, 0, 255]));
+        let stroke = stroke_layer(&dot, 300, [255, 0, 0]);
+        assert_eq!(stroke.get_pixel(500, 200)[3], 255);
+        assert_eq!(stroke.get_pixel(712, 712)[3], 255);
+        assert_eq!(stroke.get_pixel(500, 198)[3], 0);
+        assert_eq!(stroke.get_pixel(0, 0)[3], 0);
+        let blurred = box_blur(&alpha_plane(&dot), 1000, 1000, 300);
+        assert!((blurred[500 * 1000 + 500] - 1.0 / (601.0 * 601.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn shadow_adds_translucent_pixels_offset_from_the_subject() {
+        let out = apply_effects(
+            &square_cutout(),
+            &[Effect::Shadow {
+                blur: 1,
+                offset: (3, 3),
+                opacity: 0.5,
+                color: [0, 0, 0],
+            }],
+        );
+        let shadow = out.get_pixel(16, 16);
+        assert!(shadow[3] > 0 && shadow[3] < 255, "alpha {}", shadow[3]);
+        assert_eq!(out.get_pixel(2, 2)[3], 0);
+        assert_eq!(out.get_pixel(10, 10).0, [10, 200, 10, 255]);
+    }
+
+    fn crc32(data: &[u8]) -> u32 {
+        let mut crc = 0xFFFF_FFFFu32;
+        for &byte in data {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            }
+        }
+        !crc
+    }
+
+    #[test]
+    fn safe_decode_rejects_header_claiming_huge_dimensions() {
+        let mut png = png_bytes(&DynamicImage::ImageRgb8(image::RgbImage::new(1, 1)));
+        // IHDR data starts after the 8-byte signature, 4-byte length and 4-byte chunk type.
+        png[16..20].copy_from_slice(&100_000u32.to_be_bytes());
+        png[20..24].copy_from_slice(&100_000u32.to_be_bytes());
+        let crc = crc32(&png[12..29]);
+        png[29..33].copy_from_slice(&crc.to_be_bytes());
+        // Only the header is read, so the claimed size costs nothing to report.
+        assert_eq!(image_dimensions(&png, &DecodeHints::default()).unwrap(), (100_000, 100_000));
+        assert!(matches!(
+            image_dimensions(b"not an image", &DecodeHints::default()),
+            Err(DecodeError::UnknownFormat)
+        ));
+
+        let err = safe_decode(&png, &DecodeHints::default(), &DecodeLimits::default()).unwrap_err();
+        match err {
+            DecodeError::TooManyPixels {
+                width,
+                height,
+                max_pixels,
+                ..
+            } => {
+                assert_eq!((width, height), (100_000, 100_000));
+                assert_eq!(max_pixels, DecodeLimits::default().max_pixels);
+            }
+            other => panic!("expected pixel limit error, got {other}"),
+        }
+
+        let tight = DecodeLimits {
+            max_pixels: u64::MAX,
+            max_alloc: 1024,
+        };
+        assert!(matches!(
+            safe_decode(&png, &DecodeHints::default(), &tight),
+            Err(DecodeError::AllocationLimit { .. })
+        ));
+
+        // The cutout path decodes its source within the same limits.
+        assert!(matches!(
+            decode_source_and_mask(&png, &png),
+            Err(CutoutError::Source(DecodeError::TooManyPixels { .. }))
+        ));
+    }
+
+    #[test]
+    fn mask_tiles_stream_in_row_major_order_with_clipped_edges() {
+        let mut delivered = Vec::new();
+        stream_mask_tiles(
+            5,
+            3,
+            2,
+            |tile| Ok::<_, ()>(GrayImage::from_pixel(tile.width, tile.height, image::Luma([(tile.x + tile.y * 10) as u8]))),
+            |tile, alpha| {
+                assert_eq!(alpha.dimensions(), (tile.width, tile.height));
+                delivered.push((tile.x, tile.y, tile.width, tile.height, alpha.get_pixel(0, 0)[0]));
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            delivered,
+            vec![
+                (0, 0, 2, 2, 0),
+                (2, 0, 2, 2, 2),
+                (4, 0, 1, 2, 4),
+                (0, 2, 2, 1, 20),
+                (2, 2, 2, 1, 22),
+                (4, 2, 1, 1, 24),
+            ]
+        );
+    }
+
+    #[test]
+    fn upscaled_mask_tiles_join_into_the_whole_upscaled_mask() {
+        let mask = GrayImage::from_fn(4, 3, |x, y| image::Luma([(x * 60 + y * 20) as u8]));
+        let whole = upscale_mask_tile(&mask, 10, 7, TileRect { x: 0, y: 0, width: 10, height: 7 });
+        assert_eq!(whole.get_pixel(0, 0)[0], 0);
+        assert_eq!(whole.get_pixel(9, 6)[0], 220);
+
+        let mut joined = GrayImage::new(10, 7);
+        for tile in tile_grid(10, 7, 3) {
+            let part = upscale_mask_tile(&mask, 10, 7, tile);
+            assert_eq!(part.dimensions(), (tile.width, tile.height));
+            image::imageops::replace(&mut joined, &part, tile.x as i64, tile.y as i64);
+        }
+        assert_eq!(joined, whole);
+        assert_eq!(upscale_mask_tile(&mask, 4, 3, TileRect { x: 0, y: 0, width: 4, height: 3 }), mask);
+    }
+
+    #[test]
+    fn png_text_chunks_round_trip_without_touching_pixels() {
+        let mask = GrayImage::from_fn(3, 2, |x, y| image::Luma([(x * 80 + y * 7) as u8]));
+        let png = png_bytes(&DynamicImage::ImageLuma8(mask.clone()));
+        let entries = vec![
+            ("unbg:model".to_string(), "rmbg-1.4".to_string()),
+            ("Comment".to_string(), "café".to_string()),
+        ];
+        let tagged = embed_png_text(&png, &entries, &EncodeOptions::default()).expect("embed text");
+
+        assert_eq!(read_png_text(&tagged).unwrap(), entries);
+        assert!(read_png_text(&png).unwrap().is_empty());
+        let decoded = image::load_from_memory(&tagged).expect("still a valid png");
+        assert_eq!(decoded.to_luma8(), mask);
+        assert!(matches!(
+            embed_png_text(&png, &[(String::new(), "x".to_string())], &EncodeOptions::default()),
+            Err(PngTextError::InvalidKeyword(_))
+        ));
+        assert_eq!(
+            embed_png_text(b"GIF89a", &entries, &EncodeOptions::default()).unwrap_err(),
+            PngTextError::NotPng
+        );
+    }
+
+    #[test]
+    fn streamed_cutout_matches_in_memory_encoding() {
+        let (width, height) = (640, 480);
+        let source = image::RgbImage::from_fn(width, height, |x, y| image::Rgb([(x % 256) as u8, (y % 256) as u8, ((x ^ y) % 256) as u8]));
+        let mask = GrayImage::from_fn(width, height, |x, y| image::Luma([((x + 2 * y) % 256) as u8]));
+        let (source_png, mask_png) = (png_bytes(&source.clone().into()), png_bytes(&mask.clone().into()));
+        let text = vec![("unbg:model".to_string(), "rmbg-1.4".to_string())];
+
+        for background in [None, Some([255, 255, 255])] {
+            let mut cutout = DynamicImage::ImageRgb8(source.clone()).to_rgba8();
+            for (x, y, px) in cutout.enumerate_pixels_mut() {
+                px[3] = mask.get_pixel(x, y)[0];
+                if let Some(color) = background {
+                    flatten_onto(px, color);
+                }
+            }
+            let mut in_memory = Vec::new();
+            encode_rgba_png(&cutout, &text, &EncodeOptions::default(), &mut in_memory).unwrap();
+
+            let stream = CutoutStream::new(std::io::Cursor::new(&source_png), std::io::Cursor::new(&mask_png)).unwrap();
+            assert_eq!(stream.dimensions(), (width, height));
+            let mut streamed = Vec::new();
+            stream.write_png(background, &text, &EncodeOptions::default(), &mut streamed).unwrap();
+            assert!(streamed == in_memory, "streamed cutout differs from in-memory encoding");
+            assert_eq!(read_png_text(&streamed).unwrap(), text);
+        }
+
+        let small_mask = png_bytes(&GrayImage::new(2, 2).into());
+        assert!(matches!(
+            CutoutStream::new(std::io::Cursor::new(&source_png), std::io::Cursor::new(&small_mask)),
+            Err(CutoutStreamError::DimensionMismatch { mask_width: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn contact_sheet_fits_each_group_into_square_cells() {
+        let wide = RgbaImage::from_pixel(40, 20, Rgba([255, 0, 0, 255]));
+        let tall = RgbaImage::from_pixel(10, 30, Rgba([0, 0, 255, 255]));
+        let groups = vec![vec![wide.clone(), tall.clone()], vec![tall, wide], vec![RgbaImage::new(5, 5)]];
+
+        let sheet = contact_sheet(&groups, 2, 16);
+        assert_eq!(sheet.dimensions(), (2 * 2 * 16, 2 * 16));
+        // The 40x20 image shrinks to 16x8 and is centered vertically in its cell.
+        assert_eq!(sheet.get_pixel(0, 3)[3], 0);
+        assert_eq!(sheet.get_pixel(0, 4).0, [255, 0, 0, 255]);
+        assert_eq!(sheet.get_pixel(16 + 8, 8).0, [0, 0, 255, 255]);
+        assert_eq!(sheet.get_pixel(16, 8)[3], 0);
+    }
+
+    #[test]
+    fn png_compression_level_changes_size_but_not_pixels() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(64, 64, |x, y| Rgba([(x * 4) as u8, (y * 4) as u8, 90, 255])));
+        let at_level = |level| {
+            let options = EncodeOptions {
+                png_compression: level,
+                ..EncodeOptions::default()
+            };
+            encode_image(&img, ImageFormat::Png, &options).unwrap()
+        };
+        let stored = at_level(Some(0));
+        let smallest = at_level(Some(9));
+        assert!(stored.len() > smallest.len(), "{} vs {}", stored.len(), smallest.len());
+        let decode = |png: &[u8]| image::load_from_memory(png).unwrap().to_rgba8();
+        assert_eq!(decode(&stored), decode(&smallest));
+        assert_eq!(decode(&stored), img.to_rgba8());
+
+        assert_eq!(at_level(None), png_bytes(&img));
+
+        let low = EncodeOptions {
+            quality: Some(10),
+            ..EncodeOptions::default()
+        };
+        let high = EncodeOptions {
+            quality: Some(95),
+            ..EncodeOptions::default()
+        };
+        assert!(encode_image(&img, ImageFormat::Jpeg, &low).unwrap().len() < encode_image(&img, ImageFormat::Jpeg, &high).unwrap().len());
+    }
+
+    #[test]
+    fn apply_mask_to_rgba_resizes_a_smaller_mask_to_the_source() {
+        let source = png_bytes(&RgbaImage::from_pixel(8, 4, Rgba([10, 20, 30, 255])).into());
+        let mask = png_bytes(&GrayImage::from_fn(2, 1, |x, _| image::Luma([if x == 0 { 255 } else { 0 }])).into());
+
+        let cutout = image::load_from_memory(&apply_mask_to_rgba(&source, &mask).unwrap()).unwrap().to_rgba8();
+        assert_eq!(cutout.dimensions(), (8, 4));
+        assert_eq!(cutout.get_pixel(0, 0).0, [10, 20, 30, 255]);
+        assert_eq!(cutout.get_pixel(7, 3).0, [10, 20, 30, 0]);
+
+        assert!(matches!(apply_mask_to_rgba(&source, b"not a png"), Err(CutoutError::Mask(_))));
+
+        let level = |png_compression| EncodeOptions {
+            png_compression: Some(png_compression),
+            ..EncodeOptions::default()
+        };
+        let fill = Backdrop::Color([255, 255, 255, 255]);
+        let stored = composite_png(&source, &mask, &fill, &level(0)).unwrap();
+        let smallest = composite_png(&source, &mask, &fill, &level(9)).unwrap();
+        assert!(smallest.len() < stored.len(), "{} vs {}", smallest.len(), stored.len());
+        assert_eq!(
+            image::load_from_memory(&stored).unwrap().to_rgba8(),
+            image::load_from_memory(&smallest).unwrap().to_rgba8()
+        );
+    }
+
+    #[test]
+    fn strip_metadata_applies_orientation_and_drops_exif() {
+        let mut jpeg = Vec::new();
+        DynamicImage::ImageRgb8(image::RgbImage::from_pixel(4, 2, image::Rgb([200, 100, 50])))
+            .write_to(&mut std::io::Cursor::new(&mut jpeg), ImageFormat::Jpeg)
+            .unwrap();
+        // Little-endian TIFF: IFD0 holds Orientation = 6 (rotate 90 degrees) and a GPS IFD
+        // pointer; the GPS IFD holds GPSLatitudeRef = "N".
+        let mut tiff = b"II*\0\x08\0\0\0".to_vec();
+        tiff.extend_from_slice(&[2, 0]);
+        tiff.extend_from_slice(&[0x12, 0x01, 3, 0, 1, 0, 0, 0, 6, 0, 0, 0]);
+        tiff.extend_from_slice(&[0x25, 0x88, 4, 0, 1, 0, 0, 0, 38, 0, 0, 0]);
+        tiff.extend_from_slice(&[0, 0, 0, 0]);
+        tiff.extend_from_slice(&[1, 0, 0x01, 0x00, 2, 0, 2, 0, 0, 0, b'N', 0, 0, 0, 0, 0, 0, 0]);
+        let mut exif = b"Exif\0\0".to_vec();
+        exif.extend_from_slice(&tiff);
+        let mut tagged = jpeg[..2].to_vec();
+        tagged.extend_from_slice(&[0xFF, 0xE1]);
+        tagged.extend_from_slice(&((exif.len() + 2) as u16).to_be_bytes());
+        tagged.extend_from_slice(&exif);
+        tagged.extend_from_slice(&jpeg[2..]);
+        assert_eq!(decode_image(&tagged, &DecodeHints::default()).unwrap().to_rgb8().dimensions(), (4, 2));
+
+        let stripped = strip_metadata(&tagged, &DecodeHints::default()).unwrap();
+        assert_eq!(sniff_format(&stripped), SourceFormat::Png);
+        assert_eq!(image::load_from_memory(&stripped).unwrap().to_rgba8().dimensions(), (2, 4));
+        let contains = |needle: &[u8]| stripped.windows(needle.len()).any(|w| w == needle);
+        for needle in [&b"Exif"[..], b"eXIf", b"iCCP", b"tEXt", b"II*\0"] {
+            assert!(!contains(needle), "stripped output still holds {:?}", String::from_utf8_lossy(needle));
+        }
+    }
+
+    #[test]
+    fn composite_over_background_blends_color_and_resized_image() {
+        let source = RgbaImage::from_pixel(4, 2, Rgba([200, 0, 0, 255]));
+        let mask = GrayImage::from_fn(4, 2, |x, _| image::Luma([[255, 0, 128, 0][x as usize]]));
+
+        let white = composite_over_background(&source, &mask, &Backdrop::Color([255, 255, 255, 255])).unwrap();
+        assert_eq!(white.get_pixel(0, 0).0, [200, 0, 0, 255]);
+        assert_eq!(white.get_pixel(1, 0).0, [255, 255, 255, 255]);
+        assert_eq!(white.get_pixel(2, 0).0, [227, 127, 127, 255]);
+
+        let tinted = composite_over_background(&source, &mask, &Backdrop::Color([0, 0, 255, 128])).unwrap();
+        assert_eq!(tinted.get_pixel(1, 0).0, [0, 0, 255, 128]);
+        assert_eq!(tinted.get_pixel(0, 0).0, [200, 0, 0, 255]);
+
+        let mut backdrop = png_bytes(&DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, Rgba([0, 90, 0, 255]))));
+        let pictured = composite_over_background(&source, &mask, &Backdrop::Image(&backdrop)).unwrap();
+        assert_eq!(pictured.dimensions(), (4, 2));
+        assert_eq!(pictured.get_pixel(3, 1).0, [0, 90, 0, 255]);
+        assert!(matches!(
+            composite_over_background(&source, &mask, &Backdrop::Image(b"nope")),
+            Err(CutoutError::Background(_))
+        ));
+
+        // A backdrop claiming huge dimensions is refused from its header, like an oversized input.
+        backdrop[16..20].copy_from_slice(&100_000u32.to_be_bytes());
+        backdrop[20..24].copy_from_slice(&100_000u32.to_be_bytes());
+        let crc = crc32(&backdrop[12..29]);
+        backdrop[29..33].copy_from_slice(&crc.to_be_bytes());
+        assert!(matches!(
+            composite_over_background(&source, &mask, &Backdrop::Image(&backdrop)),
+            Err(CutoutError::Background(DecodeError::TooManyPixels { .. }))
+        ));
+    }
+
+    #[test]
+    fn feather_softens_edges_without_wrapping() {
+        let mask = GrayImage::from_fn(20, 6, |x, _| image::Luma([if x < 10 { 255 } else { 0 }]));
+        let feathered = feather_mask(&mask, 2.0);
+        let row: Vec<u8> = (0..20).map(|x| feathered.get_pixel(x, 3)[0]).collect();
+        assert_eq!((row[0], row[19]), (255, 0), "edges must not pick up the opposite border");
+        assert!(row[9] < 255 && row[10] > 0 && row[9] > row[10]);
+        assert!(row.windows(2).all(|pair| pair[0] >= pair[1]));
+        assert_eq!(feathered.get_pixel(4, 0), feathered.get_pixel(4, 5));
+        assert_eq!(feather_mask(&mask, 0.0), mask);
+        assert_eq!(feather_mask(&mask, 1e9), feather_mask(&mask, 20.0), "radius is capped at the longer side");
+    }
+
+    fn rgb_gradient(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(image::RgbImage::from_fn(width, height, |x, y| {
+            image::Rgb([(x * 255 / width) as u8, (y * 255 / height) as u8, 128])
+        }))
+    }
+
+    #[test]
+    fn image_resizer_hits_the_exact_size() {
+        let resized = ImageResizer.resize(&rgb_gradient(300, 200), 64, 48);
+        assert_eq!((resized.width(), resized.height()), (64, 48));
+        let mask = ImageResizer.resize_gray(&GrayImage::from_pixel(10, 10, image::Luma([200])), 25, 5);
+        assert_eq!(mask.dimensions(), (25, 5));
+        assert!(mask.pixels().all(|p| p[0] == 200));
+        assert_eq!(resizer(ResizeBackend::Image).map(|r| r.name()), Some("image"));
+        assert!("bogus".parse::<ResizeBackend>().is_err());
+        assert_eq!(resize::resizer_from_env(None).name(), "image");
+        assert_eq!(resize::resizer_from_env(Some("bogus")).name(), "image");
+    }
+
+    #[cfg(not(feature = "fast-resize"))]
+    #[test]
+    fn fast_backend_is_unavailable_without_the_feature() {
+        assert!(resizer(ResizeBackend::Fast).is_none());
+    }
+
+    #[cfg(feature = "fast-resize")]
+    #[test]
+    fn fast_resizer_matches_image_resizer() {
+        let source = rgb_gradient(300, 200);
+        for (width, height) in [(64, 48), (1024, 1024), (301, 7)] {
+            let slow = ImageResizer.resize(&source, width, height).into_rgb8();
+            let fast = FastResizer.resize(&source, width, height).into_rgb8();
+            assert_eq!(slow.dimensions(), fast.dimensions());
+            let max_diff = slow
+                .as_raw()
+                .iter()
+                .zip(fast.as_raw())
+                .map(|(a, b)| a.abs_diff(*b))
+                .max()
+                .unwrap_or(0);
+            assert!(max_diff <= 8, "{}x{} differs by up to {}", width, height, max_diff);
+        }
+        let mask = GrayImage::from_fn(40, 40, |x, _| image::Luma([if x < 20 { 255 } else { 0 }]));
+        assert_eq!(FastResizer.resize_gray(&mask, 80, 80).dimensions(), (80, 80));
+    }
+}
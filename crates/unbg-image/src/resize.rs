@@ -0,0 +1,146 @@
+//! Resize backends for the pre/post-processing hot path.
+//!
+//! [`ImageResizer`] wraps `image::imageops` and is always available. With the `fast-resize`
+//! feature, `FastResizer` uses the SIMD kernels of `fast_image_resize` for 8-bit images and
+//! falls back to `image` for everything else. Both use a triangle (bilinear) filter.
+
+use std::sync::OnceLock;
+
+use image::imageops::FilterType;
+use image::{DynamicImage, GrayImage};
+
+/// Resamples images to an exact size with a triangle filter.
+pub trait Resizer: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn resize(&self, img: &DynamicImage, width: u32, height: u32) -> DynamicImage;
+
+    fn resize_gray(&self, img: &GrayImage, width: u32, height: u32) -> GrayImage {
+        self.resize(&DynamicImage::ImageLuma8(img.clone()), width, height).into_luma8()
+    }
+}
+
+/// The `image` crate's resampler; the default backend.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ImageResizer;
+
+impl Resizer for ImageResizer {
+    fn name(&self) -> &'static str {
+        "image"
+    }
+
+    fn resize(&self, img: &DynamicImage, width: u32, height: u32) -> DynamicImage {
+        img.resize_exact(width, height, FilterType::Triangle)
+    }
+
+    fn resize_gray(&self, img: &GrayImage, width: u32, height: u32) -> GrayImage {
+        image::imageops::resize(img, width, height, FilterType::Triangle)
+    }
+}
+
+/// `fast_image_resize` convolution for 8-bit luma, RGB and RGBA images.
+#[cfg(feature = "fast-resize")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FastResizer;
+
+#[cfg(feature = "fast-resize")]
+impl FastResizer {
+    /// Resizes straight from the borrowed pixel buffer, so the source is never copied.
+    fn resize_u8(
+        &self,
+        data: &[u8],
+        (src_w, src_h): (u32, u32),
+        (width, height): (u32, u32),
+        pixel_type: fast_image_resize::PixelType,
+    ) -> Option<Vec<u8>> {
+        use fast_image_resize::images::{Image, ImageRef};
+        use fast_image_resize::{FilterType as FirFilter, ResizeAlg, ResizeOptions};
+
+        if src_w == 0 || src_h == 0 || width == 0 || height == 0 {
+            return None;
+        }
+        let src = ImageRef::new(src_w, src_h, data, pixel_type).ok()?;
+        let mut dst = Image::new(width, height, pixel_type);
+        let options = ResizeOptions::new().resize_alg(ResizeAlg::Convolution(FirFilter::Bilinear));
+        fast_image_resize::Resizer::new().resize(&src, &mut dst, &options).ok()?;
+        Some(dst.into_vec())
+    }
+}
+
+#[cfg(feature = "fast-resize")]
+impl Resizer for FastResizer {
+    fn name(&self) -> &'static str {
+        "fast"
+    }
+
+    fn resize(&self, img: &DynamicImage, width: u32, height: u32) -> DynamicImage {
+        use fast_image_resize::PixelType;
+
+        let src = (img.width(), img.height());
+        let dst = (width, height);
+        let resized = match img {
+            DynamicImage::ImageLuma8(buf) => return DynamicImage::ImageLuma8(self.resize_gray(buf, width, height)),
+            DynamicImage::ImageRgb8(buf) => self
+                .resize_u8(buf.as_raw(), src, dst, PixelType::U8x3)
+                .and_then(|data| image::RgbImage::from_raw(width, height, data))
+                .map(DynamicImage::ImageRgb8),
+            DynamicImage::ImageRgba8(buf) => self
+                .resize_u8(buf.as_raw(), src, dst, PixelType::U8x4)
+                .and_then(|data| image::RgbaImage::from_raw(width, height, data))
+                .map(DynamicImage::ImageRgba8),
+            _ => None,
+        };
+        resized.unwrap_or_else(|| ImageResizer.resize(img, width, height))
+    }
+
+    fn resize_gray(&self, img: &GrayImage, width: u32, height: u32) -> GrayImage {
+        self.resize_u8(img.as_raw(), img.dimensions(), (width, height), fast_image_resize::PixelType::U8)
+            .and_then(|data| GrayImage::from_raw(width, height, data))
+            .unwrap_or_else(|| ImageResizer.resize_gray(img, width, height))
+    }
+}
+
+/// Which [`Resizer`] to use; parsed from `image` or `fast`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ResizeBackend {
+    #[default]
+    Image,
+    Fast,
+}
+
+impl std::str::FromStr for ResizeBackend {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "image" => Ok(Self::Image),
+            "fast" => Ok(Self::Fast),
+            other => Err(format!("unknown resize backend '{}' (expected image or fast)", other)),
+        }
+    }
+}
+
+/// The resizer for `backend`, or `None` when this build lacks it (`Fast` without `fast-resize`).
+pub fn resizer(backend: ResizeBackend) -> Option<&'static dyn Resizer> {
+    match backend {
+        ResizeBackend::Image => Some(&ImageResizer),
+        #[cfg(feature = "fast-resize")]
+        ResizeBackend::Fast => Some(&FastResizer),
+        #[cfg(not(feature = "fast-resize"))]
+        ResizeBackend::Fast => None,
+    }
+}
+
+/// The resizer selected by `UNBG_RESIZE_BACKEND`; unset, unknown or unavailable backends use
+/// [`ImageResizer`]. The variable is read on first use, so later changes have no effect.
+pub fn active_resizer() -> &'static dyn Resizer {
+    static ACTIVE: OnceLock<&'static dyn Resizer> = OnceLock::new();
+    *ACTIVE.get_or_init(|| resizer_from_env(std::env::var("UNBG_RESIZE_BACKEND").ok().as_deref()))
+}
+
+pub(crate) fn resizer_from_env(value: Option<&str>) -> &'static dyn Resizer {
+    value
+        .and_then(|value| value.parse().ok())
+        .and_then(resizer)
+        .unwrap_or(&ImageResizer)
+}
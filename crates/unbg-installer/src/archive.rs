@@ -0,0 +1,160 @@
+//! `.zip` and `.tar.gz` readers for model bundles, plus a deterministic `.tar.gz` writer.
+//!
+//! Only regular files and directories are extracted; links and other special entries are rejected,
+//! as is any path that would land outside the destination.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use tar::{EntryType, Header};
+use zip::ZipArchive;
+
+use crate::{InstallError, Result};
+
+/// Extracts `archive` into `dest`, detecting the format from its leading bytes.
+pub(crate) fn extract_archive(archive: &Path, dest: &Path) -> Result<()> {
+    let mut file = File::open(archive)?;
+    let mut magic = [0u8; 4];
+    let read = file.read(&mut magic)?;
+    file.seek(SeekFrom::Start(0))?;
+    match &magic[..read] {
+        [0x50, 0x4b, 0x03, 0x04] => extract_zip(file, dest),
+        [0x1f, 0x8b, ..] => extract_tar(GzDecoder::new(file), dest),
+        _ => Err(invalid(format!(
+            "{} is not a .zip or .tar.gz archive",
+            archive.display()
+        ))),
+    }
+}
+
+fn invalid(message: String) -> InstallError {
+    InstallError::InvalidArchive(message)
+}
+
+/// Joins an archive entry name onto `dest`, rejecting absolute paths and `..` components.
+fn safe_join(dest: &Path, name: &str) -> Result<PathBuf> {
+    let relative = Path::new(name);
+    if name.contains('\\') || relative.components().any(|c| !matches!(c, Component::Normal(_) | Component::CurDir)) {
+        return Err(invalid(format!("archive entry '{}' escapes the destination", name)));
+    }
+    Ok(dest.join(relative))
+}
+
+fn write_entry(dest: &Path, name: &str, reader: &mut impl Read) -> Result<()> {
+    let target = safe_join(dest, name)?;
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    io::copy(reader, &mut File::create(&target)?)?;
+    Ok(())
+}
+
+/// GNU long names and pax headers are resolved by `tar` into the entry's path.
+fn extract_tar(reader: impl Read, dest: &Path) -> Result<()> {
+    let mut archive = tar::Archive::new(reader);
+    let entries = archive
+        .entries()
+        .map_err(|e| invalid(format!("corrupt tar archive: {}", e)))?;
+    for entry in entries {
+        let mut entry = entry.map_err(|e| invalid(format!("corrupt tar archive: {}", e)))?;
+        let name = entry
+            .path()
+            .map_err(|e| invalid(format!("invalid tar entry name: {}", e)))?
+            .to_str()
+            .ok_or_else(|| invalid("tar entry name is not UTF-8".to_string()))?
+            .to_string();
+        match entry.header().entry_type() {
+            EntryType::Regular | EntryType::Continuous => write_entry(dest, &name, &mut entry)?,
+            EntryType::Directory => fs::create_dir_all(safe_join(dest, &name)?)?,
+            other => {
+                return Err(invalid(format!(
+                    "unsupported tar entry type {:?} for {}",
+                    other, name
+                )))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes `entries` (archive name, source file) as a `.tar.gz`. Entries are written in name order
+/// with zeroed timestamps and ownership so the same inputs always produce the same bytes; names
+/// past the 100-byte header field get a GNU long-name record.
+pub(crate) fn write_tar_gz(out: &Path, entries: &[(String, PathBuf)]) -> Result<()> {
+    let mut sorted: Vec<_> = entries.iter().collect();
+    sorted.sort_by(|a, b| a.0.cmp(&b.0));
+    let encoder = GzEncoder::new(io::BufWriter::new(File::create(out)?), Compression::default());
+    let mut builder = tar::Builder::new(encoder);
+    for (name, source) in sorted {
+        let file = File::open(source)?;
+        let size = file.metadata()?.len();
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Regular);
+        header.set_size(size);
+        header.set_mode(0o644);
+        header.set_mtime(0);
+        header.set_uid(0);
+        header.set_gid(0);
+        let body = ExactLen {
+            inner: file,
+            remaining: size,
+        };
+        builder.append_data(&mut header, name, body).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => invalid("file changed size while it was being archived".to_string()),
+            _ => e.into(),
+        })?;
+    }
+    builder.into_inner()?.finish()?.into_inner().map_err(|e| e.into_error())?;
+    Ok(())
+}
+
+/// Yields exactly `remaining` bytes of `inner` and fails if it ends sooner, so the body always
+/// matches the size already written to its header.
+struct ExactLen<R> {
+    inner: R,
+    remaining: u64,
+}
+
+impl<R: Read> Read for ExactLen<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 {
+            return Ok(0);
+        }
+        let limit = buf.len().min(usize::try_from(self.remaining).unwrap_or(usize::MAX));
+        let read = self.inner.read(&mut buf[..limit])?;
+        if read == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.remaining -= read as u64;
+        Ok(read)
+    }
+}
+
+/// Streams each entry to disk; `zip` checks every entry's size and CRC as it is read.
+fn extract_zip(file: File, dest: &Path) -> Result<()> {
+    let mut archive = ZipArchive::new(file).map_err(|e| invalid(format!("corrupt zip archive: {}", e)))?;
+    for index in 0..archive.len() {
+        let mut entry = archive
+            .by_index(index)
+            .map_err(|e| invalid(format!("corrupt zip archive: {}", e)))?;
+        let name = entry.name().to_string();
+        if entry.is_symlink() {
+            return Err(invalid(format!("zip entry {} is a link", name)));
+        }
+        if entry.is_dir() {
+            fs::create_dir_all(safe_join(dest, &name)?)?;
+            continue;
+        }
+        write_entry(dest, &name, &mut entry).map_err(|err| match err {
+            InstallError::Io(e) if e.kind() == io::ErrorKind::InvalidData => {
+                invalid(format!("zip entry {} is corrupt: {}", name, e))
+            }
+            other => other,
+        })?;
+    }
+    Ok(())
+}
@@ -1,33 +1,169 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::env;
 use std::fs::{self, File};
 use std::io::{Read, Write};
-use std::path::{Path, PathBuf};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use anyhow::{anyhow, Context, Result};
 use reqwest::blocking::Client;
 use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, RANGE, USER_AGENT};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use tempfile::Builder;
-use unbg_core::OnnxVariant;
+use thiserror::Error;
+use unbg_core::{CancelToken, OnnxVariant};
 use unbg_model_registry::{
-    built_in_manifest, ensure_layout, merge_lock_models, model_revision_dir, read_lockfile, resolve_model_paths,
-    write_lockfile, KnownModel, LockFileEntry, LockModel, ModelLock,
+    built_in_manifest, cache_key_for_model_id, ensure_layout, merge_lock_models, model_id_revision_dir, model_revision_dir,
+    read_custom_models, read_lockfile, register_custom_model, resolve_model_paths, validate_model_id, validate_revision,
+    write_lockfile, CustomModel, KnownModel, LockFileEntry, LockModel, ModelLock, RegistryError, CUSTOM_MODELS_FILE_NAME,
+    LOCKFILE_NAME,
 };
 use walkdir::WalkDir;
 
+mod archive;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InstallRequest {
     pub model_dir: Option<PathBuf>,
     pub install_all: bool,
     pub models: Vec<KnownModel>,
+    /// Models outside [`KnownModel`] to fetch by Hugging Face repo id at their `revision`; each is
+    /// registered in the model dir so the runtime can find its spec.
+    #[serde(default)]
+    pub custom_models: Vec<CustomModel>,
     pub hf_token_env: String,
     pub revision_rmbg14: String,
     pub revision_rmbg20: String,
     pub verify_only: bool,
     pub onnx_variant: OnnxVariant,
+    /// Download a revision again even if it looks complete, replacing it only once the new copy is in.
+    pub force: bool,
+    /// Leave the staging directory in place when a download fails so its contents can be inspected.
+    /// `UNBG_KEEP_DOWNLOADS=1` enables this too.
+    #[serde(default)]
+    pub keep_temp_on_failure: bool,
+    /// Parent of the in-progress download directory; defaults to `cache/downloads` in the model
+    /// dir. `UNBG_DOWNLOAD_TMP` sets it too.
+    #[serde(default)]
+    pub temp_dir: Option<PathBuf>,
+    /// Read/write buffer used while streaming downloads and re-hashing partial files and installed revisions.
+    #[serde(default = "default_download_buffer_size")]
+    pub download_buffer_size: usize,
+    /// Record bytes per second for every downloaded file in [`InstallReport::throughput`].
+    #[serde(default)]
+    pub report_throughput: bool,
+    #[serde(skip)]
+    pub cancel: Option<CancelToken>,
+    /// Receives a copy of every downloaded byte while it is written to disk and hashed.
+    #[serde(skip)]
+    pub download_sink: Option<Arc<dyn DownloadSink>>,
+}
+
+/// Tee for downloaded model files, e.g. to fill a CDN cache or apply an extra verification
+/// policy without re-reading the file from disk. An error from the sink fails the install.
+pub trait DownloadSink: std::fmt::Debug + Send + Sync {
+    /// Consecutive chunks of `file_path`, starting at byte 0; bytes from a resumed partial
+    /// download are replayed first.
+    fn write(&self, file_path: &str, bytes: &[u8]) -> std::io::Result<()>;
+
+    /// Called once `file_path` is complete, with its size and SHA-256, before it is moved out of
+    /// its `.part` file; an error here keeps it from being installed.
+    fn finish(&self, _file_path: &str, _size: u64, _sha256: &str) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+pub const DEFAULT_DOWNLOAD_BUFFER_SIZE: usize = 256 * 1024;
+
+fn default_download_buffer_size() -> usize {
+    DEFAULT_DOWNLOAD_BUFFER_SIZE
+}
+
+pub type Result<T, E = InstallError> = std::result::Result<T, E>;
+
+#[derive(Debug, Error)]
+pub enum InstallError {
+    #[error("missing {token_env} for gated model {model_id}")]
+    MissingToken { token_env: String, model_id: String },
+    #[error("invalid token for authorization header")]
+    InvalidToken,
+    #[error("network error: {0}")]
+    Network(String),
+    #[error("checksum mismatch: {0}")]
+    Checksum(String),
+    #[error("disk full: {0}")]
+    DiskFull(std::io::Error),
+    #[error("not found: {0}")]
+    NotFound(String),
+    #[error("invalid archive: {0}")]
+    InvalidArchive(String),
+    #[error(transparent)]
+    Cancelled(#[from] DownloadCancelled),
+    /// A failed download whose staging directory was kept for inspection.
+    #[error("{source}; partial download kept at {}", kept_dir.display())]
+    PartialDownloadKept {
+        kept_dir: PathBuf,
+        #[source]
+        source: Box<InstallError>,
+    },
+    #[error("registry error: {0}")]
+    Registry(RegistryError),
+    #[error("io error: {0}")]
+    Io(std::io::Error),
+}
+
+impl InstallError {
+    /// Stable kebab-case identifier for FFI and UI layers.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::MissingToken { .. } => "missing-token",
+            Self::InvalidToken => "invalid-token",
+            Self::Network(_) => "network",
+            Self::Checksum(_) => "checksum",
+            Self::DiskFull(_) => "disk-full",
+            Self::NotFound(_) => "not-found",
+            Self::InvalidArchive(_) => "invalid-archive",
+            Self::Cancelled(_) => "cancelled",
+            Self::PartialDownloadKept { source, .. } => source.code(),
+            Self::Registry(_) => "registry",
+            Self::Io(_) => "io",
+        }
+    }
+}
+
+impl From<std::io::Error> for InstallError {
+    fn from(err: std::io::Error) -> Self {
+        if err.kind() == std::io::ErrorKind::StorageFull {
+            Self::DiskFull(err)
+        } else {
+            Self::Io(err)
+        }
+    }
+}
+
+impl From<RegistryError> for InstallError {
+    fn from(err: RegistryError) -> Self {
+        match err {
+            RegistryError::Io(io) => io.into(),
+            other => Self::Registry(other),
+        }
+    }
+}
+
+impl From<reqwest::Error> for InstallError {
+    fn from(err: reqwest::Error) -> Self {
+        Self::Network(err.to_string())
+    }
+}
+
+/// Returned when a download is aborted through the request's [`CancelToken`].
+///
+/// The partially downloaded file is left on disk so a later run can resume it.
+#[derive(Debug, Error)]
+#[error("download cancelled; partial file kept at {}", partial_path.display())]
+pub struct DownloadCancelled {
+    pub partial_path: PathBuf,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -36,14 +172,44 @@ pub struct InstallReport {
     pub installed: Vec<String>,
     pub skipped: Vec<String>,
     pub lockfile_written: bool,
+    /// Per-file download speed, filled when [`InstallRequest::report_throughput`] is set.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub throughput: Vec<DownloadThroughput>,
+}
+
+/// How fast one file came down from the hub. Bytes already on disk from a resumed partial
+/// download are not counted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DownloadThroughput {
+    pub model_id: String,
+    pub path: String,
+    pub bytes: u64,
+    pub elapsed_ms: u64,
+    pub bytes_per_sec: f64,
+}
+
+impl DownloadThroughput {
+    fn new(model_id: &str, path: &str, bytes: u64, elapsed: Duration) -> Self {
+        Self {
+            model_id: model_id.to_string(),
+            path: path.to_string(),
+            bytes,
+            elapsed_ms: elapsed.as_millis() as u64,
+            bytes_per_sec: bytes as f64 / elapsed.as_secs_f64().max(1e-6),
+        }
+    }
 }
 
 pub fn install_models(request: &InstallRequest) -> Result<InstallReport> {
+    install_models_from(request, &hf_endpoint())
+}
+
+fn install_models_from(request: &InstallRequest, endpoint: &str) -> Result<InstallReport> {
     let paths = resolve_model_paths(request.model_dir.as_deref())?;
     ensure_layout(&paths)?;
 
     let mut targets = request.models.clone();
-    if request.install_all || targets.is_empty() {
+    if request.install_all || (targets.is_empty() && request.custom_models.is_empty()) {
         targets = KnownModel::all().to_vec();
     }
 
@@ -52,45 +218,54 @@ pub fn install_models(request: &InstallRequest) -> Result<InstallReport> {
     let token = env::var(&request.hf_token_env).ok().filter(|s| !s.trim().is_empty());
     require_gated_token_if_needed(&targets, &manifest_by_id, &request.hf_token_env, token.as_deref())?;
 
+    let temp_parent = download_temp_dir(request).unwrap_or_else(|| paths.cache_downloads_dir.clone());
+    fs::create_dir_all(&temp_parent)?;
+
     let mut lock_models = Vec::new();
     let mut installed = Vec::new();
     let mut skipped = Vec::new();
+    let mut throughput = Vec::new();
 
-    for model in targets {
+    // Custom repos are not in the manifest, so they are never treated as gated; the token is
+    // still sent when set.
+    let builtin = targets.iter().map(|model| {
         let revision = match model {
-            KnownModel::Rmbg14 => request.revision_rmbg14.as_str(),
-            KnownModel::Rmbg20 => request.revision_rmbg20.as_str(),
+            KnownModel::Rmbg14 => &request.revision_rmbg14,
+            KnownModel::Rmbg20 => &request.revision_rmbg20,
         };
-        let model_id = model.model_id().to_string();
-        let rev_dir = model_revision_dir(&paths, model, revision);
-
-        let lock_model = if rev_dir.exists() {
-            if has_onnx_file(&rev_dir)? {
-                skipped.push(model_id.clone());
-                lock_from_existing_dir(&model_id, revision, &rev_dir)?
-            } else {
-                fs::remove_dir_all(&rev_dir)?;
-                let downloaded = download_model_to_revision(
-                    &paths.cache_downloads_dir,
-                    &model_id,
-                    revision,
-                    token.as_deref(),
-                    &rev_dir,
-                    request.onnx_variant,
-                )?;
-                installed.push(model_id.clone());
-                downloaded
-            }
+        (model.model_id().to_string(), revision.as_str())
+    });
+    let custom = request.custom_models.iter().map(|model| (model.id.clone(), model.revision.as_str()));
+    let targets: Vec<(String, &str)> = builtin.chain(custom).collect();
+    for (model_id, revision) in &targets {
+        validate_model_id(model_id)?;
+        validate_revision(revision)?;
+    }
+    for (model_id, revision) in targets {
+        let rev_dir = model_id_revision_dir(&paths, &model_id, revision);
+
+        let lock_model = if !request.force && rev_dir.exists() && has_onnx_file(&rev_dir)? {
+            skipped.push(model_id.clone());
+            lock_from_existing_dir(&model_id, revision, &rev_dir, request.download_buffer_size)?
         } else {
-            let downloaded = download_model_to_revision(
-                &paths.cache_downloads_dir,
+            // A forced reinstall keeps the current revision until the new one has downloaded.
+            let (downloaded, timings) = download_model_to_revision(
+                &temp_parent,
+                endpoint,
                 &model_id,
                 revision,
                 token.as_deref(),
                 &rev_dir,
                 request.onnx_variant,
+                request.cancel.as_ref(),
+                keep_temp_on_failure(request),
+                request.download_buffer_size,
+                request.download_sink.as_deref(),
             )?;
             installed.push(model_id.clone());
+            if request.report_throughput {
+                throughput.extend(timings);
+            }
             downloaded
         };
         lock_models.push(lock_model);
@@ -98,13 +273,13 @@ pub fn install_models(request: &InstallRequest) -> Result<InstallReport> {
 
     let mut lockfile_written = false;
     if !request.verify_only {
+        // Registered first: the lockfile only accepts ids of built-in or registered models.
+        for model in &request.custom_models {
+            register_custom_model(&paths, model.clone())?;
+        }
         validate_lock_models(&paths, &lock_models)?;
         let existing = read_lockfile(&paths).ok();
-        let generated_at = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .map(|d| d.as_secs().to_string())
-            .unwrap_or_else(|_| "0".to_string());
-        let lock = merge_lock_models(existing, lock_models, generated_at);
+        let lock = merge_lock_models(existing, lock_models, unix_seconds_now());
         write_lockfile(&paths, &lock)?;
         lockfile_written = true;
     }
@@ -114,51 +289,504 @@ pub fn install_models(request: &InstallRequest) -> Result<InstallReport> {
         installed,
         skipped,
         lockfile_written,
+        throughput,
     })
 }
 
+/// Installs the models in a `.zip` or `.tar.gz` bundle laid out like a model directory
+/// (`manifests/unbg-model-lock.json` plus `models/<cache_key>/<revision>/...`, optionally under
+/// one top-level folder). Every revision is re-hashed and checked for a loadable `.onnx` file
+/// while still in staging; only then is each swapped in, with any installed copy restored if the
+/// swap fails. The bundled entries are merged into `model_dir`'s lockfile, and the custom models
+/// the bundle registers (`manifests/custom-models.json`) are registered in `model_dir`.
+pub fn install_from_archive(archive_path: &Path, model_dir: Option<&Path>) -> Result<InstallReport> {
+    let paths = resolve_model_paths(model_dir)?;
+    ensure_layout(&paths)?;
+    let staging = Builder::new()
+        .prefix("unbg-archive-")
+        .tempdir_in(&paths.cache_downloads_dir)?;
+    archive::extract_archive(archive_path, staging.path())?;
+
+    let bundle_root = find_bundle_root(staging.path())?;
+    let bundled: ModelLock = serde_json::from_slice(&fs::read(bundle_root.join("manifests").join(LOCKFILE_NAME))?)
+        .map_err(|e| InstallError::InvalidArchive(format!("bundled lockfile is invalid: {}", e)))?;
+    if bundled.models.is_empty() {
+        return Err(InstallError::InvalidArchive("bundled lockfile lists no models".to_string()));
+    }
+
+    let bundled_custom = read_custom_models(&resolve_model_paths(Some(&bundle_root))?)
+        .map_err(|e| InstallError::InvalidArchive(format!("bundled {} is invalid: {}", CUSTOM_MODELS_FILE_NAME, e)))?;
+    let mut registered = read_custom_models(&paths)?;
+    registered.extend(bundled_custom.iter().cloned());
+
+    let mut staged = Vec::with_capacity(bundled.models.len());
+    for model in &bundled.models {
+        check_lock_model(model, &registered).map_err(|e| InstallError::InvalidArchive(e.to_string()))?;
+        let source = bundle_root
+            .join("models")
+            .join(cache_key_for_model_id(&model.model_id))
+            .join(&model.revision);
+        verify_lock_entries(model, &source)?;
+        validate_revision_dir(model, &source)?;
+        staged.push(source);
+    }
+
+    let mut installed = Vec::new();
+    for (source, model) in staged.into_iter().zip(&bundled.models) {
+        let target = model_id_revision_dir(&paths, &model.model_id, &model.revision);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        swap_into_place(&source, &target)?;
+        installed.push(model.model_id.clone());
+    }
+
+    for model in bundled_custom {
+        register_custom_model(&paths, model)?;
+    }
+    let lock = merge_lock_models(read_lockfile(&paths).ok(), bundled.models, unix_seconds_now());
+    write_lockfile(&paths, &lock)?;
+    Ok(InstallReport {
+        model_dir: paths.root,
+        installed,
+        skipped: Vec::new(),
+        lockfile_written: true,
+        throughput: Vec::new(),
+    })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportReport {
+    pub archive: PathBuf,
+    pub models: Vec<String>,
+    pub file_count: usize,
+}
+
+/// Packages installed revisions and their lockfile entries into a `.tar.gz` that
+/// [`install_from_archive`] accepts. An empty `models` selection exports everything in the
+/// lockfile, custom models included along with their registered specs. Only files tracked by the
+/// lockfile are included, and each is re-hashed first.
+pub fn export_models(model_dir: Option<&Path>, models: &[KnownModel], out: &Path) -> Result<ExportReport> {
+    let paths = resolve_model_paths(model_dir)?;
+    let lock = read_lockfile(&paths)?;
+    let selected: Vec<LockModel> = lock
+        .models
+        .iter()
+        .filter(|m| {
+            models.is_empty() || KnownModel::from_model_id(&m.model_id).is_some_and(|kind| models.contains(&kind))
+        })
+        .cloned()
+        .collect();
+    if let Some(missing) = models
+        .iter()
+        .find(|kind| !selected.iter().any(|m| KnownModel::from_model_id(&m.model_id) == Some(**kind)))
+    {
+        return Err(InstallError::NotFound(format!(
+            "{} is not installed in {}",
+            missing.model_id(),
+            paths.root.display()
+        )));
+    }
+
+    let staging = Builder::new()
+        .prefix("unbg-export-")
+        .tempdir_in(&paths.cache_downloads_dir)?;
+    let bundled_lock = ModelLock {
+        schema_version: lock.schema_version,
+        generated_at: lock.generated_at,
+        models: selected,
+    };
+    let lock_path = staging.path().join(LOCKFILE_NAME);
+    fs::write(&lock_path, serde_json::to_vec_pretty(&bundled_lock).map_err(std::io::Error::other)?)?;
+
+    let registered = read_custom_models(&paths)?;
+    let bundle_paths = resolve_model_paths(Some(staging.path()))?;
+    let mut model_entries = Vec::new();
+    for model in &bundled_lock.models {
+        check_lock_model(model, &registered)?;
+        if let Some(spec) = registered.iter().find(|spec| spec.id == model.model_id) {
+            register_custom_model(&bundle_paths, spec.clone())?;
+        }
+        let revision_dir = model_id_revision_dir(&paths, &model.model_id, &model.revision);
+        verify_lock_entries(model, &revision_dir)?;
+        for file in &model.files {
+            model_entries.push((
+                format!("models/{}/{}/{}", cache_key_for_model_id(&model.model_id), model.revision, file.path),
+                revision_dir.join(&file.path),
+            ));
+        }
+    }
+    let file_count = model_entries.len();
+    let mut entries = vec![(format!("manifests/{}", LOCKFILE_NAME), lock_path)];
+    let custom_path = bundle_paths.manifests_dir.join(CUSTOM_MODELS_FILE_NAME);
+    if custom_path.is_file() {
+        entries.push((format!("manifests/{}", CUSTOM_MODELS_FILE_NAME), custom_path));
+    }
+    entries.extend(model_entries);
+    archive::write_tar_gz(out, &entries)?;
+    Ok(ExportReport {
+        archive: out.to_path_buf(),
+        models: bundled_lock.models.iter().map(|m| m.model_id.clone()).collect(),
+        file_count,
+    })
+}
+
+/// The extracted directory holding `manifests/`, allowing for a single wrapping folder.
+fn find_bundle_root(extracted: &Path) -> Result<PathBuf> {
+    let has_lock = |dir: &Path| dir.join("manifests").join(LOCKFILE_NAME).is_file();
+    if has_lock(extracted) {
+        return Ok(extracted.to_path_buf());
+    }
+    let mut children = fs::read_dir(extracted)?.filter_map(std::result::Result::ok);
+    match (children.next(), children.next()) {
+        (Some(only), None) if has_lock(&only.path()) => Ok(only.path()),
+        _ => Err(InstallError::InvalidArchive(format!(
+            "archive has no manifests/{}",
+            LOCKFILE_NAME
+        ))),
+    }
+}
+
+/// Lockfile ids and revisions become directory names, so only built-in models and `registered`
+/// custom models are accepted, and only with an id and revision that stay inside `models/`.
+fn check_lock_model(model: &LockModel, registered: &[CustomModel]) -> Result<()> {
+    validate_model_id(&model.model_id)?;
+    validate_revision(&model.revision)?;
+    if KnownModel::from_model_id(&model.model_id).is_none() && !registered.iter().any(|m| m.id == model.model_id) {
+        return Err(InstallError::NotFound(format!("unknown model id in lockfile: {}", model.model_id)));
+    }
+    Ok(())
+}
+
+fn verify_lock_entries(model: &LockModel, revision_dir: &Path) -> Result<()> {
+    if model.files.is_empty() {
+        return Err(InstallError::NotFound(format!(
+            "{}@{} lists no files",
+            model.model_id, model.revision
+        )));
+    }
+    for file in &model.files {
+        if Path::new(&file.path)
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_)))
+        {
+            return Err(InstallError::InvalidArchive(format!(
+                "{}@{} lists a file outside its revision: {}",
+                model.model_id, model.revision, file.path
+            )));
+        }
+        let path = revision_dir.join(&file.path);
+        if !path.is_file() {
+            return Err(InstallError::NotFound(format!(
+                "bundle is missing {}@{} {}",
+                model.model_id, model.revision, file.path
+            )));
+        }
+        if fs::metadata(&path)?.len() != file.size || sha256_file(&path, DEFAULT_DOWNLOAD_BUFFER_SIZE)? != file.sha256 {
+            return Err(InstallError::Checksum(format!(
+                "{}@{} {}",
+                model.model_id, model.revision, file.path
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn unix_seconds_now() -> String {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs().to_string())
+        .unwrap_or_else(|_| "0".to_string())
+}
+
 pub fn verify_models(model_dir: Option<PathBuf>) -> Result<ModelLock> {
     let paths = resolve_model_paths(model_dir.as_deref())?;
     let lock = read_lockfile(&paths)?;
+    let registered = read_custom_models(&paths)?;
     for model in &lock.models {
-        let model_kind = unbg_model_registry::KnownModel::from_model_id(&model.model_id)
-            .ok_or_else(|| anyhow!("unknown model id in lockfile: {}", model.model_id))?;
-        let revision_dir = model_revision_dir(&paths, model_kind, &model.revision);
+        check_lock_model(model, &registered)?;
+        let revision_dir = model_id_revision_dir(&paths, &model.model_id, &model.revision);
         for file in &model.files {
             let file_path = revision_dir.join(&file.path);
             if !file_path.exists() {
-                return Err(anyhow!(
+                return Err(InstallError::NotFound(format!(
                     "missing file for {}@{}: {}",
                     model.model_id,
                     model.revision,
                     file.path
-                ));
+                )));
             }
             let metadata = fs::metadata(&file_path)?;
             if metadata.len() != file.size {
-                return Err(anyhow!(
+                return Err(InstallError::Checksum(format!(
                     "size mismatch for {}@{} {}: expected {}, got {}",
                     model.model_id,
                     model.revision,
                     file.path,
                     file.size,
                     metadata.len()
-                ));
+                )));
             }
-            let digest = sha256_file(&file_path)?;
+            let digest = sha256_file(&file_path, DEFAULT_DOWNLOAD_BUFFER_SIZE)?;
             if digest != file.sha256 {
-                return Err(anyhow!(
-                    "checksum mismatch for {}@{} {}",
-                    model.model_id,
-                    model.revision,
-                    file.path
-                ));
+                return Err(InstallError::Checksum(format!(
+                    "{}@{} {}",
+                    model.model_id, model.revision, file.path
+                )));
             }
         }
     }
     Ok(lock)
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MigrationReport {
+    pub model_dir: PathBuf,
+    pub dry_run: bool,
+    pub migrated: Vec<MigratedModel>,
+    /// Legacy directories left untouched because the target revision already has an onnx file.
+    pub skipped: Vec<PathBuf>,
+    /// Legacy directories left untouched because their files match no known revision's hashes.
+    pub unverified: Vec<PathBuf>,
+    pub lockfile_written: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MigratedModel {
+    pub model_id: String,
+    pub revision: String,
+    pub from: PathBuf,
+    pub to: PathBuf,
+    /// Paths relative to `to`.
+    pub files: Vec<String>,
+}
+
+/// Moves model files from a pre-`models/<cache_key>/<revision>` layout into `model_dir` and
+/// records them in its lockfile, so older installs do not have to be downloaded again.
+///
+/// Any directory under `from` (up to a few levels deep, including `from` itself) whose name ends
+/// with the model's repo name, e.g. `RMBG-1.4`, `briaai--RMBG-1.4` or `rmbg_1_4`, and that holds
+/// an `.onnx` file is a legacy install candidate. Its files are hashed and matched against the
+/// revisions recorded in `known`, in any `unbg-model-lock.json` under `from` and in `model_dir`'s
+/// own lockfile; the revision whose hashes they match is the one they are installed as, and a
+/// directory matching none is reported as unverified and left alone. Matching `.onnx` files go
+/// to `onnx/` and `config.json` / `preprocessor_config.json` to the revision root; anything else
+/// is left behind.
+///
+/// Files are copied into a staging directory next to the revision, checked again and renamed
+/// into place before the originals are removed, so a run that fails part-way leaves the legacy
+/// install intact and running it again picks up where it stopped. Once done, running it again
+/// finds nothing left to move.
+pub fn migrate_legacy_layout(from: &Path, model_dir: Option<&Path>, known: &[LockModel], dry_run: bool) -> Result<MigrationReport> {
+    if !from.is_dir() {
+        return Err(InstallError::NotFound(format!("{} is not a directory", from.display())));
+    }
+    let paths = resolve_model_paths(model_dir)?;
+    let mut known = known.to_vec();
+    known.extend(find_legacy_lockfiles(from, &paths.models_dir).into_iter().flat_map(|lock| lock.models));
+    if let Ok(lock) = read_lockfile(&paths) {
+        known.extend(lock.models);
+    }
+
+    let mut migrated = Vec::new();
+    let mut skipped = Vec::new();
+    let mut unverified = Vec::new();
+    let mut lock_models = Vec::new();
+    for (kind, legacy_dir) in find_legacy_model_dirs(from, &paths.models_dir)? {
+        let Some((known_model, moves)) = match_known_revision(kind, &legacy_dir, &known)? else {
+            unverified.push(legacy_dir);
+            continue;
+        };
+        let rev_dir = model_revision_dir(&paths, kind, &known_model.revision);
+        if has_onnx_file(&rev_dir)? {
+            skipped.push(legacy_dir);
+            continue;
+        }
+        let lock_model = LockModel {
+            files: known_model
+                .files
+                .iter()
+                .filter(|file| moves.values().any(|target| *target == file.path))
+                .cloned()
+                .collect(),
+            ..known_model.clone()
+        };
+        if !dry_run {
+            stage_legacy_files(&moves, &lock_model, &rev_dir)?;
+        }
+        migrated.push(MigratedModel {
+            model_id: kind.model_id().to_string(),
+            revision: lock_model.revision.clone(),
+            from: legacy_dir,
+            to: rev_dir,
+            files: moves.into_values().collect(),
+        });
+        lock_models.push(lock_model);
+    }
+
+    let mut lockfile_written = false;
+    if !dry_run && !lock_models.is_empty() {
+        ensure_layout(&paths)?;
+        validate_lock_models(&paths, &lock_models)?;
+        let lock = merge_lock_models(read_lockfile(&paths).ok(), lock_models, unix_seconds_now());
+        write_lockfile(&paths, &lock)?;
+        lockfile_written = true;
+    }
+
+    Ok(MigrationReport {
+        model_dir: paths.root,
+        dry_run,
+        migrated,
+        skipped,
+        unverified,
+        lockfile_written,
+    })
+}
+
+/// The known revision of `kind` that `legacy_dir`'s files belong to, with the moves to make.
+///
+/// Every `.onnx` file must match an entry of that revision by target path, size and hash; other
+/// files are only moved when they match too. Files without an entry stay behind.
+fn match_known_revision<'a>(
+    kind: KnownModel,
+    legacy_dir: &Path,
+    known: &'a [LockModel],
+) -> Result<Option<(&'a LockModel, BTreeMap<PathBuf, String>)>> {
+    let candidates = legacy_file_moves(legacy_dir);
+    let mut digests = HashMap::new();
+    for source in candidates.keys() {
+        digests.insert(source.clone(), (fs::metadata(source)?.len(), sha256_file(source, DEFAULT_DOWNLOAD_BUFFER_SIZE)?));
+    }
+    for model in known.iter().filter(|m| m.model_id == kind.model_id()) {
+        let entry_for = |target: &str| model.files.iter().find(|file| file.path == target);
+        let matches = |source: &PathBuf, target: &str| {
+            let (size, sha256) = &digests[source];
+            entry_for(target).is_some_and(|file| file.size == *size && file.sha256 == *sha256)
+        };
+        let onnx_match = candidates
+            .iter()
+            .filter(|(_, target)| target.ends_with(".onnx"))
+            .all(|(source, target)| matches(source, target));
+        if !onnx_match {
+            continue;
+        }
+        let moves: BTreeMap<PathBuf, String> = candidates
+            .iter()
+            .filter(|(source, target)| matches(source, target))
+            .map(|(source, target)| (source.clone(), target.clone()))
+            .collect();
+        return Ok(Some((model, moves)));
+    }
+    Ok(None)
+}
+
+/// Copies `moves` into a staging directory beside `rev_dir`, re-checks each copy against
+/// `model`, renames the staging directory into place and only then removes the originals.
+fn stage_legacy_files(moves: &BTreeMap<PathBuf, String>, model: &LockModel, rev_dir: &Path) -> Result<()> {
+    let revision = rev_dir.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let staging = rev_dir.with_file_name(format!(".{}.migrating", revision));
+    // Left over from a run that failed part-way; the legacy files it copied are still in place.
+    if staging.exists() {
+        fs::remove_dir_all(&staging)?;
+    }
+    for (source, target) in moves {
+        let staged = staging.join(target);
+        if let Some(parent) = staged.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(source, &staged)?;
+        let expected = model.files.iter().find(|file| file.path == *target).map(|file| file.sha256.as_str());
+        if expected != Some(sha256_file(&staged, DEFAULT_DOWNLOAD_BUFFER_SIZE)?.as_str()) {
+            return Err(InstallError::Checksum(format!("copy of {} does not match its lockfile hash", source.display())));
+        }
+    }
+    // The revision dir holds no onnx file here, so anything in it is an incomplete install.
+    if rev_dir.exists() {
+        fs::remove_dir_all(rev_dir)?;
+    }
+    fs::rename(&staging, rev_dir)?;
+    for source in moves.keys() {
+        fs::remove_file(source)?;
+    }
+    Ok(())
+}
+
+/// Lockfiles an older install left under `from`, as extra sources of known revision hashes.
+fn find_legacy_lockfiles(from: &Path, models_dir: &Path) -> Vec<ModelLock> {
+    WalkDir::new(from)
+        .max_depth(4)
+        .into_iter()
+        .filter_entry(|e| !e.path().starts_with(models_dir))
+        .filter_map(std::result::Result::ok)
+        .filter(|e| e.file_type().is_file() && e.file_name() == LOCKFILE_NAME)
+        .filter_map(|e| serde_json::from_slice(&fs::read(e.path()).ok()?).ok())
+        .collect()
+}
+
+/// Directories under `from` that look like a legacy install, skipping the current `models_dir`.
+fn find_legacy_model_dirs(from: &Path, models_dir: &Path) -> Result<Vec<(KnownModel, PathBuf)>> {
+    let mut found: Vec<(KnownModel, PathBuf)> = Vec::new();
+    let mut walker = WalkDir::new(from)
+        .max_depth(4)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| !e.path().starts_with(models_dir));
+    while let Some(entry) = walker.next() {
+        let Ok(entry) = entry else { continue };
+        if !entry.file_type().is_dir() {
+            continue;
+        }
+        let Some(kind) = entry.file_name().to_str().and_then(legacy_model_for_dir_name) else {
+            continue;
+        };
+        if found.iter().any(|(seen, _)| *seen == kind) || !has_onnx_file(entry.path())? {
+            continue;
+        }
+        found.push((kind, entry.path().to_path_buf()));
+        walker.skip_current_dir();
+    }
+    Ok(found)
+}
+
+fn legacy_model_for_dir_name(name: &str) -> Option<KnownModel> {
+    let normalize = |s: &str| -> String {
+        s.chars()
+            .filter(char::is_ascii_alphanumeric)
+            .map(|c| c.to_ascii_lowercase())
+            .collect()
+    };
+    let name = normalize(name);
+    KnownModel::all().into_iter().find(|kind| {
+        let repo = kind.model_id().rsplit('/').next().unwrap_or_default();
+        name.ends_with(&normalize(repo))
+    })
+}
+
+/// Source file to path inside the revision directory; the first file wins a name clash.
+fn legacy_file_moves(legacy_dir: &Path) -> BTreeMap<PathBuf, String> {
+    let mut targets = BTreeSet::new();
+    let mut moves = BTreeMap::new();
+    for entry in WalkDir::new(legacy_dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(std::result::Result::ok)
+        .filter(|e| e.file_type().is_file())
+    {
+        let Some(name) = entry.file_name().to_str() else { continue };
+        let target = if name.to_ascii_lowercase().ends_with(".onnx") {
+            format!("onnx/{}", name)
+        } else if matches!(name, "config.json" | "preprocessor_config.json") {
+            name.to_string()
+        } else {
+            continue;
+        };
+        if targets.insert(target.clone()) {
+            moves.insert(entry.path().to_path_buf(), target);
+        }
+    }
+    moves
+}
+
 fn has_onnx_file(revision_dir: &Path) -> Result<bool> {
     for entry in WalkDir::new(revision_dir)
         .into_iter()
@@ -189,68 +817,176 @@ fn require_gated_token_if_needed(
         let gated = manifest_by_id
             .get(model_id)
             .map(|m| m.gated)
-            .ok_or_else(|| anyhow!("model not found in manifest: {}", model_id))?;
+            .ok_or_else(|| InstallError::NotFound(format!("model not found in manifest: {}", model_id)))?;
         if gated && token.is_none() {
-            return Err(anyhow!("missing {} for gated model {}", token_env, model_id));
+            return Err(InstallError::MissingToken {
+                token_env: token_env.to_string(),
+                model_id: model_id.to_string(),
+            });
         }
     }
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 fn download_model_to_revision(
-    cache_downloads_dir: &Path,
+    temp_parent: &Path,
+    endpoint: &str,
     model_id: &str,
     revision: &str,
     token: Option<&str>,
     final_revision_dir: &Path,
     onnx_variant: OnnxVariant,
-) -> Result<LockModel> {
+    cancel: Option<&CancelToken>,
+    keep_on_failure: bool,
+    buffer_size: usize,
+    sink: Option<&dyn DownloadSink>,
+) -> Result<(LockModel, Vec<DownloadThroughput>)> {
     let client = hf_client(token)?;
-    let files = list_model_files(&client, model_id, revision, onnx_variant)?;
+    let files = list_model_files(&client, endpoint, model_id, revision, onnx_variant)?;
     if files.is_empty() {
-        return Err(anyhow!("no files listed for {}@{}", model_id, revision));
+        return Err(InstallError::NotFound(format!("no files listed for {}@{}", model_id, revision)));
     }
 
-    fs::create_dir_all(
-        final_revision_dir
-            .parent()
-            .ok_or_else(|| anyhow!("invalid final revision directory"))?,
-    )?;
+    if let Some(parent) = final_revision_dir.parent() {
+        fs::create_dir_all(parent)?;
+    }
 
-    let tempdir = Builder::new()
-        .prefix("unbg-download-")
-        .tempdir_in(cache_downloads_dir)?;
-    let temp_path = tempdir.path().to_path_buf();
+    let temp_path = download_staging_dir(temp_parent, model_id, revision);
+    fs::create_dir_all(&temp_path)?;
 
     let mut lock_entries = Vec::with_capacity(files.len());
+    let mut throughput = Vec::with_capacity(files.len());
     for relative_path in files {
         let local_path = temp_path.join(&relative_path);
         if let Some(parent) = local_path.parent() {
             fs::create_dir_all(parent)?;
         }
-        let (size, sha256) = download_file(&client, model_id, revision, &relative_path, &local_path)?;
+        let downloaded =
+            match download_file(
+                &client,
+                endpoint,
+                model_id,
+                revision,
+                &relative_path,
+                &local_path,
+                cancel,
+                buffer_size,
+                sink,
+            ) {
+                Ok(downloaded) => downloaded,
+                // The staging directory stays so the next run resumes the `.part` file.
+                Err(err @ InstallError::Cancelled(_)) => return Err(err),
+                Err(err) if keep_on_failure => {
+                    return Err(InstallError::PartialDownloadKept {
+                        kept_dir: temp_path,
+                        source: Box::new(err),
+                    });
+                }
+                Err(err) => {
+                    let _ = fs::remove_dir_all(&temp_path);
+                    return Err(err);
+                }
+            };
+        throughput.push(DownloadThroughput::new(model_id, &relative_path, downloaded.streamed, downloaded.elapsed));
         lock_entries.push(LockFileEntry {
             path: relative_path,
-            size,
-            sha256,
+            size: downloaded.size,
+            sha256: downloaded.sha256,
         });
     }
 
-    let kept = tempdir.keep();
-    fs::rename(&kept, final_revision_dir).or_else(|err| {
-        let _ = fs::remove_dir_all(&kept);
-        Err(err)
+    swap_into_place(&temp_path, final_revision_dir).inspect_err(|_| {
+        let _ = fs::remove_dir_all(&temp_path);
     })?;
 
-    Ok(LockModel {
+    let lock = LockModel {
         model_id: model_id.to_string(),
         revision: revision.to_string(),
         source: "huggingface".to_string(),
         files: lock_entries,
+    };
+    Ok((lock, throughput))
+}
+
+/// Staging directory for one revision's download. The name is fixed so a cancelled download's
+/// `.part` files are found again and resumed by the next run.
+fn download_staging_dir(temp_parent: &Path, model_id: &str, revision: &str) -> PathBuf {
+    temp_parent.join(format!("unbg-download-{}-{}", cache_key_for_model_id(model_id), revision))
+}
+
+fn download_temp_dir(request: &InstallRequest) -> Option<PathBuf> {
+    request.temp_dir.clone().or_else(|| {
+        env::var("UNBG_DOWNLOAD_TMP")
+            .ok()
+            .filter(|v| !v.trim().is_empty())
+            .map(PathBuf::from)
     })
 }
 
-fn lock_from_existing_dir(model_id: &str, revision: &str, revision_dir: &Path) -> Result<LockModel> {
+/// Moves the verified `staged` directory to `target`. An existing `target` is renamed aside
+/// first and restored if the move fails, so a failed reinstall never leaves the model missing.
+fn swap_into_place(staged: &Path, target: &Path) -> Result<()> {
+    if !target.exists() {
+        return Ok(move_dir(staged, target)?);
+    }
+    let mut aside = target.as_os_str().to_owned();
+    aside.push(format!(".replaced-{}", std::process::id()));
+    let aside = PathBuf::from(aside);
+    if aside.exists() {
+        fs::remove_dir_all(&aside)?;
+    }
+    fs::rename(target, &aside)?;
+    if let Err(err) = move_dir(staged, target) {
+        let _ = fs::remove_dir_all(target);
+        fs::rename(&aside, target)?;
+        return Err(err.into());
+    }
+    let _ = fs::remove_dir_all(&aside);
+    Ok(())
+}
+
+/// Renames `source` to `target`, copying instead when they are on different filesystems.
+fn move_dir(source: &Path, target: &Path) -> std::io::Result<()> {
+    move_dir_with(source, target, |from, to| fs::rename(from, to))
+}
+
+fn move_dir_with(
+    source: &Path,
+    target: &Path,
+    rename: impl FnOnce(&Path, &Path) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    match rename(source, target) {
+        Err(err) if err.kind() == std::io::ErrorKind::CrossesDevices => {
+            copy_dir(source, target).inspect_err(|_| {
+                let _ = fs::remove_dir_all(target);
+            })?;
+            fs::remove_dir_all(source)
+        }
+        other => other,
+    }
+}
+
+fn copy_dir(source: &Path, target: &Path) -> std::io::Result<()> {
+    for entry in WalkDir::new(source) {
+        let entry = entry.map_err(std::io::Error::other)?;
+        let relative = entry.path().strip_prefix(source).map_err(std::io::Error::other)?;
+        let destination = target.join(relative);
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&destination)?;
+        } else {
+            fs::copy(entry.path(), &destination)?;
+        }
+    }
+    Ok(())
+}
+
+fn keep_temp_on_failure(request: &InstallRequest) -> bool {
+    request.keep_temp_on_failure
+        || env::var("UNBG_KEEP_DOWNLOADS").is_ok_and(|v| matches!(v.trim(), "1" | "true" | "yes"))
+}
+
+fn lock_from_existing_dir(model_id: &str, revision: &str, revision_dir: &Path, buffer_size: usize) -> Result<LockModel> {
     let mut files = Vec::new();
     for entry in WalkDir::new(revision_dir)
         .into_iter()
@@ -260,14 +996,14 @@ fn lock_from_existing_dir(model_id: &str, revision: &str, revision_dir: &Path) -
         let full_path = entry.path();
         let rel = full_path
             .strip_prefix(revision_dir)
-            .context("failed to strip revision dir prefix")?
+            .map_err(|_| InstallError::NotFound(format!("{} is outside {}", full_path.display(), revision_dir.display())))?
             .to_string_lossy()
             .replace('\\', "/");
         let metadata = fs::metadata(full_path)?;
         files.push(LockFileEntry {
             path: rel,
             size: metadata.len(),
-            sha256: sha256_file(full_path)?,
+            sha256: sha256_file(full_path, buffer_size)?,
         });
     }
     files.sort_by(|a, b| a.path.cmp(&b.path));
@@ -279,6 +1015,14 @@ fn lock_from_existing_dir(model_id: &str, revision: &str, revision_dir: &Path) -
     })
 }
 
+fn hf_endpoint() -> String {
+    env::var("HF_ENDPOINT")
+        .ok()
+        .map(|v| v.trim().trim_end_matches('/').to_string())
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| "https://huggingface.co".to_string())
+}
+
 fn hf_client(token: Option<&str>) -> Result<Client> {
     let mut headers = HeaderMap::new();
     headers.insert(USER_AGENT, HeaderValue::from_static("unbg-installer/0.1"));
@@ -286,7 +1030,7 @@ fn hf_client(token: Option<&str>) -> Result<Client> {
         let value = format!("Bearer {}", token);
         headers.insert(
             AUTHORIZATION,
-            HeaderValue::from_str(&value).context("invalid token for authorization header")?,
+            HeaderValue::from_str(&value).map_err(|_| InstallError::InvalidToken)?,
         );
     }
     Ok(Client::builder().default_headers(headers).build()?)
@@ -299,18 +1043,19 @@ struct HfTreeEntry {
     kind: String,
 }
 
-fn list_model_files(client: &Client, model_id: &str, revision: &str, onnx_variant: OnnxVariant) -> Result<Vec<String>> {
-    let url = format!(
-        "https://huggingface.co/api/models/{}/tree/{}?recursive=1",
-        model_id, revision
-    );
+fn list_model_files(
+    client: &Client,
+    endpoint: &str,
+    model_id: &str,
+    revision: &str,
+    onnx_variant: OnnxVariant,
+) -> Result<Vec<String>> {
+    let url = format!("{}/api/models/{}/tree/{}?recursive=1", endpoint, model_id, revision);
     let response = client.get(url).send()?;
     if !response.status().is_success() {
-        return Err(anyhow!(
-            "failed to list files for {}@{}: {}",
-            model_id,
-            revision,
-            response.status()
+        return Err(http_status_error(
+            response.status(),
+            format!("failed to list files for {}@{}", model_id, revision),
         ));
     }
     let entries: Vec<HfTreeEntry> = response.json()?;
@@ -356,19 +1101,31 @@ fn filter_model_files_for_variant(all_files: &[String], onnx_variant: OnnxVarian
     out
 }
 
+#[derive(Debug)]
+struct DownloadedFile {
+    size: u64,
+    sha256: String,
+    /// Bytes read from the response, excluding a resumed partial file.
+    streamed: u64,
+    elapsed: Duration,
+}
+
+#[allow(clippy::too_many_arguments)]
 fn download_file(
     client: &Client,
+    endpoint: &str,
     model_id: &str,
     revision: &str,
     file_path: &str,
     destination: &Path,
-) -> Result<(u64, String)> {
-    let url = format!(
-        "https://huggingface.co/{}/resolve/{}/{}",
-        model_id, revision, file_path
-    );
+    cancel: Option<&CancelToken>,
+    buffer_size: usize,
+    sink: Option<&dyn DownloadSink>,
+) -> Result<DownloadedFile> {
+    let started = Instant::now();
+    let url = format!("{}/{}/resolve/{}/{}", endpoint, model_id, revision, file_path);
     let partial_path = destination.with_extension("part");
-    let resume_from = fs::metadata(&partial_path).map(|m| m.len()).unwrap_or(0);
+    let mut resume_from = fs::metadata(&partial_path).map(|m| m.len()).unwrap_or(0);
     let mut response = if resume_from > 0 {
         client
             .get(url.clone())
@@ -377,60 +1134,91 @@ fn download_file(
     } else {
         client.get(url.clone()).send()?
     };
-    if resume_from > 0 && response.status().as_u16() == 200 {
-        let _ = fs::remove_file(&partial_path);
-        response = client.get(url).send()?;
+    if resume_from > 0 {
+        match response.status().as_u16() {
+            // The server ignored the range and is sending the whole file, so take it from byte 0.
+            200 => resume_from = 0,
+            // The partial file is not a prefix the server knows; start over.
+            416 => {
+                resume_from = 0;
+                response = client.get(url).send()?;
+            }
+            _ => {}
+        }
     }
     if !response.status().is_success() {
-        return Err(anyhow!(
-            "failed downloading {} for {}@{}: {}",
-            file_path,
-            model_id,
-            revision,
-            response.status()
+        return Err(http_status_error(
+            response.status(),
+            format!("failed downloading {} for {}@{}", file_path, model_id, revision),
         ));
     }
 
+    // Only a partial file the server agreed to continue is kept and re-hashed; otherwise it is truncated.
     let mut file = if resume_from > 0 {
-        fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&partial_path)?
+        fs::OpenOptions::new().append(true).open(&partial_path)?
     } else {
         File::create(&partial_path)?
     };
     let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; buffer_size.max(1)];
     if resume_from > 0 {
         let mut existing = File::open(&partial_path)?;
-        let mut existing_buf = [0u8; 16 * 1024];
         loop {
-            let read = existing.read(&mut existing_buf)?;
+            let read = existing.read(&mut buf)?;
             if read == 0 {
                 break;
             }
-            hasher.update(&existing_buf[..read]);
+            hasher.update(&buf[..read]);
+            if let Some(sink) = sink {
+                sink.write(file_path, &buf[..read])?;
+            }
         }
     }
-    let mut buf = [0u8; 16 * 1024];
+    let mut streamed = 0u64;
     loop {
+        if cancel.is_some_and(CancelToken::is_cancelled) {
+            file.flush()?;
+            return Err(InstallError::Cancelled(DownloadCancelled { partial_path }));
+        }
         let read = response.read(&mut buf)?;
         if read == 0 {
             break;
         }
         file.write_all(&buf[..read])?;
+        streamed += read as u64;
         hasher.update(&buf[..read]);
+        if let Some(sink) = sink {
+            sink.write(file_path, &buf[..read])?;
+        }
     }
     file.flush()?;
-    fs::rename(&partial_path, destination)?;
-    let total_size = fs::metadata(destination)?.len();
+    let total_size = file.metadata()?.len();
     let digest = hex::encode(hasher.finalize());
-    Ok((total_size, digest))
+    if let Some(sink) = sink {
+        sink.finish(file_path, total_size, &digest)?;
+    }
+    fs::rename(&partial_path, destination)?;
+    Ok(DownloadedFile {
+        size: total_size,
+        sha256: digest,
+        streamed,
+        elapsed: started.elapsed(),
+    })
+}
+
+fn http_status_error(status: reqwest::StatusCode, context: String) -> InstallError {
+    if status == reqwest::StatusCode::NOT_FOUND {
+        InstallError::NotFound(format!("{}: {}", context, status))
+    } else {
+        InstallError::Network(format!("{}: {}", context, status))
+    }
 }
 
-fn sha256_file(path: &Path) -> Result<String> {
+/// Hashes `path`, reading it `buffer_size` bytes at a time.
+fn sha256_file(path: &Path, buffer_size: usize) -> Result<String> {
     let mut file = File::open(path)?;
     let mut hasher = Sha256::new();
-    let mut buf = [0u8; 16 * 1024];
+    let mut buf = vec![0u8; buffer_size.max(1)];
     loop {
         let read = file.read(&mut buf)?;
         if read == 0 {
@@ -442,26 +1230,877 @@ fn sha256_file(path: &Path) -> Result<String> {
 }
 
 fn validate_lock_models(paths: &unbg_model_registry::ModelPaths, models: &[LockModel]) -> Result<()> {
+    let registered = read_custom_models(paths)?;
     for model in models {
-        if model.files.is_empty() {
-            return Err(anyhow!("model {} has no tracked files", model.model_id));
-        }
-        let kind = KnownModel::from_model_id(&model.model_id)
-            .ok_or_else(|| anyhow!("unknown model id in lock entries: {}", model.model_id))?;
-        let revision_dir = model_revision_dir(paths, kind, &model.revision);
-        if !has_onnx_file(&revision_dir)? {
-            return Err(anyhow!(
-                "revision {} for {} has no onnx file",
-                model.revision,
-                model.model_id
-            ));
+        check_lock_model(model, &registered)?;
+        validate_revision_dir(model, &model_id_revision_dir(paths, &model.model_id, &model.revision))?;
+    }
+    Ok(())
+}
+
+/// Checks that `revision_dir` holds every file `model` tracks, at least one of them `.onnx`.
+fn validate_revision_dir(model: &LockModel, revision_dir: &Path) -> Result<()> {
+    if model.files.is_empty() {
+        return Err(InstallError::NotFound(format!("model {} has no tracked files", model.model_id)));
+    }
+    if !has_onnx_file(revision_dir)? {
+        return Err(InstallError::NotFound(format!(
+            "revision {} for {} has no onnx file",
+            model.revision, model.model_id
+        )));
+    }
+    for entry in &model.files {
+        let full = revision_dir.join(&entry.path);
+        if !full.exists() {
+            return Err(InstallError::NotFound(format!("missing file before lock write: {}", full.display())));
         }
-        for entry in &model.files {
-            let full = revision_dir.join(&entry.path);
-            if !full.exists() {
-                return Err(anyhow!("missing file before lock write: {}", full.display()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    /// The request path and the start of a `Range: bytes=<start>-` header, if any.
+    fn read_request(stream: &TcpStream) -> (String, Option<usize>) {
+        let mut reader = BufReader::new(stream.try_clone().expect("clone stream"));
+        let mut request_line = String::new();
+        let _ = reader.read_line(&mut request_line);
+        let mut range = None;
+        let mut line = String::new();
+        while reader.read_line(&mut line).map(|n| n > 2).unwrap_or(false) {
+            let lower = line.to_ascii_lowercase();
+            if let Some(value) = lower.strip_prefix("range: bytes=") {
+                range = value.trim().trim_end_matches('-').parse().ok();
             }
+            line.clear();
         }
+        (request_line.split_whitespace().nth(1).unwrap_or("/").to_string(), range)
+    }
+
+    /// Serves fixed bodies by request path (query string ignored), honouring `Range` requests,
+    /// and counts file downloads.
+    fn serve_routes(routes: Vec<(&'static str, Vec<u8>)>) -> (String, Arc<AtomicUsize>) {
+        let (endpoint, downloads, _) = serve_routes_recording_ranges(routes);
+        (endpoint, downloads)
+    }
+
+    fn serve_routes_recording_ranges(
+        routes: Vec<(&'static str, Vec<u8>)>,
+    ) -> (String, Arc<AtomicUsize>, Arc<Mutex<Vec<usize>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock server");
+        let addr = listener.local_addr().expect("mock server addr");
+        let downloads = Arc::new(AtomicUsize::new(0));
+        let counter = downloads.clone();
+        let ranges = Arc::new(Mutex::new(Vec::new()));
+        let seen_ranges = ranges.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { continue };
+                let (path, range) = read_request(&stream);
+                let path = path.split('?').next().unwrap_or("").to_string();
+                let response = match routes.iter().find(|(route, _)| *route == path) {
+                    Some((route, body)) => {
+                        if route.contains("/resolve/") {
+                            counter.fetch_add(1, Ordering::SeqCst);
+                        }
+                        let (status, body) = match range {
+                            Some(start) if start <= body.len() => {
+                                seen_ranges.lock().unwrap().push(start);
+                                ("206 Partial Content", &body[start..])
+                            }
+                            _ => ("200 OK", &body[..]),
+                        };
+                        let mut out = format!(
+                            "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                            status,
+                            body.len()
+                        )
+                        .into_bytes();
+                        out.extend_from_slice(body);
+                        out
+                    }
+                    None => b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_vec(),
+                };
+                let _ = stream.write_all(&response);
+            }
+        });
+        (format!("http://{}", addr), downloads, ranges)
+    }
+
+    fn rmbg14_hub(onnx_body: &[u8]) -> (String, Arc<AtomicUsize>) {
+        serve_routes(vec![
+            (
+                "/api/models/briaai/RMBG-1.4/tree/main",
+                br#"[{"path":"onnx/model_fp16.onnx","type":"file"}]"#.to_vec(),
+            ),
+            ("/briaai/RMBG-1.4/resolve/main/onnx/model_fp16.onnx", onnx_body.to_vec()),
+        ])
+    }
+
+    fn rmbg14_request(model_dir: &Path) -> InstallRequest {
+        InstallRequest {
+            model_dir: Some(model_dir.to_path_buf()),
+            install_all: false,
+            models: vec![KnownModel::Rmbg14],
+            custom_models: Vec::new(),
+            hf_token_env: "UNBG_TEST_UNSET_TOKEN".to_string(),
+            revision_rmbg14: "main".to_string(),
+            revision_rmbg20: "main".to_string(),
+            verify_only: false,
+            onnx_variant: OnnxVariant::Fp16,
+            force: false,
+            keep_temp_on_failure: false,
+            temp_dir: None,
+            download_buffer_size: DEFAULT_DOWNLOAD_BUFFER_SIZE,
+            report_throughput: false,
+            cancel: None,
+            download_sink: None,
+        }
+    }
+
+    fn seed_revision(model_dir: &Path, onnx_body: &[u8]) -> PathBuf {
+        let paths = resolve_model_paths(Some(model_dir)).expect("paths");
+        let rev_dir = model_revision_dir(&paths, KnownModel::Rmbg14, "main");
+        fs::create_dir_all(rev_dir.join("onnx")).expect("create revision dir");
+        fs::write(rev_dir.join("onnx").join("model_fp16.onnx"), onnx_body).expect("seed onnx");
+        rev_dir
+    }
+
+    #[test]
+    fn force_redownloads_even_when_files_are_present() {
+        let (endpoint, downloads) = rmbg14_hub(b"fresh-bytes");
+        let dir = tempfile::tempdir().expect("tempdir");
+        let rev_dir = seed_revision(dir.path(), b"stale");
+
+        let skipped = install_models_from(&rmbg14_request(dir.path()), &endpoint).expect("plain install");
+        assert_eq!(skipped.skipped, vec!["briaai/RMBG-1.4".to_string()]);
+        assert_eq!(downloads.load(Ordering::SeqCst), 0);
+
+        let mut request = rmbg14_request(dir.path());
+        request.force = true;
+        let report = install_models_from(&request, &endpoint).expect("forced install");
+
+        assert_eq!(report.installed, vec!["briaai/RMBG-1.4".to_string()]);
+        assert_eq!(downloads.load(Ordering::SeqCst), 1);
+        let onnx = rev_dir.join("onnx").join("model_fp16.onnx");
+        assert_eq!(fs::read(&onnx).expect("read onnx"), b"fresh-bytes");
+        let lock = verify_models(Some(dir.path().to_path_buf())).expect("lockfile verifies");
+        assert_eq!(lock.models[0].files[0].sha256, sha256_file(&onnx, DEFAULT_DOWNLOAD_BUFFER_SIZE).expect("hash"));
+    }
+
+    #[test]
+    fn failed_forced_reinstall_keeps_the_installed_revision() {
+        let (endpoint, _) = serve_routes(vec![(
+            "/api/models/briaai/RMBG-1.4/tree/main",
+            br#"[{"path":"onnx/model_fp16.onnx","type":"file"}]"#.to_vec(),
+        )]);
+        let dir = tempfile::tempdir().expect("tempdir");
+        let rev_dir = seed_revision(dir.path(), b"installed");
+        let mut request = rmbg14_request(dir.path());
+        request.force = true;
+
+        install_models_from(&request, &endpoint).expect_err("download 404s");
+
+        assert_eq!(fs::read(rev_dir.join("onnx").join("model_fp16.onnx")).unwrap(), b"installed");
+        let siblings = fs::read_dir(rev_dir.parent().unwrap()).unwrap().count();
+        assert_eq!(siblings, 1, "no leftover directories next to the revision");
+    }
+
+    #[test]
+    fn custom_model_installs_by_repo_id_and_is_registered() {
+        let (endpoint, _) = serve_routes(vec![
+            (
+                "/api/models/acme/product-matting/tree/v2",
+                br#"[{"path":"onnx/model_fp16.onnx","type":"file"}]"#.to_vec(),
+            ),
+            ("/acme/product-matting/resolve/v2/onnx/model_fp16.onnx", b"custom-bytes".to_vec()),
+        ]);
+        let dir = tempfile::tempdir().expect("tempdir");
+        let spec = CustomModel {
+            id: "acme/product-matting".to_string(),
+            revision: "v2".to_string(),
+            input_size: 512,
+            normalization: unbg_model_registry::Normalization::IMAGENET,
+        };
+        let mut request = rmbg14_request(dir.path());
+        request.models.clear();
+        request.custom_models = vec![spec.clone()];
+
+        let report = install_models_from(&request, &endpoint).expect("custom install");
+        assert_eq!(report.installed, vec!["acme/product-matting".to_string()]);
+        let lock = verify_models(Some(dir.path().to_path_buf())).expect("lockfile verifies");
+        assert_eq!(lock.models.len(), 1);
+        assert_eq!(lock.models[0].model_id, "acme/product-matting");
+        let paths = resolve_model_paths(Some(dir.path())).expect("paths");
+        let (registered, installed) =
+            unbg_model_registry::find_custom_model(std::slice::from_ref(&paths), &spec.id).expect("registered");
+        assert_eq!(registered, spec);
+        assert_eq!(fs::read(installed.revision_dir.join("onnx/model_fp16.onnx")).expect("onnx"), b"custom-bytes");
+    }
+
+    /// Serves a single GET with `chunks` written one by one, running `between` after the first chunk.
+    fn serve_chunked_once(chunks: Vec<Vec<u8>>, between: impl FnOnce() + Send + 'static) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock server");
+        let addr = listener.local_addr().expect("mock server addr");
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().expect("accept");
+            let mut reader = BufReader::new(stream.try_clone().expect("clone stream"));
+            let mut line = String::new();
+            while reader.read_line(&mut line).map(|n| n > 2).unwrap_or(false) {
+                line.clear();
+            }
+            let total: usize = chunks.iter().map(Vec::len).sum();
+            let header = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nContent-Type: application/octet-stream\r\n\r\n",
+                total
+            );
+            let _ = stream.write_all(header.as_bytes());
+            let mut between = Some(between);
+            for chunk in chunks {
+                if stream.write_all(&chunk).is_err() {
+                    return;
+                }
+                let _ = stream.flush();
+                thread::sleep(Duration::from_millis(150));
+                if let Some(hook) = between.take() {
+                    hook();
+                }
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    #[test]
+    fn cancelled_download_keeps_partial_file() {
+        let cancel = CancelToken::new();
+        let trigger = cancel.clone();
+        let endpoint = serve_chunked_once(vec![vec![1u8; 1024], vec![2u8; 1024], vec![3u8; 1024]], move || {
+            trigger.cancel()
+        });
+        let dir = tempfile::tempdir().expect("tempdir");
+        let destination = dir.path().join("model.onnx");
+
+        let err = download_file(
+            &Client::new(),
+            &endpoint,
+            "briaai/RMBG-1.4",
+            "main",
+            "onnx/model.onnx",
+            &destination,
+            Some(&cancel),
+            DEFAULT_DOWNLOAD_BUFFER_SIZE,
+            None,
+        )
+        .expect_err("download should be cancelled");
+
+        let InstallError::Cancelled(cancelled) = err else {
+            panic!("expected typed cancellation error, got {:?}", err);
+        };
+        assert_eq!(cancelled.partial_path, destination.with_extension("part"));
+        let partial_len = fs::metadata(&cancelled.partial_path).expect("partial kept").len();
+        assert!((1024..3 * 1024).contains(&partial_len), "partial length {}", partial_len);
+        assert!(!destination.exists());
+    }
+
+    #[test]
+    fn install_resumes_a_cancelled_partial_download() {
+        let body: Vec<u8> = (0..=255u8).cycle().take(4096).collect();
+        let (endpoint, _, ranges) = serve_routes_recording_ranges(vec![
+            (
+                "/api/models/briaai/RMBG-1.4/tree/main",
+                br#"[{"path":"onnx/model_fp16.onnx","type":"file"}]"#.to_vec(),
+            ),
+            ("/briaai/RMBG-1.4/resolve/main/onnx/model_fp16.onnx", body.clone()),
+        ]);
+        let dir = tempfile::tempdir().expect("tempdir");
+        let paths = resolve_model_paths(Some(dir.path())).expect("paths");
+        // What a cancelled run leaves behind.
+        let staging = download_staging_dir(&paths.cache_downloads_dir, "briaai/RMBG-1.4", "main");
+        fs::create_dir_all(staging.join("onnx")).expect("staging dir");
+        fs::write(staging.join("onnx").join("model_fp16.part"), &body[..1000]).expect("partial file");
+
+        install_models_from(&rmbg14_request(dir.path()), &endpoint).expect("resumed install");
+
+        assert_eq!(*ranges.lock().unwrap(), vec![1000]);
+        let onnx = model_revision_dir(&paths, KnownModel::Rmbg14, "main").join("onnx").join("model_fp16.onnx");
+        assert_eq!(fs::read(&onnx).expect("onnx"), body);
+        let lock = verify_models(Some(dir.path().to_path_buf())).expect("lockfile verifies");
+        assert_eq!(lock.models[0].files[0].sha256, hex::encode(Sha256::digest(&body)));
+        assert!(!staging.exists());
+    }
+
+    #[test]
+    fn full_response_to_a_range_request_replaces_the_partial_file() {
+        let body: Vec<u8> = (0..=255u8).cycle().take(4096).collect();
+        let (endpoint, downloads, ranges) = serve_routes_recording_ranges(vec![
+            (
+                "/api/models/briaai/RMBG-1.4/tree/main",
+                br#"[{"path":"onnx/model_fp16.onnx","type":"file"}]"#.to_vec(),
+            ),
+            ("/briaai/RMBG-1.4/resolve/main/onnx/model_fp16.onnx", body.clone()),
+        ]);
+        let dir = tempfile::tempdir().expect("tempdir");
+        let paths = resolve_model_paths(Some(dir.path())).expect("paths");
+        // Longer than the file, so the mock server answers the range with a plain 200.
+        let staging = download_staging_dir(&paths.cache_downloads_dir, "briaai/RMBG-1.4", "main");
+        fs::create_dir_all(staging.join("onnx")).expect("staging dir");
+        fs::write(staging.join("onnx").join("model_fp16.part"), vec![9u8; 5000]).expect("partial file");
+
+        install_models_from(&rmbg14_request(dir.path()), &endpoint).expect("restarted install");
+
+        assert!(ranges.lock().unwrap().is_empty());
+        assert_eq!(downloads.load(Ordering::SeqCst), 1, "the 200 body is used, not fetched again");
+        let onnx = model_revision_dir(&paths, KnownModel::Rmbg14, "main").join("onnx").join("model_fp16.onnx");
+        assert_eq!(fs::read(&onnx).expect("onnx"), body);
+        let lock = verify_models(Some(dir.path().to_path_buf())).expect("lockfile verifies");
+        assert_eq!(lock.models[0].files[0].sha256, hex::encode(Sha256::digest(&body)));
+    }
+
+    #[test]
+    fn failed_install_keeps_staging_directory_only_when_asked() {
+        let (endpoint, _downloads) = serve_routes(vec![
+            (
+                "/api/models/briaai/RMBG-1.4/tree/main",
+                br#"[{"path":"onnx/model_fp16.onnx","type":"file"},{"path":"config.json","type":"file"}]"#.to_vec(),
+            ),
+            ("/briaai/RMBG-1.4/resolve/main/onnx/model_fp16.onnx", b"onnx-bytes".to_vec()),
+        ]);
+        let dir = tempfile::tempdir().expect("tempdir");
+        let paths = resolve_model_paths(Some(dir.path())).expect("paths");
+        let staged = || -> Vec<PathBuf> {
+            fs::read_dir(&paths.cache_downloads_dir)
+                .map(|entries| entries.filter_map(|e| e.ok().map(|e| e.path())).collect())
+                .unwrap_or_default()
+        };
+
+        install_models_from(&rmbg14_request(dir.path()), &endpoint).expect_err("missing config.json should fail");
+        assert!(staged().is_empty(), "staging dir should be removed: {:?}", staged());
+
+        let mut request = rmbg14_request(dir.path());
+        request.keep_temp_on_failure = true;
+        let err = install_models_from(&request, &endpoint).expect_err("missing config.json should fail");
+        let kept = staged();
+        assert_eq!(kept.len(), 1);
+        assert!(format!("{:#}", err).contains(&kept[0].display().to_string()), "{:#}", err);
+        assert_eq!(fs::read(kept[0].join("onnx").join("model_fp16.onnx")).unwrap(), b"onnx-bytes");
+    }
+
+    #[test]
+    fn download_stages_in_temp_dir_and_copies_across_devices() {
+        let (endpoint, _downloads) = rmbg14_hub(b"onnx-bytes");
+        let dir = tempfile::tempdir().expect("tempdir");
+        let temp = tempfile::tempdir().expect("tempdir");
+        let mut request = rmbg14_request(dir.path());
+        request.temp_dir = Some(temp.path().join("staging"));
+        install_models_from(&request, &endpoint).expect("install");
+
+        let paths = resolve_model_paths(Some(dir.path())).expect("paths");
+        let rev_dir = model_revision_dir(&paths, KnownModel::Rmbg14, "main");
+        assert_eq!(fs::read(rev_dir.join("onnx").join("model_fp16.onnx")).unwrap(), b"onnx-bytes");
+        assert_eq!(fs::read_dir(temp.path().join("staging")).unwrap().count(), 0);
+
+        let source = temp.path().join("download");
+        fs::create_dir_all(source.join("onnx")).unwrap();
+        fs::write(source.join("onnx").join("model.onnx"), b"weights").unwrap();
+        fs::write(source.join("config.json"), b"{}").unwrap();
+        let target = dir.path().join("moved");
+        move_dir_with(&source, &target, |_, _| Err(std::io::ErrorKind::CrossesDevices.into())).expect("copy fallback");
+
+        assert!(!source.exists());
+        assert_eq!(fs::read(target.join("onnx").join("model.onnx")).unwrap(), b"weights");
+        assert_eq!(fs::read(target.join("config.json")).unwrap(), b"{}");
+
+        let other = temp.path().join("other");
+        fs::create_dir_all(&other).unwrap();
+        let err = move_dir_with(&other, &dir.path().join("nope"), |_, _| Err(std::io::ErrorKind::PermissionDenied.into()))
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+        assert!(other.exists());
+    }
+
+    #[test]
+    fn small_download_buffer_still_hashes_correctly() {
+        let body: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        let (endpoint, _downloads) = rmbg14_hub(&body);
+        let dir = tempfile::tempdir().expect("tempdir");
+        let mut request = rmbg14_request(dir.path());
+        request.download_buffer_size = 7;
+        install_models_from(&request, &endpoint).expect("install with small buffer");
+
+        let lock = verify_models(Some(dir.path().to_path_buf())).expect("lockfile verifies");
+        assert_eq!(lock.models[0].files[0].sha256, hex::encode(Sha256::digest(&body)));
+        assert_eq!(lock.models[0].files[0].size, body.len() as u64);
+
+        // The installed revision is re-hashed with the same buffer when the install is skipped.
+        let report = install_models_from(&request, &endpoint).expect("skipped install");
+        assert_eq!(report.skipped, vec!["briaai/RMBG-1.4".to_string()]);
+        let paths = resolve_model_paths(Some(dir.path())).expect("paths");
+        let onnx = model_revision_dir(&paths, KnownModel::Rmbg14, "main").join("onnx").join("model_fp16.onnx");
+        assert_eq!(sha256_file(&onnx, 7).expect("hash"), hex::encode(Sha256::digest(&body)));
+    }
+
+    #[derive(Debug, Default)]
+    struct CountingSink {
+        written: AtomicUsize,
+        finished: std::sync::Mutex<Vec<(String, u64, String)>>,
+        reject: bool,
+    }
+
+    impl DownloadSink for CountingSink {
+        fn write(&self, _file_path: &str, bytes: &[u8]) -> std::io::Result<()> {
+            self.written.fetch_add(bytes.len(), Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn finish(&self, file_path: &str, size: u64, sha256: &str) -> std::io::Result<()> {
+            self.finished
+                .lock()
+                .unwrap()
+                .push((file_path.to_string(), size, sha256.to_string()));
+            if self.reject {
+                return Err(std::io::Error::other("rejected by policy"));
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn download_sink_sees_every_byte() {
+        let body: Vec<u8> = (0..5_000u32).map(|i| (i % 253) as u8).collect();
+        let (endpoint, _downloads) = rmbg14_hub(&body);
+        let dir = tempfile::tempdir().expect("tempdir");
+        let sink = Arc::new(CountingSink::default());
+        let mut request = rmbg14_request(dir.path());
+        request.download_buffer_size = 512;
+        request.download_sink = Some(sink.clone());
+        install_models_from(&request, &endpoint).expect("install with sink");
+
+        let lock = verify_models(Some(dir.path().to_path_buf())).expect("lockfile verifies");
+        let file = &lock.models[0].files[0];
+        assert_eq!(sink.written.load(Ordering::SeqCst) as u64, file.size);
+        assert_eq!(
+            *sink.finished.lock().unwrap(),
+            vec![(file.path.clone(), file.size, file.sha256.clone())]
+        );
+    }
+
+    #[test]
+    fn download_sink_rejecting_a_file_keeps_it_out_of_the_model_dir() {
+        let (endpoint, _downloads) = rmbg14_hub(b"weights");
+        let dir = tempfile::tempdir().expect("tempdir");
+        let sink = Arc::new(CountingSink {
+            reject: true,
+            ..CountingSink::default()
+        });
+        let mut request = rmbg14_request(dir.path());
+        request.download_sink = Some(sink.clone());
+        let err = install_models_from(&request, &endpoint).expect_err("sink rejects the file");
+        assert!(err.to_string().contains("rejected by policy"), "{}", err);
+        assert_eq!(sink.finished.lock().unwrap().len(), 1);
+        let paths = resolve_model_paths(Some(dir.path())).expect("paths");
+        assert!(!model_revision_dir(&paths, KnownModel::Rmbg14, "main").exists());
+    }
+
+    #[test]
+    fn throughput_is_reported_only_when_requested() {
+        let body = vec![7u8; 20_000];
+        let (endpoint, _downloads) = rmbg14_hub(&body);
+        let dir = tempfile::tempdir().expect("tempdir");
+        let mut request = rmbg14_request(dir.path());
+        request.force = true;
+        assert!(install_models_from(&request, &endpoint).expect("install").throughput.is_empty());
+
+        request.report_throughput = true;
+        let report = install_models_from(&request, &endpoint).expect("install with throughput");
+        assert_eq!(report.throughput.len(), 1);
+        let file = &report.throughput[0];
+        assert_eq!(file.model_id, "briaai/RMBG-1.4");
+        assert_eq!(file.path, "onnx/model_fp16.onnx");
+        assert_eq!(file.bytes, body.len() as u64);
+        assert!(file.bytes_per_sec > 0.0 && file.bytes_per_sec.is_finite());
+    }
+
+    #[test]
+    fn gated_model_without_token_is_missing_token_error() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let mut request = rmbg14_request(dir.path());
+        request.models = vec![KnownModel::Rmbg20];
+        let err = install_models_from(&request, "http://127.0.0.1:9").expect_err("gated install needs a token");
+        assert!(
+            matches!(&err, InstallError::MissingToken { token_env, model_id }
+                if token_env == "UNBG_TEST_UNSET_TOKEN" && model_id == "briaai/RMBG-2.0"),
+            "{:?}",
+            err
+        );
+        assert_eq!(err.code(), "missing-token");
+    }
+
+    /// Writes a deflated zip holding `entries`.
+    fn write_zip(path: &Path, entries: &[(&str, &[u8])]) {
+        let mut zip = zip::ZipWriter::new(File::create(path).expect("create zip"));
+        for (name, body) in entries {
+            zip.start_file(*name, zip::write::SimpleFileOptions::default())
+                .expect("start zip entry");
+            zip.write_all(body).expect("write zip entry");
+        }
+        zip.finish().expect("finish zip");
+    }
+
+    fn rmbg14_bundle_lock(onnx_body: &[u8]) -> Vec<u8> {
+        let lock = ModelLock {
+            schema_version: 1,
+            generated_at: "0".to_string(),
+            models: vec![LockModel {
+                model_id: "briaai/RMBG-1.4".to_string(),
+                revision: "main".to_string(),
+                source: "bundle".to_string(),
+                files: vec![LockFileEntry {
+                    path: "onnx/model_fp16.onnx".to_string(),
+                    size: onnx_body.len() as u64,
+                    sha256: hex::encode(Sha256::digest(onnx_body)),
+                }],
+            }],
+        };
+        serde_json::to_vec(&lock).expect("serialize lock")
+    }
+
+    #[test]
+    fn installs_models_from_a_zip_bundle() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let onnx_body = b"fake onnx weights";
+        let lock = rmbg14_bundle_lock(onnx_body);
+        let archive = dir.path().join("bundle.zip");
+        write_zip(
+            &archive,
+            &[
+                ("bundle/manifests/unbg-model-lock.json", &lock),
+                ("bundle/models/briaai__RMBG-1.4/main/onnx/model_fp16.onnx", onnx_body),
+            ],
+        );
+
+        let model_dir = dir.path().join("models");
+        let report = install_from_archive(&archive, Some(&model_dir)).expect("install from archive");
+        assert_eq!(report.installed, vec!["briaai/RMBG-1.4".to_string()]);
+        let verified = verify_models(Some(model_dir)).expect("bundle verifies");
+        assert_eq!(verified.models.len(), 1);
+    }
+
+    #[test]
+    fn archive_entries_outside_the_bundle_are_rejected() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let archive = dir.path().join("evil.zip");
+        write_zip(&archive, &[("../evil.txt", b"nope")]);
+        let err = install_from_archive(&archive, Some(&dir.path().join("models"))).expect_err("traversal rejected");
+        assert!(matches!(err, InstallError::InvalidArchive(_)), "{:?}", err);
+        assert!(!dir.path().join("evil.txt").exists());
+    }
+
+    #[test]
+    fn long_tar_names_round_trip_as_gnu_records() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let source = dir.path().join("weights.onnx");
+        fs::write(&source, b"long-named weights").expect("seed source");
+        let long_name = format!("bundle/models/{}/main/onnx/model.onnx", "n".repeat(120));
+        let archive = dir.path().join("bundle.tar.gz");
+        archive::write_tar_gz(&archive, &[(long_name.clone(), source)]).expect("write tar");
+
+        let mut raw = Vec::new();
+        flate2::read::GzDecoder::new(File::open(&archive).expect("open tar"))
+            .read_to_end(&mut raw)
+            .expect("gunzip");
+        assert_eq!(raw[156], b'L', "long name record first");
+        assert_eq!(&raw[257..265], b"ustar  \0", "long name record has a GNU header");
+        let mut entries = tar::Archive::new(raw.as_slice());
+        let names: Vec<_> = entries
+            .entries()
+            .expect("entries")
+            .map(|entry| entry.expect("entry").path().expect("path").to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, vec![long_name.clone()]);
+
+        let extracted = dir.path().join("extracted");
+        archive::extract_archive(&archive, &extracted).expect("extract");
+        assert_eq!(fs::read(extracted.join(&long_name)).expect("extracted file"), b"long-named weights");
+    }
+
+    #[test]
+    fn bundle_with_tampered_file_fails_checksum() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let lock = rmbg14_bundle_lock(b"original weights");
+        let archive = dir.path().join("bundle.zip");
+        write_zip(
+            &archive,
+            &[
+                ("manifests/unbg-model-lock.json", &lock),
+                ("models/briaai__RMBG-1.4/main/onnx/model_fp16.onnx", b"tampered weights"),
+            ],
+        );
+        let model_dir = dir.path().join("models");
+        let err = install_from_archive(&archive, Some(&model_dir)).expect_err("hash mismatch");
+        assert_eq!(err.code(), "checksum");
+        let paths = resolve_model_paths(Some(&model_dir)).expect("paths");
+        assert!(!model_revision_dir(&paths, KnownModel::Rmbg14, "main").exists());
+    }
+
+    #[test]
+    fn bundle_revisions_and_file_lists_are_checked_before_install() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let model_dir = dir.path().join("models");
+        let bundle = |edit: &dyn Fn(&mut LockModel)| {
+            let mut lock: ModelLock = serde_json::from_slice(&rmbg14_bundle_lock(b"weights")).expect("lock");
+            edit(&mut lock.models[0]);
+            let archive = dir.path().join("bundle.zip");
+            write_zip(
+                &archive,
+                &[
+                    ("manifests/unbg-model-lock.json", &serde_json::to_vec(&lock).expect("serialize")),
+                    ("models/briaai__RMBG-1.4/main/onnx/model_fp16.onnx", b"weights"),
+                ],
+            );
+            install_from_archive(&archive, Some(&model_dir))
+        };
+
+        for revision in ["../main", "..", "a/b", "/abs", ""] {
+            let err = bundle(&|model| model.revision = revision.to_string()).expect_err("revision rejected");
+            assert!(matches!(err, InstallError::InvalidArchive(_)), "{}: {:?}", revision, err);
+        }
+        for path in ["../escape.onnx", "onnx/../../escape.onnx", "/abs.onnx"] {
+            let err = bundle(&|model| model.files[0].path = path.to_string()).expect_err("file path rejected");
+            assert!(matches!(err, InstallError::InvalidArchive(_)), "{}: {:?}", path, err);
+        }
+        let err = bundle(&|model| model.files.clear()).expect_err("empty file list rejected");
+        assert!(err.to_string().contains("lists no files"), "{}", err);
+        assert!(!model_dir.join("models").join("briaai__RMBG-1.4").exists());
+    }
+
+    #[test]
+    fn invalid_bundle_keeps_the_installed_revision() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let model_dir = dir.path().join("models");
+        let good = dir.path().join("good.zip");
+        write_zip(
+            &good,
+            &[
+                ("manifests/unbg-model-lock.json", &rmbg14_bundle_lock(b"weights")),
+                ("models/briaai__RMBG-1.4/main/onnx/model_fp16.onnx", b"weights"),
+            ],
+        );
+        install_from_archive(&good, Some(&model_dir)).expect("first install");
+
+        let mut lock: ModelLock = serde_json::from_slice(&rmbg14_bundle_lock(b"notes")).expect("lock");
+        lock.models[0].files[0].path = "notes.txt".to_string();
+        let no_onnx = dir.path().join("no-onnx.zip");
+        write_zip(
+            &no_onnx,
+            &[
+                ("manifests/unbg-model-lock.json", &serde_json::to_vec(&lock).expect("serialize")),
+                ("models/briaai__RMBG-1.4/main/notes.txt", b"notes"),
+            ],
+        );
+        let err = install_from_archive(&no_onnx, Some(&model_dir)).expect_err("bundle without onnx rejected");
+        assert!(err.to_string().contains("no onnx file"), "{}", err);
+        let paths = resolve_model_paths(Some(&model_dir)).expect("paths");
+        let installed = model_revision_dir(&paths, KnownModel::Rmbg14, "main");
+        assert_eq!(fs::read(installed.join("onnx/model_fp16.onnx")).expect("kept"), b"weights");
+        verify_models(Some(model_dir)).expect("installed revision still verifies");
+    }
+
+    /// A lockfile entry for `files` (path in the revision, contents) at `revision` of RMBG-1.4.
+    fn known_rmbg14(revision: &str, files: &[(&str, &[u8])]) -> LockModel {
+        LockModel {
+            model_id: "briaai/RMBG-1.4".to_string(),
+            revision: revision.to_string(),
+            source: "huggingface".to_string(),
+            files: files
+                .iter()
+                .map(|(path, body)| LockFileEntry {
+                    path: path.to_string(),
+                    size: body.len() as u64,
+                    sha256: hex::encode(Sha256::digest(body)),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn legacy_flat_layout_migrates_once_and_verifies() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let legacy = dir.path().join("old").join("RMBG-1.4");
+        fs::create_dir_all(&legacy).expect("create legacy dir");
+        fs::write(legacy.join("model_fp16.onnx"), b"legacy weights").expect("seed onnx");
+        fs::write(legacy.join("config.json"), b"{}").expect("seed config");
+        fs::write(legacy.join("notes.txt"), b"keep me").expect("seed notes");
+        // The old install's lockfile knows the files as revision `v1`, not the manifest default.
+        let known = known_rmbg14("v1", &[("config.json", b"{}"), ("onnx/model_fp16.onnx", b"legacy weights")]);
+        let old_lock = merge_lock_models(None, vec![known], "1700000000".to_string());
+        fs::write(dir.path().join("old").join(LOCKFILE_NAME), serde_json::to_vec(&old_lock).expect("lock json")).expect("seed lock");
+        let model_dir = dir.path().join("models");
+
+        let planned = migrate_legacy_layout(&dir.path().join("old"), Some(&model_dir), &[], true).expect("dry run");
+        assert_eq!(planned.migrated.len(), 1);
+        assert_eq!(planned.migrated[0].revision, "v1");
+        assert_eq!(planned.migrated[0].files, vec!["config.json", "onnx/model_fp16.onnx"]);
+        assert!(!planned.lockfile_written);
+        assert!(legacy.join("model_fp16.onnx").exists());
+        assert!(!model_dir.exists());
+
+        let report = migrate_legacy_layout(&dir.path().join("old"), Some(&model_dir), &[], false).expect("migrate");
+        assert_eq!(report.migrated[0].model_id, "briaai/RMBG-1.4");
+        assert!(report.lockfile_written);
+        let verified = verify_models(Some(model_dir.clone())).expect("verify");
+        assert_eq!(verified.models[0].revision, "v1");
+        let paths: Vec<_> = verified.models[0].files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, vec!["config.json", "onnx/model_fp16.onnx"]);
+        assert!(legacy.join("notes.txt").exists());
+        assert!(!legacy.join("model_fp16.onnx").exists());
+
+        let again = migrate_legacy_layout(&dir.path().join("old"), Some(&model_dir), &[], false).expect("rerun");
+        assert!(again.migrated.is_empty() && !again.lockfile_written);
+        let in_place = migrate_legacy_layout(&model_dir, Some(&model_dir), &[], false).expect("current layout");
+        assert!(in_place.migrated.is_empty() && in_place.skipped.is_empty());
+    }
+
+    #[test]
+    fn legacy_files_matching_no_known_hash_are_left_alone() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let legacy = dir.path().join("old").join("rmbg_1_4");
+        fs::create_dir_all(&legacy).expect("create legacy dir");
+        fs::write(legacy.join("model.onnx"), b"tampered weights").expect("seed onnx");
+        let model_dir = dir.path().join("models");
+        let known = [known_rmbg14("main", &[("onnx/model.onnx", b"released weights")])];
+
+        let report = migrate_legacy_layout(&dir.path().join("old"), Some(&model_dir), &known, false).expect("migrate");
+        assert!(report.migrated.is_empty() && !report.lockfile_written);
+        assert_eq!(report.unverified, vec![legacy.clone()]);
+        assert!(legacy.join("model.onnx").exists());
+        let nothing_known = migrate_legacy_layout(&dir.path().join("old"), Some(&model_dir), &[], false).expect("migrate");
+        assert_eq!(nothing_known.unverified, vec![legacy]);
+    }
+
+    #[test]
+    fn legacy_migration_completes_on_a_rerun_after_failing_part_way() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let legacy = dir.path().join("old").join("RMBG-1.4");
+        fs::create_dir_all(&legacy).expect("create legacy dir");
+        fs::write(legacy.join("model.onnx"), b"legacy weights").expect("seed onnx");
+        fs::write(legacy.join("preprocessor_config.json"), b"{\"size\":1024}").expect("seed config");
+        let model_dir = dir.path().join("models");
+        let known = [known_rmbg14(
+            "main",
+            &[("onnx/model.onnx", b"legacy weights"), ("preprocessor_config.json", b"{\"size\":1024}")],
+        )];
+
+        // What an interrupted run leaves: a half-written staging copy and the legacy files intact.
+        let paths = resolve_model_paths(Some(&model_dir)).expect("paths");
+        let rev_dir = model_revision_dir(&paths, KnownModel::Rmbg14, "main");
+        let staging = rev_dir.with_file_name(".main.migrating");
+        fs::create_dir_all(staging.join("onnx")).expect("create staging");
+        fs::write(staging.join("onnx/model.onnx"), b"legacy wei").expect("seed partial copy");
+        assert!(!has_onnx_file(&rev_dir).expect("scan"), "a partial run must not look migrated");
+
+        let report = migrate_legacy_layout(&dir.path().join("old"), Some(&model_dir), &known, false).expect("rerun");
+        assert_eq!(report.migrated.len(), 1);
+        assert!(report.skipped.is_empty());
+        assert!(!staging.exists());
+        assert_eq!(fs::read(rev_dir.join("onnx/model.onnx")).expect("migrated onnx"), b"legacy weights");
+        assert!(!legacy.join("model.onnx").exists());
+        verify_models(Some(model_dir)).expect("verify");
+    }
+
+    #[test]
+    fn exported_bundle_round_trips_through_install_from_archive() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let source_dir = dir.path().join("source");
+        let rev_dir = seed_revision(&source_dir, b"exported weights");
+        fs::write(rev_dir.join("untracked.txt"), b"not in the lockfile").expect("seed untracked file");
+        let paths = resolve_model_paths(Some(&source_dir)).expect("paths");
+        let mut model = lock_from_existing_dir("briaai/RMBG-1.4", "main", &rev_dir, DEFAULT_DOWNLOAD_BUFFER_SIZE).expect("lock model");
+        model.files.retain(|f| f.path != "untracked.txt");
+        write_lockfile(&paths, &merge_lock_models(None, vec![model], "1700000000".to_string())).expect("write lock");
+
+        let first = dir.path().join("first.tar.gz");
+        let second = dir.path().join("second.tar.gz");
+        let report = export_models(Some(&source_dir), &[KnownModel::Rmbg14], &first).expect("export");
+        assert_eq!(report.models, vec!["briaai/RMBG-1.4".to_string()]);
+        assert_eq!(report.file_count, 1);
+        export_models(Some(&source_dir), &[], &second).expect("export all");
+        assert_eq!(fs::read(&first).expect("first"), fs::read(&second).expect("second"));
+
+        let target_dir = dir.path().join("target");
+        install_from_archive(&first, Some(&target_dir)).expect("import");
+        let verified = verify_models(Some(target_dir.clone())).expect("verify");
+        assert_eq!(verified.models[0].model_id, "briaai/RMBG-1.4");
+        let target_paths = resolve_model_paths(Some(&target_dir)).expect("paths");
+        let imported = model_revision_dir(&target_paths, KnownModel::Rmbg14, "main");
+        assert!(!imported.join("untracked.txt").exists());
+
+        let err = export_models(Some(&source_dir), &[KnownModel::Rmbg20], &first).expect_err("not installed");
+        assert_eq!(err.code(), "not-found");
+    }
+
+    #[test]
+    fn custom_models_round_trip_through_a_bundle() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let source_dir = dir.path().join("source");
+        let paths = resolve_model_paths(Some(&source_dir)).expect("paths");
+        let spec = CustomModel {
+            id: "acme/product-matting".to_string(),
+            revision: "v2".to_string(),
+            input_size: 512,
+            normalization: unbg_model_registry::Normalization::IMAGENET,
+        };
+        let rev_dir = model_id_revision_dir(&paths, &spec.id, "v2");
+        fs::create_dir_all(rev_dir.join("onnx")).expect("revision dir");
+        fs::write(rev_dir.join("onnx/model_fp16.onnx"), b"custom weights").expect("seed onnx");
+        let model = lock_from_existing_dir(&spec.id, "v2", &rev_dir, DEFAULT_DOWNLOAD_BUFFER_SIZE).expect("lock model");
+        write_lockfile(&paths, &merge_lock_models(None, vec![model.clone()], "1700000000".to_string())).expect("write lock");
+
+        // Unregistered, the id is not trusted as a directory name.
+        let bundle = dir.path().join("custom.tar.gz");
+        assert_eq!(export_models(Some(&source_dir), &[], &bundle).expect_err("unregistered").code(), "not-found");
+        assert_eq!(verify_models(Some(source_dir.clone())).expect_err("unregistered").code(), "not-found");
+
+        register_custom_model(&paths, spec.clone()).expect("register");
+        let report = export_models(Some(&source_dir), &[], &bundle).expect("export");
+        assert_eq!(report.models, vec![spec.id.clone()]);
+        assert_eq!(report.file_count, 1);
+
+        let target_dir = dir.path().join("target");
+        install_from_archive(&bundle, Some(&target_dir)).expect("import");
+        verify_models(Some(target_dir.clone())).expect("verify");
+        let target_paths = resolve_model_paths(Some(&target_dir)).expect("paths");
+        let (registered, installed) =
+            unbg_model_registry::find_custom_model(std::slice::from_ref(&target_paths), &spec.id).expect("registered");
+        assert_eq!(registered, spec);
+        assert_eq!(fs::read(installed.revision_dir.join("onnx/model_fp16.onnx")).expect("onnx"), b"custom weights");
+    }
+
+    #[test]
+    fn install_rejects_ids_and_revisions_that_leave_the_models_dir() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let mut request = rmbg14_request(dir.path());
+        request.models.clear();
+        request.custom_models = vec![CustomModel {
+            id: "acme/..".to_string(),
+            revision: "main".to_string(),
+            input_size: 512,
+            normalization: unbg_model_registry::Normalization::IMAGENET,
+        }];
+        request.force = true;
+        let err = install_models_from(&request, "http://127.0.0.1:9").expect_err("escaping id");
+        assert!(matches!(err, InstallError::Registry(RegistryError::InvalidModelId(_))), "{:?}", err);
+
+        let mut request = rmbg14_request(dir.path());
+        request.revision_rmbg14 = "../../elsewhere".to_string();
+        let err = install_models_from(&request, "http://127.0.0.1:9").expect_err("escaping revision");
+        assert!(matches!(err, InstallError::Registry(RegistryError::InvalidRevision(_))), "{:?}", err);
     }
-    Ok(())
 }
@@ -1,12 +1,18 @@
 use std::fmt::{Display, Formatter};
+use std::env;
+use std::ffi::OsString;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 pub const LOCKFILE_NAME: &str = "unbg-model-lock.json";
+pub const MODEL_PATH_ENV: &str = "UNBG_MODEL_PATH";
 pub const SCHEMA_VERSION: u32 = 1;
+/// Registered [`CustomModel`] specs, kept next to the lockfile.
+pub const CUSTOM_MODELS_FILE_NAME: &str = "custom-models.json";
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
@@ -23,6 +29,7 @@ impl KnownModel {
         }
     }
 
+    /// Directory name under `models/`; the same as [`cache_key_for_model_id`] of the model id.
     pub fn cache_key(self) -> &'static str {
         match self {
             Self::Rmbg14 => "briaai__RMBG-1.4",
@@ -49,6 +56,58 @@ impl Display for KnownModel {
     }
 }
 
+/// Per-channel `(sample / max - mean) / std` applied to a model's RGB input.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Normalization {
+    pub mean: [f32; 3],
+    pub std: [f32; 3],
+}
+
+impl Normalization {
+    /// `pixel / max - 0.5`, as used by RMBG-1.4.
+    pub const CENTERED: Self = Self {
+        mean: [0.5; 3],
+        std: [1.0; 3],
+    };
+
+    /// ImageNet mean and std, as used by RMBG-2.0 and most torchvision backbones.
+    pub const IMAGENET: Self = Self {
+        mean: [0.485, 0.456, 0.406],
+        std: [0.229, 0.224, 0.225],
+    };
+}
+
+impl std::str::FromStr for Normalization {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "centered" | "rmbg-1.4" => Ok(Self::CENTERED),
+            "imagenet" | "rmbg-2.0" => Ok(Self::IMAGENET),
+            other => Err(format!("unknown normalization '{}' (expected centered or imagenet)", other)),
+        }
+    }
+}
+
+/// A segmentation model from any Hugging Face repo, installed and run alongside the built-in
+/// [`KnownModel`]s. Its files live under `models/<cache key>/<revision>` like theirs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CustomModel {
+    /// Hugging Face repo id, e.g. `acme/product-matting`.
+    pub id: String,
+    /// Revision installed by default.
+    pub revision: String,
+    /// Square input edge the export expects, in pixels.
+    pub input_size: u32,
+    pub normalization: Normalization,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct CustomModelRegistry {
+    models: Vec<CustomModel>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct LockFileEntry {
@@ -77,6 +136,70 @@ pub struct ModelLock {
     pub models: Vec<LockModel>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LockModelSummary {
+    pub model_id: String,
+    pub revision: String,
+    pub file_count: usize,
+    pub total_bytes: u64,
+    pub onnx_variants: Vec<String>,
+}
+
+impl LockModel {
+    pub fn summary(&self) -> LockModelSummary {
+        let mut onnx_variants: Vec<String> = self
+            .files
+            .iter()
+            .filter(|f| f.path.to_ascii_lowercase().ends_with(".onnx"))
+            .map(|f| classify_onnx_name(&f.path).unwrap_or(OnnxFileVariant::Fp32).label().to_string())
+            .collect();
+        onnx_variants.sort();
+        onnx_variants.dedup();
+        LockModelSummary {
+            model_id: self.model_id.clone(),
+            revision: self.revision.clone(),
+            file_count: self.files.len(),
+            total_bytes: self.files.iter().map(|f| f.size).sum(),
+            onnx_variants,
+        }
+    }
+}
+
+/// Precision an `.onnx` file was exported in, as told by its file name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnnxFileVariant {
+    Fp16,
+    Fp32,
+    Quantized,
+}
+
+impl OnnxFileVariant {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Fp16 => "fp16",
+            Self::Fp32 => "fp32",
+            Self::Quantized => "quantized",
+        }
+    }
+}
+
+/// Variant an `.onnx` path is named for, or `None` when the name follows none of the
+/// `*quantized*`/`*q8*`, `*fp16*` or `model.onnx` conventions. Pass the path relative to the
+/// revision dir so parent directory names cannot skew the result.
+pub fn classify_onnx_name(path: &str) -> Option<OnnxFileVariant> {
+    let lower = path.to_ascii_lowercase();
+    if lower.contains("quantized") || lower.contains("q8") {
+        Some(OnnxFileVariant::Quantized)
+    } else if lower.contains("fp16") {
+        Some(OnnxFileVariant::Fp16)
+    } else if lower.contains("model.onnx") {
+        Some(OnnxFileVariant::Fp32)
+    } else {
+        None
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ModelManifest {
@@ -108,6 +231,12 @@ pub enum RegistryError {
     Io(#[from] std::io::Error),
     #[error("json error: {0}")]
     Json(#[from] serde_json::Error),
+    #[error("invalid lockfile timestamp '{0}'; expected unix seconds")]
+    InvalidTimestamp(String),
+    #[error("invalid model id '{0}'; expected owner/name made of letters, digits, '.', '_' and '-'")]
+    InvalidModelId(String),
+    #[error("invalid revision '{0}'; expected a single name made of letters, digits, '.', '_' and '-'")]
+    InvalidRevision(String),
 }
 
 #[derive(Debug, Clone)]
@@ -118,26 +247,199 @@ pub struct ModelPaths {
     pub cache_downloads_dir: PathBuf,
 }
 
+/// Platform default for model storage, used when neither an explicit directory nor
+/// `UNBG_MODEL_PATH` is given:
+///
+/// - Windows: `%LOCALAPPDATA%\unbg\models`
+/// - macOS: `~/Library/Application Support/unbg/models`
+/// - iOS / Android: the app data directory, `unbg/models` inside it
+/// - elsewhere: `~/.unbg/models`
+///
+/// An existing `~/.unbg/models` keeps winning on every platform so earlier installs are not
+/// orphaned, and it is also the fallback when the platform directory cannot be determined.
 pub fn default_model_dir() -> Result<PathBuf, RegistryError> {
-    let home = dirs::home_dir().ok_or(RegistryError::NoDefaultModelDir)?;
-    Ok(home.join(".unbg").join("models"))
+    let dirs = PlatformDirs {
+        home: dirs::home_dir(),
+        data_local: dirs::data_local_dir(),
+        data: dirs::data_dir(),
+    };
+    default_model_dir_for(env::consts::OS, &dirs, |legacy| legacy.is_dir()).ok_or(RegistryError::NoDefaultModelDir)
 }
 
-pub fn resolve_model_paths(model_dir: Option<&Path>) -> Result<ModelPaths, RegistryError> {
-    let root = if let Some(dir) = model_dir {
-        dir.to_path_buf()
-    } else {
-        default_model_dir()?
+struct PlatformDirs {
+    home: Option<PathBuf>,
+    data_local: Option<PathBuf>,
+    data: Option<PathBuf>,
+}
+
+fn default_model_dir_for(os: &str, dirs: &PlatformDirs, exists: impl Fn(&Path) -> bool) -> Option<PathBuf> {
+    let legacy = dirs.home.as_ref().map(|home| home.join(".unbg").join("models"));
+    if let Some(legacy) = legacy.as_ref().filter(|dir| exists(dir)) {
+        return Some(legacy.clone());
+    }
+    let base = match os {
+        "windows" => dirs.data_local.as_ref(),
+        "macos" | "ios" | "android" => dirs.data.as_ref(),
+        _ => None,
     };
+    base.map(|base| base.join("unbg").join("models")).or(legacy)
+}
+
+#[derive(Debug, Clone)]
+pub struct InstalledModel {
+    pub paths: ModelPaths,
+    pub lock: LockModel,
+    pub revision_dir: PathBuf,
+}
 
-    Ok(ModelPaths {
+/// Where installs go: the first writable directory of [`model_search_path`].
+pub fn resolve_model_paths(model_dir: Option<&Path>) -> Result<ModelPaths, RegistryError> {
+    Ok(writable_model_paths(model_search_path(model_dir)?))
+}
+
+/// Ordered model directories to search. An explicit `model_dir` wins; otherwise
+/// `UNBG_MODEL_PATH` is split like `PATH`, falling back to the default directory.
+pub fn model_search_path(model_dir: Option<&Path>) -> Result<Vec<ModelPaths>, RegistryError> {
+    search_path_from(model_dir, env::var_os(MODEL_PATH_ENV))
+}
+
+/// [`model_search_path`] with the `UNBG_MODEL_PATH` value supplied by the caller.
+pub fn search_path_from(model_dir: Option<&Path>, env_value: Option<OsString>) -> Result<Vec<ModelPaths>, RegistryError> {
+    if let Some(dir) = model_dir {
+        return Ok(vec![model_paths_at(dir.to_path_buf())]);
+    }
+    let chained: Vec<ModelPaths> = env_value
+        .iter()
+        .flat_map(env::split_paths)
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .map(model_paths_at)
+        .collect();
+    if !chained.is_empty() {
+        return Ok(chained);
+    }
+    Ok(vec![model_paths_at(default_model_dir()?)])
+}
+
+fn model_paths_at(root: PathBuf) -> ModelPaths {
+    ModelPaths {
         manifests_dir: root.join("manifests"),
         models_dir: root.join("models"),
         cache_downloads_dir: root.join("cache").join("downloads"),
         root,
+    }
+}
+
+fn writable_model_paths(search: Vec<ModelPaths>) -> ModelPaths {
+    let index = search.iter().position(|paths| is_writable_root(&paths.root)).unwrap_or(0);
+    search.into_iter().nth(index).expect("model search path is never empty")
+}
+
+/// Whether this process can write under `root`, or create it when it does not exist yet. Probes
+/// with a real file, since permission bits say nothing about ACLs, ownership or read-only mounts.
+fn is_writable_root(root: &Path) -> bool {
+    match root.ancestors().find(|dir| dir.exists()) {
+        Some(dir) if dir.is_dir() => can_create_file_in(dir),
+        _ => false,
+    }
+}
+
+fn can_create_file_in(dir: &Path) -> bool {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.subsec_nanos());
+    let probe = dir.join(format!(".unbg-write-probe-{}-{}", std::process::id(), nanos));
+    match fs::OpenOptions::new().write(true).create_new(true).open(&probe) {
+        Ok(_) => {
+            let _ = fs::remove_file(&probe);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// Checks that `model_id` is `owner/name`. Ids and revisions become directory names under
+/// `models/`, so anything that could climb out of it is rejected.
+pub fn validate_model_id(model_id: &str) -> Result<(), RegistryError> {
+    match model_id.split_once('/') {
+        Some((owner, name)) if is_plain_segment(owner) && is_plain_segment(name) => Ok(()),
+        _ => Err(RegistryError::InvalidModelId(model_id.to_string())),
+    }
+}
+
+/// Checks that `revision` is a single directory name, under the same rules as a model id segment.
+pub fn validate_revision(revision: &str) -> Result<(), RegistryError> {
+    if is_plain_segment(revision) {
+        Ok(())
+    } else {
+        Err(RegistryError::InvalidRevision(revision.to_string()))
+    }
+}
+
+fn is_plain_segment(segment: &str) -> bool {
+    !segment.is_empty()
+        && segment != "."
+        && segment != ".."
+        && segment
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+/// First directory in the search path whose lockfile lists `model` with its revision on disk.
+pub fn find_installed_model(search: &[ModelPaths], model: KnownModel) -> Option<InstalledModel> {
+    find_installed_model_id(search, model.model_id())
+}
+
+/// [`find_installed_model`] by model id, for built-in and custom models alike.
+/// Ids and lockfile revisions that fail validation are treated as not installed.
+pub fn find_installed_model_id(search: &[ModelPaths], model_id: &str) -> Option<InstalledModel> {
+    validate_model_id(model_id).ok()?;
+    search.iter().find_map(|paths| {
+        let lock = read_lockfile(paths).ok()?;
+        let entry = lock.models.into_iter().find(|m| m.model_id == model_id)?;
+        validate_revision(&entry.revision).ok()?;
+        let revision_dir = model_id_revision_dir(paths, model_id, &entry.revision);
+        revision_dir.is_dir().then(|| InstalledModel {
+            paths: paths.clone(),
+            lock: entry,
+            revision_dir,
+        })
     })
 }
 
+/// The registered spec for custom model `id` and its install, from the first directory in the
+/// search path that has both.
+pub fn find_custom_model(search: &[ModelPaths], id: &str) -> Option<(CustomModel, InstalledModel)> {
+    search.iter().find_map(|paths| {
+        let spec = read_custom_models(paths).ok()?.into_iter().find(|m| m.id == id)?;
+        let installed = find_installed_model_id(std::slice::from_ref(paths), id)?;
+        Some((spec, installed))
+    })
+}
+
+/// Registered custom models in `paths`; empty when none were registered.
+pub fn read_custom_models(paths: &ModelPaths) -> Result<Vec<CustomModel>, RegistryError> {
+    let file = paths.manifests_dir.join(CUSTOM_MODELS_FILE_NAME);
+    if !file.exists() {
+        return Ok(Vec::new());
+    }
+    let registry: CustomModelRegistry = serde_json::from_slice(&fs::read(file)?)?;
+    Ok(registry.models)
+}
+
+/// Adds `model` to the custom model registry in `paths`, replacing an entry with the same id.
+pub fn register_custom_model(paths: &ModelPaths, model: CustomModel) -> Result<(), RegistryError> {
+    validate_model_id(&model.id)?;
+    validate_revision(&model.revision)?;
+    let mut models = read_custom_models(paths)?;
+    models.retain(|m| m.id != model.id);
+    models.push(model);
+    models.sort_by(|a, b| a.id.cmp(&b.id));
+    fs::create_dir_all(&paths.manifests_dir)?;
+    let data = serde_json::to_vec_pretty(&CustomModelRegistry { models })?;
+    fs::write(paths.manifests_dir.join(CUSTOM_MODELS_FILE_NAME), data)?;
+    Ok(())
+}
+
 pub fn ensure_layout(paths: &ModelPaths) -> Result<(), RegistryError> {
     fs::create_dir_all(&paths.manifests_dir)?;
     fs::create_dir_all(&paths.models_dir)?;
@@ -149,6 +451,31 @@ pub fn model_revision_dir(paths: &ModelPaths, model: KnownModel, revision: &str)
     paths.models_dir.join(model.cache_key()).join(revision)
 }
 
+/// [`model_revision_dir`] by model id, for built-in and custom models alike. Callers validate
+/// `model_id` and `revision` first ([`validate_model_id`], [`validate_revision`]).
+pub fn model_id_revision_dir(paths: &ModelPaths, model_id: &str, revision: &str) -> PathBuf {
+    paths.models_dir.join(cache_key_for_model_id(model_id)).join(revision)
+}
+
+/// Directory name under `models/` for `model_id`: `org/name` becomes `org__name`.
+pub fn cache_key_for_model_id(model_id: &str) -> String {
+    model_id.replace('/', "__")
+}
+
+/// Revision directory names present on disk for `model`, sorted.
+pub fn list_installed_revisions(paths: &ModelPaths, model: KnownModel) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(paths.models_dir.join(model.cache_key())) else {
+        return Vec::new();
+    };
+    let mut revisions: Vec<String> = entries
+        .filter_map(std::result::Result::ok)
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().to_str().map(str::to_string))
+        .collect();
+    revisions.sort();
+    revisions
+}
+
 pub fn lockfile_path(paths: &ModelPaths) -> PathBuf {
     paths.manifests_dir.join(LOCKFILE_NAME)
 }
@@ -165,6 +492,37 @@ pub fn read_lockfile(paths: &ModelPaths) -> Result<ModelLock, RegistryError> {
     Ok(serde_json::from_slice(&data)?)
 }
 
+/// Lockfile metadata for tooling that does not need the per-file entries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockInfo {
+    pub schema_version: u32,
+    pub generated_at: SystemTime,
+    pub model_count: usize,
+}
+
+impl LockInfo {
+    /// Whether the lockfile was written with the schema this build understands.
+    pub fn is_current_schema(&self) -> bool {
+        self.schema_version == SCHEMA_VERSION
+    }
+}
+
+pub fn lockfile_info(paths: &ModelPaths) -> Result<LockInfo, RegistryError> {
+    let lock = read_lockfile(paths)?;
+    Ok(LockInfo {
+        schema_version: lock.schema_version,
+        generated_at: parse_generated_at(&lock.generated_at)?,
+        model_count: lock.models.len(),
+    })
+}
+
+fn parse_generated_at(raw: &str) -> Result<SystemTime, RegistryError> {
+    raw.trim()
+        .parse::<u64>()
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+        .map_err(|_| RegistryError::InvalidTimestamp(raw.to_string()))
+}
+
 pub fn merge_lock_models(existing: Option<ModelLock>, updates: Vec<LockModel>, generated_at: String) -> ModelLock {
     let mut by_id = std::collections::BTreeMap::new();
     if let Some(lock) = existing {
@@ -182,6 +540,50 @@ pub fn merge_lock_models(existing: Option<ModelLock>, updates: Vec<LockModel>, g
     }
 }
 
+/// One way a lockfile entry disagrees with the manifest, from [`check_lock_against_manifest`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case", rename_all_fields = "camelCase")]
+pub enum LockDrift {
+    /// Installed at a revision other than the manifest's `default_revision`.
+    OffDefaultRevision {
+        model_id: String,
+        revision: String,
+        default_revision: String,
+    },
+    /// A gated model whose lockfile entry does not record where it came from.
+    GatedWithoutSource { model_id: String },
+    /// A lockfile entry for a model the manifest does not list.
+    NotInManifest { model_id: String },
+}
+
+/// Compares installed revisions against `manifest`; an empty result means the lockfile is consistent.
+///
+/// This is an audit of what was installed, not of file contents; checksums are checked on install.
+pub fn check_lock_against_manifest(lock: &ModelLock, manifest: &[ModelManifest]) -> Vec<LockDrift> {
+    let mut drift = Vec::new();
+    for model in &lock.models {
+        let Some(entry) = manifest.iter().find(|m| m.model_id == model.model_id) else {
+            drift.push(LockDrift::NotInManifest {
+                model_id: model.model_id.clone(),
+            });
+            continue;
+        };
+        if model.revision != entry.default_revision {
+            drift.push(LockDrift::OffDefaultRevision {
+                model_id: model.model_id.clone(),
+                revision: model.revision.clone(),
+                default_revision: entry.default_revision.clone(),
+            });
+        }
+        if entry.gated && model.source.trim().is_empty() {
+            drift.push(LockDrift::GatedWithoutSource {
+                model_id: model.model_id.clone(),
+            });
+        }
+    }
+    drift
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -213,4 +615,296 @@ mod tests {
         assert_eq!(merged.models.len(), 1);
         assert_eq!(merged.models[0].revision, "new");
     }
+
+    #[test]
+    fn lock_check_flags_drifted_revision_unsourced_gated_and_unknown_models() {
+        let lock_model = |model_id: &str, revision: &str, source: &str| LockModel {
+            model_id: model_id.to_string(),
+            revision: revision.to_string(),
+            source: source.to_string(),
+            files: vec![],
+        };
+        let consistent = ModelLock {
+            schema_version: SCHEMA_VERSION,
+            generated_at: "1".to_string(),
+            models: vec![
+                lock_model("briaai/RMBG-1.4", "main", "huggingface"),
+                lock_model("briaai/RMBG-2.0", "main", "huggingface"),
+            ],
+        };
+        assert!(check_lock_against_manifest(&consistent, &built_in_manifest()).is_empty());
+
+        let drifted = ModelLock {
+            models: vec![
+                lock_model("briaai/RMBG-1.4", "a1b2c3", "huggingface"),
+                lock_model("briaai/RMBG-2.0", "main", ""),
+                lock_model("someone/other-model", "main", "huggingface"),
+            ],
+            ..consistent
+        };
+        assert_eq!(
+            check_lock_against_manifest(&drifted, &built_in_manifest()),
+            vec![
+                LockDrift::OffDefaultRevision {
+                    model_id: "briaai/RMBG-1.4".to_string(),
+                    revision: "a1b2c3".to_string(),
+                    default_revision: "main".to_string(),
+                },
+                LockDrift::GatedWithoutSource {
+                    model_id: "briaai/RMBG-2.0".to_string(),
+                },
+                LockDrift::NotInManifest {
+                    model_id: "someone/other-model".to_string(),
+                },
+            ]
+        );
+        let json = serde_json::to_value(&check_lock_against_manifest(&drifted, &built_in_manifest())[0]).unwrap();
+        assert_eq!(json["kind"], "off-default-revision");
+        assert_eq!(json["defaultRevision"], "main");
+    }
+
+    #[test]
+    fn onnx_names_classify_by_convention() {
+        assert_eq!(classify_onnx_name("onnx/model_q8.onnx"), Some(OnnxFileVariant::Quantized));
+        assert_eq!(classify_onnx_name("onnx/Model_FP16.onnx"), Some(OnnxFileVariant::Fp16));
+        assert_eq!(classify_onnx_name("onnx/model.onnx"), Some(OnnxFileVariant::Fp32));
+        assert_eq!(classify_onnx_name("onnx/matting.onnx"), None);
+    }
+
+    #[test]
+    fn summary_totals_match_file_sizes() {
+        let model = LockModel {
+            model_id: "briaai/RMBG-1.4".to_string(),
+            revision: "main".to_string(),
+            source: "huggingface".to_string(),
+            files: vec![
+                LockFileEntry {
+                    path: "onnx/model_fp16.onnx".to_string(),
+                    size: 1_000,
+                    sha256: "a".to_string(),
+                },
+                LockFileEntry {
+                    path: "onnx/model_quantized.onnx".to_string(),
+                    size: 250,
+                    sha256: "b".to_string(),
+                },
+                LockFileEntry {
+                    path: "config.json".to_string(),
+                    size: 42,
+                    sha256: "c".to_string(),
+                },
+            ],
+        };
+
+        let summary = model.summary();
+        assert_eq!(summary.file_count, 3);
+        assert_eq!(summary.total_bytes, model.files.iter().map(|f| f.size).sum::<u64>());
+        assert_eq!(summary.onnx_variants, vec!["fp16".to_string(), "quantized".to_string()]);
+    }
+
+    #[test]
+    fn writable_root_is_probed_rather_than_read_from_permissions() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        assert!(is_writable_root(dir.path()));
+        assert!(is_writable_root(&dir.path().join("not").join("created").join("yet")));
+        let file = dir.path().join("a-file");
+        fs::write(&file, b"x").expect("file");
+        assert!(!is_writable_root(&file.join("models")));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1, "probe files are cleaned up");
+
+        let search = vec![model_paths_at(file.join("models")), model_paths_at(dir.path().to_path_buf())];
+        assert_eq!(writable_model_paths(search).root, dir.path());
+    }
+
+    #[test]
+    fn search_path_reads_shared_dir_and_installs_into_writable_one() {
+        let shared = tempfile::tempdir().expect("shared dir");
+        let user = tempfile::tempdir().expect("user dir");
+        let shared_paths = model_paths_at(shared.path().to_path_buf());
+        write_lockfile(
+            &shared_paths,
+            &ModelLock {
+                schema_version: SCHEMA_VERSION,
+                generated_at: "1".to_string(),
+                models: vec![LockModel {
+                    model_id: KnownModel::Rmbg14.model_id().to_string(),
+                    revision: "main".to_string(),
+                    source: "huggingface".to_string(),
+                    files: vec![],
+                }],
+            },
+        )
+        .expect("write shared lockfile");
+        fs::create_dir_all(model_revision_dir(&shared_paths, KnownModel::Rmbg14, "main")).expect("revision dir");
+        let mut perms = fs::metadata(shared.path()).expect("metadata").permissions();
+        perms.set_readonly(true);
+        fs::set_permissions(shared.path(), perms.clone()).expect("make shared dir read-only");
+
+        let joined = env::join_paths([shared.path(), user.path()]).expect("join paths");
+        let search = search_path_from(None, Some(joined)).expect("search path");
+        assert_eq!(search.len(), 2);
+
+        let installed = find_installed_model(&search, KnownModel::Rmbg14).expect("found in shared dir");
+        assert_eq!(installed.paths.root, shared.path());
+        assert!(find_installed_model(&search, KnownModel::Rmbg20).is_none());
+        // Root ignores the read-only bit; the probe sees that and keeps the shared dir.
+        if !is_writable_root(shared.path()) {
+            assert_eq!(writable_model_paths(search).root, user.path());
+        }
+
+        #[allow(clippy::permissions_set_readonly_false)]
+        perms.set_readonly(false);
+        fs::set_permissions(shared.path(), perms).expect("restore permissions");
+    }
+
+    #[test]
+    fn explicit_model_dir_overrides_search_path() {
+        let search = search_path_from(Some(Path::new("/opt/models")), Some(OsString::from("/a:/b"))).expect("search path");
+        assert_eq!(search.len(), 1);
+        assert_eq!(search[0].root, Path::new("/opt/models"));
+    }
+
+    #[test]
+    fn lists_every_installed_revision() {
+        let dir = tempfile::tempdir().expect("model dir");
+        let paths = model_paths_at(dir.path().to_path_buf());
+        assert!(list_installed_revisions(&paths, KnownModel::Rmbg14).is_empty());
+        for revision in ["main", "abc123"] {
+            fs::create_dir_all(model_revision_dir(&paths, KnownModel::Rmbg14, revision)).expect("revision dir");
+        }
+        fs::write(paths.models_dir.join(KnownModel::Rmbg14.cache_key()).join("stray.txt"), b"x").expect("stray file");
+
+        assert_eq!(
+            list_installed_revisions(&paths, KnownModel::Rmbg14),
+            vec!["abc123".to_string(), "main".to_string()]
+        );
+        assert!(list_installed_revisions(&paths, KnownModel::Rmbg20).is_empty());
+    }
+
+    #[test]
+    fn model_ids_and_revisions_must_stay_inside_the_models_dir() {
+        for id in ["acme/product-matting", "briaai/RMBG-1.4", "a_b/c.d-e"] {
+            validate_model_id(id).unwrap_or_else(|err| panic!("{}: {}", id, err));
+        }
+        for id in ["..", "a/..", "../a", "./a", "a", "a/b/c", "a/", "/a", "a\\b/c", "a/b c", ""] {
+            assert!(matches!(validate_model_id(id), Err(RegistryError::InvalidModelId(_))), "{}", id);
+        }
+        validate_revision("main").expect("branch");
+        validate_revision("v1.2").expect("tag");
+        for revision in ["", ".", "..", "refs/pr/1", "../main", "a\\b"] {
+            assert!(matches!(validate_revision(revision), Err(RegistryError::InvalidRevision(_))), "{}", revision);
+        }
+
+        let dir = tempfile::tempdir().expect("model dir");
+        let paths = model_paths_at(dir.path().to_path_buf());
+        let escaping = CustomModel {
+            id: "a/..".to_string(),
+            revision: "main".to_string(),
+            input_size: 512,
+            normalization: Normalization::IMAGENET,
+        };
+        assert!(register_custom_model(&paths, escaping).is_err());
+        assert!(read_custom_models(&paths).expect("registry").is_empty());
+    }
+
+    #[test]
+    fn custom_model_round_trips_through_registry_and_lockfile() {
+        let dir = tempfile::tempdir().expect("model dir");
+        let paths = model_paths_at(dir.path().to_path_buf());
+        let spec = CustomModel {
+            id: "acme/product-matting".to_string(),
+            revision: "v2".to_string(),
+            input_size: 512,
+            normalization: Normalization::IMAGENET,
+        };
+        assert_eq!(KnownModel::Rmbg14.cache_key(), cache_key_for_model_id(KnownModel::Rmbg14.model_id()));
+        assert!(find_custom_model(std::slice::from_ref(&paths), &spec.id).is_none());
+
+        register_custom_model(&paths, spec.clone()).expect("register");
+        let revision_dir = model_id_revision_dir(&paths, &spec.id, "v2");
+        assert!(revision_dir.ends_with("models/acme__product-matting/v2"));
+        fs::create_dir_all(&revision_dir).expect("revision dir");
+        let lock = merge_lock_models(
+            None,
+            vec![LockModel {
+                model_id: spec.id.clone(),
+                revision: "v2".to_string(),
+                source: "huggingface".to_string(),
+                files: vec![],
+            }],
+            "1".to_string(),
+        );
+        write_lockfile(&paths, &lock).expect("write lockfile");
+
+        let (found, installed) = find_custom_model(std::slice::from_ref(&paths), &spec.id).expect("custom model");
+        assert_eq!(found, spec);
+        assert_eq!(installed.lock.model_id, "acme/product-matting");
+        assert_eq!(installed.revision_dir, revision_dir);
+        assert_eq!(read_lockfile(&paths).expect("read lockfile").models[0].model_id, spec.id);
+    }
+
+    #[test]
+    fn lockfile_info_parses_unix_seconds_timestamp() {
+        let dir = tempfile::tempdir().expect("model dir");
+        let paths = model_paths_at(dir.path().to_path_buf());
+        let lock = merge_lock_models(
+            None,
+            vec![LockModel {
+                model_id: KnownModel::Rmbg14.model_id().to_string(),
+                revision: "main".to_string(),
+                source: "huggingface".to_string(),
+                files: vec![],
+            }],
+            "1700000000".to_string(),
+        );
+        write_lockfile(&paths, &lock).expect("write lockfile");
+
+        let info = lockfile_info(&paths).expect("lockfile info");
+        assert_eq!(info.generated_at, UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        assert_eq!(info.model_count, 1);
+        assert!(info.is_current_schema());
+
+        assert!(matches!(
+            parse_generated_at("2024-01-01"),
+            Err(RegistryError::InvalidTimestamp(raw)) if raw == "2024-01-01"
+        ));
+    }
+
+    fn fake_dirs() -> PlatformDirs {
+        PlatformDirs {
+            home: Some(PathBuf::from("/home/u")),
+            data_local: Some(PathBuf::from("/local")),
+            data: Some(PathBuf::from("/data")),
+        }
+    }
+
+    #[test]
+    fn default_model_dir_follows_platform_conventions() {
+        let dirs = fake_dirs();
+        let none = |_: &Path| false;
+        assert_eq!(default_model_dir_for("windows", &dirs, none), Some(PathBuf::from("/local/unbg/models")));
+        assert_eq!(default_model_dir_for("macos", &dirs, none), Some(PathBuf::from("/data/unbg/models")));
+        assert_eq!(default_model_dir_for("ios", &dirs, none), Some(PathBuf::from("/data/unbg/models")));
+        assert_eq!(default_model_dir_for("android", &dirs, none), Some(PathBuf::from("/data/unbg/models")));
+        assert_eq!(default_model_dir_for("linux", &dirs, none), Some(PathBuf::from("/home/u/.unbg/models")));
+    }
+
+    #[test]
+    fn existing_legacy_model_dir_wins_and_backs_up_missing_platform_dirs() {
+        let dirs = fake_dirs();
+        let legacy = PathBuf::from("/home/u/.unbg/models");
+        assert_eq!(default_model_dir_for("windows", &dirs, |p: &Path| p == legacy), Some(legacy.clone()));
+
+        let no_data = PlatformDirs {
+            data: None,
+            ..fake_dirs()
+        };
+        assert_eq!(default_model_dir_for("android", &no_data, |_: &Path| false), Some(legacy));
+        let nothing = PlatformDirs {
+            home: None,
+            data_local: None,
+            data: None,
+        };
+        assert_eq!(default_model_dir_for("macos", &nothing, |_: &Path| false), None);
+    }
 }
@@ -0,0 +1,372 @@
+use std::time::Duration;
+
+use reqwest::blocking::Client;
+use reqwest::StatusCode;
+use unbg_core::{
+    v1, BackendRegistry, BackgroundFill, CoreError, EmptyMaskPolicy, ErrorCode, ErrorInfo, ExecutionProvider, GpuBackendPreference, InferenceBackend,
+    InferenceRequest, InferenceResult, ModelKind, OnnxVariant,
+};
+
+/// Registry id for [`RemoteHttpBackend`].
+pub const REMOTE_BACKEND_ID: &str = "remote";
+
+#[derive(Debug, Clone)]
+pub struct RemoteBackendConfig {
+    /// Full URL of the remove-background endpoint, e.g. `http://gpu-box:8080/v1/remove-background`.
+    pub endpoint: String,
+    /// Sent as a bearer token when set.
+    pub token: Option<String>,
+    pub timeout: Duration,
+}
+
+impl RemoteBackendConfig {
+    pub fn new(endpoint: impl Into<String>) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            token: None,
+            timeout: Duration::from_secs(30),
+        }
+    }
+
+    /// Reads `UNBG_REMOTE_ENDPOINT`, `UNBG_REMOTE_TOKEN` and `UNBG_REMOTE_TIMEOUT_MS`.
+    pub fn from_env() -> Option<Self> {
+        let endpoint = std::env::var("UNBG_REMOTE_ENDPOINT")
+            .ok()
+            .filter(|v| !v.trim().is_empty())?;
+        let mut config = Self::new(endpoint.trim());
+        config.token = std::env::var("UNBG_REMOTE_TOKEN").ok().filter(|v| !v.trim().is_empty());
+        if let Some(ms) = std::env::var("UNBG_REMOTE_TIMEOUT_MS")
+            .ok()
+            .and_then(|v| v.trim().parse::<u64>().ok())
+        {
+            config.timeout = Duration::from_millis(ms);
+        }
+        Some(config)
+    }
+}
+
+/// Runs inference on a server exposing the v1 remove-background endpoint.
+pub struct RemoteHttpBackend {
+    client: Client,
+    config: RemoteBackendConfig,
+}
+
+impl RemoteHttpBackend {
+    pub fn new(config: RemoteBackendConfig) -> Result<Self, CoreError> {
+        let client = Client::builder()
+            .timeout(config.timeout)
+            .build()
+            .map_err(|err| CoreError::Backend(format!("failed to build http client: {}", err)))?;
+        Ok(Self { client, config })
+    }
+}
+
+/// Registers a [`RemoteHttpBackend`] for `config` under [`REMOTE_BACKEND_ID`].
+pub fn register_remote_backend(registry: &mut BackendRegistry, config: RemoteBackendConfig) {
+    registry.register(REMOTE_BACKEND_ID, move || match RemoteHttpBackend::new(config.clone()) {
+        Ok(backend) => Box::new(backend),
+        Err(err) => Box::new(FailedBackend(err.to_string())),
+    });
+}
+
+struct FailedBackend(String);
+
+impl InferenceBackend for FailedBackend {
+    fn infer(&self, _request: &InferenceRequest, _selected_model: ModelKind) -> Result<InferenceResult, CoreError> {
+        Err(CoreError::Backend(self.0.clone()))
+    }
+}
+
+impl InferenceBackend for RemoteHttpBackend {
+    fn infer(&self, request: &InferenceRequest, selected_model: ModelKind) -> Result<InferenceResult, CoreError> {
+        if let Some(option) = unsupported_option(request) {
+            return Err(CoreError::InvalidArgument(format!(
+                "{} not supported by the remote backend",
+                option
+            )));
+        }
+        let image_bytes = match (&request.input_bytes, &request.input_path) {
+            (Some(bytes), _) => bytes.clone(),
+            (None, Some(path)) => std::fs::read(path)
+                .map_err(|err| CoreError::Backend(format!("failed to read input {}: {}", path.display(), err)))?,
+            (None, None) => return Err(CoreError::MissingInput),
+        };
+        let body = v1::RemoveBackgroundRequest {
+            image_bytes,
+            width: request.width,
+            height: request.height,
+            model: model_label(selected_model).to_string(),
+            onnx_variant: Some(onnx_variant_label(request.onnx_variant).to_string()),
+            execution_provider: Some(execution_provider_label(request.execution_provider).to_string()),
+            gpu_backend: Some(gpu_backend_label(request.gpu_backend).to_string()),
+            benchmark_provider: Some(request.benchmark_provider),
+            model_dir: None,
+            max_inference_pixels: request.max_inference_pixels,
+            mask_threshold: request.mask_threshold,
+            background: BackgroundFill::Transparent,
+            collect_timings: request.collect_timings,
+            emit_mask_rle: request.emit_mask_rle,
+        };
+
+        let mut call = self.client.post(&self.config.endpoint).json(&body);
+        if let Some(token) = &self.config.token {
+            call = call.bearer_auth(token);
+        }
+        let response = call.send().map_err(|err| {
+            if err.is_timeout() {
+                CoreError::Backend(format!(
+                    "remote backend timed out after {}ms",
+                    self.config.timeout.as_millis()
+                ))
+            } else {
+                CoreError::Backend(format!("remote backend request failed: {}", err))
+            }
+        })?;
+        let status = response.status();
+        let text = response
+            .text()
+            .map_err(|err| CoreError::Backend(format!("failed to read remote response: {}", err)))?;
+        if !status.is_success() {
+            return Err(map_http_error(status, &text));
+        }
+        let out: v1::RemoveBackgroundResponse = serde_json::from_str(&text)
+            .map_err(|err| CoreError::Backend(format!("invalid remote response: {}", err)))?;
+        let mut result = InferenceResult::new(
+            parse_model_label(&out.model_used).unwrap_or(selected_model),
+            out.mask_png,
+            out.width,
+            out.height,
+            out.provider_selected,
+        );
+        result.gpu_backend_selected = out.backend_selected;
+        result.fallback_used = out.fallback_used;
+        result.timings = out.timings;
+        result.mask_rle = out.mask_rle;
+        Ok(result)
+    }
+}
+
+/// The first option set on `request` that the v1 contract cannot carry, so a remote run would
+/// silently ignore it. Every [`InferenceRequest`] field is either forwarded in
+/// [`v1::RemoveBackgroundRequest`], only tunes the local runtime (`model_dir`,
+/// `inference_retries`, `session_tuning`), or is listed here.
+fn unsupported_option(request: &InferenceRequest) -> Option<&'static str> {
+    [
+        (request.ensemble.is_some(), "ensemble requests are"),
+        (request.trimap.is_some(), "trimap output is"),
+        (request.roi.is_some(), "region-of-interest requests are"),
+        (request.seed_mask.is_some(), "seed masks are"),
+        (request.feather_radius.is_some(), "feathered masks are"),
+        (request.on_empty_mask != EmptyMaskPolicy::default(), "empty-mask policies other than flag are"),
+        (request.custom_model.is_some(), "custom models are"),
+        (request.revision.is_some(), "model revisions are"),
+        (request.alpha_curve.is_some(), "alpha curves are"),
+        (request.input_size.is_some(), "model input sizes are"),
+        (request.max_dimension.is_some(), "max-dimension downscaling is"),
+        (request.mask_activation.is_some(), "mask activations are"),
+        (request.low_confidence_range.is_some(), "low-confidence ranges are"),
+        (!request.foreground_channels.is_empty(), "foreground channels are"),
+        (request.png_compression.is_some(), "png compression levels are"),
+        (request.source_max_value.is_some(), "source sample ranges are"),
+        (!request.free_dimension_overrides.is_empty(), "free dimension overrides are"),
+        (request.emit_mask_raw, "raw float masks are"),
+        (request.strict_model, "strict model selection is"),
+    ]
+    .into_iter()
+    .find_map(|(set, option)| set.then_some(option))
+}
+
+/// Maps an error status to `CoreError`, preferring the server's `ErrorInfo` body when present.
+fn map_http_error(status: StatusCode, body: &str) -> CoreError {
+    match serde_json::from_str::<ErrorInfo>(body) {
+        Ok(info) => match info.code {
+            ErrorCode::Rmbg20Disabled => CoreError::Rmbg20Disabled,
+            ErrorCode::MissingInput => CoreError::MissingInput,
+            ErrorCode::BackendError => CoreError::Backend(format!("remote backend returned {}: {}", status, info.message)),
+            ErrorCode::Cancelled => CoreError::Cancelled,
+            ErrorCode::InvalidArgument => CoreError::InvalidArgument(info.message),
+            // Missing on the server, so not something a local `models install` would fix.
+            ErrorCode::ModelNotInstalled | ErrorCode::OnnxFileMissing => {
+                CoreError::Backend(format!("remote backend returned {}: {}", status, info.message))
+            }
+        },
+        Err(_) if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN => {
+            CoreError::Backend(format!("remote backend rejected credentials ({})", status))
+        }
+        Err(_) => CoreError::Backend(format!("remote backend returned {}: {}", status, body.trim())),
+    }
+}
+
+fn model_label(model: ModelKind) -> &'static str {
+    match model {
+        ModelKind::Auto => "auto",
+        ModelKind::Rmbg14 => "rmbg-1.4",
+        ModelKind::Rmbg20 => "rmbg-2.0",
+    }
+}
+
+fn parse_model_label(raw: &str) -> Option<ModelKind> {
+    match raw.to_ascii_lowercase().as_str() {
+        "auto" => Some(ModelKind::Auto),
+        "fast" | "rmbg-1.4" => Some(ModelKind::Rmbg14),
+        "quality" | "rmbg-2.0" => Some(ModelKind::Rmbg20),
+        _ => None,
+    }
+}
+
+fn onnx_variant_label(variant: OnnxVariant) -> &'static str {
+    match variant {
+        OnnxVariant::Auto => "auto",
+        OnnxVariant::Fp16 => "fp16",
+        OnnxVariant::Fp32 => "fp32",
+        OnnxVariant::Quantized => "quantized",
+    }
+}
+
+fn execution_provider_label(provider: ExecutionProvider) -> &'static str {
+    match provider {
+        ExecutionProvider::Auto => "auto",
+        ExecutionProvider::Gpu => "gpu",
+        ExecutionProvider::Cpu => "cpu",
+    }
+}
+
+fn gpu_backend_label(backend: GpuBackendPreference) -> &'static str {
+    match backend {
+        GpuBackendPreference::Auto => "auto",
+        GpuBackendPreference::DirectML => "directml",
+        GpuBackendPreference::Cuda => "cuda",
+        GpuBackendPreference::CoreML => "coreml",
+        GpuBackendPreference::Metal => "metal",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+
+    /// Serves one request with `status` and `body`, reporting the request headers and body back.
+    fn canned_server(status: &'static str, body: String) -> (String, mpsc::Receiver<(String, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock server");
+        let addr = listener.local_addr().expect("server addr");
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let Ok((mut stream, _)) = listener.accept() else { return };
+            let mut reader = BufReader::new(stream.try_clone().expect("clone stream"));
+            let mut headers = String::new();
+            let mut content_length = 0usize;
+            let mut line = String::new();
+            while reader.read_line(&mut line).map(|n| n > 2).unwrap_or(false) {
+                if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                    content_length = value.trim().parse().unwrap_or(0);
+                }
+                headers.push_str(&line);
+                line.clear();
+            }
+            let mut request_body = vec![0u8; content_length];
+            let _ = reader.read_exact(&mut request_body);
+            let _ = tx.send((headers, String::from_utf8_lossy(&request_body).into_owned()));
+            let _ = write!(
+                stream,
+                "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+        });
+        (format!("http://{}/v1/remove-background", addr), rx)
+    }
+
+    fn sample_request() -> InferenceRequest {
+        InferenceRequest::builder()
+            .requested_model(ModelKind::Rmbg14)
+            .input_bytes(vec![9, 8, 7])
+            .dimensions(4, 3)
+            .build()
+    }
+
+    #[test]
+    fn remote_backend_posts_request_and_maps_response() {
+        let canned = v1::RemoveBackgroundResponse {
+            model_used: "rmbg-1.4".to_string(),
+            width: 4,
+            height: 3,
+            mask_png: vec![1, 2, 3],
+            provider_selected: "gpu".to_string(),
+            backend_selected: Some("cuda".to_string()),
+            fallback_used: false,
+            composite_png: None,
+            timings: None,
+            mask_rle: None,
+        };
+        let (endpoint, seen) = canned_server("200 OK", serde_json::to_string(&canned).unwrap());
+        let mut config = RemoteBackendConfig::new(endpoint);
+        config.token = Some("secret".to_string());
+        let backend = RemoteHttpBackend::new(config).unwrap();
+
+        let result = backend.infer(&sample_request(), ModelKind::Rmbg14).expect("remote inference");
+        assert_eq!(result.model_used, ModelKind::Rmbg14);
+        assert_eq!(result.mask_png, vec![1, 2, 3]);
+        assert_eq!(result.gpu_backend_selected.as_deref(), Some("cuda"));
+
+        let (headers, body) = seen.recv().unwrap();
+        assert!(headers.to_ascii_lowercase().contains("authorization: bearer secret"));
+        let sent: v1::RemoveBackgroundRequest = serde_json::from_str(&body).unwrap();
+        assert_eq!(sent.image_bytes, vec![9, 8, 7]);
+        assert_eq!(sent.model, "rmbg-1.4");
+    }
+
+    #[test]
+    fn remote_error_body_maps_to_core_error() {
+        let info = ErrorInfo {
+            code: ErrorCode::Rmbg20Disabled,
+            message: "rmbg-2.0 is disabled by runtime policy".to_string(),
+        };
+        let (endpoint, _seen) = canned_server("422 Unprocessable Entity", serde_json::to_string(&info).unwrap());
+        let backend = RemoteHttpBackend::new(RemoteBackendConfig::new(endpoint)).unwrap();
+        let err = backend.infer(&sample_request(), ModelKind::Rmbg20).expect_err("error status should fail");
+        assert!(matches!(err, CoreError::Rmbg20Disabled));
+
+        let (endpoint, _seen) = canned_server("503 Service Unavailable", "overloaded".to_string());
+        let backend = RemoteHttpBackend::new(RemoteBackendConfig::new(endpoint)).unwrap();
+        let err = backend.infer(&sample_request(), ModelKind::Rmbg14).expect_err("error status should fail");
+        assert!(err.to_string().contains("503"), "{}", err);
+    }
+
+    #[test]
+    fn local_only_options_are_rejected_before_any_request() {
+        // Nothing listens on the discard port, so reaching the network would be a different error.
+        let backend = RemoteHttpBackend::new(RemoteBackendConfig::new("http://127.0.0.1:9/v1/remove-background")).unwrap();
+        let mut seeded = sample_request();
+        seeded.seed_mask = Some(vec![1, 2, 3]);
+        let err = backend.infer(&seeded, ModelKind::Rmbg14).expect_err("seed mask rejected");
+        assert!(matches!(&err, CoreError::InvalidArgument(message) if message.contains("seed masks")), "{:?}", err);
+        assert_eq!(err.as_error_info().code, ErrorCode::InvalidArgument);
+
+        let mut feathered = sample_request();
+        feathered.feather_radius = Some(1.5);
+        let err = backend.infer(&feathered, ModelKind::Rmbg14).expect_err("feather rejected");
+        assert!(matches!(&err, CoreError::InvalidArgument(message) if message.contains("feathered")), "{:?}", err);
+
+        let mut strict = sample_request();
+        strict.on_empty_mask = EmptyMaskPolicy::Error;
+        let err = backend.infer(&strict, ModelKind::Rmbg14).expect_err("empty-mask policy rejected");
+        assert!(matches!(&err, CoreError::InvalidArgument(message) if message.contains("empty-mask")), "{:?}", err);
+
+        let mut custom = sample_request();
+        custom.custom_model = Some("acme/product-matting".to_string());
+        let err = backend.infer(&custom, ModelKind::Rmbg14).expect_err("custom model rejected");
+        assert!(matches!(&err, CoreError::InvalidArgument(message) if message.contains("custom models")), "{:?}", err);
+
+        let mut curved = sample_request();
+        curved.alpha_curve = Some(vec![(0.0, 0.0), (1.0, 1.0)]);
+        curved.png_compression = Some(9);
+        let err = backend.infer(&curved, ModelKind::Rmbg14).expect_err("alpha curve rejected");
+        assert!(matches!(&err, CoreError::InvalidArgument(message) if message.contains("alpha curves")), "{:?}", err);
+        assert_eq!(unsupported_option(&sample_request()), None);
+    }
+}
@@ -1,28 +1,304 @@
-use std::cell::RefCell;
+use std::borrow::Cow;
 use std::env;
 use std::fs;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
-use std::sync::{Mutex, OnceLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
 use std::time::Instant;
 
 use anyhow::{anyhow, Result};
-use image::{imageops::FilterType, DynamicImage, GrayImage, ImageFormat, Luma};
-use ort::{inputs, session::Session, value::Tensor};
+use image::{ColorType, DynamicImage, GrayImage, ImageFormat, Luma};
+use ort::{
+    inputs,
+    logging::LogLevel,
+    session::{builder::GraphOptimizationLevel, Session},
+    value::{Outlet, Tensor, ValueType},
+};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use unbg_core::{
-    CoreError, ExecutionProvider, GpuBackendPreference, InferenceBackend, InferenceRequest, InferenceResult, ModelKind, OnnxVariant,
+    encode_mask_rle, foreground_ratio, validate_alpha_curve, BackendRegistry, CancelToken, CoreError, EmptyMaskPolicy, EnsembleMode, ExecutionProvider, GpuBackendPreference, GraphOptLevel,
+    InferenceBackend, InferenceRequest, InferenceResult, InferenceTimings, MaskActivation, MaskCoverage, ModelKind, OnnxVariant, RegionOfInterest, SessionTuning,
+    ThreadPriority, TrimapThresholds, DEFAULT_BACKEND_ID, PROVIDER_BENCHMARK_FAILED,
+};
+use unbg_image::{
+    active_resizer, clamp_to_max_dimension, clamp_to_max_pixels, encode_image, estimate_rgba_bytes, feather_mask, image_dimensions, safe_decode,
+    stream_mask_tiles, upscale_mask_tile, DecodeHints, DecodeLimits, EncodeOptions, ImageSize, TileRect,
+};
+use unbg_model_registry::{
+    classify_onnx_name, find_custom_model, find_installed_model, model_revision_dir, model_search_path, resolve_model_paths,
+    validate_revision, CustomModel, KnownModel, Normalization, OnnxFileVariant,
 };
-use unbg_model_registry::{model_revision_dir, read_lockfile, resolve_model_paths, KnownModel};
 use walkdir::WalkDir;
 
-#[derive(Debug, Clone)]
+/// Provider preference a [`LocalOrtBackend`] applies to requests that leave
+/// `execution_provider` or `gpu_backend` on `Auto`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct RuntimeDescriptor {
-    pub execution_provider: String,
+    pub execution_provider: ExecutionProvider,
+    pub gpu_backend: GpuBackendPreference,
+}
+
+impl RuntimeDescriptor {
+    pub fn new(execution_provider: ExecutionProvider, gpu_backend: GpuBackendPreference) -> Self {
+        Self {
+            execution_provider,
+            gpu_backend,
+        }
+    }
+}
+
+impl Default for RuntimeDescriptor {
+    fn default() -> Self {
+        Self::new(ExecutionProvider::Auto, GpuBackendPreference::Auto)
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct LocalOrtBackend {
     descriptor: RuntimeDescriptor,
+    preprocessors: Vec<(ModelKind, Arc<dyn Preprocessor>)>,
+    postprocess_chains: Vec<(ModelKind, PostprocessChain)>,
+    /// Provider of the most recent successful run; shared between clones.
+    last_provider: Arc<Mutex<Option<String>>>,
+    /// Run-history log to append to; `None` defers to `UNBG_RUN_HISTORY`.
+    run_history: Option<PathBuf>,
+    /// Whether a failed run may return the placeholder mask; `None` defers to `UNBG_ALLOW_PLACEHOLDER`.
+    placeholder_fallback: Option<bool>,
+}
+
+/// Target layout for the model input tensor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InputSpec {
+    pub width: u32,
+    pub height: u32,
+    pub source_max_value: Option<f32>,
+    /// Channels the session's image input takes: 1 for single-channel exports, otherwise 3.
+    pub channels: usize,
+}
+
+/// Tensor data ready to be handed to the session, in `shape` order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PreparedInput {
+    pub shape: [usize; 4],
+    pub data: Vec<f32>,
+}
+
+/// Turns a decoded image into model input. Register per model with
+/// [`LocalOrtBackend::with_preprocessor`]; [`default_preprocessor`] picks one otherwise.
+pub trait Preprocessor: std::fmt::Debug + Send + Sync {
+    fn prepare(&self, image: &DynamicImage, spec: &InputSpec) -> Result<PreparedInput>;
+}
+
+/// Raw model output at model resolution, one value per pixel in row-major order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaskBuffer {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<f32>,
+}
+
+/// One mask stage. Values leaving the last stage are read as alpha in `0.0..=1.0`.
+pub trait Postprocessor: std::fmt::Debug + Send + Sync {
+    fn process(&self, mask: MaskBuffer, request: &InferenceRequest) -> Result<MaskBuffer>;
+}
+
+/// Ordered mask stages, run at model output resolution before the mask is upscaled to the
+/// source size and PNG-encoded.
+///
+/// The default chain is just [`MinMaxNormalize`]; [`PostprocessChain::default_for`] picks the
+/// chain used when none is registered. Order matters for stages added after it:
+/// thresholding before feathering gives a soft edge, feathering before thresholding re-hardens it.
+#[derive(Debug, Clone)]
+pub struct PostprocessChain {
+    stages: Vec<Arc<dyn Postprocessor>>,
+}
+
+impl Default for PostprocessChain {
+    fn default() -> Self {
+        Self::empty().then(Arc::new(MinMaxNormalize))
+    }
+}
+
+impl PostprocessChain {
+    pub fn empty() -> Self {
+        Self { stages: Vec::new() }
+    }
+
+    /// Chain used for `model` when none is registered: a single [`RequestActivation`] stage.
+    /// Requests without a `mask_activation` get the model's default: RMBG-2.0 already emits
+    /// probabilities (or logits), so [`SigmoidOrClamp`]; RMBG-1.4 and anything else [`MinMaxNormalize`].
+    pub fn default_for(model: ModelKind) -> Self {
+        let fallback = match model {
+            ModelKind::Rmbg20 => MaskActivation::Sigmoid,
+            ModelKind::Rmbg14 | ModelKind::Auto => MaskActivation::MinMax,
+        };
+        Self::empty().then(Arc::new(RequestActivation(fallback)))
+    }
+
+    pub fn then(mut self, stage: Arc<dyn Postprocessor>) -> Self {
+        self.stages.push(stage);
+        self
+    }
+
+    pub fn run(&self, mut mask: MaskBuffer, request: &InferenceRequest) -> Result<MaskBuffer> {
+        for stage in &self.stages {
+            mask = stage.process(mask, request)?;
+        }
+        Ok(mask)
+    }
+}
+
+/// Stretches the output so its smallest value maps to 0 and its largest to 1.
+///
+/// When the request sets `low_confidence_range` and the output spans less than it, the
+/// values are passed through a clamp (or a sigmoid for logits) instead, so an uncertain
+/// model does not get stretched into a confident-looking mask.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MinMaxNormalize;
+
+impl Postprocessor for MinMaxNormalize {
+    fn process(&self, mut mask: MaskBuffer, request: &InferenceRequest) -> Result<MaskBuffer> {
+        let min_v = mask.data.iter().copied().fold(f32::INFINITY, f32::min);
+        let max_v = mask.data.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        if request.low_confidence_range.is_some_and(|limit| max_v - min_v < limit) {
+            let is_probability = min_v >= 0.0 && max_v <= 1.0;
+            for v in &mut mask.data {
+                *v = if is_probability { v.clamp(0.0, 1.0) } else { 1.0 / (1.0 + (-*v).exp()) };
+            }
+            return Ok(mask);
+        }
+        let range = (max_v - min_v).max(1e-6f32);
+        for v in &mut mask.data {
+            *v = ((*v - min_v) / range).clamp(0.0f32, 1.0f32);
+        }
+        Ok(mask)
+    }
+}
+
+/// Reads the output as alpha without stretching it: values already in `0.0..=1.0` are kept,
+/// anything else is treated as logits and passed through a sigmoid.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SigmoidOrClamp;
+
+impl Postprocessor for SigmoidOrClamp {
+    fn process(&self, mut mask: MaskBuffer, _request: &InferenceRequest) -> Result<MaskBuffer> {
+        let is_probability = mask.data.iter().all(|v| (0.0..=1.0).contains(v));
+        for v in &mut mask.data {
+            *v = if is_probability { v.clamp(0.0, 1.0) } else { 1.0 / (1.0 + (-*v).exp()) };
+        }
+        Ok(mask)
+    }
+}
+
+/// Clamps the output to `0.0..=1.0` without a sigmoid.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Clamp;
+
+impl Postprocessor for Clamp {
+    fn process(&self, mut mask: MaskBuffer, _request: &InferenceRequest) -> Result<MaskBuffer> {
+        for v in &mut mask.data {
+            *v = v.clamp(0.0, 1.0);
+        }
+        Ok(mask)
+    }
+}
+
+/// Applies the request's `mask_activation`: [`MinMaxNormalize`], [`SigmoidOrClamp`] or [`Clamp`].
+/// The wrapped activation is used when the request leaves it unset.
+#[derive(Debug, Clone, Copy)]
+pub struct RequestActivation(pub MaskActivation);
+
+impl Postprocessor for RequestActivation {
+    fn process(&self, mask: MaskBuffer, request: &InferenceRequest) -> Result<MaskBuffer> {
+        match request.mask_activation.unwrap_or(self.0) {
+            MaskActivation::MinMax => MinMaxNormalize.process(mask, request),
+            MaskActivation::Sigmoid => SigmoidOrClamp.process(mask, request),
+            MaskActivation::Clamp => Clamp.process(mask, request),
+        }
+    }
+}
+
+/// Resize to the spec, scale by the source max and shift by -0.5, laid out as NCHW.
+///
+/// Grayscale inputs are replicated into R, G and B for 3-channel sessions and fed as their
+/// single luma plane when the spec asks for one channel; color inputs are converted to luma then.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RmbgPreprocessor;
+
+impl Preprocessor for RmbgPreprocessor {
+    fn prepare(&self, image: &DynamicImage, spec: &InputSpec) -> Result<PreparedInput> {
+        Ok(prepare_nchw(image, spec, &RMBG14_STATS))
+    }
+}
+
+/// Like [`RmbgPreprocessor`], but normalizes with the ImageNet mean and std RMBG-2.0 was trained on.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Rmbg20Preprocessor;
+
+impl Preprocessor for Rmbg20Preprocessor {
+    fn prepare(&self, image: &DynamicImage, spec: &InputSpec) -> Result<PreparedInput> {
+        Ok(prepare_nchw(image, spec, &IMAGENET_STATS))
+    }
+}
+
+/// Normalizes with a custom model's registered [`Normalization`].
+#[derive(Debug, Clone, Copy)]
+pub struct NormalizedPreprocessor(pub Normalization);
+
+impl Preprocessor for NormalizedPreprocessor {
+    fn prepare(&self, image: &DynamicImage, spec: &InputSpec) -> Result<PreparedInput> {
+        let stats = ChannelStats {
+            mean: self.0.mean,
+            std: self.0.std,
+        };
+        Ok(prepare_nchw(image, spec, &stats))
+    }
+}
+
+/// The registered spec of `request.custom_model`, if the request names one.
+fn custom_model_spec(request: &InferenceRequest) -> Result<Option<CustomModel>, CoreError> {
+    let Some(id) = request.custom_model.as_deref() else {
+        return Ok(None);
+    };
+    let search = model_search_path(request.model_dir.as_deref()).map_err(|e| CoreError::Backend(e.to_string()))?;
+    find_custom_model(&search, id)
+        .map(|(spec, _)| Some(spec))
+        .ok_or_else(|| CoreError::Backend(format!("custom model {} is not registered and installed", id)))
+}
+
+/// Preprocessor used for `model` when none is registered.
+pub fn default_preprocessor(model: ModelKind) -> Arc<dyn Preprocessor> {
+    match model {
+        ModelKind::Rmbg20 => Arc::new(Rmbg20Preprocessor),
+        ModelKind::Rmbg14 | ModelKind::Auto => Arc::new(RmbgPreprocessor),
+    }
+}
+
+/// Per-channel `(sample / max - mean) / std`.
+struct ChannelStats {
+    mean: [f32; 3],
+    std: [f32; 3],
+}
+
+// BRIA's RMBG-1.4 utilities use `pixel / max - 0.5` for each channel.
+const RMBG14_STATS: ChannelStats = ChannelStats {
+    mean: [0.5; 3],
+    std: [1.0; 3],
+};
+
+const IMAGENET_STATS: ChannelStats = ChannelStats {
+    mean: [0.485, 0.456, 0.406],
+    std: [0.229, 0.224, 0.225],
+};
+
+fn prepare_nchw(image: &DynamicImage, spec: &InputSpec, stats: &ChannelStats) -> PreparedInput {
+    let channels = if spec.channels == 1 { 1 } else { 3 };
+    PreparedInput {
+        shape: [1, channels, spec.height as usize, spec.width as usize],
+        data: normalize_input(image, spec.width, spec.height, spec.source_max_value, channels, stats),
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -34,8 +310,121 @@ enum ProviderChoice {
 }
 
 static AUTO_PROVIDER_CACHE: OnceLock<Mutex<std::collections::HashMap<String, ProviderChoice>>> = OnceLock::new();
-thread_local! {
-    static SESSION_CACHE: RefCell<std::collections::HashMap<String, Session>> = RefCell::new(std::collections::HashMap::new());
+// Shared by every thread, so a worker pool builds each session once. ONNX Runtime allows concurrent
+// `Run` calls on one session, but `ort` exposes `Session::run` through `&mut Session`, so runs on the
+// same session are serialized by its entry lock while different sessions run in parallel.
+static SESSION_CACHE: OnceLock<SessionCache<Session>> = OnceLock::new();
+
+fn session_cache() -> &'static SessionCache<Session> {
+    SESSION_CACHE.get_or_init(SessionCache::default)
+}
+
+static SESSION_CACHE_HITS: AtomicU64 = AtomicU64::new(0);
+static SESSION_CACHE_MISSES: AtomicU64 = AtomicU64::new(0);
+static PROVIDER_CACHE_HITS: AtomicU64 = AtomicU64::new(0);
+static PROVIDER_CACHE_MISSES: AtomicU64 = AtomicU64::new(0);
+
+/// Session cache lookups across all threads since process start.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+pub fn session_cache_stats() -> SessionCacheStats {
+    SessionCacheStats {
+        hits: SESSION_CACHE_HITS.load(Ordering::Relaxed),
+        misses: SESSION_CACHE_MISSES.load(Ordering::Relaxed),
+    }
+}
+
+/// Auto-provider cache lookups (in memory or from the persisted file) since process start.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheStats {
+    pub session: SessionCacheStats,
+    pub provider: ProviderCacheStats,
+}
+
+/// Hit/miss counters for the session and auto-provider caches. A steadily growing miss count
+/// usually means the cache key changes between requests.
+pub fn cache_stats() -> CacheStats {
+    CacheStats {
+        session: session_cache_stats(),
+        provider: ProviderCacheStats {
+            hits: PROVIDER_CACHE_HITS.load(Ordering::Relaxed),
+            misses: PROVIDER_CACHE_MISSES.load(Ordering::Relaxed),
+        },
+    }
+}
+
+/// One slot per session key. The map lock is only held to find or add a slot; building and running
+/// happen under the slot's own lock, so a slow build blocks only callers of the same key.
+type SessionSlot<S> = Arc<Mutex<Option<S>>>;
+
+struct SessionCache<S> {
+    sessions: Mutex<std::collections::HashMap<String, SessionSlot<S>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl<S> Default for SessionCache<S> {
+    fn default() -> Self {
+        Self {
+            sessions: Mutex::new(std::collections::HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+}
+
+impl<S> SessionCache<S> {
+    fn slot(&self, key: &str) -> SessionSlot<S> {
+        self.sessions
+            .lock()
+            .expect("session cache lock poisoned")
+            .entry(key.to_string())
+            .or_default()
+            .clone()
+    }
+
+    /// Runs `run` on the session for `key`, building it with `build` first when it is missing or
+    /// an earlier build failed.
+    fn with_session<T>(&self, key: &str, build: impl FnOnce() -> Result<S>, run: impl FnOnce(&mut S) -> Result<T>) -> Result<T> {
+        let slot = self.slot(key);
+        let mut entry = slot.lock().expect("session lock poisoned");
+        if entry.is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            SESSION_CACHE_HITS.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            SESSION_CACHE_MISSES.fetch_add(1, Ordering::Relaxed);
+            *entry = Some(build()?);
+        }
+        let session = entry.as_mut().ok_or_else(|| anyhow!("session cache failed to initialize"))?;
+        run(session)
+    }
+
+    /// Drops the session for `key`; callers already running on it keep their handle.
+    fn remove(&self, key: &str) {
+        self.sessions.lock().expect("session cache lock poisoned").remove(key);
+    }
+
+    #[cfg(test)]
+    fn stats(&self) -> SessionCacheStats {
+        SessionCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
@@ -45,34 +434,125 @@ struct PersistedProviderCache {
 
 impl Default for LocalOrtBackend {
     fn default() -> Self {
-        Self {
-            descriptor: RuntimeDescriptor {
-                execution_provider: "cpu".to_string(),
-            },
-        }
+        Self::new(RuntimeDescriptor::default())
     }
 }
 
 impl LocalOrtBackend {
+    pub fn new(descriptor: RuntimeDescriptor) -> Self {
+        Self {
+            descriptor,
+            preprocessors: Vec::new(),
+            postprocess_chains: Vec::new(),
+            last_provider: Arc::new(Mutex::new(None)),
+            run_history: None,
+            placeholder_fallback: None,
+        }
+    }
+
     pub fn descriptor(&self) -> &RuntimeDescriptor {
         &self.descriptor
     }
 
-    fn load_image(&self, request: &InferenceRequest) -> Result<DynamicImage, CoreError> {
-        if let Some(bytes) = &request.input_bytes {
-            return image::load_from_memory(bytes).map_err(|e| CoreError::Backend(e.to_string()));
-        }
-        if let Some(path) = &request.input_path {
-            let bytes = fs::read(path).map_err(|e| CoreError::Backend(e.to_string()))?;
-            return image::load_from_memory(&bytes).map_err(|e| CoreError::Backend(e.to_string()));
+    /// Provider that served the most recent successful inference (`cpu`, `directml`, `cuda` or
+    /// `coreml`), or `None` before the first one.
+    pub fn last_provider(&self) -> Option<String> {
+        self.last_provider.lock().expect("last provider lock poisoned").clone()
+    }
+
+    fn record_provider(&self, result: &InferenceResult) {
+        let provider = result
+            .gpu_backend_selected
+            .clone()
+            .unwrap_or_else(|| result.execution_provider_selected.clone());
+        *self.last_provider.lock().expect("last provider lock poisoned") = Some(provider);
+    }
+
+    /// `request` with the descriptor's preference filled in where it leaves the provider on `Auto`.
+    fn with_descriptor_preference<'a>(&self, request: &'a InferenceRequest) -> Cow<'a, InferenceRequest> {
+        let provider = match request.execution_provider {
+            ExecutionProvider::Auto => self.descriptor.execution_provider,
+            explicit => explicit,
+        };
+        let gpu_backend = match request.gpu_backend {
+            GpuBackendPreference::Auto => self.descriptor.gpu_backend,
+            explicit => explicit,
+        };
+        if (provider, gpu_backend) == (request.execution_provider, request.gpu_backend) {
+            return Cow::Borrowed(request);
         }
-        Err(CoreError::MissingInput)
+        let mut preferred = request.clone();
+        preferred.execution_provider = provider;
+        preferred.gpu_backend = gpu_backend;
+        Cow::Owned(preferred)
+    }
+
+    pub fn with_preprocessor(mut self, model: ModelKind, preprocessor: Arc<dyn Preprocessor>) -> Self {
+        self.preprocessors.retain(|(kind, _)| *kind != model);
+        self.preprocessors.push((model, preprocessor));
+        self
+    }
+
+    pub fn preprocessor_for(&self, model: ModelKind) -> Arc<dyn Preprocessor> {
+        self.preprocessors
+            .iter()
+            .find(|(kind, _)| *kind == model)
+            .map(|(_, p)| p.clone())
+            .unwrap_or_else(|| default_preprocessor(model))
+    }
+
+    /// Appends a [`RunHistoryEntry`] to `path` after every `infer`, whatever `UNBG_RUN_HISTORY` says.
+    pub fn with_run_history(mut self, path: PathBuf) -> Self {
+        self.run_history = Some(path);
+        self
+    }
+
+    /// Allows or forbids the placeholder mask for a run whose model cannot load, whatever
+    /// `UNBG_ALLOW_PLACEHOLDER` says.
+    pub fn with_placeholder_fallback(mut self, allowed: bool) -> Self {
+        self.placeholder_fallback = Some(allowed);
+        self
+    }
+
+    pub fn with_postprocess_chain(mut self, model: ModelKind, chain: PostprocessChain) -> Self {
+        self.postprocess_chains.retain(|(kind, _)| *kind != model);
+        self.postprocess_chains.push((model, chain));
+        self
+    }
+
+    pub fn postprocess_chain_for(&self, model: ModelKind) -> PostprocessChain {
+        self.postprocess_chains
+            .iter()
+            .find(|(kind, _)| *kind == model)
+            .map(|(_, chain)| chain.clone())
+            .unwrap_or_else(|| PostprocessChain::default_for(model))
+    }
+
+    /// Decodes the request's input and, while a run history is kept for it, hashes the encoded
+    /// bytes for the entry so the input is never read twice.
+    fn load_image(&self, request: &InferenceRequest) -> Result<(DynamicImage, Option<String>), CoreError> {
+        let hints = request.input_path.as_deref().map(DecodeHints::from_path).unwrap_or_default();
+        let read;
+        let bytes = match (&request.input_bytes, &request.input_path) {
+            (Some(bytes), _) => bytes.as_slice(),
+            (None, Some(path)) => {
+                read = fs::read(path).map_err(|e| CoreError::Backend(e.to_string()))?;
+                read.as_slice()
+            }
+            (None, None) => return Err(CoreError::MissingInput),
+        };
+        // The header dimensions are checked against the limits before any pixels are decoded,
+        // so an oversized input fails here rather than in the downscale for inference.
+        let image = safe_decode(bytes, &hints, &DecodeLimits::default()).map_err(|e| CoreError::Backend(e.to_string()))?;
+        let digest = self.run_history_path(request).map(|_| hex::encode(Sha256::digest(bytes)));
+        Ok((image, digest))
     }
 
     fn infer_fallback(
         &self,
         selected_model: ModelKind,
         image: DynamicImage,
+        request: &InferenceRequest,
     ) -> Result<InferenceResult, CoreError> {
         let rgb = image.to_rgb8();
         let (width, height) = rgb.dimensions();
@@ -82,38 +562,246 @@ impl LocalOrtBackend {
             let alpha = if brightness > 25 { 255 } else { 0 };
             mask.put_pixel(x, y, Luma([alpha]));
         }
-        let mut encoded = Vec::new();
-        DynamicImage::ImageLuma8(mask)
-            .write_to(&mut std::io::Cursor::new(&mut encoded), ImageFormat::Png)
-            .map_err(|e| CoreError::Backend(e.to_string()))?;
-        Ok(InferenceResult {
-            model_used: selected_model,
-            mask_png: encoded,
-            width,
-            height,
-            execution_provider_selected: "cpu".to_string(),
-            gpu_backend_selected: None,
-            fallback_used: false,
-        })
+        let mut result = InferenceResult::new(selected_model, Vec::new(), width, height, "cpu");
+        store_mask(&mut result, mask, request)?;
+        Ok(result)
     }
 }
 
 impl InferenceBackend for LocalOrtBackend {
     fn infer(&self, request: &InferenceRequest, selected_model: ModelKind) -> Result<InferenceResult, CoreError> {
-        let image = match self.load_image(request) {
-            Ok(img) => img,
+        self.infer_until_cancelled(request, selected_model, None)
+    }
+
+    /// Checks `cancel` after decoding, before and after each `session.run` and before the
+    /// seed mask and RLE steps. A cancel also skips the placeholder fallback.
+    fn infer_cancellable(
+        &self,
+        request: &InferenceRequest,
+        selected_model: ModelKind,
+        cancel: &CancelToken,
+    ) -> Result<InferenceResult, CoreError> {
+        self.infer_until_cancelled(request, selected_model, Some(cancel))
+    }
+
+    /// Stacks requests with matching session settings into as few `session.run` calls as the
+    /// default [`BatchOptions`] allow. Requests that cannot be batched go through `infer` one by one.
+    fn infer_batch(&self, requests: &[InferenceRequest], selected_model: ModelKind) -> Result<Vec<InferenceResult>, CoreError> {
+        self.infer_batch_until_cancelled(requests, selected_model, None)
+    }
+
+    /// Checks `cancel` before each sub-batch and around each `session.run`.
+    fn infer_batch_cancellable(
+        &self,
+        requests: &[InferenceRequest],
+        selected_model: ModelKind,
+        cancel: &CancelToken,
+    ) -> Result<Vec<InferenceResult>, CoreError> {
+        self.infer_batch_until_cancelled(requests, selected_model, Some(cancel))
+    }
+}
+
+/// Classifies the final mask by the [`foreground_ratio`] recorded when it was encoded and applies
+/// `request.on_empty_mask` when it is entirely background or foreground. Returns the ratio, or
+/// `None` when no mask was emitted.
+fn apply_empty_mask_policy(result: &mut InferenceResult, request: &InferenceRequest) -> Result<Option<f32>, CoreError> {
+    let ratio = if let Some(ratio) = result.foreground_ratio {
+        ratio
+    } else if let Some(raw) = &result.mask_raw {
+        raw.iter().filter(|&&value| value >= 0.5).count() as f32 / raw.len().max(1) as f32
+    } else {
+        return Ok(None);
+    };
+    let Some(coverage) = MaskCoverage::from_foreground_ratio(ratio) else {
+        return Ok(Some(ratio));
+    };
+    match request.on_empty_mask {
+        EmptyMaskPolicy::Error => {
+            let what = match coverage {
+                MaskCoverage::Empty => "background",
+                MaskCoverage::Full => "foreground",
+            };
+            return Err(backend_error(
+                "empty-mask",
+                format!("mask is entirely {} (foreground ratio {:.4})", what, ratio),
+            ));
+        }
+        EmptyMaskPolicy::Passthrough => {
+            if !result.mask_png.is_empty() {
+                let opaque = GrayImage::from_pixel(result.width, result.height, Luma([255]));
+                store_mask(result, opaque, request)?;
+                result.foreground_ratio = Some(ratio);
+            }
+            if let Some(raw) = result.mask_raw.as_mut() {
+                raw.fill(1.0);
+            }
+        }
+        EmptyMaskPolicy::Flag => {}
+    }
+    result.mask_coverage = Some(coverage);
+    Ok(Some(ratio))
+}
+
+/// `Err(CoreError::Cancelled)` once `cancel` has fired; `Ok` without a token.
+fn check_cancelled(cancel: Option<&CancelToken>) -> Result<(), CoreError> {
+    cancel.map_or(Ok(()), CancelToken::check)
+}
+
+impl LocalOrtBackend {
+    fn infer_until_cancelled(
+        &self,
+        request: &InferenceRequest,
+        selected_model: ModelKind,
+        cancel: Option<&CancelToken>,
+    ) -> Result<InferenceResult, CoreError> {
+        let start = Instant::now();
+        let mut foreground = None;
+        let outcome = check_cancelled(cancel)
+            .and_then(|()| self.infer_seeded(request, selected_model, cancel))
+            .and_then(|mut result| {
+                check_cancelled(cancel)?;
+                foreground = apply_empty_mask_policy(&mut result, request)?;
+                Ok(result)
+            });
+        if let Some(path) = self.run_history_path(request) {
+            let entry = RunHistoryEntry::record(request, selected_model, outcome.as_ref(), foreground, elapsed_ms(start));
+            append_run_history(&path, &entry);
+        }
+        outcome
+    }
+
+    fn run_history_path(&self, request: &InferenceRequest) -> Option<PathBuf> {
+        self.run_history.clone().or_else(|| {
+            run_history_file(env::var("UNBG_RUN_HISTORY").ok().as_deref(), request.model_dir.as_deref())
+        })
+    }
+
+    fn infer_seeded(
+        &self,
+        request: &InferenceRequest,
+        selected_model: ModelKind,
+        cancel: Option<&CancelToken>,
+    ) -> Result<InferenceResult, CoreError> {
+        let allow_placeholder = self.placeholder_fallback.unwrap_or_else(placeholder_fallback_allowed);
+        let Some(seed_png) = &request.seed_mask else {
+            return self.infer_with(request, selected_model, allow_placeholder, cancel);
+        };
+        let seed = image::load_from_memory(seed_png)
+            .map_err(|e| CoreError::Backend(format!("failed to decode seed mask: {}", e)))?
+            .to_luma8();
+        let mut result = self.infer_with(request, selected_model, allow_placeholder, cancel)?;
+        if !result.mask_png.is_empty() {
+            let mask = apply_seed_mask(&result.mask_png, &seed)?;
+            store_mask(&mut result, mask, request)?;
+        }
+        Ok(result)
+    }
+}
+
+fn check_seed_dimensions((seed_width, seed_height): (u32, u32), width: u32, height: u32) -> Result<(), CoreError> {
+    if (seed_width, seed_height) != (width, height) {
+        return Err(CoreError::InvalidArgument(format!(
+            "seed mask is {}x{} but the input is {}x{}",
+            seed_width, seed_height, width, height
+        )));
+    }
+    Ok(())
+}
+
+/// Forces the definite regions of `seed` onto the predicted mask: 255 becomes foreground and 0
+/// background regardless of the model, everything else keeps the prediction.
+fn apply_seed_mask(mask_png: &[u8], seed: &GrayImage) -> Result<GrayImage, CoreError> {
+    let mut mask = image::load_from_memory(mask_png)
+        .map_err(|e| CoreError::Backend(format!("failed to decode mask: {}", e)))?
+        .to_luma8();
+    check_seed_dimensions(seed.dimensions(), mask.width(), mask.height())?;
+    for (pixel, hint) in mask.pixels_mut().zip(seed.pixels()) {
+        match hint[0] {
+            0 => pixel[0] = 0,
+            255 => pixel[0] = 255,
+            _ => {}
+        }
+    }
+    Ok(mask)
+}
+
+/// Encodes `mask` into `result.mask_png` and, when the request sets `emit_mask_rle`, run-length
+/// encodes the same pixels into `result.mask_rle`. Records its [`foreground_ratio`] on the way.
+fn store_mask(result: &mut InferenceResult, mask: GrayImage, request: &InferenceRequest) -> Result<(), CoreError> {
+    result.foreground_ratio = Some(foreground_ratio(mask.as_raw()));
+    result.mask_rle = request.emit_mask_rle.then(|| encode_mask_rle(mask.as_raw()));
+    result.mask_png = encode_mask(mask, request.png_compression)?;
+    Ok(())
+}
+
+/// Encodes a mask as PNG at the request's compression level.
+fn encode_mask(mask: GrayImage, png_compression: Option<u8>) -> Result<Vec<u8>, CoreError> {
+    let options = EncodeOptions {
+        png_compression,
+        ..EncodeOptions::default()
+    };
+    encode_image(&DynamicImage::ImageLuma8(mask), ImageFormat::Png, &options).map_err(|e| CoreError::Backend(e.to_string()))
+}
+
+impl LocalOrtBackend {
+    fn infer_with(
+        &self,
+        request: &InferenceRequest,
+        selected_model: ModelKind,
+        allow_placeholder: bool,
+        cancel: Option<&CancelToken>,
+    ) -> Result<InferenceResult, CoreError> {
+        let request = self.with_descriptor_preference(request);
+        let result = self.run_request(&request, selected_model, allow_placeholder, cancel, MaskScale::Input)?;
+        self.record_provider(&result);
+        Ok(result)
+    }
+
+    fn run_request(
+        &self,
+        request: &InferenceRequest,
+        selected_model: ModelKind,
+        allow_placeholder: bool,
+        cancel: Option<&CancelToken>,
+        scale: MaskScale,
+    ) -> Result<InferenceResult, CoreError> {
+        validate_request_shape(request)?;
+        if let Some(mode) = request.ensemble {
+            // Both members must run for real; a placeholder mask would silently skew the blend.
+            return infer_ensemble(request, mode, |member, model| self.infer_with(member, model, false, cancel));
+        }
+        let (image, input_sha256) = match self.load_image(request) {
+            Ok(loaded) => loaded,
             Err(err) => {
-                if placeholder_fallback_allowed() {
-                    return self.infer_fallback(selected_model, DynamicImage::new_rgb8(request.width.max(1), request.height.max(1)));
+                if allow_placeholder {
+                    return self.infer_fallback(selected_model, DynamicImage::new_rgb8(request.width.max(1), request.height.max(1)), request);
                 }
                 return Err(err);
             }
         };
-        let model_file = match resolve_model_onnx_file(request, selected_model) {
-            Ok(path) => path,
+        check_cancelled(cancel)?;
+        let custom = custom_model_spec(request)?;
+        let custom_request;
+        let request = match &custom {
+            Some(spec) if request.input_size.is_none() => {
+                let mut sized = request.clone();
+                sized.input_size = Some(spec.input_size);
+                custom_request = sized;
+                &custom_request
+            }
+            _ => request,
+        };
+        let resolved = match resolve_model(request, selected_model) {
+            Ok(resolved) => resolved,
             Err(err) => {
-                if placeholder_fallback_allowed() {
-                    return self.infer_fallback(selected_model, image);
+                if let Some(other) = fallback_model(request, selected_model) {
+                    return self.run_fallback_model(request, other, allow_placeholder, cancel, scale);
+                }
+                if allow_placeholder {
+                    return self.infer_fallback(selected_model, image, request).map(|mut res| {
+                        res.input_sha256 = input_sha256;
+                        res
+                    });
                 }
                 return Err(err);
             }
@@ -122,584 +810,4404 @@ impl InferenceBackend for LocalOrtBackend {
         if candidates.is_empty() {
             return Err(CoreError::Backend("no execution providers available".to_string()));
         }
-
-        let result = if request.execution_provider == ExecutionProvider::Auto {
-            if request.benchmark_provider {
-                run_auto_bench_path(&image, &model_file, selected_model, request, &candidates)
-            } else {
-                run_auto_cached_path(&image, &model_file, selected_model, request, &candidates)
-            }
-        } else {
-            run_sequential_path(&image, &model_file, selected_model, &candidates, request.emit_mask_png)
+        let preprocessor = match &custom {
+            Some(spec) => Arc::new(NormalizedPreprocessor(spec.normalization)),
+            None => self.preprocessor_for(selected_model),
         };
+        let postprocess = self.postprocess_chain_for(selected_model);
+        let pipeline = Pipeline {
+            preprocessor: preprocessor.as_ref(),
+            postprocess: &postprocess,
+            cancel,
+        };
+
+        let model_file = &resolved.file;
+        let result = infer_within_roi(&image, request, |region| {
+            infer_within_max_dimension(region, request, scale, |image, request| {
+                if request.execution_provider == ExecutionProvider::Auto {
+                    if request.benchmark_provider {
+                        run_auto_bench_path(image, model_file, selected_model, request, &pipeline, &candidates)
+                    } else {
+                        run_auto_cached_path(image, model_file, selected_model, request, &pipeline, &candidates)
+                    }
+                } else {
+                    run_sequential_path(image, model_file, selected_model, request, &pipeline, &candidates)
+                }
+            })
+        });
+        // Provider fallback folds a cancel into its error list, so check before any placeholder.
+        check_cancelled(cancel)?;
 
         match result {
-            Ok(res) => Ok(res),
+            Ok(mut res) => {
+                resolved.stamp(&mut res);
+                res.input_sha256 = input_sha256;
+                Ok(res)
+            }
             Err(err) => {
-                if placeholder_fallback_allowed() {
-                    self.infer_fallback(selected_model, image)
+                if let Some(other) = fallback_model(request, selected_model) {
+                    self.run_fallback_model(request, other, allow_placeholder, cancel, scale)
+                } else if allow_placeholder {
+                    self.infer_fallback(selected_model, image, request).map(|mut res| {
+                        res.input_sha256 = input_sha256;
+                        res
+                    })
                 } else {
                     Err(err)
                 }
             }
         }
     }
-}
 
-fn placeholder_fallback_allowed() -> bool {
-    match env::var("UNBG_ALLOW_PLACEHOLDER") {
-        Ok(value) => {
-            let normalized = value.trim().to_ascii_lowercase();
-            normalized == "1" || normalized == "true" || normalized == "yes"
-        }
-        Err(_) => false,
+    /// Runs `model` in place of the auto-selected one and flags the result.
+    fn run_fallback_model(
+        &self,
+        request: &InferenceRequest,
+        model: ModelKind,
+        allow_placeholder: bool,
+        cancel: Option<&CancelToken>,
+        scale: MaskScale,
+    ) -> Result<InferenceResult, CoreError> {
+        let mut result = self.run_request(request, model, allow_placeholder, cancel, scale)?;
+        result.model_fallback_used = true;
+        Ok(result)
     }
-}
 
-fn resolve_model_onnx_file(request: &InferenceRequest, selected_model: ModelKind) -> Result<PathBuf, CoreError> {
-    let paths = resolve_model_paths(request.model_dir.as_deref()).map_err(|e| CoreError::Backend(e.to_string()))?;
-    let lock = read_lockfile(&paths).map_err(|e| CoreError::Backend(e.to_string()))?;
-    let wanted_id = match selected_model {
-        ModelKind::Rmbg14 => KnownModel::Rmbg14.model_id(),
-        ModelKind::Rmbg20 => KnownModel::Rmbg20.model_id(),
-        ModelKind::Auto => return Err(CoreError::Backend("auto model cannot resolve onnx directly".to_string())),
-    };
-    let model = lock
-        .models
-        .iter()
-        .find(|m| m.model_id == wanted_id)
-        .ok_or_else(|| CoreError::Backend(format!("model not found in lockfile: {}", wanted_id)))?;
-    let known_model = KnownModel::from_model_id(&model.model_id)
-        .ok_or_else(|| CoreError::Backend(format!("unknown model id: {}", model.model_id)))?;
-    let rev_dir = model_revision_dir(&paths, known_model, &model.revision);
-    find_preferred_onnx_file(&rev_dir, request.onnx_variant).ok_or_else(|| {
-        CoreError::Backend(format!(
-            "no .onnx file found for {} revision {} in {}",
-            model.model_id,
-            model.revision,
-            rev_dir.display()
-        ))
-    })
+    /// Runs `request` like [`InferenceBackend::infer`] but hands the full-size mask to `sink` in
+    /// `tile_size` tiles, in [`unbg_image::tile_grid`] order, instead of encoding it whole. When the request
+    /// downscales for inference, each tile is upscaled from the small mask on its own, so the
+    /// full-resolution mask is never held in memory. The returned result has an empty `mask_png`.
+    ///
+    /// Region, ensemble, seed-mask and feather requests need the whole mask and are rejected.
+    pub fn infer_mask_tiles(
+        &self,
+        request: &InferenceRequest,
+        selected_model: ModelKind,
+        tile_size: u32,
+        sink: impl FnMut(TileRect, &GrayImage) -> Result<(), CoreError>,
+    ) -> Result<InferenceResult, CoreError> {
+        if request.roi.is_some() || request.ensemble.is_some() || request.seed_mask.is_some() || request.feather_radius.is_some() {
+            return Err(CoreError::Backend(
+                "streamed mask tiles do not support region, ensemble, seed-mask or feather requests".to_string(),
+            ));
+        }
+        let request = self.with_descriptor_preference(request);
+        validate_request_shape(&request)?;
+        // Threshold and trimap are applied per tile below, after the upscale.
+        let mut soft = request.as_ref().clone();
+        soft.mask_threshold = None;
+        soft.trimap = None;
+        let mut result = self.run_request(&soft, selected_model, false, None, MaskScale::Inference)?;
+        self.record_provider(&result);
+        let mask = image::load_from_memory(&result.mask_png)
+            .map_err(|e| CoreError::Backend(format!("failed to decode mask: {}", e)))?
+            .to_luma8();
+        let (width, height) = (result.width, result.height);
+        stream_mask_tiles(
+            width,
+            height,
+            tile_size,
+            |tile| {
+                let mut alpha = upscale_mask_tile(&mask, width, height, tile);
+                if let Some(threshold) = request.mask_threshold {
+                    apply_mask_threshold(&mut alpha, threshold)?;
+                }
+                if let Some(thresholds) = request.trimap {
+                    apply_trimap(&mut alpha, thresholds)?;
+                }
+                Ok(alpha)
+            },
+            sink,
+        )?;
+        result.mask_png = Vec::new();
+        Ok(result)
+    }
 }
 
-fn run_sequential_path(
-    image: &DynamicImage,
-    model_file: &Path,
-    selected_model: ModelKind,
-    candidates: &[ProviderChoice],
-    emit_mask_png: bool,
-) -> Result<InferenceResult, CoreError> {
-    let preferred = candidates[0];
-    let mut errors = Vec::new();
-    for provider in candidates {
-        match run_provider(image, model_file, selected_model, *provider, emit_mask_png) {
-            Ok((mut result, _)) => {
-                result.fallback_used = *provider != preferred;
-                return Ok(result);
-            }
-            Err(err) => errors.push(format!("{}: {}", provider_label(*provider), err)),
+/// The model to try when `selected_model` cannot be loaded or run: RMBG-1.4 in place of an
+/// auto-selected RMBG-2.0, unless the request is strict or names a custom model. RMBG-1.4 must
+/// be installed too; otherwise a placeholder keeps reporting the model that was selected.
+fn fallback_model(request: &InferenceRequest, selected_model: ModelKind) -> Option<ModelKind> {
+    (!request.strict_model
+        && request.custom_model.is_none()
+        && request.requested_model == ModelKind::Auto
+        && selected_model == ModelKind::Rmbg20)
+        .then_some(ModelKind::Rmbg14)
+        .filter(|&other| resolve_model(request, other).is_ok())
+}
+
+/// Limits for [`LocalOrtBackend::infer_batch_with`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchOptions {
+    /// Estimated bytes one `session.run` may use: the decoded inputs and their tensors plus a
+    /// fixed allowance for the model. Batches over it are split into several runs.
+    pub memory_budget: u64,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        Self {
+            memory_budget: 2 * 1024 * 1024 * 1024,
         }
     }
-    Err(backend_error(
-        "provider-exhausted",
-        format!("all providers failed: {}", errors.join(" | ")),
-    ))
 }
 
-fn run_auto_bench_path(
-    image: &DynamicImage,
-    model_file: &Path,
-    selected_model: ModelKind,
-    request: &InferenceRequest,
-    candidates: &[ProviderChoice],
-) -> Result<InferenceResult, CoreError> {
-    let cache_key = provider_cache_key(selected_model, request);
-    let cache = AUTO_PROVIDER_CACHE.get_or_init(|| Mutex::new(std::collections::HashMap::new()));
-    if let Some(cached) = load_cached_provider(&cache_key, request.model_dir.as_deref()) {
-        if let Ok((result, _)) = run_provider(image, model_file, selected_model, cached, request.emit_mask_png) {
-            return Ok(result);
+/// Weights and runtime workspace, counted once per sub-batch.
+const BATCH_MODEL_OVERHEAD_BYTES: u64 = 512 * 1024 * 1024;
+/// The 3-channel input and 1-channel output tensors at model resolution, per image.
+const BATCH_TENSOR_BYTES: u64 = 4 * 1024 * 1024 * 4;
+
+/// Splits `sizes` into consecutive runs whose estimated footprint stays within `budget`. Every run
+/// takes at least one item, so an image over budget on its own is still processed.
+fn plan_sub_batches(sizes: &[ImageSize], budget: u64) -> Vec<Range<usize>> {
+    let mut batches = Vec::new();
+    let mut start = 0;
+    let mut used = BATCH_MODEL_OVERHEAD_BYTES;
+    for (idx, size) in sizes.iter().enumerate() {
+        let bytes = estimate_rgba_bytes(*size).saturating_add(BATCH_TENSOR_BYTES);
+        if idx > start && used.saturating_add(bytes) > budget {
+            batches.push(start..idx);
+            start = idx;
+            used = BATCH_MODEL_OVERHEAD_BYTES;
         }
+        used = used.saturating_add(bytes);
     }
+    if start < sizes.len() {
+        batches.push(start..sizes.len());
+    }
+    batches
+}
 
-    let mut best: Option<(InferenceResult, ProviderChoice, u128)> = None;
-    let mut errors = Vec::new();
-    for provider in candidates {
-        match run_provider(image, model_file, selected_model, *provider, request.emit_mask_png) {
-            Ok((result, elapsed_ms)) => {
-                if let Some((_, _, best_ms)) = &best {
-                    if elapsed_ms < *best_ms {
-                        best = Some((result, *provider, elapsed_ms));
-                    }
-                } else {
-                    best = Some((result, *provider, elapsed_ms));
-                }
-            }
-            Err(err) => errors.push(format!("{}: {}", provider_label(*provider), err)),
+/// Runs `run` on each planned sub-batch of `sizes` and concatenates the results in input order.
+fn run_sub_batches<T>(
+    sizes: &[ImageSize],
+    budget: u64,
+    mut run: impl FnMut(Range<usize>) -> Result<Vec<T>, CoreError>,
+) -> Result<Vec<T>, CoreError> {
+    let mut out = Vec::with_capacity(sizes.len());
+    for range in plan_sub_batches(sizes, budget) {
+        let expected = range.len();
+        let results = run(range)?;
+        if results.len() != expected {
+            return Err(CoreError::Backend(format!(
+                "sub-batch returned {} results for {} inputs",
+                results.len(),
+                expected
+            )));
         }
+        out.extend(results);
     }
+    Ok(out)
+}
 
-    if let Some((result, provider, _)) = best {
-        cache
-            .lock()
-            .expect("provider cache lock poisoned")
-            .insert(cache_key.clone(), provider);
-        persist_cached_provider(&cache_key, provider, request.model_dir.as_deref());
-        return Ok(result);
+impl LocalOrtBackend {
+    /// Runs `requests` on `selected_model` with as few `session.run` calls as
+    /// `options.memory_budget` allows, returning the results in request order.
+    ///
+    /// Needs a model export with a dynamic batch dimension and every request's `dimensions` set.
+    /// Requests are grouped by the settings that pick the model file and session (model dir,
+    /// variant, revision, provider, dimension overrides, tuning and input size), and each group
+    /// runs on its own. Region, downscale, ensemble and seed-mask requests, and inputs over their
+    /// `max_inference_pixels` budget, are per-image only and go through `infer`.
+    pub fn infer_batch_with(
+        &self,
+        requests: &[InferenceRequest],
+        selected_model: ModelKind,
+        options: BatchOptions,
+    ) -> Result<Vec<InferenceResult>, CoreError> {
+        if !requests.iter().all(batchable) {
+            return Err(CoreError::Backend(
+                "batch inference does not support roi, max_dimension, ensemble, seed_mask or custom_model requests, or inputs over max_inference_pixels"
+                    .to_string(),
+            ));
+        }
+        if requests.iter().any(|r| r.width == 0 || r.height == 0) {
+            return Err(CoreError::Backend("batch inference needs the dimensions of every input".to_string()));
+        }
+        let all: Vec<usize> = (0..requests.len()).collect();
+        self.run_batch_groups(requests, &all, selected_model, options, None)
     }
 
-    Err(backend_error(
-        "benchmark-failed",
-        format!("auto provider benchmark failed: {}", errors.join(" | ")),
-    ))
-}
+    fn infer_batch_until_cancelled(
+        &self,
+        requests: &[InferenceRequest],
+        selected_model: ModelKind,
+        cancel: Option<&CancelToken>,
+    ) -> Result<Vec<InferenceResult>, CoreError> {
+        let (stacked, single): (Vec<usize>, Vec<usize>) =
+            (0..requests.len()).partition(|&idx| batchable(&requests[idx]) && requests[idx].width > 0 && requests[idx].height > 0);
+        let mut results: Vec<Option<InferenceResult>> = (0..requests.len()).map(|_| None).collect();
+        for (idx, result) in stacked
+            .iter()
+            .zip(self.run_batch_groups(requests, &stacked, selected_model, BatchOptions::default(), cancel)?)
+        {
+            results[*idx] = Some(result);
+        }
+        for idx in single {
+            results[idx] = Some(self.infer_until_cancelled(&requests[idx], selected_model, cancel)?);
+        }
+        Ok(results.into_iter().flatten().collect())
+    }
 
-fn run_auto_cached_path(
-    image: &DynamicImage,
-    model_file: &Path,
-    selected_model: ModelKind,
-    request: &InferenceRequest,
-    candidates: &[ProviderChoice],
-) -> Result<InferenceResult, CoreError> {
-    let cache_key = provider_cache_key(selected_model, request);
-    if let Some(cached) = load_cached_provider(&cache_key, request.model_dir.as_deref()) {
-        if candidates.contains(&cached) {
-            if let Ok((result, _)) = run_provider(image, model_file, selected_model, cached, request.emit_mask_png) {
-                return Ok(result);
+    /// Runs `requests[indices]` group by group (see [`BatchKey`]) and returns their results in
+    /// the order of `indices`.
+    fn run_batch_groups(
+        &self,
+        requests: &[InferenceRequest],
+        indices: &[usize],
+        selected_model: ModelKind,
+        options: BatchOptions,
+        cancel: Option<&CancelToken>,
+    ) -> Result<Vec<InferenceResult>, CoreError> {
+        let mut groups: Vec<(BatchKey, Vec<usize>)> = Vec::new();
+        for (pos, &idx) in indices.iter().enumerate() {
+            let key = BatchKey::of(&requests[idx]);
+            match groups.iter_mut().find(|(existing, _)| *existing == key) {
+                Some((_, members)) => members.push(pos),
+                None => groups.push((key, vec![pos])),
+            }
+        }
+        let mut results: Vec<Option<InferenceResult>> = (0..indices.len()).map(|_| None).collect();
+        for (_, members) in groups {
+            let group: Vec<&InferenceRequest> = members.iter().map(|&pos| &requests[indices[pos]]).collect();
+            for (pos, result) in members.into_iter().zip(self.run_batch_group(&group, selected_model, options, cancel)?) {
+                results[pos] = Some(result);
             }
         }
+        Ok(results.into_iter().flatten().collect())
     }
 
-    let mut errors = Vec::new();
-    for provider in candidates {
-        match run_provider(image, model_file, selected_model, *provider, request.emit_mask_png) {
-            Ok((result, _)) => {
-                persist_cached_provider(&cache_key, *provider, request.model_dir.as_deref());
-                return Ok(result);
+    /// Runs one group of requests that share a [`BatchKey`], split into sub-batches by
+    /// `options.memory_budget`.
+    fn run_batch_group(
+        &self,
+        requests: &[&InferenceRequest],
+        selected_model: ModelKind,
+        options: BatchOptions,
+        cancel: Option<&CancelToken>,
+    ) -> Result<Vec<InferenceResult>, CoreError> {
+        let Some(first) = requests.first() else {
+            return Ok(Vec::new());
+        };
+        let first = self.with_descriptor_preference(first);
+        let history = self.run_history_path(&first);
+        // Each input of a failed group or sub-batch gets its own entry, like a failed `infer`.
+        let record_failure = |chunk: &[&InferenceRequest], err: &CoreError, start: Instant| {
+            if let Some(path) = &history {
+                for request in chunk {
+                    let entry = RunHistoryEntry::record(request, selected_model, Err(err), None, elapsed_ms(start));
+                    append_run_history(path, &entry);
+                }
             }
-            Err(err) => errors.push(format!("{}: {}", provider_label(*provider), err)),
+        };
+        let group_start = Instant::now();
+        let fail_group = |err: CoreError| {
+            record_failure(requests, &err, group_start);
+            err
+        };
+        check_cancelled(cancel).map_err(fail_group)?;
+        for request in requests {
+            validate_request_shape(request).map_err(fail_group)?;
+        }
+        let resolved = resolve_model(&first, selected_model).map_err(fail_group)?;
+        let model_file = &resolved.file;
+        let candidates = candidate_providers(&first);
+        if candidates.is_empty() {
+            return Err(fail_group(CoreError::Backend("no execution providers available".to_string())));
+        }
+        let preprocessor = self.preprocessor_for(selected_model);
+        let postprocess = self.postprocess_chain_for(selected_model);
+        let pipeline = Pipeline {
+            preprocessor: preprocessor.as_ref(),
+            postprocess: &postprocess,
+            cancel,
+        };
+
+        let sizes: Vec<ImageSize> = requests
+            .iter()
+            .map(|r| ImageSize {
+                width: r.width,
+                height: r.height,
+            })
+            .collect();
+        let run_chunk = |chunk: &[&InferenceRequest]| -> Result<Vec<InferenceResult>, CoreError> {
+            check_cancelled(cancel)?;
+            let (images, digests): (Vec<_>, Vec<_>) = chunk
+                .iter()
+                .map(|r| self.load_image(r))
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter()
+                .unzip();
+            let attempt = try_providers(&candidates, first.inference_retries, |provider| {
+                run_batch_on_provider(&images, model_file, provider, chunk, &pipeline)
+            });
+            // Provider fallback folds a cancel into its error list.
+            check_cancelled(cancel)?;
+            let (masks, provider, errors) = attempt
+                .map_err(|errors| backend_error("provider-exhausted", format!("all providers failed: {}", errors.join(" | "))))?;
+            let (execution_provider_selected, gpu_backend_selected) = provider_selection(provider);
+            let fallback_used = provider != candidates[0];
+            Ok(images
+                .iter()
+                .zip(masks)
+                .zip(digests)
+                .map(|((image, mask), input_sha256)| {
+                    let mut result = InferenceResult::new(
+                        selected_model,
+                        mask.png,
+                        image.width(),
+                        image.height(),
+                        execution_provider_selected.clone(),
+                    );
+                    result.gpu_backend_selected = gpu_backend_selected.clone();
+                    result.fallback_used = fallback_used;
+                    result.fallback_reason = fallback_used.then(|| errors.join(" | "));
+                    result.mask_rle = mask.rle;
+                    result.foreground_ratio = mask.foreground_ratio;
+                    result.input_sha256 = input_sha256;
+                    set_mask_raw(&mut result, mask.raw);
+                    resolved.stamp(&mut result);
+                    result
+                })
+                .collect())
+        };
+        let results = run_sub_batches(&sizes, options.memory_budget, |range| {
+            let start = Instant::now();
+            let chunk = &requests[range];
+            let results = run_chunk(chunk).inspect_err(|err| record_failure(chunk, err, start))?;
+            results
+                .into_iter()
+                .zip(chunk)
+                .map(|(mut result, request)| {
+                    let outcome = apply_empty_mask_policy(&mut result, request);
+                    if let Some(path) = &history {
+                        let foreground = outcome.as_ref().ok().copied().flatten();
+                        let recorded = outcome.as_ref().map(|_| &result);
+                        let entry = RunHistoryEntry::record(request, selected_model, recorded, foreground, elapsed_ms(start));
+                        append_run_history(path, &entry);
+                    }
+                    outcome.map(|_| result)
+                })
+                .collect()
+        })?;
+        if let Some(last) = results.last() {
+            self.record_provider(last);
         }
+        Ok(results)
     }
+}
 
-    Err(backend_error(
-        "auto-provider-failed",
-        format!("all providers failed: {}", errors.join(" | ")),
-    ))
+/// The settings that pick the model file and the session a request runs on. Only requests with
+/// equal keys are stacked into one `session.run`.
+#[derive(Debug, PartialEq)]
+struct BatchKey<'a> {
+    model_dir: Option<&'a Path>,
+    onnx_variant: OnnxVariant,
+    revision: Option<&'a str>,
+    execution_provider: ExecutionProvider,
+    gpu_backend: GpuBackendPreference,
+    free_dimension_overrides: &'a [(String, i64)],
+    session_tuning: SessionTuning,
+    input_size: Option<u32>,
+    inference_retries: u32,
 }
 
-fn run_provider(
-    image: &DynamicImage,
-    model_file: &Path,
-    selected_model: ModelKind,
-    provider: ProviderChoice,
-    emit_mask_png: bool,
-) -> Result<(InferenceResult, u128)> {
-    let session_key = session_cache_key(model_file, provider);
-    let start = Instant::now();
-    let mask_png = SESSION_CACHE.with(|cache| {
-        let mut cache_ref = cache.borrow_mut();
-        if !cache_ref.contains_key(&session_key) {
-            let session = build_session_for_provider(model_file, provider)?;
-            cache_ref.insert(session_key.clone(), session);
-        }
-        let session = cache_ref
-            .get_mut(&session_key)
-            .ok_or_else(|| anyhow!("session cache failed to initialize"))?;
-        run_onnx_inference(image, session, emit_mask_png)
-    })
-    .map_err(|e| anyhow!(e.to_string()))?;
-    let elapsed = start.elapsed().as_millis();
-    let (execution_provider_selected, gpu_backend_selected) = match provider {
-        ProviderChoice::Cpu => ("cpu".to_string(), None),
-        ProviderChoice::DirectML => ("gpu".to_string(), Some("directml".to_string())),
-        ProviderChoice::Cuda => ("gpu".to_string(), Some("cuda".to_string())),
-        ProviderChoice::CoreML => ("gpu".to_string(), Some("coreml".to_string())),
-    };
-    Ok((
-        InferenceResult {
-            model_used: selected_model,
-            mask_png,
-            width: image.width(),
-            height: image.height(),
-            execution_provider_selected,
-            gpu_backend_selected,
-            fallback_used: false,
-        },
-        elapsed,
-    ))
+impl<'a> BatchKey<'a> {
+    fn of(request: &'a InferenceRequest) -> Self {
+        Self {
+            model_dir: request.model_dir.as_deref(),
+            onnx_variant: request.onnx_variant,
+            revision: request.revision.as_deref(),
+            execution_provider: request.execution_provider,
+            gpu_backend: request.gpu_backend,
+            free_dimension_overrides: &request.free_dimension_overrides,
+            session_tuning: request.session_tuning,
+            input_size: request.input_size,
+            inference_retries: request.inference_retries,
+        }
+    }
 }
 
-fn session_cache_key(model_file: &Path, provider: ProviderChoice) -> String {
-    format!(
-        "{}|{}|{}",
-        model_file.display(),
-        provider_label(provider),
-        std::env::var("ORT_DYLIB_PATH").unwrap_or_default()
-    )
+/// Whether `request` can share a `session.run` with others; see [`LocalOrtBackend::infer_batch_with`].
+/// Inputs over their `max_inference_pixels` budget have to be downscaled first, so they are not.
+fn batchable(request: &InferenceRequest) -> bool {
+    let over_budget = request
+        .max_inference_pixels
+        .is_some_and(|max| u64::from(request.width) * u64::from(request.height) > u64::from(max));
+    !over_budget
+        && request.roi.is_none()
+        && request.max_dimension.is_none()
+        && request.ensemble.is_none()
+        && request.seed_mask.is_none()
+        && request.custom_model.is_none()
 }
 
-fn load_cached_provider(cache_key: &str, model_dir: Option<&Path>) -> Option<ProviderChoice> {
-    let memory_cache = AUTO_PROVIDER_CACHE.get_or_init(|| Mutex::new(std::collections::HashMap::new()));
-    if let Some(provider) = memory_cache
-        .lock()
-        .expect("provider cache lock poisoned")
-        .get(cache_key)
-        .copied()
-    {
-        return Some(provider);
-    }
+fn run_batch_on_provider(
+    images: &[DynamicImage],
+    model_file: &Path,
+    provider: ProviderChoice,
+    requests: &[&InferenceRequest],
+    pipeline: &Pipeline,
+) -> Result<Vec<FinishedMask>> {
+    let overrides = &requests[0].free_dimension_overrides;
+    let tuning = requests[0].session_tuning;
+    let session_key = session_cache_key(model_file, provider, overrides, tuning);
+    session_cache()
+        .with_session(
+            &session_key,
+            || build_session_for_provider(model_file, provider, onnx_file_variant(model_file), overrides, tuning),
+            |session| run_onnx_batch(images, session, requests, pipeline),
+        )
+        .map_err(|e| anyhow!(e.to_string()))
+}
 
-    let cache_path = provider_cache_file(model_dir)?;
-    let raw = fs::read_to_string(cache_path).ok()?;
-    let parsed: PersistedProviderCache = serde_json::from_str(&raw).ok()?;
-    let provider = parsed.providers.get(cache_key).and_then(|v| parse_provider_choice(v))?;
-    memory_cache
-        .lock()
-        .expect("provider cache lock poisoned")
-        .insert(cache_key.to_string(), provider);
-    Some(provider)
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SelfTestReport {
+    pub model_used: ModelKind,
+    pub execution_provider_selected: String,
+    pub gpu_backend_selected: Option<String>,
+    pub fallback_used: bool,
+    pub elapsed_ms: u64,
+    pub mask_width: u32,
+    pub mask_height: u32,
 }
 
-fn persist_cached_provider(cache_key: &str, provider: ProviderChoice, model_dir: Option<&Path>) {
-    let memory_cache = AUTO_PROVIDER_CACHE.get_or_init(|| Mutex::new(std::collections::HashMap::new()));
-    memory_cache
-        .lock()
-        .expect("provider cache lock poisoned")
-        .insert(cache_key.to_string(), provider);
+/// Runs real inference on a small synthetic image and reports what ran.
+///
+/// Never uses the placeholder fallback, so a missing model or ONNX Runtime is an error.
+pub fn self_test(model: ModelKind, provider_pref: ExecutionProvider) -> Result<SelfTestReport, CoreError> {
+    self_test_in(model, provider_pref, None)
+}
 
-    let Some(cache_path) = provider_cache_file(model_dir) else {
-        return;
+/// Same as [`self_test`], but resolves models from `model_dir` when given.
+pub fn self_test_in(model: ModelKind, provider_pref: ExecutionProvider, model_dir: Option<&Path>) -> Result<SelfTestReport, CoreError> {
+    let selected_model = match model {
+        ModelKind::Auto => ModelKind::Rmbg14,
+        other => other,
     };
-    if let Some(parent) = cache_path.parent() {
-        let _ = fs::create_dir_all(parent);
+    let (width, height) = (64u32, 48u32);
+    let input_bytes = synthetic_png(width, height)?;
+    let request = InferenceRequest::builder()
+        .requested_model(selected_model)
+        .execution_provider(provider_pref)
+        .input_bytes(input_bytes)
+        .model_dir(model_dir.map(Path::to_path_buf))
+        .dimensions(width, height)
+        .build();
+
+    let start = Instant::now();
+    let result = LocalOrtBackend::default()
+        .infer_with(&request, selected_model, false, None)
+        .map_err(|e| match e {
+            CoreError::Backend(message) => CoreError::Backend(format!("self-test failed: {}", message)),
+            other => other,
+        })?;
+    let elapsed_ms = start.elapsed().as_millis() as u64;
+    let mask = image::load_from_memory(&result.mask_png)
+        .map_err(|e| CoreError::Backend(format!("self-test produced an unreadable mask: {}", e)))?;
+    if (mask.width(), mask.height()) != (width, height) {
+        return Err(CoreError::Backend(format!(
+            "self-test mask is {}x{}, expected {}x{}",
+            mask.width(),
+            mask.height(),
+            width,
+            height
+        )));
     }
-    let existing = fs::read_to_string(&cache_path)
-        .ok()
-        .and_then(|raw| serde_json::from_str::<PersistedProviderCache>(&raw).ok())
-        .unwrap_or_default();
-    let mut updated = existing;
-    updated
-        .providers
-        .insert(cache_key.to_string(), provider_label(provider).to_string());
-    if let Ok(serialized) = serde_json::to_string_pretty(&updated) {
-        let _ = fs::write(cache_path, serialized);
+    Ok(SelfTestReport {
+        model_used: result.model_used,
+        execution_provider_selected: result.execution_provider_selected,
+        gpu_backend_selected: result.gpu_backend_selected,
+        fallback_used: result.fallback_used,
+        elapsed_ms,
+        mask_width: mask.width(),
+        mask_height: mask.height(),
+    })
+}
+
+/// Runs `run` on the `request.roi` crop of `image` and pastes the mask back into a full-size,
+/// zero-filled mask at the region's offset.
+fn infer_within_roi(
+    image: &DynamicImage,
+    request: &InferenceRequest,
+    run: impl FnOnce(&DynamicImage) -> Result<InferenceResult, CoreError>,
+) -> Result<InferenceResult, CoreError> {
+    let Some(roi) = request.roi else {
+        return run(image);
+    };
+    roi.validate_within(image.width(), image.height())?;
+    let mut result = run(&image.crop_imm(roi.x, roi.y, roi.width, roi.height))?;
+    if !result.mask_png.is_empty() {
+        let mask = place_mask_in_frame(&result.mask_png, roi, image.width(), image.height())?;
+        store_mask(&mut result, mask, request)?;
     }
+    result.width = image.width();
+    result.height = image.height();
+    Ok(result)
 }
 
-fn provider_cache_file(model_dir: Option<&Path>) -> Option<PathBuf> {
-    resolve_model_paths(model_dir)
-        .ok()
-        .map(|paths| paths.root.join("cache").join("provider-selection.json"))
+fn place_mask_in_frame(mask_png: &[u8], roi: RegionOfInterest, width: u32, height: u32) -> Result<GrayImage, CoreError> {
+    let region = image::load_from_memory(mask_png)
+        .map_err(|e| CoreError::Backend(format!("failed to decode region mask: {}", e)))?
+        .to_luma8();
+    let mut full = GrayImage::new(width, height);
+    image::imageops::replace(&mut full, &region, roi.x as i64, roi.y as i64);
+    Ok(full)
 }
 
-fn parse_provider_choice(value: &str) -> Option<ProviderChoice> {
-    match value {
-        "cpu" => Some(ProviderChoice::Cpu),
-        "directml" => Some(ProviderChoice::DirectML),
-        "cuda" => Some(ProviderChoice::Cuda),
-        "coreml" => Some(ProviderChoice::CoreML),
-        _ => None,
+/// Size at which [`infer_within_max_dimension`] returns a mask inferred on a downscaled copy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MaskScale {
+    /// Scaled back up to the input and finished, as `infer` returns it.
+    Input,
+    /// Left at inference size for the caller to upscale, as [`LocalOrtBackend::infer_mask_tiles`]
+    /// does tile by tile. The result still reports the input's dimensions.
+    Inference,
+}
+
+/// Size an `original`-sized input is shrunk to for inference under `request.max_dimension` and
+/// `request.max_inference_pixels`; `original` itself when it is within both.
+fn inference_size(original: ImageSize, request: &InferenceRequest) -> ImageSize {
+    let capped = match request.max_dimension {
+        Some(max) => clamp_to_max_dimension(original, max),
+        None => original,
+    };
+    match request.max_inference_pixels {
+        Some(max) => clamp_to_max_pixels(capped, max),
+        None => capped,
     }
 }
 
-fn provider_cache_key(selected_model: ModelKind, request: &InferenceRequest) -> String {
-    let model = match selected_model {
-        ModelKind::Rmbg14 => "rmbg14",
-        ModelKind::Rmbg20 => "rmbg20",
-        ModelKind::Auto => "auto",
+/// Runs `run` on a copy of `image` shrunk to [`inference_size`], then scales the mask back up to
+/// the original size unless `scale` asks for it at inference size. Images already within both
+/// caps are passed through untouched. Threshold, feather and trimap are left to the full-size
+/// mask, so they apply once and the feather radius stays in original pixels.
+fn infer_within_max_dimension(
+    image: &DynamicImage,
+    request: &InferenceRequest,
+    scale: MaskScale,
+    run: impl FnOnce(&DynamicImage, &InferenceRequest) -> Result<InferenceResult, CoreError>,
+) -> Result<InferenceResult, CoreError> {
+    let (orig_w, orig_h) = (image.width(), image.height());
+    let capped = inference_size(
+        ImageSize {
+            width: orig_w,
+            height: orig_h,
+        },
+        request,
+    );
+    if (capped.width, capped.height) == (orig_w, orig_h) {
+        return run(image, request);
+    }
+    let mut soft = request.clone();
+    soft.mask_threshold = None;
+    soft.feather_radius = None;
+    soft.trimap = None;
+    let mut result = run(&active_resizer().resize(image, capped.width, capped.height), &soft)?;
+    if !result.mask_png.is_empty() && scale == MaskScale::Input {
+        let mask = image::load_from_memory(&result.mask_png)
+            .map_err(|e| CoreError::Backend(format!("failed to decode downscaled mask: {}", e)))?
+            .to_luma8();
+        let mut full_size = active_resizer().resize_gray(&mask, orig_w, orig_h);
+        if let Some(threshold) = request.mask_threshold {
+            apply_mask_threshold(&mut full_size, threshold)?;
+        }
+        if let Some(radius) = request.feather_radius {
+            apply_feather(&mut full_size, radius)?;
+        }
+        if let Some(thresholds) = request.trimap {
+            apply_trimap(&mut full_size, thresholds)?;
+        }
+        store_mask(&mut result, full_size, request)?;
+    }
+    result.width = orig_w;
+    result.height = orig_h;
+    Ok(result)
+}
+
+/// Runs RMBG-1.4 and RMBG-2.0 through `run` and blends their masks according to `mode`.
+fn infer_ensemble(
+    request: &InferenceRequest,
+    mode: EnsembleMode,
+    run: impl Fn(&InferenceRequest, ModelKind) -> Result<InferenceResult, CoreError>,
+) -> Result<InferenceResult, CoreError> {
+    let mut member = request.clone();
+    member.ensemble = None;
+    member.trimap = None;
+    member.mask_threshold = None;
+    member.feather_radius = None;
+    member.emit_mask_raw = false;
+    member.emit_mask_rle = false;
+    // Each member runs exactly its model; falling back is decided here for the ensemble as a whole.
+    member.strict_model = true;
+    let decode = |png: &[u8]| {
+        image::load_from_memory(png)
+            .map(|img| img.to_luma8())
+            .map_err(|e| CoreError::Backend(format!("failed to decode ensemble member mask: {}", e)))
     };
-    let variant = match request.onnx_variant {
-        OnnxVariant::Fp16 => "fp16",
-        OnnxVariant::Fp32 => "fp32",
-        OnnxVariant::Quantized => "quantized",
-        OnnxVariant::Auto => "auto",
+    let (fast, quality) = match (run(&member, ModelKind::Rmbg14), run(&member, ModelKind::Rmbg20)) {
+        (Ok(fast), Ok(quality)) => (fast, quality),
+        (Ok(survivor), Err(err)) | (Err(err), Ok(survivor)) if !request.strict_model && !matches!(err, CoreError::Cancelled) => {
+            let mut mask = decode(&survivor.mask_png)?;
+            finish_ensemble_mask(&mut mask, request)?;
+            let mut result = survivor;
+            store_mask(&mut result, mask, request)?;
+            result.model_fallback_used = true;
+            return Ok(result);
+        }
+        (Err(err), _) | (_, Err(err)) => return Err(err),
     };
-    let fingerprint = format!(
-        "{}|{}|{}",
-        std::env::consts::OS,
-        std::env::consts::ARCH,
-        std::env::var("ORT_DYLIB_PATH").unwrap_or_default()
+    let fast_mask = decode(&fast.mask_png)?;
+    let quality_mask = decode(&quality.mask_png)?;
+    if fast_mask.dimensions() != quality_mask.dimensions() {
+        return Err(CoreError::Backend(format!(
+            "ensemble masks differ in size: {:?} vs {:?}",
+            fast_mask.dimensions(),
+            quality_mask.dimensions()
+        )));
+    }
+    let mut blended = GrayImage::from_fn(fast_mask.width(), fast_mask.height(), |x, y| {
+        Luma([blend_alpha(
+            fast_mask.get_pixel(x, y)[0],
+            quality_mask.get_pixel(x, y)[0],
+            mode,
+        )])
+    });
+    finish_ensemble_mask(&mut blended, request)?;
+    let mut result = InferenceResult::new(
+        ModelKind::Rmbg20,
+        Vec::new(),
+        quality.width,
+        quality.height,
+        quality.execution_provider_selected.clone(),
     );
-    format!("{}|{}|{}", model, variant, fingerprint)
+    store_mask(&mut result, blended, request)?;
+    result.gpu_backend_selected = quality.gpu_backend_selected.clone();
+    result.fallback_used = fast.fallback_used || quality.fallback_used;
+    result.ensemble_used = true;
+    result.fallback_reason = quality.fallback_reason.clone().or_else(|| fast.fallback_reason.clone());
+    result.model_fallback_used = quality.model_fallback_used;
+    result.provider_benchmarks = quality.provider_benchmarks.clone();
+    result.mask_coverage = quality.mask_coverage;
+    result.model_revision = quality.model_revision.clone();
+    result.onnx_variant = quality.onnx_variant;
+    Ok(result)
 }
 
-fn candidate_providers(request: &InferenceRequest) -> Vec<ProviderChoice> {
-    let mut out = Vec::new();
-    match request.execution_provider {
-        ExecutionProvider::Cpu => out.push(ProviderChoice::Cpu),
-        ExecutionProvider::Gpu => {
-            out.extend(gpu_candidates(request.gpu_backend));
-            out.push(ProviderChoice::Cpu);
-        }
-        ExecutionProvider::Auto => {
-            out.extend(gpu_candidates(request.gpu_backend));
-            out.push(ProviderChoice::Cpu);
-        }
+/// Applies the request's threshold, feather and trimap, which ensemble members run without.
+fn finish_ensemble_mask(mask: &mut GrayImage, request: &InferenceRequest) -> Result<(), CoreError> {
+    if let Some(threshold) = request.mask_threshold {
+        apply_mask_threshold(mask, threshold)?;
     }
-    dedup_providers(out)
+    if let Some(radius) = request.feather_radius {
+        apply_feather(mask, radius)?;
+    }
+    if let Some(thresholds) = request.trimap {
+        apply_trimap(mask, thresholds)?;
+    }
+    Ok(())
 }
 
-fn gpu_candidates(pref: GpuBackendPreference) -> Vec<ProviderChoice> {
-    let mut providers = Vec::new();
-    match pref {
-        GpuBackendPreference::DirectML => providers.push(ProviderChoice::DirectML),
-        GpuBackendPreference::Cuda => providers.push(ProviderChoice::Cuda),
-        GpuBackendPreference::CoreML | GpuBackendPreference::Metal => providers.push(ProviderChoice::CoreML),
-        GpuBackendPreference::Auto => {
-            #[cfg(target_os = "windows")]
-            {
-                if cuda_likely_available() {
-                    providers.push(ProviderChoice::Cuda);
-                }
-                providers.push(ProviderChoice::DirectML);
-            }
-            #[cfg(target_os = "linux")]
-            {
-                if cuda_likely_available() {
-                    providers.push(ProviderChoice::Cuda);
-                }
-            }
-            #[cfg(any(target_os = "macos", target_os = "ios"))]
-            {
-                providers.push(ProviderChoice::CoreML);
-            }
+fn blend_alpha(fast: u8, quality: u8, mode: EnsembleMode) -> u8 {
+    match mode {
+        EnsembleMode::Weighted { weight } => {
+            let weight = weight.clamp(0.0, 1.0);
+            (quality as f32 * weight + fast as f32 * (1.0 - weight)).round() as u8
         }
+        EnsembleMode::Average => (fast as u16 + quality as u16).div_ceil(2) as u8,
+        EnsembleMode::Max => fast.max(quality),
     }
-    providers
 }
 
-fn cuda_likely_available() -> bool {
-    #[cfg(target_os = "windows")]
-    {
-        let system32 = std::env::var("WINDIR")
-            .ok()
-            .map(|d| PathBuf::from(d).join("System32").join("nvcuda.dll"));
-        if let Some(candidate) = system32 {
-            if candidate.exists() {
-                return true;
+/// PNG with a flat subject on a gradient, used for self-tests and warmup runs.
+fn synthetic_png(width: u32, height: u32) -> Result<Vec<u8>, CoreError> {
+    let sample = image::RgbImage::from_fn(width, height, |x, y| {
+        let inside = (width / 4..width * 3 / 4).contains(&x) && (height / 4..height * 3 / 4).contains(&y);
+        if inside {
+            image::Rgb([220, 120, 40])
+        } else {
+            image::Rgb([(x * 4) as u8, (y * 5) as u8, 200])
+        }
+    });
+    let mut bytes = Vec::new();
+    DynamicImage::ImageRgb8(sample)
+        .write_to(&mut std::io::Cursor::new(&mut bytes), ImageFormat::Png)
+        .map_err(|e| CoreError::Backend(e.to_string()))?;
+    Ok(bytes)
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WarmReport {
+    pub model_used: ModelKind,
+    pub execution_provider_selected: String,
+    pub gpu_backend_selected: Option<String>,
+    pub elapsed_ms: u64,
+}
+
+impl LocalOrtBackend {
+    /// Builds and runs a session for every model/provider pair.
+    ///
+    /// Sessions are cached process-wide, so one call warms every thread. `base` supplies the
+    /// variant, model directory and other options the real requests will use.
+    pub fn warm_all(
+        &self,
+        models: &[ModelKind],
+        providers: &[ExecutionProvider],
+        base: &InferenceRequest,
+    ) -> Result<Vec<WarmReport>, CoreError> {
+        let (width, height) = (64u32, 48u32);
+        let input_bytes = synthetic_png(width, height)?;
+        let mut reports = Vec::new();
+        for &model in models {
+            let selected_model = match model {
+                ModelKind::Auto => ModelKind::Rmbg14,
+                other => other,
+            };
+            for &provider in providers {
+                let mut request = base.clone();
+                request.requested_model = selected_model;
+                request.execution_provider = provider;
+                request.benchmark_provider = false;
+                request.input_path = None;
+                request.input_bytes = Some(input_bytes.clone());
+                request.width = width;
+                request.height = height;
+                let start = Instant::now();
+                let result = self.infer_with(&request, selected_model, false, None)?;
+                reports.push(WarmReport {
+                    model_used: result.model_used,
+                    execution_provider_selected: result.execution_provider_selected,
+                    gpu_backend_selected: result.gpu_backend_selected,
+                    elapsed_ms: start.elapsed().as_millis() as u64,
+                });
             }
         }
-        if let Some(path) = std::env::var_os("PATH") {
-            for dir in std::env::split_paths(&path) {
-                if dir.join("nvcuda.dll").exists() {
-                    return true;
+        Ok(reports)
+    }
+
+    /// Builds the session for `model` on the provider a real call with `request` would select and
+    /// runs it once on a zero-filled input, so the first `infer` skips both.
+    ///
+    /// An `Auto` provider reuses the cached selection when it is still a candidate; otherwise the
+    /// candidates are tried in order.
+    pub fn warmup(&self, model: ModelKind, request: &InferenceRequest) -> Result<(), CoreError> {
+        let selected_model = match model {
+            ModelKind::Auto => ModelKind::Rmbg14,
+            other => other,
+        };
+        let request = self.with_descriptor_preference(request);
+        validate_request_shape(&request)?;
+        let mut request = request.into_owned();
+        if let Some(spec) = custom_model_spec(&request)? {
+            request.input_size = request.input_size.or(Some(spec.input_size));
+        }
+        let model_file = resolve_model_onnx_file(&request, selected_model)?;
+        let candidates = candidate_providers(&request);
+        let cached = match request.execution_provider {
+            ExecutionProvider::Auto => {
+                load_cached_provider(&provider_cache_key(selected_model, &request), request.model_dir.as_deref())
+                    .filter(|provider| candidates.contains(provider))
+            }
+            _ => None,
+        };
+        let mut errors = Vec::new();
+        for provider in cached.into_iter().chain(candidates.iter().copied()) {
+            match warm_session(&model_file, provider, &request) {
+                Ok(()) => {
+                    *self.last_provider.lock().expect("last provider lock poisoned") =
+                        Some(provider_label(provider).to_string());
+                    return Ok(());
                 }
+                Err(err) => errors.push(format!("{}: {}", provider_label(provider), err)),
             }
         }
-        return false;
-    }
-    #[cfg(target_os = "linux")]
-    {
-        let candidates = [
-            "/usr/lib/x86_64-linux-gnu/libcuda.so.1",
-            "/usr/lib64/libcuda.so.1",
-            "/usr/lib/wsl/lib/libcuda.so.1",
-        ];
-        return candidates.iter().any(|p| Path::new(p).exists());
-    }
-    #[cfg(not(any(target_os = "windows", target_os = "linux")))]
-    {
-        false
+        Err(backend_error(
+            "warmup-failed",
+            format!("all providers failed: {}", errors.join(" | ")),
+        ))
     }
 }
 
-fn dedup_providers(list: Vec<ProviderChoice>) -> Vec<ProviderChoice> {
-    let mut out = Vec::new();
-    for provider in list {
-        if !out.contains(&provider) {
-            out.push(provider);
+#[derive(Debug, Clone)]
+pub struct MaskCacheConfig {
+    pub capacity: usize,
+    pub persist_dir: Option<PathBuf>,
+}
+
+impl MaskCacheConfig {
+    /// `UNBG_MASK_CACHE_SIZE` enables the cache with that many in-memory entries;
+    /// `UNBG_MASK_CACHE_DIR` additionally persists masks there.
+    pub fn from_env() -> Option<Self> {
+        let capacity = env::var("UNBG_MASK_CACHE_SIZE").ok()?.trim().parse::<usize>().ok()?;
+        if capacity == 0 {
+            return None;
         }
+        Some(Self {
+            capacity,
+            persist_dir: env::var("UNBG_MASK_CACHE_DIR")
+                .ok()
+                .filter(|v| !v.trim().is_empty())
+                .map(PathBuf::from),
+        })
     }
-    out
 }
 
-fn provider_label(provider: ProviderChoice) -> &'static str {
-    match provider {
-        ProviderChoice::Cpu => "cpu",
-        ProviderChoice::DirectML => "directml",
-        ProviderChoice::Cuda => "cuda",
-        ProviderChoice::CoreML => "coreml",
-    }
+/// Serves repeat requests for the same input and options from a bounded LRU of results.
+pub struct CachedBackend<B> {
+    inner: B,
+    config: Option<MaskCacheConfig>,
+    entries: Mutex<std::collections::VecDeque<(String, InferenceResult)>>,
 }
 
-fn build_session_for_provider(model_file: &Path, provider: ProviderChoice) -> Result<Session> {
-    match provider {
-        ProviderChoice::Cpu => Session::builder()?.commit_from_file(model_file).map_err(Into::into),
-        ProviderChoice::DirectML => {
-            #[cfg(feature = "directml")]
-            {
-                Session::builder()?
-                    .with_execution_providers([ort::ep::DirectML::default().build()])?
-                    .commit_from_file(model_file)
-                    .map_err(Into::into)
-            }
-            #[cfg(not(feature = "directml"))]
-            {
-                Err(anyhow!("directml feature not enabled"))
-            }
+impl<B: InferenceBackend> CachedBackend<B> {
+    pub fn new(inner: B, config: MaskCacheConfig) -> Self {
+        Self {
+            inner,
+            config: Some(config),
+            entries: Mutex::new(std::collections::VecDeque::new()),
         }
-        ProviderChoice::Cuda => {
-            #[cfg(feature = "cuda")]
-            {
-                Session::builder()?
-                    .with_execution_providers([ort::ep::CUDA::default().build()])?
-                    .commit_from_file(model_file)
-                    .map_err(Into::into)
-            }
-            #[cfg(not(feature = "cuda"))]
-            {
-                Err(anyhow!("cuda feature not enabled"))
-            }
+    }
+
+    /// Caches only when [`MaskCacheConfig::from_env`] enables it; otherwise passes through.
+    pub fn from_env(inner: B) -> Self {
+        Self {
+            inner,
+            config: MaskCacheConfig::from_env(),
+            entries: Mutex::new(std::collections::VecDeque::new()),
         }
-        ProviderChoice::CoreML => {
-            #[cfg(feature = "coreml")]
-            {
-                Session::builder()?
-                    .with_execution_providers([ort::ep::CoreML::default().build()])?
-                    .commit_from_file(model_file)
-                    .map_err(Into::into)
-            }
-            #[cfg(not(feature = "coreml"))]
-            {
-                Err(anyhow!("coreml feature not enabled"))
-            }
+    }
+
+    fn lookup(&self, key: &str, config: &MaskCacheConfig) -> Option<InferenceResult> {
+        let mut entries = self.entries.lock().expect("mask cache lock poisoned");
+        if let Some(pos) = entries.iter().position(|(k, _)| k == key) {
+            let entry = entries.remove(pos)?;
+            let result = entry.1.clone();
+            entries.push_back(entry);
+            return Some(result);
         }
+        drop(entries);
+        let result = read_persisted_mask(config.persist_dir.as_deref()?, key)?;
+        self.remember(key, &result, config);
+        Some(result)
     }
-}
 
-fn backend_error(kind: &str, message: String) -> CoreError {
-    CoreError::Backend(format!("{}: {}", kind, message))
+    fn remember(&self, key: &str, result: &InferenceResult, config: &MaskCacheConfig) {
+        let mut entries = self.entries.lock().expect("mask cache lock poisoned");
+        entries.retain(|(k, _)| k != key);
+        entries.push_back((key.to_string(), result.clone()));
+        while entries.len() > config.capacity {
+            entries.pop_front();
+        }
+    }
 }
 
-fn find_preferred_onnx_file(base_dir: &Path, onnx_variant: OnnxVariant) -> Option<PathBuf> {
-    let mut candidates: Vec<PathBuf> = WalkDir::new(base_dir)
-        .into_iter()
-        .filter_map(std::result::Result::ok)
-        .filter(|e| e.file_type().is_file())
-        .map(|e| e.into_path())
-        .filter(|p| p.extension().map(|e| e == "onnx").unwrap_or(false))
-        .collect();
-    candidates.sort_by_key(|p| {
-        let lower = p.to_string_lossy().to_lowercase();
-        match onnx_variant {
-            OnnxVariant::Fp16 => {
-                if lower.contains("model_fp16.onnx") {
-                    0
-                } else if lower.contains("model.onnx") {
-                    1
-                } else if lower.contains("quantized") || lower.contains("q8") {
-                    2
-                } else {
-                    3
-                }
-            }
-            OnnxVariant::Fp32 => {
-                if lower.contains("model.onnx") && !lower.contains("fp16") && !lower.contains("quantized") {
-                    0
-                } else if lower.contains("model_fp16.onnx") {
-                    1
-                } else if lower.contains("quantized") || lower.contains("q8") {
-                    2
-                } else {
-                    3
-                }
-            }
-            OnnxVariant::Quantized => {
-                if lower.contains("quantized") || lower.contains("q8") {
-                    0
-                } else if lower.contains("model_fp16.onnx") {
-                    1
-                } else if lower.contains("model.onnx") {
-                    2
-                } else {
-                    3
-                }
-            }
-            OnnxVariant::Auto => {
-                if lower.contains("model_fp16.onnx") {
-                    0
-                } else if lower.contains("model.onnx") {
-                    1
-                } else if lower.contains("quantized") || lower.contains("q8") {
-                    2
-                } else {
-                    3
+impl<B: InferenceBackend> CachedBackend<B> {
+    fn infer_inner(
+        &self,
+        request: &InferenceRequest,
+        selected_model: ModelKind,
+        cancel: Option<&CancelToken>,
+    ) -> Result<InferenceResult, CoreError> {
+        match cancel {
+            Some(cancel) => self.inner.infer_cancellable(request, selected_model, cancel),
+            None => self.inner.infer(request, selected_model),
+        }
+    }
+
+    fn infer_cached(
+        &self,
+        request: &InferenceRequest,
+        selected_model: ModelKind,
+        cancel: Option<&CancelToken>,
+    ) -> Result<InferenceResult, CoreError> {
+        let Some(config) = &self.config else {
+            return self.infer_inner(request, selected_model, cancel);
+        };
+        // A path input is read once here; the inner backend decodes the bytes the key hashed.
+        let read;
+        let request = match (&request.input_bytes, &request.input_path) {
+            (None, Some(path)) => match fs::read(path) {
+                Ok(bytes) => {
+                    let mut with_bytes = request.clone();
+                    with_bytes.input_bytes = Some(bytes);
+                    read = with_bytes;
+                    &read
                 }
-            }
+                Err(_) => return self.infer_inner(request, selected_model, cancel),
+            },
+            _ => request,
+        };
+        let Some(key) = mask_cache_key(request, selected_model) else {
+            return self.infer_inner(request, selected_model, cancel);
+        };
+        if let Some(mut hit) = self.lookup(&key, config) {
+            // The stored timings describe the run that filled the cache, not this request.
+            hit.timings = None;
+            // The policy is not part of the key, so this request's policy is applied to the hit.
+            hit.mask_coverage = None;
+            apply_empty_mask_policy(&mut hit, request)?;
+            return Ok(hit);
         }
-    });
-    candidates.into_iter().next()
+        let result = self.infer_inner(request, selected_model, cancel)?;
+        if result.mask_coverage.is_some() && request.on_empty_mask == EmptyMaskPolicy::Passthrough {
+            // The mask was replaced, so the hit could not serve a request with another policy.
+            return Ok(result);
+        }
+        self.remember(&key, &result, config);
+        // The float mask stays in memory only; the on-disk cache holds the PNG.
+        if let (Some(dir), None) = (&config.persist_dir, &result.mask_raw) {
+            persist_mask(dir, &key, &result);
+        }
+        Ok(result)
+    }
 }
 
-fn run_onnx_inference(image: &DynamicImage, session: &mut Session, emit_mask_png: bool) -> Result<Vec<u8>> {
-    let orig_w = image.width();
-    let orig_h = image.height();
-    let input_size = 1024u32;
-    let resized = image.resize_exact(input_size, input_size, FilterType::Triangle).to_rgb8();
+impl<B: InferenceBackend> InferenceBackend for CachedBackend<B> {
+    fn infer(&self, request: &InferenceRequest, selected_model: ModelKind) -> Result<InferenceResult, CoreError> {
+        self.infer_cached(request, selected_model, None)
+    }
+
+    fn infer_cancellable(
+        &self,
+        request: &InferenceRequest,
+        selected_model: ModelKind,
+        cancel: &CancelToken,
+    ) -> Result<InferenceResult, CoreError> {
+        self.infer_cached(request, selected_model, Some(cancel))
+    }
+
+    fn infer_batch(&self, requests: &[InferenceRequest], selected_model: ModelKind) -> Result<Vec<InferenceResult>, CoreError> {
+        if self.config.is_none() {
+            return self.inner.infer_batch(requests, selected_model);
+        }
+        requests.iter().map(|request| self.infer(request, selected_model)).collect()
+    }
 
-    let mut input_data = vec![0f32; (1 * 3 * input_size as usize * input_size as usize) as usize];
-    for y in 0..input_size as usize {
-        for x in 0..input_size as usize {
-            let p = resized.get_pixel(x as u32, y as u32);
-            let idx = y * input_size as usize + x;
-            // RMBG-1.4 preprocessing aligns with BRIA utilities:
-            // image = (pixel/255.0) - 0.5 for each channel.
-            input_data[idx] = (p[0] as f32 / 255.0) - 0.5;
-            input_data[input_size as usize * input_size as usize + idx] = (p[1] as f32 / 255.0) - 0.5;
-            input_data[2 * input_size as usize * input_size as usize + idx] = (p[2] as f32 / 255.0) - 0.5;
+    fn infer_batch_cancellable(
+        &self,
+        requests: &[InferenceRequest],
+        selected_model: ModelKind,
+        cancel: &CancelToken,
+    ) -> Result<Vec<InferenceResult>, CoreError> {
+        if self.config.is_none() {
+            return self.inner.infer_batch_cancellable(requests, selected_model, cancel);
         }
+        requests
+            .iter()
+            .map(|request| self.infer_cancellable(request, selected_model, cancel))
+            .collect()
     }
+}
 
-    let input_tensor = Tensor::<f32>::from_array((
-        [1usize, 3, input_size as usize, input_size as usize],
-        input_data,
-    ))?;
-    let outputs = session.run(inputs![input_tensor])?;
-    if outputs.len() == 0 {
-        return Err(anyhow!("model returned no outputs"));
+/// Registry with the local ORT backend (behind the env-configured mask cache) under [`DEFAULT_BACKEND_ID`].
+pub fn default_registry() -> BackendRegistry {
+    let mut registry = BackendRegistry::new();
+    registry.register(DEFAULT_BACKEND_ID, || Box::new(CachedBackend::from_env(LocalOrtBackend::default())));
+    registry
+}
+
+/// Sets the OS scheduling priority of the calling thread: its nice value on Linux and Android,
+/// the background band on Apple platforms and `SetThreadPriority` on Windows; a no-op elsewhere.
+/// On Linux, threads spawned afterwards (e.g. ONNX Runtime's pool for a new session) inherit it.
+pub fn set_current_thread_priority(priority: ThreadPriority) -> Result<(), CoreError> {
+    set_os_thread_priority(priority)
+        .map_err(|e| CoreError::Backend(format!("failed to set thread priority {:?}: {}", priority, e)))
+}
+
+/// Runs `work` at `priority`. Lowered priorities get a dedicated worker thread, since an
+/// unprivileged thread is not allowed to raise its priority back; `Normal` runs inline.
+///
+/// Only that worker is lowered: ONNX Runtime's intra-op threads keep the priority of the thread
+/// that built the session, which is this worker only if `work` is the first to load it, and
+/// which carries over at all only on Linux and Android.
+pub fn run_with_thread_priority<T: Send>(priority: ThreadPriority, work: impl FnOnce() -> T + Send) -> Result<T, CoreError> {
+    if priority == ThreadPriority::Normal {
+        return Ok(work());
     }
-    if !emit_mask_png {
-        return Ok(Vec::new());
+    std::thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                set_current_thread_priority(priority)?;
+                Ok(work())
+            })
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn set_os_thread_priority(priority: ThreadPriority) -> std::io::Result<()> {
+    let nice = match priority {
+        ThreadPriority::Normal => 0,
+        ThreadPriority::Low => 10,
+        ThreadPriority::Lowest => 19,
+    };
+    // The nice value is per thread on Linux, and `who = 0` is the calling thread.
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) } == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
     }
-    let view = outputs[0].try_extract_array::<f32>()?;
+}
 
-    let (mask_h, mask_w) = match view.ndim() {
-        4 => (view.shape()[2], view.shape()[3]),
-        3 => (view.shape()[1], view.shape()[2]),
-        2 => (view.shape()[0], view.shape()[1]),
-        _ => return Err(anyhow!("unsupported output dimensions: {:?}", view.shape())),
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn set_os_thread_priority(priority: ThreadPriority) -> std::io::Result<()> {
+    let band = match priority {
+        ThreadPriority::Normal => 0,
+        ThreadPriority::Low | ThreadPriority::Lowest => libc::PRIO_DARWIN_BG,
     };
+    if unsafe { libc::setpriority(libc::PRIO_DARWIN_THREAD, 0, band) } == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
 
-    let mut raw = Vec::with_capacity(mask_w * mask_h);
-    let mut min_v = f32::INFINITY;
-    let mut max_v = f32::NEG_INFINITY;
-    for y in 0..mask_h {
-        for x in 0..mask_w {
-            let v = match view.ndim() {
-                4 => view[[0, 0, y, x]],
-                3 => view[[0, y, x]],
-                2 => view[[y, x]],
-                _ => unreachable!(),
-            };
-            min_v = min_v.min(v);
-            max_v = max_v.max(v);
-            raw.push(v);
+#[cfg(windows)]
+fn set_os_thread_priority(priority: ThreadPriority) -> std::io::Result<()> {
+    use windows_sys::Win32::System::Threading::{
+        GetCurrentThread, SetThreadPriority, THREAD_PRIORITY_BELOW_NORMAL, THREAD_PRIORITY_LOWEST, THREAD_PRIORITY_NORMAL,
+    };
+    let level = match priority {
+        ThreadPriority::Normal => THREAD_PRIORITY_NORMAL,
+        ThreadPriority::Low => THREAD_PRIORITY_BELOW_NORMAL,
+        ThreadPriority::Lowest => THREAD_PRIORITY_LOWEST,
+    };
+    if unsafe { SetThreadPriority(GetCurrentThread(), level) } != 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios", windows)))]
+fn set_os_thread_priority(_priority: ThreadPriority) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Bumped whenever [`mask_cache_key`] hashes a different set of fields or changes how one is
+/// written, so masks persisted under the old scheme are never served.
+const MASK_CACHE_KEY_VERSION: u32 = 1;
+
+/// Hashes the input and seed bytes and, by name, every request field that changes the stored
+/// result. Left out on purpose: the path (the bytes are hashed instead), the size hints,
+/// retries, timings, session tuning and the empty-mask policy, which is applied to each hit.
+/// A new request field goes in one list or the other, with the version bumped when it is hashed.
+fn mask_cache_key(request: &InferenceRequest, selected_model: ModelKind) -> Option<String> {
+    let input = request.input_bytes.as_deref()?;
+    let fields: [(&str, String); 28] = [
+        ("selected_model", format!("{:?}", selected_model)),
+        ("requested_model", format!("{:?}", request.requested_model)),
+        ("onnx_variant", format!("{:?}", request.onnx_variant)),
+        ("execution_provider", format!("{:?}", request.execution_provider)),
+        ("gpu_backend", format!("{:?}", request.gpu_backend)),
+        ("benchmark_provider", format!("{:?}", request.benchmark_provider)),
+        ("emit_mask_png", format!("{:?}", request.emit_mask_png)),
+        ("model_dir", format!("{:?}", request.model_dir)),
+        ("source_max_value", format!("{:?}", request.source_max_value)),
+        ("low_confidence_range", format!("{:?}", request.low_confidence_range)),
+        ("revision", format!("{:?}", request.revision)),
+        ("free_dimension_overrides", format!("{:?}", request.free_dimension_overrides)),
+        ("ensemble", format!("{:?}", request.ensemble)),
+        ("foreground_channels", format!("{:?}", request.foreground_channels)),
+        ("trimap", format!("{:?}", request.trimap)),
+        ("max_dimension", format!("{:?}", request.max_dimension)),
+        ("roi", format!("{:?}", request.roi)),
+        ("png_compression", format!("{:?}", request.png_compression)),
+        ("mask_threshold", format!("{:?}", request.mask_threshold)),
+        ("mask_activation", format!("{:?}", request.mask_activation)),
+        ("emit_mask_raw", format!("{:?}", request.emit_mask_raw)),
+        ("input_size", format!("{:?}", request.input_size)),
+        ("emit_mask_rle", format!("{:?}", request.emit_mask_rle)),
+        ("max_inference_pixels", format!("{:?}", request.max_inference_pixels)),
+        ("feather_radius", format!("{:?}", request.feather_radius)),
+        ("strict_model", format!("{:?}", request.strict_model)),
+        ("custom_model", format!("{:?}", request.custom_model)),
+        ("alpha_curve", format!("{:?}", request.alpha_curve)),
+    ];
+    let mut hasher = Sha256::new();
+    hasher.update(format!("mask-cache-v{}\n", MASK_CACHE_KEY_VERSION));
+    hasher.update((input.len() as u64).to_le_bytes());
+    hasher.update(input);
+    if let Some(seed) = &request.seed_mask {
+        hasher.update(b"seed_mask=");
+        hasher.update((seed.len() as u64).to_le_bytes());
+        hasher.update(seed);
+    }
+    for (name, value) in fields {
+        hasher.update(format!("\n{}={}", name, value));
+    }
+    Some(hex::encode(hasher.finalize()))
+}
+
+fn read_persisted_mask(dir: &Path, key: &str) -> Option<InferenceResult> {
+    let meta = fs::read(dir.join(format!("{}.json", key))).ok()?;
+    let mut result: InferenceResult = serde_json::from_slice(&meta).ok()?;
+    result.mask_png = fs::read(dir.join(format!("{}.png", key))).ok()?;
+    Some(result)
+}
+
+fn persist_mask(dir: &Path, key: &str, result: &InferenceResult) {
+    if fs::create_dir_all(dir).is_err() {
+        return;
+    }
+    let mut meta = result.clone();
+    meta.mask_png = Vec::new();
+    // The PNG goes first: an entry is only read once its metadata exists.
+    if write_replacing(&dir.join(format!("{}.png", key)), &result.mask_png).is_ok() {
+        if let Ok(serialized) = serde_json::to_vec(&meta) {
+            let _ = write_replacing(&dir.join(format!("{}.json", key)), &serialized);
+        }
+    }
+}
+
+/// Writes `bytes` to a temp file beside `path` and renames it into place, so a concurrent
+/// reader sees the old file or the new one, never a partial write.
+fn write_replacing(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    static NEXT_TEMP: AtomicU64 = AtomicU64::new(0);
+    let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+    let temp = path.with_extension(format!(
+        "{}.{}.{}.tmp",
+        extension,
+        std::process::id(),
+        NEXT_TEMP.fetch_add(1, Ordering::Relaxed)
+    ));
+    let written = fs::write(&temp, bytes).and_then(|()| fs::rename(&temp, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written
+}
+
+struct Pipeline<'a> {
+    preprocessor: &'a dyn Preprocessor,
+    postprocess: &'a PostprocessChain,
+    cancel: Option<&'a CancelToken>,
+}
+
+impl Pipeline<'_> {
+    /// Stage-boundary cancel check; ORT cannot stop a `session.run` already in progress.
+    fn check_cancelled(&self) -> Result<()> {
+        Ok(check_cancelled(self.cancel)?)
+    }
+}
+
+fn placeholder_fallback_allowed() -> bool {
+    match env::var("UNBG_ALLOW_PLACEHOLDER") {
+        Ok(value) => {
+            let normalized = value.trim().to_ascii_lowercase();
+            normalized == "1" || normalized == "true" || normalized == "yes"
+        }
+        Err(_) => false,
+    }
+}
+
+fn resolve_model_onnx_file(request: &InferenceRequest, selected_model: ModelKind) -> Result<PathBuf, CoreError> {
+    resolve_model(request, selected_model).map(|model| model.file)
+}
+
+/// The `.onnx` file a request runs and the revision it was installed under.
+#[derive(Debug, Clone)]
+struct ResolvedModel {
+    file: PathBuf,
+    revision: String,
+    /// Id of the custom model the file belongs to, if it is not an RMBG model.
+    custom_model: Option<String>,
+}
+
+impl ResolvedModel {
+    /// Records where the weights came from on a result they produced.
+    fn stamp(&self, result: &mut InferenceResult) {
+        result.model_revision = Some(self.revision.clone());
+        result.onnx_variant = Some(onnx_file_variant(&self.file));
+        result.custom_model_used = self.custom_model.clone();
+    }
+}
+
+fn resolve_model(request: &InferenceRequest, selected_model: ModelKind) -> Result<ResolvedModel, CoreError> {
+    let search = model_search_path(request.model_dir.as_deref()).map_err(|e| CoreError::Backend(e.to_string()))?;
+    if let Some(id) = request.custom_model.as_deref() {
+        let (_, installed) = find_custom_model(&search, id).ok_or_else(|| CoreError::ModelNotInstalled {
+            model_id: id.to_string(),
+        })?;
+        let revision = installed.lock.revision;
+        return match find_preferred_onnx_file(&installed.revision_dir, request.onnx_variant) {
+            Some(file) => Ok(ResolvedModel {
+                file,
+                revision,
+                custom_model: Some(id.to_string()),
+            }),
+            None => Err(CoreError::OnnxFileMissing {
+                model_id: id.to_string(),
+                revision,
+            }),
+        };
+    }
+    let known_model = match selected_model {
+        ModelKind::Rmbg14 => KnownModel::Rmbg14,
+        ModelKind::Rmbg20 => KnownModel::Rmbg20,
+        ModelKind::Auto => return Err(CoreError::Backend("auto model cannot resolve onnx directly".to_string())),
+    };
+    let (revision, rev_dir) = match request.revision.as_deref() {
+        Some(revision) => {
+            validate_revision(revision).map_err(|e| CoreError::InvalidArgument(e.to_string()))?;
+            let rev_dir = search
+                .iter()
+                .map(|paths| model_revision_dir(paths, known_model, revision))
+                .find(|dir| dir.is_dir())
+                .ok_or_else(|| CoreError::OnnxFileMissing {
+                    model_id: known_model.model_id().to_string(),
+                    revision: revision.to_string(),
+                })?;
+            (revision.to_string(), rev_dir)
+        }
+        None => {
+            let installed = find_installed_model(&search, known_model).ok_or_else(|| CoreError::ModelNotInstalled {
+                model_id: known_model.model_id().to_string(),
+            })?;
+            (installed.lock.revision, installed.revision_dir)
+        }
+    };
+    match find_preferred_onnx_file(&rev_dir, request.onnx_variant) {
+        Some(file) => Ok(ResolvedModel {
+            file,
+            revision,
+            custom_model: None,
+        }),
+        None => Err(CoreError::OnnxFileMissing {
+            model_id: known_model.model_id().to_string(),
+            revision,
+        }),
+    }
+}
+
+fn run_sequential_path(
+    image: &DynamicImage,
+    model_file: &Path,
+    selected_model: ModelKind,
+    request: &InferenceRequest,
+    pipeline: &Pipeline,
+    candidates: &[ProviderChoice],
+) -> Result<InferenceResult, CoreError> {
+    let preferred = candidates[0];
+    match try_providers(candidates, request.inference_retries, |provider| {
+        run_provider(image, model_file, selected_model, provider, request, pipeline)
+    }) {
+        Ok(((mut result, _), provider, errors)) => {
+            result.fallback_used = provider != preferred;
+            if result.fallback_used {
+                result.fallback_reason = Some(errors.join(" | "));
+            }
+            Ok(result)
         }
+        Err(errors) => Err(backend_error(
+            "provider-exhausted",
+            format!("all providers failed: {}", errors.join(" | ")),
+        )),
     }
+}
 
-    let range = (max_v - min_v).max(1e-6f32);
-    let mut mask = GrayImage::new(mask_w as u32, mask_h as u32);
-    let mut idx = 0usize;
-    for y in 0..mask_h {
-        for x in 0..mask_w {
-            let normalized = ((raw[idx] - min_v) / range).clamp(0.0f32, 1.0f32);
-            let alpha = (normalized * 255.0f32) as u8;
-            mask.put_pixel(x as u32, y as u32, Luma([alpha]));
-            idx += 1;
+/// Runs `attempt` on each candidate in order, retrying a failing provider `retries` times before
+/// moving on. Returns the first success with its provider and the errors seen before it.
+fn try_providers<T>(
+    candidates: &[ProviderChoice],
+    retries: u32,
+    mut attempt: impl FnMut(ProviderChoice) -> Result<T>,
+) -> std::result::Result<(T, ProviderChoice, Vec<String>), Vec<String>> {
+    let mut errors = Vec::new();
+    for provider in candidates {
+        for _ in 0..=retries {
+            match attempt(*provider) {
+                Ok(value) => return Ok((value, *provider, errors)),
+                Err(err) => errors.push(format!("{}: {}", provider_label(*provider), err)),
+            }
         }
     }
+    Err(errors)
+}
 
-    let full_size = image::imageops::resize(&mask, orig_w, orig_h, FilterType::Triangle);
-    let mut encoded = Vec::new();
-    DynamicImage::ImageLuma8(full_size).write_to(&mut std::io::Cursor::new(&mut encoded), ImageFormat::Png)?;
-    Ok(encoded)
+fn run_auto_bench_path(
+    image: &DynamicImage,
+    model_file: &Path,
+    selected_model: ModelKind,
+    request: &InferenceRequest,
+    pipeline: &Pipeline,
+    candidates: &[ProviderChoice],
+) -> Result<InferenceResult, CoreError> {
+    let cache_key = provider_cache_key(selected_model, request);
+    let cache = AUTO_PROVIDER_CACHE.get_or_init(|| Mutex::new(std::collections::HashMap::new()));
+    if let Some(cached) = load_cached_provider(&cache_key, request.model_dir.as_deref()) {
+        if let Ok((result, _)) = run_provider(image, model_file, selected_model, cached, request, pipeline) {
+            return Ok(result);
+        }
+    }
+
+    let benchmark = benchmark_providers(candidates, |provider| {
+        run_provider(image, model_file, selected_model, provider, request, pipeline)
+    });
+    if let Some((mut result, provider)) = benchmark.best {
+        cache
+            .lock()
+            .expect("provider cache lock poisoned")
+            .insert(cache_key.clone(), provider);
+        persist_cached_provider(&cache_key, provider, request.model_dir.as_deref());
+        result.provider_benchmarks = Some(benchmark.timings);
+        return Ok(result);
+    }
+
+    Err(backend_error(
+        "benchmark-failed",
+        format!("auto provider benchmark failed: {}", benchmark.errors.join(" | ")),
+    ))
+}
+
+struct ProviderBenchmark {
+    /// Result from the fastest provider that succeeded.
+    best: Option<(InferenceResult, ProviderChoice)>,
+    timings: Vec<(String, u128)>,
+    errors: Vec<String>,
+}
+
+/// Runs every candidate through `run` and keeps the fastest result plus each provider's timing.
+fn benchmark_providers(
+    candidates: &[ProviderChoice],
+    mut run: impl FnMut(ProviderChoice) -> Result<(InferenceResult, u128)>,
+) -> ProviderBenchmark {
+    let mut best: Option<(InferenceResult, ProviderChoice, u128)> = None;
+    let mut timings = Vec::with_capacity(candidates.len());
+    let mut errors = Vec::new();
+    for &provider in candidates {
+        match run(provider) {
+            Ok((result, elapsed_ms)) => {
+                timings.push((provider_label(provider).to_string(), elapsed_ms));
+                if best.as_ref().is_none_or(|(_, _, best_ms)| elapsed_ms < *best_ms) {
+                    best = Some((result, provider, elapsed_ms));
+                }
+            }
+            Err(err) => {
+                timings.push((provider_label(provider).to_string(), PROVIDER_BENCHMARK_FAILED));
+                errors.push(format!("{}: {}", provider_label(provider), err));
+            }
+        }
+    }
+    ProviderBenchmark {
+        best: best.map(|(result, provider, _)| (result, provider)),
+        timings,
+        errors,
+    }
+}
+
+fn run_auto_cached_path(
+    image: &DynamicImage,
+    model_file: &Path,
+    selected_model: ModelKind,
+    request: &InferenceRequest,
+    pipeline: &Pipeline,
+    candidates: &[ProviderChoice],
+) -> Result<InferenceResult, CoreError> {
+    let cache_key = provider_cache_key(selected_model, request);
+    if let Some(cached) = load_cached_provider(&cache_key, request.model_dir.as_deref()) {
+        if candidates.contains(&cached) {
+            if let Ok((result, _)) = run_provider(image, model_file, selected_model, cached, request, pipeline) {
+                return Ok(result);
+            }
+        }
+    }
+
+    let mut errors = Vec::new();
+    for provider in candidates {
+        match run_provider(image, model_file, selected_model, *provider, request, pipeline) {
+            Ok((result, _)) => {
+                persist_cached_provider(&cache_key, *provider, request.model_dir.as_deref());
+                return Ok(result);
+            }
+            Err(err) => errors.push(format!("{}: {}", provider_label(*provider), err)),
+        }
+    }
+
+    Err(backend_error(
+        "auto-provider-failed",
+        format!("all providers failed: {}", errors.join(" | ")),
+    ))
+}
+
+fn run_provider(
+    image: &DynamicImage,
+    model_file: &Path,
+    selected_model: ModelKind,
+    provider: ProviderChoice,
+    request: &InferenceRequest,
+    pipeline: &Pipeline,
+) -> Result<(InferenceResult, u128)> {
+    // Skip building sessions for the remaining fallback providers once cancelled.
+    pipeline.check_cancelled()?;
+    let session_key = session_cache_key(
+        model_file,
+        provider,
+        &request.free_dimension_overrides,
+        request.session_tuning,
+    );
+    let start = Instant::now();
+    let cache = session_cache();
+    let mask = cache
+        .with_session(
+            &session_key,
+            || {
+                build_session_for_provider(
+                    model_file,
+                    provider,
+                    onnx_file_variant(model_file),
+                    &request.free_dimension_overrides,
+                    request.session_tuning,
+                )
+            },
+            |session| run_onnx_inference(image, session, request, pipeline),
+        )
+        .inspect_err(|_| {
+            if request.inference_retries > 0 {
+                // Rebuild the session on retry in case the failure left it in a bad state.
+                cache.remove(&session_key);
+            }
+        })
+        .map_err(|e| anyhow!(e.to_string()))?;
+    let elapsed = start.elapsed().as_millis();
+    if timing_history_enabled() {
+        // Only the model run: `elapsed` also covers building the session on a first call.
+        record_inference_ms(
+            run_model_label(selected_model, request),
+            provider,
+            ImageSize {
+                width: image.width(),
+                height: image.height(),
+            },
+            mask.session_run_ms.into(),
+            request.model_dir.as_deref(),
+        );
+    }
+    let timings = mask.timings.map(|timings| InferenceTimings {
+        total_ms: elapsed.try_into().unwrap_or(u64::MAX),
+        ..timings
+    });
+    let (execution_provider_selected, gpu_backend_selected) = provider_selection(provider);
+    let mut result = InferenceResult::new(selected_model, mask.png, image.width(), image.height(), execution_provider_selected);
+    result.gpu_backend_selected = gpu_backend_selected;
+    result.timings = timings;
+    result.mask_rle = mask.rle;
+    result.foreground_ratio = mask.foreground_ratio;
+    set_mask_raw(&mut result, mask.raw);
+    Ok((result, elapsed))
+}
+
+/// Builds (or reuses) the cached session for `provider` and runs it on a zero-filled input of the
+/// request's input size.
+fn warm_session(model_file: &Path, provider: ProviderChoice, request: &InferenceRequest) -> Result<()> {
+    let session_key = session_cache_key(
+        model_file,
+        provider,
+        &request.free_dimension_overrides,
+        request.session_tuning,
+    );
+    session_cache().with_session(
+        &session_key,
+        || {
+            build_session_for_provider(
+                model_file,
+                provider,
+                onnx_file_variant(model_file),
+                &request.free_dimension_overrides,
+                request.session_tuning,
+            )
+        },
+        |session| {
+            let spec = input_spec(session, request);
+            let shape = [1, spec.channels, spec.height as usize, spec.width as usize];
+            let input_tensor = Tensor::<f32>::from_array((shape, vec![0.0f32; shape.iter().product()]))?;
+            session.run(inputs![input_tensor])?;
+            Ok(())
+        },
+    )
+}
+
+/// `execution_provider_selected` and `gpu_backend_selected` as reported for `provider`.
+fn provider_selection(provider: ProviderChoice) -> (String, Option<String>) {
+    match provider {
+        ProviderChoice::Cpu => ("cpu".to_string(), None),
+        ProviderChoice::DirectML => ("gpu".to_string(), Some("directml".to_string())),
+        ProviderChoice::Cuda => ("gpu".to_string(), Some("cuda".to_string())),
+        ProviderChoice::CoreML => ("gpu".to_string(), Some("coreml".to_string())),
+    }
+}
+
+fn session_cache_key(
+    model_file: &Path,
+    provider: ProviderChoice,
+    dimension_overrides: &[(String, i64)],
+    tuning: SessionTuning,
+) -> String {
+    let mut overrides: Vec<String> = dimension_overrides
+        .iter()
+        .map(|(name, size)| format!("{}={}", name, size))
+        .collect();
+    overrides.sort();
+    format!(
+        "{}|{}|{}|{:?}|{:?}|{:?}|{}",
+        model_file.display(),
+        provider_label(provider),
+        std::env::var("ORT_DYLIB_PATH").unwrap_or_default(),
+        tuning.intra_threads,
+        tuning.inter_threads,
+        tuning.graph_opt,
+        overrides.join(",")
+    )
+}
+
+fn load_cached_provider(cache_key: &str, model_dir: Option<&Path>) -> Option<ProviderChoice> {
+    let provider = lookup_cached_provider(cache_key, model_dir);
+    let counter = if provider.is_some() { &PROVIDER_CACHE_HITS } else { &PROVIDER_CACHE_MISSES };
+    counter.fetch_add(1, Ordering::Relaxed);
+    provider
+}
+
+fn lookup_cached_provider(cache_key: &str, model_dir: Option<&Path>) -> Option<ProviderChoice> {
+    let memory_cache = AUTO_PROVIDER_CACHE.get_or_init(|| Mutex::new(std::collections::HashMap::new()));
+    if let Some(provider) = memory_cache
+        .lock()
+        .expect("provider cache lock poisoned")
+        .get(cache_key)
+        .copied()
+    {
+        return Some(provider);
+    }
+
+    let cache_path = provider_cache_file(model_dir)?;
+    let raw = fs::read_to_string(cache_path).ok()?;
+    let parsed: PersistedProviderCache = serde_json::from_str(&raw).ok()?;
+    let provider = parsed.providers.get(cache_key).and_then(|v| parse_provider_choice(v))?;
+    memory_cache
+        .lock()
+        .expect("provider cache lock poisoned")
+        .insert(cache_key.to_string(), provider);
+    Some(provider)
+}
+
+fn persist_cached_provider(cache_key: &str, provider: ProviderChoice, model_dir: Option<&Path>) {
+    let memory_cache = AUTO_PROVIDER_CACHE.get_or_init(|| Mutex::new(std::collections::HashMap::new()));
+    memory_cache
+        .lock()
+        .expect("provider cache lock poisoned")
+        .insert(cache_key.to_string(), provider);
+
+    let Some(cache_path) = provider_cache_file(model_dir) else {
+        return;
+    };
+    if let Some(parent) = cache_path.parent() {
+        let _ = fs::create_dir_all(parent);
+    }
+    let existing = fs::read_to_string(&cache_path)
+        .ok()
+        .and_then(|raw| serde_json::from_str::<PersistedProviderCache>(&raw).ok())
+        .unwrap_or_default();
+    let mut updated = existing;
+    updated
+        .providers
+        .insert(cache_key.to_string(), provider_label(provider).to_string());
+    if let Ok(serialized) = serde_json::to_string_pretty(&updated) {
+        let _ = fs::write(cache_path, serialized);
+    }
+}
+
+fn provider_cache_file(model_dir: Option<&Path>) -> Option<PathBuf> {
+    resolve_model_paths(model_dir)
+        .ok()
+        .map(|paths| paths.root.join("cache").join("provider-selection.json"))
+}
+
+fn parse_provider_choice(value: &str) -> Option<ProviderChoice> {
+    match value {
+        "cpu" => Some(ProviderChoice::Cpu),
+        "directml" => Some(ProviderChoice::DirectML),
+        "cuda" => Some(ProviderChoice::Cuda),
+        "coreml" => Some(ProviderChoice::CoreML),
+        _ => None,
+    }
+}
+
+fn model_cache_label(model: ModelKind) -> &'static str {
+    match model {
+        ModelKind::Rmbg14 => "rmbg14",
+        ModelKind::Rmbg20 => "rmbg20",
+        ModelKind::Auto => "auto",
+    }
+}
+
+/// Leads every provider cache key; bump it when the key layout changes so entries written in an
+/// older layout in `provider-selection.json` no longer match and are ignored.
+const PROVIDER_CACHE_KEY_VERSION: &str = "v2";
+
+/// `model_cache_label`, or the custom model's id when the request runs one in its place.
+fn run_model_label(selected_model: ModelKind, request: &InferenceRequest) -> &str {
+    request
+        .custom_model
+        .as_deref()
+        .unwrap_or_else(|| model_cache_label(selected_model))
+}
+
+fn provider_cache_key(selected_model: ModelKind, request: &InferenceRequest) -> String {
+    let model = run_model_label(selected_model, request);
+    let variant = match request.onnx_variant {
+        OnnxVariant::Fp16 => "fp16",
+        OnnxVariant::Fp32 => "fp32",
+        OnnxVariant::Quantized => "quantized",
+        OnnxVariant::Auto => "auto",
+    };
+    let gpu_backend = match request.gpu_backend {
+        GpuBackendPreference::Auto => "auto",
+        GpuBackendPreference::DirectML => "directml",
+        GpuBackendPreference::Cuda => "cuda",
+        GpuBackendPreference::CoreML => "coreml",
+        GpuBackendPreference::Metal => "metal",
+    };
+    let fingerprint = format!(
+        "{}|{}|{}",
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        std::env::var("ORT_DYLIB_PATH").unwrap_or_default()
+    );
+    format!("{}|{}|{}|{}|{}", PROVIDER_CACHE_KEY_VERSION, model, variant, gpu_backend, fingerprint)
+}
+
+/// One line of the run-history log: what a single `infer` ran on and how it went.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunHistoryEntry {
+    /// Unix time the run finished, in milliseconds.
+    pub timestamp_ms: u64,
+    /// SHA-256 of the encoded input; `None` when a run on a file input failed before reading it.
+    pub input_sha256: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_path: Option<PathBuf>,
+    /// The model that produced the mask, or the one selected when the run failed.
+    pub model: ModelKind,
+    pub onnx_variant: OnnxVariant,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub custom_model: Option<String>,
+    /// `cpu` or `gpu`; `None` when the run failed.
+    pub provider: Option<String>,
+    pub gpu_backend: Option<String>,
+    /// Wall time of the run; batched inputs report their whole sub-batch.
+    pub elapsed_ms: u64,
+    pub width: u32,
+    pub height: u32,
+    /// Share of mask pixels at least half opaque.
+    pub foreground_ratio: Option<f32>,
+    pub mask_coverage: Option<MaskCoverage>,
+    pub fallback_used: bool,
+    pub model_fallback_used: bool,
+    pub fallback_reason: Option<String>,
+    pub error: Option<String>,
+}
+
+impl RunHistoryEntry {
+    fn record(
+        request: &InferenceRequest,
+        selected_model: ModelKind,
+        outcome: Result<&InferenceResult, &CoreError>,
+        foreground_ratio: Option<f32>,
+        elapsed_ms: u64,
+    ) -> Self {
+        // Bytes already in memory are cheap to hash again; a file is never re-read for the log.
+        let input_sha256 = outcome
+            .ok()
+            .and_then(|result| result.input_sha256.clone())
+            .or_else(|| request.input_bytes.as_deref().map(|bytes| hex::encode(Sha256::digest(bytes))));
+        let timestamp_ms = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis().try_into().unwrap_or(u64::MAX));
+        let mut entry = Self {
+            timestamp_ms,
+            input_sha256,
+            input_path: request.input_path.clone(),
+            model: selected_model,
+            onnx_variant: request.onnx_variant,
+            custom_model: request.custom_model.clone(),
+            provider: None,
+            gpu_backend: None,
+            elapsed_ms,
+            width: request.width,
+            height: request.height,
+            foreground_ratio,
+            mask_coverage: None,
+            fallback_used: false,
+            model_fallback_used: false,
+            fallback_reason: None,
+            error: None,
+        };
+        match outcome {
+            Ok(result) => {
+                entry.model = result.model_used;
+                entry.provider = Some(result.execution_provider_selected.clone());
+                entry.gpu_backend = result.gpu_backend_selected.clone();
+                entry.width = result.width;
+                entry.height = result.height;
+                entry.mask_coverage = result.mask_coverage;
+                entry.fallback_used = result.fallback_used;
+                entry.model_fallback_used = result.model_fallback_used;
+                entry.fallback_reason = result.fallback_reason.clone();
+            }
+            Err(err) => entry.error = Some(err.to_string()),
+        }
+        entry
+    }
+}
+
+/// Run-history log selected by an `UNBG_RUN_HISTORY` value: `1`, `true` or `on` picks
+/// `<root>/cache/run-history.jsonl`, unset, empty, `0`, `false` or `off` disables the log, and
+/// anything else is the log file path.
+pub fn run_history_file(setting: Option<&str>, model_dir: Option<&Path>) -> Option<PathBuf> {
+    match setting.map(str::trim)? {
+        "" | "0" | "false" | "off" => None,
+        "1" | "true" | "on" => resolve_model_paths(model_dir)
+            .ok()
+            .map(|paths| paths.root.join("cache").join("run-history.jsonl")),
+        path => Some(PathBuf::from(path)),
+    }
+}
+
+/// Best effort, like the other caches: a log that cannot be written never fails inference.
+fn append_run_history(path: &Path, entry: &RunHistoryEntry) {
+    let Ok(mut line) = serde_json::to_string(entry) else {
+        return;
+    };
+    line.push('\n');
+    if let Some(parent) = path.parent() {
+        let _ = fs::create_dir_all(parent);
+    }
+    if let Ok(mut file) = fs::OpenOptions::new().create(true).append(true).open(path) {
+        let _ = std::io::Write::write_all(&mut file, line.as_bytes());
+    }
+}
+
+/// Every well-formed entry in the log at `path`, oldest first, read a line at a time so a long
+/// log is never held in memory. A missing log reads as empty and lines that do not parse (a write
+/// cut short, say) are skipped.
+pub fn read_run_history(path: &Path) -> Result<impl Iterator<Item = RunHistoryEntry>, CoreError> {
+    let file = match fs::File::open(path) {
+        Ok(file) => Some(file),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+        Err(err) => {
+            return Err(CoreError::Backend(format!(
+                "failed to read run history {}: {}",
+                path.display(),
+                err
+            )))
+        }
+    };
+    Ok(file
+        .into_iter()
+        .flat_map(|file| std::io::BufRead::split(std::io::BufReader::new(file), b'\n'))
+        .map_while(Result::ok)
+        .filter_map(|line| serde_json::from_slice(&line).ok()))
+}
+
+/// Observed `session.run` times per `model|provider` key, bucketed by input megapixels.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PersistedTimingHistory {
+    timings: std::collections::HashMap<String, std::collections::BTreeMap<u32, TimingStats>>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct TimingStats {
+    runs: u32,
+    mean_ms: f64,
+}
+
+/// Runs after which new samples stop being averaged in equally, so the mean follows
+/// driver or hardware changes instead of being pinned by old history.
+const TIMING_HISTORY_WINDOW: u32 = 16;
+
+/// Predicts how long inference of `model` on `provider` (`cpu`, `cuda`, `directml` or `coreml`)
+/// takes for an input of `size`, from the times recorded by earlier runs under `model_dir`.
+/// Uses the closest recorded size when this one has no history; `None` without any data.
+/// Runs are only recorded while `UNBG_TIMING_HISTORY` is set to `1`, `true` or `on`.
+pub fn estimate_inference_ms(model: ModelKind, provider: &str, size: ImageSize, model_dir: Option<&Path>) -> Option<u64> {
+    let provider = parse_provider_choice(provider)?;
+    let raw = fs::read_to_string(timing_history_file(model_dir)?).ok()?;
+    let history: PersistedTimingHistory = serde_json::from_str(&raw).ok()?;
+    let buckets = history.timings.get(&timing_history_key(model_cache_label(model), provider))?;
+    let target = megapixel_bucket(size);
+    let (_, stats) = buckets.iter().min_by_key(|(bucket, _)| bucket.abs_diff(target))?;
+    Some(stats.mean_ms.round() as u64)
+}
+
+/// Whether `UNBG_TIMING_HISTORY` opts in to recording run times for [`estimate_inference_ms`].
+fn timing_history_enabled() -> bool {
+    env::var("UNBG_TIMING_HISTORY")
+        .map(|value| matches!(value.trim(), "1" | "true" | "on"))
+        .unwrap_or(false)
+}
+
+/// Best effort: the history is rewritten through a temp file and a rename so readers and
+/// concurrent writers never see a torn file, and a file that does not parse is left as it is
+/// rather than replaced by a single sample.
+fn record_inference_ms(model: &str, provider: ProviderChoice, size: ImageSize, elapsed_ms: u128, model_dir: Option<&Path>) {
+    let Some(history_path) = timing_history_file(model_dir) else {
+        return;
+    };
+    let mut history = match fs::read_to_string(&history_path) {
+        Ok(raw) => match serde_json::from_str::<PersistedTimingHistory>(&raw) {
+            Ok(history) => history,
+            Err(_) => return,
+        },
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => PersistedTimingHistory::default(),
+        Err(_) => return,
+    };
+    let stats = history
+        .timings
+        .entry(timing_history_key(model, provider))
+        .or_default()
+        .entry(megapixel_bucket(size))
+        .or_insert(TimingStats { runs: 0, mean_ms: 0.0 });
+    stats.runs = (stats.runs + 1).min(TIMING_HISTORY_WINDOW);
+    stats.mean_ms += (elapsed_ms as f64 - stats.mean_ms) / stats.runs as f64;
+    let Ok(serialized) = serde_json::to_string_pretty(&history) else {
+        return;
+    };
+    if let Some(parent) = history_path.parent() {
+        let _ = fs::create_dir_all(parent);
+    }
+    let _ = write_replacing(&history_path, serialized.as_bytes());
+}
+
+fn timing_history_file(model_dir: Option<&Path>) -> Option<PathBuf> {
+    resolve_model_paths(model_dir)
+        .ok()
+        .map(|paths| paths.root.join("cache").join("timing-history.json"))
+}
+
+/// `model` is a [`model_cache_label`] or a custom model id, so custom runs get their own entries.
+fn timing_history_key(model: &str, provider: ProviderChoice) -> String {
+    format!("{}|{}", model, provider_label(provider))
+}
+
+/// Input size rounded up to whole megapixels; anything up to 1 MP shares bucket 1.
+fn megapixel_bucket(size: ImageSize) -> u32 {
+    ((size.width as u64 * size.height as u64).div_ceil(1_000_000)).max(1) as u32
+}
+
+/// One model input or output as reported by ONNX Runtime.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TensorSpec {
+    pub name: String,
+    /// Element type for tensors (`f32`, `u8`, ...) or the container type otherwise.
+    pub element_type: String,
+    /// `-1` marks a dynamic dimension; empty for non-tensor values.
+    pub shape: Vec<i64>,
+    /// Symbolic names of dynamic dimensions, empty strings for fixed ones.
+    pub dimension_names: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelSpec {
+    pub inputs: Vec<TensorSpec>,
+    pub outputs: Vec<TensorSpec>,
+}
+
+impl ModelSpec {
+    /// One NCHW image input and one mask output, the shape the built-in pipeline expects.
+    pub fn is_supported_segmentation_model(&self) -> bool {
+        matches!(self.inputs.as_slice(), [input] if input.shape.len() == 4)
+            && matches!(self.outputs.as_slice(), [output] if (2..=4).contains(&output.shape.len()))
+    }
+}
+
+/// Channel count of an NCHW image input: 1 when the export fixes it to one, otherwise 3.
+fn input_channels(input: &TensorSpec) -> usize {
+    match input.shape.as_slice() {
+        [_, 1, _, _] => 1,
+        _ => 3,
+    }
+}
+
+/// Loads `model_file` on the CPU provider and reports its inputs and outputs without running it.
+pub fn inspect_model(model_file: &Path) -> Result<ModelSpec, CoreError> {
+    let session = session_builder(&[], SessionTuning::default())
+        .and_then(|builder| commit_session(builder, model_file))
+        .map_err(|e| CoreError::Backend(format!("failed to load {}: {}", model_file.display(), e)))?;
+    Ok(ModelSpec {
+        inputs: session.inputs().iter().map(tensor_spec).collect(),
+        outputs: session.outputs().iter().map(tensor_spec).collect(),
+    })
+}
+
+fn tensor_spec(outlet: &Outlet) -> TensorSpec {
+    match outlet.dtype() {
+        ValueType::Tensor {
+            ty,
+            shape,
+            dimension_symbols,
+        } => TensorSpec {
+            name: outlet.name().to_string(),
+            element_type: ty.to_string(),
+            shape: shape.to_vec(),
+            dimension_names: dimension_symbols.to_vec(),
+        },
+        other => TensorSpec {
+            name: outlet.name().to_string(),
+            element_type: other.to_string(),
+            shape: Vec::new(),
+            dimension_names: Vec::new(),
+        },
+    }
+}
+
+/// What a caller can afford for one run, for [`choose_model`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModelConstraints {
+    /// Peak working memory the run may use, in bytes.
+    pub max_memory: Option<u64>,
+    pub target_latency_ms: Option<u32>,
+    /// 0.0 picks the fastest model, 1.0 the best mask; values between trade one for the other.
+    pub quality_priority: f32,
+}
+
+impl Default for ModelConstraints {
+    fn default() -> Self {
+        Self {
+            max_memory: None,
+            target_latency_ms: None,
+            quality_priority: 0.5,
+        }
+    }
+}
+
+/// Rough per-model costs at the 1024x1024 input size on a desktop CPU:
+/// `(peak working memory in bytes, latency in ms, relative mask quality)`.
+fn model_profile(model: KnownModel) -> (u64, u32, f32) {
+    match model {
+        KnownModel::Rmbg14 => (700 * 1024 * 1024, 400, 0.6),
+        KnownModel::Rmbg20 => (2_500 * 1024 * 1024, 1_600, 1.0),
+    }
+}
+
+/// Picks the installed model that best matches `constraints`, scoring each by
+/// `quality_priority` between mask quality and speed.
+///
+/// Models over the memory or latency budget are skipped; if every installed model is over
+/// budget the lightest one is returned. Returns [`ModelKind::Auto`] when nothing is installed,
+/// leaving the choice to [`unbg_core::resolve_model`].
+pub fn choose_model(constraints: ModelConstraints, installed: &[KnownModel]) -> ModelKind {
+    let fits = |model: &&KnownModel| {
+        let (memory, latency, _) = model_profile(**model);
+        constraints.max_memory.is_none_or(|max| memory <= max)
+            && constraints.target_latency_ms.is_none_or(|target| latency <= target)
+    };
+    let candidates: Vec<KnownModel> = installed.iter().filter(fits).copied().collect();
+    let chosen = if candidates.is_empty() {
+        installed.iter().copied().min_by_key(|model| model_profile(*model).0)
+    } else {
+        let fastest = candidates.iter().map(|model| model_profile(*model).1).min().unwrap_or(1).max(1);
+        let priority = constraints.quality_priority.clamp(0.0, 1.0);
+        let score = |model: KnownModel| {
+            let (_, latency, quality) = model_profile(model);
+            priority * quality + (1.0 - priority) * (fastest as f32 / latency.max(1) as f32)
+        };
+        candidates.into_iter().max_by(|a, b| score(*a).total_cmp(&score(*b)))
+    };
+    match chosen {
+        Some(KnownModel::Rmbg14) => ModelKind::Rmbg14,
+        Some(KnownModel::Rmbg20) => ModelKind::Rmbg20,
+        None => ModelKind::Auto,
+    }
+}
+
+/// What the ORT backend would run for a request: the resolved `.onnx` file and providers in try order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecutionPlan {
+    pub model: ModelKind,
+    pub onnx_file: PathBuf,
+    pub onnx_variant: OnnxVariant,
+    pub providers: Vec<String>,
+    pub benchmark_provider: bool,
+}
+
+pub fn execution_plan(request: &InferenceRequest, selected_model: ModelKind) -> Result<ExecutionPlan, CoreError> {
+    let onnx_file = resolve_model_onnx_file(request, selected_model)?;
+    Ok(ExecutionPlan {
+        model: selected_model,
+        onnx_variant: onnx_file_variant(&onnx_file),
+        onnx_file,
+        providers: candidate_providers(request)
+            .into_iter()
+            .map(|p| provider_label(p).to_string())
+            .collect(),
+        benchmark_provider: request.benchmark_provider,
+    })
+}
+
+fn candidate_providers(request: &InferenceRequest) -> Vec<ProviderChoice> {
+    let mut out = Vec::new();
+    match request.execution_provider {
+        ExecutionProvider::Cpu => out.push(ProviderChoice::Cpu),
+        ExecutionProvider::Gpu => {
+            out.extend(gpu_candidates(request.gpu_backend));
+            out.push(ProviderChoice::Cpu);
+        }
+        ExecutionProvider::Auto => {
+            out.extend(gpu_candidates(request.gpu_backend));
+            out.push(ProviderChoice::Cpu);
+        }
+    }
+    dedup_providers(out)
+}
+
+fn gpu_candidates(pref: GpuBackendPreference) -> Vec<ProviderChoice> {
+    let mut providers = Vec::new();
+    match pref {
+        GpuBackendPreference::DirectML => providers.push(ProviderChoice::DirectML),
+        GpuBackendPreference::Cuda => providers.push(ProviderChoice::Cuda),
+        GpuBackendPreference::CoreML | GpuBackendPreference::Metal => providers.push(ProviderChoice::CoreML),
+        GpuBackendPreference::Auto => {
+            #[cfg(target_os = "windows")]
+            {
+                if cuda_likely_available() {
+                    providers.push(ProviderChoice::Cuda);
+                }
+                providers.push(ProviderChoice::DirectML);
+            }
+            #[cfg(target_os = "linux")]
+            {
+                if cuda_likely_available() {
+                    providers.push(ProviderChoice::Cuda);
+                }
+            }
+            #[cfg(any(target_os = "macos", target_os = "ios"))]
+            {
+                providers.push(ProviderChoice::CoreML);
+            }
+        }
+    }
+    providers
+}
+
+fn cuda_likely_available() -> bool {
+    #[cfg(target_os = "windows")]
+    {
+        let system32 = std::env::var("WINDIR")
+            .ok()
+            .map(|d| PathBuf::from(d).join("System32").join("nvcuda.dll"));
+        if let Some(candidate) = system32 {
+            if candidate.exists() {
+                return true;
+            }
+        }
+        if let Some(path) = std::env::var_os("PATH") {
+            for dir in std::env::split_paths(&path) {
+                if dir.join("nvcuda.dll").exists() {
+                    return true;
+                }
+            }
+        }
+        return false;
+    }
+    #[cfg(target_os = "linux")]
+    {
+        let candidates = [
+            "/usr/lib/x86_64-linux-gnu/libcuda.so.1",
+            "/usr/lib64/libcuda.so.1",
+            "/usr/lib/wsl/lib/libcuda.so.1",
+        ];
+        candidates.iter().any(|p| Path::new(p).exists())
+    }
+    #[cfg(not(any(target_os = "windows", target_os = "linux")))]
+    {
+        false
+    }
+}
+
+fn dedup_providers(list: Vec<ProviderChoice>) -> Vec<ProviderChoice> {
+    let mut out = Vec::new();
+    for provider in list {
+        if !out.contains(&provider) {
+            out.push(provider);
+        }
+    }
+    out
+}
+
+fn provider_label(provider: ProviderChoice) -> &'static str {
+    match provider {
+        ProviderChoice::Cpu => "cpu",
+        ProviderChoice::DirectML => "directml",
+        ProviderChoice::Cuda => "cuda",
+        ProviderChoice::CoreML => "coreml",
+    }
+}
+
+fn session_builder(dimension_overrides: &[(String, i64)], tuning: SessionTuning) -> Result<ort::session::builder::SessionBuilder> {
+    let mut builder = Session::builder()?.with_log_level(ort_log_level(env::var("UNBG_ORT_LOG_LEVEL").ok().as_deref()))?;
+    for (name, size) in dimension_overrides {
+        builder = builder.with_dimension_override(name, *size)?;
+    }
+    if let Some(threads) = tuning.intra_threads {
+        builder = builder.with_intra_threads(threads)?;
+    }
+    if let Some(threads) = tuning.inter_threads {
+        builder = builder.with_inter_threads(threads)?;
+    }
+    if let Some(level) = ort_optimization_level(tuning.graph_opt) {
+        builder = builder.with_optimization_level(level)?;
+    }
+    Ok(builder)
+}
+
+/// The `ort` level for `level`, or `None` for `All`, which is already ONNX Runtime's default.
+fn ort_optimization_level(level: GraphOptLevel) -> Option<GraphOptimizationLevel> {
+    match level {
+        GraphOptLevel::Disable => Some(GraphOptimizationLevel::Disable),
+        GraphOptLevel::Basic => Some(GraphOptimizationLevel::Level1),
+        GraphOptLevel::Extended => Some(GraphOptimizationLevel::Level2),
+        GraphOptLevel::Layout => Some(GraphOptimizationLevel::Level3),
+        GraphOptLevel::All => None,
+    }
+}
+
+/// Session log severity; `UNBG_ORT_LOG_LEVEL` overrides the warning default.
+fn ort_log_level(value: Option<&str>) -> LogLevel {
+    match value.map(|v| v.trim().to_ascii_lowercase()).as_deref() {
+        Some("verbose") => LogLevel::Verbose,
+        Some("info") => LogLevel::Info,
+        Some("error") => LogLevel::Error,
+        Some("fatal") => LogLevel::Fatal,
+        _ => LogLevel::Warning,
+    }
+}
+
+/// Extra CPU session settings; only quantized models deviate from the ORT defaults.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+struct CpuSessionConfig {
+    /// Flush denormals to zero; int8 kernels dequantize into tiny floats that otherwise hit slow paths.
+    denormal_as_zero: bool,
+    /// Drop leftover QuantizeLinear/DequantizeLinear pairs after fusion.
+    qdq_cleanup: bool,
+}
+
+fn cpu_session_config(variant: OnnxVariant) -> CpuSessionConfig {
+    match variant {
+        OnnxVariant::Quantized => CpuSessionConfig {
+            denormal_as_zero: true,
+            qdq_cleanup: true,
+        },
+        OnnxVariant::Fp16 | OnnxVariant::Fp32 | OnnxVariant::Auto => CpuSessionConfig::default(),
+    }
+}
+
+/// Variant of an already-resolved `.onnx` file, using the same name rules as [`rank_onnx_files`].
+/// Only the file name is classified, so a models root under e.g. `~/q8-models` cannot skew it.
+fn onnx_file_variant(model_file: &Path) -> OnnxVariant {
+    model_file
+        .file_name()
+        .and_then(|name| onnx_name_variant(&name.to_string_lossy()))
+        .unwrap_or(OnnxVariant::Fp32)
+}
+
+/// [`classify_onnx_name`] as the request-level [`OnnxVariant`].
+fn onnx_name_variant(name: &str) -> Option<OnnxVariant> {
+    classify_onnx_name(name).map(|variant| match variant {
+        OnnxFileVariant::Fp16 => OnnxVariant::Fp16,
+        OnnxFileVariant::Fp32 => OnnxVariant::Fp32,
+        OnnxFileVariant::Quantized => OnnxVariant::Quantized,
+    })
+}
+
+fn build_session_for_provider(
+    model_file: &Path,
+    provider: ProviderChoice,
+    variant: OnnxVariant,
+    dimension_overrides: &[(String, i64)],
+    tuning: SessionTuning,
+) -> Result<Session> {
+    match provider {
+        ProviderChoice::Cpu => {
+            let config = cpu_session_config(variant);
+            let mut builder = session_builder(dimension_overrides, tuning)?;
+            if config.denormal_as_zero {
+                builder = builder.with_denormal_as_zero()?;
+            }
+            if config.qdq_cleanup {
+                builder = builder.with_qdq_cleanup()?;
+            }
+            commit_session(builder, model_file)
+        }
+        ProviderChoice::DirectML => {
+            #[cfg(feature = "directml")]
+            {
+                commit_session(
+                    session_builder(dimension_overrides, tuning)?.with_execution_providers([ort::ep::DirectML::default().build()])?,
+                    model_file,
+                )
+            }
+            #[cfg(not(feature = "directml"))]
+            {
+                Err(anyhow!("directml feature not enabled"))
+            }
+        }
+        ProviderChoice::Cuda => {
+            #[cfg(feature = "cuda")]
+            {
+                commit_session(
+                    session_builder(dimension_overrides, tuning)?.with_execution_providers([ort::ep::CUDA::default().build()])?,
+                    model_file,
+                )
+            }
+            #[cfg(not(feature = "cuda"))]
+            {
+                Err(anyhow!("cuda feature not enabled"))
+            }
+        }
+        ProviderChoice::CoreML => {
+            #[cfg(feature = "coreml")]
+            {
+                commit_session(
+                    session_builder(dimension_overrides, tuning)?.with_execution_providers([ort::ep::CoreML::default().build()])?,
+                    model_file,
+                )
+            }
+            #[cfg(not(feature = "coreml"))]
+            {
+                Err(anyhow!("coreml feature not enabled"))
+            }
+        }
+    }
+}
+
+fn commit_session(builder: ort::session::builder::SessionBuilder, model_file: &Path) -> Result<Session> {
+    builder.commit_from_file(model_file).map_err(|err| {
+        let message = err.to_string();
+        match explain_version_mismatch(&message) {
+            Some(help) => anyhow::Error::new(CoreError::Backend(help)),
+            None => err.into(),
+        }
+    })
+}
+
+/// Rewrites ONNX Runtime's opset / IR version errors, which only say what failed deep inside
+/// its loader, into a message that says what to do about it.
+fn explain_version_mismatch(message: &str) -> Option<String> {
+    let lower = message.to_ascii_lowercase();
+    let number_after = |marker: &str| {
+        lower.match_indices(marker).find_map(|(at, _)| {
+            let digits: String = lower[at + marker.len()..]
+                .trim_start()
+                .chars()
+                .take_while(char::is_ascii_digit)
+                .collect();
+            (!digits.is_empty()).then_some(digits)
+        })
+    };
+    let (what, version) = if lower.contains("unsupported model ir version") {
+        ("IR version", number_after("ir version:"))
+    } else if lower.contains("opset")
+        && ["under development", "official support", "not supported", "unsupported"]
+            .iter()
+            .any(|hint| lower.contains(hint))
+    {
+        ("opset", number_after("opset"))
+    } else {
+        return None;
+    };
+    Some(format!(
+        "onnxruntime too old for model {} {}; update onnxruntime (or point ORT_DYLIB_PATH at a newer build). \
+         onnxruntime said: {}",
+        what,
+        version.as_deref().unwrap_or("(unknown)"),
+        message
+    ))
+}
+
+fn backend_error(kind: &str, message: String) -> CoreError {
+    CoreError::Backend(format!("{}: {}", kind, message))
+}
+
+/// An `.onnx` file found under a revision directory with its preference rank (lower wins).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RankedOnnxFile {
+    pub path: PathBuf,
+    pub rank: u8,
+}
+
+/// Lists every `.onnx` file under `base_dir` in the order `find_preferred_onnx_file` would pick them.
+pub fn rank_onnx_files(base_dir: &Path, onnx_variant: OnnxVariant) -> Vec<RankedOnnxFile> {
+    let mut ranked: Vec<RankedOnnxFile> = onnx_files_with_names(base_dir)
+        .map(|(path, lower)| {
+            let rank = onnx_file_rank(&lower, onnx_variant);
+            RankedOnnxFile { path, rank }
+        })
+        .collect();
+    ranked.sort_by_key(|f| f.rank);
+    ranked
+}
+
+/// The variants present under a revision directory, in `fp16`, `fp32`, `quantized` order. Files
+/// are classified with the name rules [`rank_onnx_files`] uses; unconventionally named files count
+/// as fp32.
+pub fn detect_available_variants(revision_dir: &Path) -> Vec<OnnxVariant> {
+    let present: Vec<OnnxVariant> = onnx_files_with_names(revision_dir)
+        .map(|(_, lower)| onnx_name_variant(&lower).unwrap_or(OnnxVariant::Fp32))
+        .collect();
+    [OnnxVariant::Fp16, OnnxVariant::Fp32, OnnxVariant::Quantized]
+        .into_iter()
+        .filter(|variant| present.contains(variant))
+        .collect()
+}
+
+/// Every `.onnx` file under `base_dir` with its lowercased path relative to `base_dir`, so parent
+/// directory names cannot skew the classification.
+fn onnx_files_with_names(base_dir: &Path) -> impl Iterator<Item = (PathBuf, String)> + '_ {
+    WalkDir::new(base_dir)
+        .into_iter()
+        .filter_map(std::result::Result::ok)
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.into_path())
+        .filter(|p| p.extension().map(|e| e == "onnx").unwrap_or(false))
+        .map(move |path| {
+            let lower = path.strip_prefix(base_dir).unwrap_or(&path).to_string_lossy().to_lowercase();
+            (path, lower)
+        })
+}
+
+fn find_preferred_onnx_file(base_dir: &Path, onnx_variant: OnnxVariant) -> Option<PathBuf> {
+    rank_onnx_files(base_dir, onnx_variant).into_iter().next().map(|f| f.path)
+}
+
+/// Preference rank (lower wins) of a file classified by [`onnx_name_variant`] when
+/// `onnx_variant` was requested; unclassified files come last.
+fn onnx_file_rank(lower: &str, onnx_variant: OnnxVariant) -> u8 {
+    let order = match onnx_variant {
+        OnnxVariant::Fp16 | OnnxVariant::Auto => [OnnxVariant::Fp16, OnnxVariant::Fp32, OnnxVariant::Quantized],
+        OnnxVariant::Fp32 => [OnnxVariant::Fp32, OnnxVariant::Fp16, OnnxVariant::Quantized],
+        OnnxVariant::Quantized => [OnnxVariant::Quantized, OnnxVariant::Fp16, OnnxVariant::Fp32],
+    };
+    onnx_name_variant(lower)
+        .and_then(|variant| order.iter().position(|&preferred| preferred == variant))
+        .map_or(3, |rank| rank as u8)
+}
+
+fn run_onnx_inference(
+    image: &DynamicImage,
+    session: &mut Session,
+    request: &InferenceRequest,
+    pipeline: &Pipeline,
+) -> Result<FinishedMask> {
+    let stage = Instant::now();
+    let prepared = pipeline.preprocessor.prepare(image, &input_spec(session, request))?;
+    pipeline.check_cancelled()?;
+
+    let input_tensor = Tensor::<f32>::from_array((prepared.shape, prepared.data))?;
+    let preprocess_ms = elapsed_ms(stage);
+    let stage = Instant::now();
+    let outputs = session.run(inputs![input_tensor])?;
+    let session_run_ms = elapsed_ms(stage);
+    pipeline.check_cancelled()?;
+    if outputs.len() == 0 {
+        return Err(anyhow!("model returned no outputs"));
+    }
+    let stage = Instant::now();
+    let mut finished = if !request.emit_mask_png && !request.emit_mask_raw {
+        FinishedMask::default()
+    } else {
+        let (shape, data) = outputs[0].try_extract_tensor::<f32>()?;
+        let shape: Vec<usize> = shape.iter().map(|&d| d.max(0) as usize).collect();
+        let raw = extract_mask(&shape, data, &request.foreground_channels)?;
+        finish_mask(raw, image, request, pipeline)?
+    };
+    if request.collect_timings {
+        finished.timings = Some(InferenceTimings {
+            preprocess_ms,
+            session_run_ms,
+            postprocess_ms: elapsed_ms(stage),
+            total_ms: 0,
+        });
+    }
+    finished.session_run_ms = session_run_ms;
+    Ok(finished)
+}
+
+fn elapsed_ms(start: Instant) -> u64 {
+    start.elapsed().as_millis().try_into().unwrap_or(u64::MAX)
+}
+
+/// Batched counterpart of [`run_onnx_inference`]: stacks the prepared inputs along the batch
+/// dimension, runs the session once and splits the output into one mask per image.
+fn run_onnx_batch(
+    images: &[DynamicImage],
+    session: &mut Session,
+    requests: &[&InferenceRequest],
+    pipeline: &Pipeline,
+) -> Result<Vec<FinishedMask>> {
+    let mut shape = [0usize; 4];
+    let mut data = Vec::new();
+    for (image, request) in images.iter().zip(requests) {
+        let prepared = pipeline.preprocessor.prepare(image, &input_spec(session, request))?;
+        if shape[0] > 0 && prepared.shape[1..] != shape[1..] {
+            return Err(anyhow!("batch inputs differ in shape: {:?} vs {:?}", prepared.shape, shape));
+        }
+        shape = [shape[0] + prepared.shape[0], prepared.shape[1], prepared.shape[2], prepared.shape[3]];
+        data.extend(prepared.data);
+    }
+
+    pipeline.check_cancelled()?;
+    let input_tensor = Tensor::<f32>::from_array((shape, data))?;
+    let outputs = session.run(inputs![input_tensor])?;
+    pipeline.check_cancelled()?;
+    if outputs.len() == 0 {
+        return Err(anyhow!("model returned no outputs"));
+    }
+    let (out_shape, out_data) = outputs[0].try_extract_tensor::<f32>()?;
+    let out_shape: Vec<usize> = out_shape.iter().map(|&d| d.max(0) as usize).collect();
+    let (item_shape, item_len) = batch_item_shape(&out_shape, out_data.len(), images.len())?;
+    images
+        .iter()
+        .zip(requests)
+        .zip(out_data.chunks(item_len))
+        .map(|((image, request), item)| {
+            if !request.emit_mask_png && !request.emit_mask_raw {
+                return Ok(FinishedMask::default());
+            }
+            let raw = extract_mask(&item_shape, item, &request.foreground_channels)?;
+            finish_mask(raw, image, request, pipeline)
+        })
+        .collect()
+}
+
+/// Model input edge used when the request leaves `input_size` unset.
+const DEFAULT_INPUT_SIZE: u32 = 1024;
+
+fn validate_input_size(size: Option<u32>) -> Result<(), CoreError> {
+    match size {
+        Some(size) if size == 0 || size % 32 != 0 => Err(CoreError::InvalidArgument(format!(
+            "input size must be a positive multiple of 32, got {}",
+            size
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Checks the options that are otherwise only read after the session has run, so a bad value
+/// fails before any inference work.
+fn validate_request_shape(request: &InferenceRequest) -> Result<(), CoreError> {
+    validate_input_size(request.input_size)?;
+    if let Some(threshold) = request.mask_threshold {
+        validate_mask_threshold(threshold)?;
+    }
+    if let Some(radius) = request.feather_radius {
+        validate_feather_radius(radius)?;
+    }
+    if let Some(curve) = &request.alpha_curve {
+        validate_alpha_curve(curve)?;
+    }
+    if let Some(thresholds) = request.trimap {
+        thresholds.validate()?;
+    }
+    // A seed mask's size is checked from its header when the caller told us the input size.
+    if let Some(seed) = request.seed_mask.as_deref().filter(|_| request.width > 0 && request.height > 0) {
+        let dimensions = image_dimensions(seed, &DecodeHints::default())
+            .map_err(|e| CoreError::InvalidArgument(format!("failed to read seed mask: {}", e)))?;
+        check_seed_dimensions(dimensions, request.width, request.height)?;
+    }
+    Ok(())
+}
+
+/// Shape and length of one mask in a batched output of `shape` holding `len` values.
+fn batch_item_shape(shape: &[usize], len: usize, batch: usize) -> Result<(Vec<usize>, usize)> {
+    if shape.len() < 3 {
+        return Err(anyhow!(
+            "model output {:?} has no batch dimension; the export cannot run {} inputs at once",
+            shape,
+            batch
+        ));
+    }
+    if shape[0] != batch {
+        return Err(anyhow!(
+            "model returned {} masks (output {:?}) for a batch of {} inputs; the export may have a fixed batch size",
+            shape[0],
+            shape,
+            batch
+        ));
+    }
+    let mut item_shape = shape.to_vec();
+    item_shape[0] = 1;
+    let item_len: usize = item_shape.iter().product();
+    if len < item_len * batch {
+        return Err(anyhow!("model output has {} values, expected {}", len, item_len * batch));
+    }
+    Ok((item_shape, item_len))
+}
+
+fn input_spec(session: &Session, request: &InferenceRequest) -> InputSpec {
+    let size = request.input_size.unwrap_or(DEFAULT_INPUT_SIZE);
+    InputSpec {
+        width: size,
+        height: size,
+        source_max_value: request.source_max_value,
+        channels: session.inputs().first().map(|input| input_channels(&tensor_spec(input))).unwrap_or(3),
+    }
+}
+
+/// Encoded mask plus, when `emit_mask_raw` is set, the postprocessed float mask it was made from.
+#[derive(Debug, Default)]
+struct FinishedMask {
+    png: Vec<u8>,
+    /// The same pixels as `png`, run-length encoded; set when `emit_mask_rle` is.
+    rle: Option<Vec<(u8, u32)>>,
+    raw: Option<MaskBuffer>,
+    /// Stage timings with `total_ms` left for the caller; set when `collect_timings` is.
+    timings: Option<InferenceTimings>,
+    /// Wall time of `session.run` alone, whatever `collect_timings` says.
+    session_run_ms: u64,
+    /// [`foreground_ratio`] of the quantized mask behind `png`.
+    foreground_ratio: Option<f32>,
+}
+
+/// Moves an `emit_mask_raw` buffer into the result's raw-mask fields.
+fn set_mask_raw(result: &mut InferenceResult, raw: Option<MaskBuffer>) {
+    if let Some(raw) = raw {
+        result.mask_raw_width = raw.width;
+        result.mask_raw_height = raw.height;
+        result.mask_raw = Some(raw.data);
+    }
+}
+
+/// Postprocesses a raw model mask, scales it to `image` and encodes it as PNG.
+fn finish_mask(
+    raw: MaskBuffer,
+    image: &DynamicImage,
+    request: &InferenceRequest,
+    pipeline: &Pipeline,
+) -> Result<FinishedMask> {
+    let (orig_w, orig_h) = (image.width(), image.height());
+    let mut processed = pipeline.postprocess.run(raw, request)?;
+    if let Some(curve) = &request.alpha_curve {
+        apply_alpha_curve(&mut processed.data, curve)?;
+    }
+    if !request.emit_mask_png {
+        return Ok(FinishedMask {
+            raw: Some(processed),
+            ..FinishedMask::default()
+        });
+    }
+    let mut mask = GrayImage::new(processed.width, processed.height);
+    for (pixel, v) in mask.pixels_mut().zip(&processed.data) {
+        *pixel = Luma([(v.clamp(0.0f32, 1.0f32) * 255.0f32) as u8]);
+    }
+
+    let mut full_size = active_resizer().resize_gray(&mask, orig_w, orig_h);
+    // After the resize, so interpolation cannot reintroduce in-between values.
+    if let Some(threshold) = request.mask_threshold {
+        apply_mask_threshold(&mut full_size, threshold)?;
+    }
+    // After the threshold, which would otherwise re-harden the feathered edge.
+    if let Some(radius) = request.feather_radius {
+        apply_feather(&mut full_size, radius)?;
+    }
+    if let Some(thresholds) = request.trimap {
+        apply_trimap(&mut full_size, thresholds)?;
+    }
+    Ok(FinishedMask {
+        rle: request.emit_mask_rle.then(|| encode_mask_rle(full_size.as_raw())),
+        foreground_ratio: Some(foreground_ratio(full_size.as_raw())),
+        png: encode_mask(full_size, request.png_compression)?,
+        raw: request.emit_mask_raw.then_some(processed),
+        ..FinishedMask::default()
+    })
+}
+
+/// Sets alpha below `threshold` (as a fraction of 255) to 0 and everything else to 255.
+fn validate_mask_threshold(threshold: f32) -> Result<(), CoreError> {
+    if !(0.0..=1.0).contains(&threshold) {
+        return Err(CoreError::InvalidArgument(format!(
+            "mask threshold must be between 0 and 1, got {}",
+            threshold
+        )));
+    }
+    Ok(())
+}
+
+fn apply_mask_threshold(mask: &mut GrayImage, threshold: f32) -> Result<(), CoreError> {
+    validate_mask_threshold(threshold)?;
+    for pixel in mask.pixels_mut() {
+        pixel[0] = if pixel[0] as f32 / 255.0 >= threshold { 255 } else { 0 };
+    }
+    Ok(())
+}
+
+/// Maps each alpha through the piecewise-linear `curve`, interpolating between its points.
+fn apply_alpha_curve(alpha: &mut [f32], curve: &[(f32, f32)]) -> Result<(), CoreError> {
+    validate_alpha_curve(curve)?;
+    for value in alpha {
+        let x = value.clamp(0.0, 1.0);
+        let segment = curve.partition_point(|&(px, _)| px < x).clamp(1, curve.len() - 1);
+        let ((x0, y0), (x1, y1)) = (curve[segment - 1], curve[segment]);
+        *value = y0 + (y1 - y0) * (x - x0) / (x1 - x0);
+    }
+    Ok(())
+}
+
+fn validate_feather_radius(radius: f32) -> Result<(), CoreError> {
+    if !radius.is_finite() || radius < 0.0 {
+        return Err(CoreError::InvalidArgument(format!(
+            "feather radius must be a non-negative number, got {}",
+            radius
+        )));
+    }
+    Ok(())
+}
+
+fn apply_feather(mask: &mut GrayImage, radius: f32) -> Result<(), CoreError> {
+    validate_feather_radius(radius)?;
+    // Past the longer side the blur is already flat, and the kernel would only grow.
+    let longest = mask.width().max(mask.height());
+    if radius > longest as f32 {
+        return Err(CoreError::InvalidArgument(format!(
+            "feather radius {} is larger than the {}x{} mask",
+            radius,
+            mask.width(),
+            mask.height()
+        )));
+    }
+    *mask = feather_mask(mask, radius);
+    Ok(())
+}
+
+fn apply_trimap(mask: &mut GrayImage, thresholds: TrimapThresholds) -> Result<(), CoreError> {
+    thresholds.validate()?;
+    for pixel in mask.pixels_mut() {
+        pixel[0] = thresholds.classify(pixel[0]);
+    }
+    Ok(())
+}
+
+/// Reads a row-major model output as a mask, taking the per-pixel max over `channels` (channel 0 when empty).
+///
+/// Accepts `[N, C, H, W]`, `[N, H, W]` and `[H, W]` outputs; only the first has more than one channel.
+fn extract_mask(shape: &[usize], data: &[f32], channels: &[usize]) -> Result<MaskBuffer> {
+    let (channel_count, mask_h, mask_w) = match shape.len() {
+        4 => (shape[1], shape[2], shape[3]),
+        3 => (1, shape[1], shape[2]),
+        2 => (1, shape[0], shape[1]),
+        _ => return Err(anyhow!("unsupported output dimensions: {:?}", shape)),
+    };
+    let plane = mask_w * mask_h;
+    if data.len() < plane * channel_count {
+        return Err(anyhow!("model output has {} values, expected at least {}", data.len(), plane * channel_count));
+    }
+    let channels = if channels.is_empty() { &[0][..] } else { channels };
+    if let Some(bad) = channels.iter().find(|&&c| c >= channel_count) {
+        return Err(anyhow!(
+            "foreground channel {} is out of range; model output {:?} has {} channel(s)",
+            bad,
+            shape,
+            channel_count
+        ));
+    }
+
+    let data = (0..plane)
+        .map(|i| {
+            channels
+                .iter()
+                .map(|&c| data[c * plane + i])
+                .fold(f32::NEG_INFINITY, f32::max)
+        })
+        .collect();
+    Ok(MaskBuffer {
+        width: mask_w as u32,
+        height: mask_h as u32,
+        data,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SampleDepth {
+    U8,
+    U16,
+    F32,
+}
+
+fn sample_depth(color: ColorType) -> SampleDepth {
+    match color {
+        ColorType::L16 | ColorType::La16 | ColorType::Rgb16 | ColorType::Rgba16 => SampleDepth::U16,
+        ColorType::Rgb32F | ColorType::Rgba32F => SampleDepth::F32,
+        _ => SampleDepth::U8,
+    }
+}
+
+/// Builds the NCHW input tensor data, dividing each sample by the source max value.
+///
+/// The max defaults to the full range of the decoded sample type (255 for 8-bit,
+/// 65535 for 16-bit, 1.0 for float) unless the caller overrides it, then applying `stats`. With
+/// `channels == 1` a single luma plane is built instead of three, using the averaged stats.
+fn normalize_input(
+    image: &DynamicImage,
+    width: u32,
+    height: u32,
+    source_max: Option<f32>,
+    channels: usize,
+    stats: &ChannelStats,
+) -> Vec<f32> {
+    let resized = active_resizer().resize(image, width, height);
+    if channels == 1 {
+        let (luma, max): (Vec<f32>, f32) = match sample_depth(resized.color()) {
+            SampleDepth::U8 => (
+                resized.to_luma8().pixels().map(|p| p[0] as f32).collect(),
+                source_max.unwrap_or(255.0),
+            ),
+            SampleDepth::U16 => (
+                resized.to_luma16().pixels().map(|p| p[0] as f32).collect(),
+                source_max.unwrap_or(u16::MAX as f32),
+            ),
+            SampleDepth::F32 => (resized.to_luma32f().pixels().map(|p| p[0]).collect(), source_max.unwrap_or(1.0)),
+        };
+        let mean = stats.mean.iter().sum::<f32>() / 3.0;
+        let std = stats.std.iter().sum::<f32>() / 3.0;
+        return luma.into_iter().map(|v| ((v / max) - mean) / std).collect();
+    }
+    let plane = width as usize * height as usize;
+    let mut input_data = vec![0f32; 3 * plane];
+    match sample_depth(resized.color()) {
+        SampleDepth::U8 => {
+            let max = source_max.unwrap_or(255.0);
+            for (idx, p) in resized.to_rgb8().pixels().enumerate() {
+                write_normalized_pixel(&mut input_data, plane, idx, [p[0] as f32, p[1] as f32, p[2] as f32], max, stats);
+            }
+        }
+        SampleDepth::U16 => {
+            let max = source_max.unwrap_or(u16::MAX as f32);
+            for (idx, p) in resized.to_rgb16().pixels().enumerate() {
+                write_normalized_pixel(&mut input_data, plane, idx, [p[0] as f32, p[1] as f32, p[2] as f32], max, stats);
+            }
+        }
+        SampleDepth::F32 => {
+            let max = source_max.unwrap_or(1.0);
+            for (idx, p) in resized.to_rgb32f().pixels().enumerate() {
+                write_normalized_pixel(&mut input_data, plane, idx, [p[0], p[1], p[2]], max, stats);
+            }
+        }
+    }
+    input_data
+}
+
+fn write_normalized_pixel(input_data: &mut [f32], plane: usize, idx: usize, rgb: [f32; 3], max: f32, stats: &ChannelStats) {
+    for (c, value) in rgb.into_iter().enumerate() {
+        input_data[c * plane + idx] = ((value / max) - stats.mean[c]) / stats.std[c];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::ImageBuffer;
+    use unbg_core::decode_mask_rle;
+
+    fn png_bytes(image: &DynamicImage) -> Vec<u8> {
+        let mut png = Vec::new();
+        image.write_to(&mut std::io::Cursor::new(&mut png), ImageFormat::Png).unwrap();
+        png
+    }
+
+    #[test]
+    fn sixteen_bit_input_normalizes_by_full_range() {
+        let img = ImageBuffer::<Luma<u16>, Vec<u16>>::from_pixel(4, 4, Luma([49_151u16]));
+        let data = normalize_input(&DynamicImage::ImageLuma16(img), 4, 4, None, 3, &RMBG14_STATS);
+        assert_eq!(data.len(), 3 * 16);
+        assert!((data[0] - 0.25).abs() < 1e-4, "got {}", data[0]);
+        assert!((data[16] - 0.25).abs() < 1e-4);
+        assert!((data[32] - 0.25).abs() < 1e-4);
+    }
+
+    #[test]
+    fn ranking_lists_every_candidate_in_preference_order() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let onnx_dir = dir.path().join("onnx");
+        fs::create_dir_all(&onnx_dir).expect("create onnx dir");
+        for name in ["model.onnx", "model_fp16.onnx", "model_quantized.onnx", "other.onnx", "config.json"] {
+            fs::write(onnx_dir.join(name), b"x").expect("write candidate");
+        }
+
+        let ranked = rank_onnx_files(dir.path(), OnnxVariant::Quantized);
+        let names: Vec<String> = ranked
+            .iter()
+            .map(|f| f.path.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+
+        assert_eq!(names, vec!["model_quantized.onnx", "model_fp16.onnx", "model.onnx", "other.onnx"]);
+        assert_eq!(ranked.iter().map(|f| f.rank).collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn available_variants_lists_only_the_installed_ones() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let onnx_dir = dir.path().join("onnx");
+        fs::create_dir_all(&onnx_dir).expect("create onnx dir");
+        for name in ["model_quantized.onnx", "model_fp16.onnx", "config.json"] {
+            fs::write(onnx_dir.join(name), b"x").expect("write candidate");
+        }
+
+        assert_eq!(detect_available_variants(dir.path()), vec![OnnxVariant::Fp16, OnnxVariant::Quantized]);
+        assert_eq!(
+            find_preferred_onnx_file(dir.path(), OnnxVariant::Fp32),
+            Some(onnx_dir.join("model_fp16.onnx"))
+        );
+        assert!(detect_available_variants(&dir.path().join("missing")).is_empty());
+    }
+
+    #[test]
+    fn source_max_override_replaces_default_range() {
+        let img = ImageBuffer::<Luma<u16>, Vec<u16>>::from_pixel(4, 4, Luma([1_023u16]));
+        let data = normalize_input(&DynamicImage::ImageLuma16(img), 4, 4, Some(1_023.0), 3, &RMBG14_STATS);
+        assert!((data[0] - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn grayscale_png_fills_a_single_channel_tensor_for_one_channel_sessions() {
+        let gray = GrayImage::from_fn(4, 4, |x, _| Luma([if x < 2 { 0 } else { 255 }]));
+        let png = png_bytes(&DynamicImage::ImageLuma8(gray));
+        let image = image::load_from_memory(&png).unwrap();
+        let input = TensorSpec {
+            name: "input".to_string(),
+            element_type: "f32".to_string(),
+            shape: vec![-1, 1, 4, 4],
+            dimension_names: vec!["batch".to_string(), String::new(), String::new(), String::new()],
+        };
+        let spec = InputSpec {
+            width: 4,
+            height: 4,
+            source_max_value: None,
+            channels: input_channels(&input),
+        };
+
+        let prepared = RmbgPreprocessor.prepare(&image, &spec).unwrap();
+        assert_eq!(prepared.shape, [1, 1, 4, 4]);
+        assert_eq!(prepared.data.len(), 16);
+        assert_eq!(prepared.data[0], -0.5);
+        assert_eq!(prepared.data[3], 0.5);
+
+        let rgb_input = TensorSpec {
+            shape: vec![-1, 3, 4, 4],
+            ..input
+        };
+        let replicated = RmbgPreprocessor
+            .prepare(&image, &InputSpec { channels: input_channels(&rgb_input), ..spec })
+            .unwrap();
+        assert_eq!(replicated.shape, [1, 3, 4, 4]);
+        assert_eq!(&replicated.data[..16], &replicated.data[16..32]);
+        assert_eq!(&replicated.data[..16], &prepared.data[..]);
+    }
+
+    #[test]
+    fn rmbg20_default_preprocessing_uses_imagenet_stats() {
+        let image = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(1, 1, image::Rgb([255, 128, 0])));
+        let spec = InputSpec {
+            width: 1,
+            height: 1,
+            source_max_value: None,
+            channels: 3,
+        };
+        let backend = LocalOrtBackend::default();
+
+        let rmbg14 = backend.preprocessor_for(ModelKind::Rmbg14).prepare(&image, &spec).unwrap();
+        let rmbg20 = backend.preprocessor_for(ModelKind::Rmbg20).prepare(&image, &spec).unwrap();
+        assert_ne!(rmbg14.data, rmbg20.data);
+        assert!((rmbg14.data[0] - 0.5).abs() < 1e-6);
+        assert!((rmbg20.data[0] - (1.0 - 0.485) / 0.229).abs() < 1e-5);
+        assert!((rmbg20.data[1] - (128.0 / 255.0 - 0.456) / 0.224).abs() < 1e-5);
+        assert!((rmbg20.data[2] - (0.0 - 0.406) / 0.225).abs() < 1e-5);
+    }
+
+    #[derive(Debug, Default)]
+    struct ConstantPreprocessor {
+        calls: std::sync::atomic::AtomicUsize,
+    }
+
+    impl Preprocessor for ConstantPreprocessor {
+        fn prepare(&self, _image: &DynamicImage, spec: &InputSpec) -> Result<PreparedInput> {
+            self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(PreparedInput {
+                shape: [1, spec.height as usize, spec.width as usize, 3],
+                data: vec![0.75; 3 * (spec.width * spec.height) as usize],
+            })
+        }
+    }
+
+    #[test]
+    fn custom_preprocessor_replaces_default_for_its_model() {
+        let custom = Arc::new(ConstantPreprocessor::default());
+        let backend = LocalOrtBackend::default().with_preprocessor(ModelKind::Rmbg20, custom.clone());
+        let image = DynamicImage::new_rgb8(8, 8);
+        let spec = InputSpec {
+            width: 2,
+            height: 2,
+            source_max_value: None,
+            channels: 3,
+        };
+
+        let prepared = backend.preprocessor_for(ModelKind::Rmbg20).prepare(&image, &spec).unwrap();
+        assert_eq!(custom.calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert_eq!(prepared.shape, [1, 2, 2, 3]);
+        assert!(prepared.data.iter().all(|v| *v == 0.75));
+
+        let default = backend.preprocessor_for(ModelKind::Rmbg14).prepare(&image, &spec).unwrap();
+        assert_eq!(custom.calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert_eq!(default.shape, [1, 3, 2, 2]);
+        assert_eq!(default.data, normalize_input(&image, 2, 2, None, 3, &RMBG14_STATS));
+    }
+
+    #[derive(Debug)]
+    struct Threshold(f32);
+
+    impl Postprocessor for Threshold {
+        fn process(&self, mut mask: MaskBuffer, _request: &InferenceRequest) -> Result<MaskBuffer> {
+            for v in &mut mask.data {
+                *v = if *v >= self.0 { 1.0 } else { 0.0 };
+            }
+            Ok(mask)
+        }
+    }
+
+    #[derive(Debug)]
+    struct HorizontalBlur;
+
+    impl Postprocessor for HorizontalBlur {
+        fn process(&self, mut mask: MaskBuffer, _request: &InferenceRequest) -> Result<MaskBuffer> {
+            let src = mask.data.clone();
+            let w = mask.width as usize;
+            for (i, v) in mask.data.iter_mut().enumerate() {
+                let x = i % w;
+                let left = if x > 0 { src[i - 1] } else { src[i] };
+                let right = if x + 1 < w { src[i + 1] } else { src[i] };
+                *v = (left + src[i] + right) / 3.0;
+            }
+            Ok(mask)
+        }
+    }
+
+    fn sample_request() -> InferenceRequest {
+        InferenceRequest::builder()
+            .requested_model(ModelKind::Rmbg14)
+            .execution_provider(ExecutionProvider::Cpu)
+            .dimensions(4, 1)
+            .build()
+    }
+
+    #[test]
+    fn postprocess_stages_apply_in_order() {
+        let raw = MaskBuffer {
+            width: 4,
+            height: 1,
+            data: vec![-2.0, -2.0, 6.0, 6.0],
+        };
+        let request = sample_request();
+
+        let default = PostprocessChain::default().run(raw.clone(), &request).unwrap();
+        assert_eq!(default.data, vec![0.0, 0.0, 1.0, 1.0]);
+
+        let threshold_then_blur = PostprocessChain::default()
+            .then(Arc::new(Threshold(0.5)))
+            .then(Arc::new(HorizontalBlur))
+            .run(raw.clone(), &request)
+            .unwrap();
+        let blur_then_threshold = PostprocessChain::default()
+            .then(Arc::new(HorizontalBlur))
+            .then(Arc::new(Threshold(0.5)))
+            .run(raw, &request)
+            .unwrap();
+
+        assert!((threshold_then_blur.data[1] - 1.0 / 3.0).abs() < 1e-6);
+        assert_eq!(blur_then_threshold.data, vec![0.0, 0.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn ort_log_level_defaults_to_warning_and_honors_override() {
+        assert_eq!(ort_log_level(None), LogLevel::Warning);
+        assert_eq!(ort_log_level(Some("bogus")), LogLevel::Warning);
+        assert_eq!(ort_log_level(Some(" Verbose ")), LogLevel::Verbose);
+        assert_eq!(ort_log_level(Some("error")), LogLevel::Error);
+    }
+
+    #[test]
+    fn self_test_fails_loudly_without_installed_model() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let err = self_test_in(ModelKind::Auto, ExecutionProvider::Cpu, Some(dir.path())).unwrap_err();
+        assert!(
+            matches!(&err, CoreError::ModelNotInstalled { model_id } if model_id == KnownModel::Rmbg14.model_id()),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn warmup_fails_without_installed_model() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let request = InferenceRequest::builder()
+            .execution_provider(ExecutionProvider::Cpu)
+            .model_dir(Some(dir.path().to_path_buf()))
+            .build();
+        let backend = LocalOrtBackend::default();
+        let err = backend.warmup(ModelKind::Auto, &request).unwrap_err();
+        assert!(err.to_string().contains(KnownModel::Rmbg14.model_id()), "{}", err);
+        assert_eq!(backend.last_provider(), None);
+    }
+
+    #[test]
+    fn postprocess_defaults_depend_on_model_unless_the_request_sets_one() {
+        let backend = LocalOrtBackend::default();
+        let request = sample_request();
+        let probabilities = MaskBuffer {
+            width: 3,
+            height: 1,
+            data: vec![0.2, 0.4, 0.6],
+        };
+        let logits = MaskBuffer {
+            width: 2,
+            height: 1,
+            data: vec![-4.0, 0.0],
+        };
+
+        let rmbg14 = backend.postprocess_chain_for(ModelKind::Rmbg14);
+        let stretched = rmbg14.run(probabilities.clone(), &request).unwrap();
+        assert!((stretched.data[0]).abs() < 1e-6 && (stretched.data[2] - 1.0).abs() < 1e-6);
+        let sigmoid = InferenceRequest::builder().mask_activation(Some(MaskActivation::Sigmoid)).build();
+        assert_eq!(rmbg14.run(probabilities.clone(), &sigmoid).unwrap().data, probabilities.data);
+
+        let rmbg20 = backend.postprocess_chain_for(ModelKind::Rmbg20);
+        assert_eq!(rmbg20.run(probabilities.clone(), &request).unwrap().data, probabilities.data);
+        let activated = rmbg20.run(logits.clone(), &request).unwrap();
+        assert!((activated.data[0] - 0.017_986).abs() < 1e-4 && (activated.data[1] - 0.5).abs() < 1e-6);
+        let clamp = InferenceRequest::builder().mask_activation(Some(MaskActivation::Clamp)).build();
+        assert_eq!(rmbg20.run(logits, &clamp).unwrap().data, vec![0.0, 0.0]);
+
+        let custom = backend.with_postprocess_chain(ModelKind::Rmbg20, PostprocessChain::default());
+        let overridden = custom.postprocess_chain_for(ModelKind::Rmbg20).run(probabilities, &request).unwrap();
+        assert!((overridden.data[2] - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn constant_logits_give_a_uniform_mask_instead_of_zeros() {
+        let raw = MaskBuffer {
+            width: 4,
+            height: 1,
+            data: vec![2.0; 4],
+        };
+        let chain = PostprocessChain::default_for(ModelKind::Rmbg20);
+
+        let activated = chain.run(raw.clone(), &sample_request()).unwrap();
+        assert!(activated.data.iter().all(|v| (v - 0.880_797).abs() < 1e-5), "{:?}", activated.data);
+
+        let min_max = InferenceRequest::builder().mask_activation(Some(MaskActivation::MinMax)).build();
+        assert_eq!(chain.run(raw, &min_max).unwrap().data, vec![0.0; 4]);
+    }
+
+    #[test]
+    fn sigmoid_or_clamp_activates_real_range_logits() {
+        let raw = MaskBuffer {
+            width: 5,
+            height: 1,
+            data: vec![-12.5, -3.0, 0.0, 3.0, 9.75],
+        };
+
+        let activated = SigmoidOrClamp.process(raw.clone(), &sample_request()).unwrap();
+        for (got, logit) in activated.data.iter().zip(&raw.data) {
+            let expected = 1.0 / (1.0 + (-logit).exp());
+            assert!((got - expected).abs() < 1e-6, "{} -> {}", logit, got);
+        }
+        assert!(activated.data[0] < 1e-5 && activated.data[4] > 0.9999);
+        assert!((activated.data[1] - 0.047_426).abs() < 1e-5 && (activated.data[3] - 0.952_574).abs() < 1e-5);
+    }
+
+    #[test]
+    fn low_confidence_output_is_not_stretched() {
+        let raw = MaskBuffer {
+            width: 3,
+            height: 1,
+            data: vec![0.49, 0.5, 0.51],
+        };
+        let mut request = sample_request();
+
+        let stretched = PostprocessChain::default().run(raw.clone(), &request).unwrap();
+        assert_eq!(stretched.data, vec![0.0, 0.5, 1.0]);
+
+        request.low_confidence_range = Some(0.1);
+        let kept = PostprocessChain::default().run(raw, &request).unwrap();
+        assert_eq!(kept.data, vec![0.49, 0.5, 0.51]);
+    }
+
+    #[test]
+    fn requested_revision_overrides_lockfile_revision() {
+        let dir = tempfile::tempdir().expect("model dir");
+        let paths = resolve_model_paths(Some(dir.path())).expect("paths");
+        unbg_model_registry::write_lockfile(
+            &paths,
+            &unbg_model_registry::ModelLock {
+                schema_version: unbg_model_registry::SCHEMA_VERSION,
+                generated_at: "1".to_string(),
+                models: vec![unbg_model_registry::LockModel {
+                    model_id: KnownModel::Rmbg14.model_id().to_string(),
+                    revision: "main".to_string(),
+                    source: "huggingface".to_string(),
+                    files: vec![],
+                }],
+            },
+        )
+        .expect("write lockfile");
+        for revision in ["main", "candidate"] {
+            let onnx_dir = model_revision_dir(&paths, KnownModel::Rmbg14, revision).join("onnx");
+            fs::create_dir_all(&onnx_dir).expect("onnx dir");
+            fs::write(onnx_dir.join("model_fp16.onnx"), b"x").expect("onnx file");
+        }
+
+        let mut request = InferenceRequest::builder().model_dir(Some(dir.path().to_path_buf())).build();
+        let from_lock = resolve_model_onnx_file(&request, ModelKind::Rmbg14).expect("lockfile revision");
+        assert!(from_lock.starts_with(model_revision_dir(&paths, KnownModel::Rmbg14, "main")));
+
+        request.revision = Some("candidate".to_string());
+        let picked = resolve_model_onnx_file(&request, ModelKind::Rmbg14).expect("requested revision");
+        assert!(picked.starts_with(model_revision_dir(&paths, KnownModel::Rmbg14, "candidate")));
+
+        request.revision = Some("missing".to_string());
+        let err = resolve_model_onnx_file(&request, ModelKind::Rmbg14).unwrap_err();
+        assert!(matches!(&err, CoreError::OnnxFileMissing { revision, .. } if revision == "missing"), "{}", err);
+    }
+
+    #[test]
+    fn missing_models_resolve_to_structured_errors() {
+        let dir = tempfile::tempdir().expect("model dir");
+        let request = InferenceRequest::builder().model_dir(Some(dir.path().to_path_buf())).build();
+        let err = resolve_model_onnx_file(&request, ModelKind::Rmbg14).unwrap_err();
+        assert!(
+            matches!(&err, CoreError::ModelNotInstalled { model_id } if model_id == KnownModel::Rmbg14.model_id()),
+            "{}",
+            err
+        );
+        assert_eq!(err.as_error_info().code, unbg_core::ErrorCode::ModelNotInstalled);
+
+        let paths = resolve_model_paths(Some(dir.path())).expect("paths");
+        unbg_model_registry::write_lockfile(
+            &paths,
+            &unbg_model_registry::ModelLock {
+                schema_version: unbg_model_registry::SCHEMA_VERSION,
+                generated_at: "1".to_string(),
+                models: vec![unbg_model_registry::LockModel {
+                    model_id: KnownModel::Rmbg14.model_id().to_string(),
+                    revision: "main".to_string(),
+                    source: "huggingface".to_string(),
+                    files: vec![],
+                }],
+            },
+        )
+        .expect("write lockfile");
+        fs::create_dir_all(model_revision_dir(&paths, KnownModel::Rmbg14, "main").join("onnx")).expect("onnx dir");
+        let err = resolve_model_onnx_file(&request, ModelKind::Rmbg14).unwrap_err();
+        assert!(
+            matches!(&err, CoreError::OnnxFileMissing { model_id, revision }
+                if model_id == KnownModel::Rmbg14.model_id() && revision == "main"),
+            "{}",
+            err
+        );
+        assert_eq!(err.as_error_info().code, unbg_core::ErrorCode::OnnxFileMissing);
+        assert!(err.to_string().contains("revision main"), "{}", err);
+    }
+
+    #[test]
+    fn custom_model_resolves_its_own_file_and_normalization() {
+        let dir = tempfile::tempdir().expect("model dir");
+        let paths = resolve_model_paths(Some(dir.path())).expect("paths");
+        let spec = CustomModel {
+            id: "acme/product-matting".to_string(),
+            revision: "v2".to_string(),
+            input_size: 512,
+            normalization: Normalization::IMAGENET,
+        };
+        unbg_model_registry::register_custom_model(&paths, spec.clone()).expect("register");
+        unbg_model_registry::write_lockfile(
+            &paths,
+            &unbg_model_registry::merge_lock_models(
+                None,
+                vec![unbg_model_registry::LockModel {
+                    model_id: spec.id.clone(),
+                    revision: "v2".to_string(),
+                    source: "huggingface".to_string(),
+                    files: vec![],
+                }],
+                "1".to_string(),
+            ),
+        )
+        .expect("write lockfile");
+        let onnx_dir = unbg_model_registry::model_id_revision_dir(&paths, &spec.id, "v2").join("onnx");
+        fs::create_dir_all(&onnx_dir).expect("onnx dir");
+        fs::write(onnx_dir.join("model_fp16.onnx"), b"x").expect("onnx file");
+
+        let request = InferenceRequest::builder()
+            .model_dir(Some(dir.path().to_path_buf()))
+            .custom_model(Some(spec.id.clone()))
+            .build();
+        assert_eq!(custom_model_spec(&request).unwrap(), Some(spec));
+        assert_eq!(
+            resolve_model_onnx_file(&request, ModelKind::Rmbg14).unwrap(),
+            onnx_dir.join("model_fp16.onnx")
+        );
+        assert_eq!(fallback_model(&request, ModelKind::Rmbg20), None);
+        let mut result = InferenceResult::new(ModelKind::Rmbg14, Vec::new(), 1, 1, "cpu");
+        resolve_model(&request, ModelKind::Rmbg14).unwrap().stamp(&mut result);
+        assert_eq!(result.custom_model_used.as_deref(), Some("acme/product-matting"));
+        let rmbg = InferenceRequest::builder().model_dir(Some(dir.path().to_path_buf())).build();
+        assert_ne!(provider_cache_key(ModelKind::Rmbg14, &request), provider_cache_key(ModelKind::Rmbg14, &rmbg));
+        assert_eq!(run_model_label(ModelKind::Rmbg14, &request), "acme/product-matting");
+
+        let escaping = InferenceRequest::builder()
+            .model_dir(Some(dir.path().to_path_buf()))
+            .revision(Some("../../elsewhere".to_string()))
+            .build();
+        assert!(matches!(resolve_model(&escaping, ModelKind::Rmbg14), Err(CoreError::InvalidArgument(_))));
+
+        let image = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(1, 1, image::Rgb([255, 128, 0])));
+        let input = InputSpec {
+            width: 1,
+            height: 1,
+            source_max_value: None,
+            channels: 3,
+        };
+        let custom = NormalizedPreprocessor(Normalization::IMAGENET).prepare(&image, &input).unwrap();
+        assert_eq!(custom.data, Rmbg20Preprocessor.prepare(&image, &input).unwrap().data);
+
+        let unknown = InferenceRequest::builder()
+            .model_dir(Some(dir.path().to_path_buf()))
+            .custom_model(Some("acme/missing".to_string()))
+            .build();
+        assert!(custom_model_spec(&unknown).is_err());
+        assert!(resolve_model_onnx_file(&unknown, ModelKind::Rmbg14).is_err());
+    }
+
+    #[test]
+    fn dimension_overrides_get_their_own_cached_session() {
+        let model = Path::new("model_fp16.onnx");
+        let tuning = SessionTuning::default();
+        let plain = session_cache_key(model, ProviderChoice::Cpu, &[], tuning);
+        let pinned = session_cache_key(
+            model,
+            ProviderChoice::Cpu,
+            &[("batch".to_string(), 1), ("height".to_string(), 1024)],
+            tuning,
+        );
+        let reordered = session_cache_key(
+            model,
+            ProviderChoice::Cpu,
+            &[("height".to_string(), 1024), ("batch".to_string(), 1)],
+            tuning,
+        );
+        assert_ne!(plain, pinned);
+        assert_eq!(pinned, reordered);
+        assert!(pinned.ends_with("batch=1,height=1024"));
+    }
+
+    #[test]
+    fn session_tuning_gets_its_own_cached_session() {
+        let model = Path::new("model_fp16.onnx");
+        let default = session_cache_key(model, ProviderChoice::Cpu, &[], SessionTuning::default());
+        let capped = SessionTuning {
+            intra_threads: Some(2),
+            ..SessionTuning::default()
+        };
+        let unoptimized = SessionTuning {
+            graph_opt: GraphOptLevel::Disable,
+            ..SessionTuning::default()
+        };
+        let capped_key = session_cache_key(model, ProviderChoice::Cpu, &[], capped);
+        let unoptimized_key = session_cache_key(model, ProviderChoice::Cpu, &[], unoptimized);
+        assert_ne!(default, capped_key);
+        assert_ne!(default, unoptimized_key);
+        assert_ne!(capped_key, unoptimized_key);
+        assert_eq!(ort_optimization_level(GraphOptLevel::All), None);
+        assert_eq!(ort_optimization_level(GraphOptLevel::Basic), Some(GraphOptimizationLevel::Level1));
+    }
+
+    #[test]
+    fn empty_and_full_masks_follow_the_empty_mask_policy() {
+        let backend = LocalOrtBackend::default();
+        let black = backend.infer_fallback(ModelKind::Rmbg14, DynamicImage::new_rgb8(8, 6), &InferenceRequest::default()).unwrap();
+        let white = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(8, 6, image::Rgb([255, 255, 255])));
+        let white = backend.infer_fallback(ModelKind::Rmbg14, white, &InferenceRequest::default()).unwrap();
+        let alpha = |result: &InferenceResult| image::load_from_memory(&result.mask_png).unwrap().to_luma8().into_raw();
+        assert!(alpha(&black).iter().all(|&v| v == 0));
+        assert!(alpha(&white).iter().all(|&v| v == 255));
+
+        let mut request = InferenceRequest::default();
+        let mut flagged = black.clone();
+        apply_empty_mask_policy(&mut flagged, &request).unwrap();
+        assert_eq!(flagged.mask_coverage, Some(MaskCoverage::Empty));
+        assert_eq!(flagged.mask_png, black.mask_png);
+        let mut flagged = white.clone();
+        apply_empty_mask_policy(&mut flagged, &request).unwrap();
+        assert_eq!(flagged.mask_coverage, Some(MaskCoverage::Full));
+
+        request.on_empty_mask = EmptyMaskPolicy::Error;
+        let err = apply_empty_mask_policy(&mut black.clone(), &request).unwrap_err();
+        assert!(err.to_string().contains("entirely background"), "{}", err);
+        let err = apply_empty_mask_policy(&mut white.clone(), &request).unwrap_err();
+        assert!(err.to_string().contains("entirely foreground"), "{}", err);
+
+        request.on_empty_mask = EmptyMaskPolicy::Passthrough;
+        let mut passed = black.clone();
+        apply_empty_mask_policy(&mut passed, &request).unwrap();
+        assert_eq!(passed.mask_coverage, Some(MaskCoverage::Empty));
+        assert_eq!(alpha(&passed).len(), 8 * 6);
+        assert!(alpha(&passed).iter().all(|&v| v == 255));
+
+        assert_eq!(passed.foreground_ratio, Some(0.0), "the ratio describes the model's mask");
+
+        let mut mixed = black.clone();
+        let half = GrayImage::from_fn(8, 6, |x, _| Luma([if x < 4 { 0 } else { 255 }]));
+        store_mask(&mut mixed, half, &request).unwrap();
+        apply_empty_mask_policy(&mut mixed, &request).unwrap();
+        assert_eq!(mixed.mask_coverage, None);
+        assert_eq!(mixed.foreground_ratio, Some(0.5));
+    }
+
+    struct CountingBackend {
+        calls: std::sync::atomic::AtomicUsize,
+    }
+
+    impl InferenceBackend for CountingBackend {
+        fn infer(&self, request: &InferenceRequest, selected_model: ModelKind) -> Result<InferenceResult, CoreError> {
+            self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            let mut result = InferenceResult::new(selected_model, vec![7, 7, 7], request.width, request.height, "cpu");
+            result.timings = request.collect_timings.then(InferenceTimings::default);
+            // A single zero byte stands in for an input the model sees as all background.
+            if request.input_bytes.as_deref() == Some(&[0][..]) {
+                result.foreground_ratio = Some(0.0);
+            }
+            Ok(result)
+        }
+    }
+
+    fn counting_backend() -> CountingBackend {
+        CountingBackend {
+            calls: std::sync::atomic::AtomicUsize::new(0),
+        }
+    }
+
+    #[test]
+    fn repeated_input_is_served_from_mask_cache() {
+        let cached = CachedBackend::new(
+            counting_backend(),
+            MaskCacheConfig {
+                capacity: 4,
+                persist_dir: None,
+            },
+        );
+        let request = InferenceRequest::builder()
+            .input_bytes(vec![1, 2, 3])
+            .dimensions(2, 2)
+            .collect_timings(true)
+            .build();
+
+        let first = cached.infer(&request, ModelKind::Rmbg14).unwrap();
+        let second = cached.infer(&request, ModelKind::Rmbg14).unwrap();
+        assert_eq!(cached.inner.calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert_eq!(first.mask_png, second.mask_png);
+        assert!(first.timings.is_some());
+        assert_eq!(second.timings, None, "a cache hit has no timings of its own");
+
+        let mut quantized = request.clone();
+        quantized.onnx_variant = OnnxVariant::Quantized;
+        cached.infer(&quantized, ModelKind::Rmbg14).unwrap();
+        cached.infer(&request, ModelKind::Rmbg20).unwrap();
+        assert_eq!(cached.inner.calls.load(std::sync::atomic::Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn cache_hits_follow_the_empty_mask_policy_of_the_request() {
+        let cached = CachedBackend::new(
+            counting_backend(),
+            MaskCacheConfig {
+                capacity: 4,
+                persist_dir: None,
+            },
+        );
+        let mut request = InferenceRequest::builder().input_bytes(vec![0]).build();
+        cached.infer(&request, ModelKind::Rmbg14).unwrap();
+
+        request.on_empty_mask = EmptyMaskPolicy::Error;
+        let err = cached.infer(&request, ModelKind::Rmbg14).unwrap_err();
+        assert!(err.to_string().contains("entirely background"), "{}", err);
+        request.on_empty_mask = EmptyMaskPolicy::Flag;
+        let hit = cached.infer(&request, ModelKind::Rmbg14).unwrap();
+        assert_eq!(hit.mask_coverage, Some(MaskCoverage::Empty));
+        assert_eq!(cached.inner.calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn mask_cache_evicts_least_recently_used_and_persists() {
+        let dir = tempfile::tempdir().expect("cache dir");
+        let config = MaskCacheConfig {
+            capacity: 1,
+            persist_dir: Some(dir.path().to_path_buf()),
+        };
+        let a = InferenceRequest::builder().input_bytes(vec![1]).build();
+        let b = InferenceRequest::builder().input_bytes(vec![2]).build();
+
+        let cached = CachedBackend::new(counting_backend(), config.clone());
+        cached.infer(&a, ModelKind::Rmbg14).unwrap();
+        cached.infer(&b, ModelKind::Rmbg14).unwrap();
+        assert_eq!(cached.entries.lock().unwrap().len(), 1);
+
+        let reopened = CachedBackend::new(counting_backend(), config);
+        let restored = reopened.infer(&a, ModelKind::Rmbg14).unwrap();
+        assert_eq!(restored.mask_png, vec![7, 7, 7]);
+        assert_eq!(reopened.inner.calls.load(std::sync::atomic::Ordering::SeqCst), 0);
+
+        let names: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert_eq!(names.len(), 4, "{:?}", names);
+        assert!(names.iter().all(|name| !name.ends_with(".tmp")), "{:?}", names);
+    }
+
+    #[test]
+    fn mask_cache_key_covers_the_mask_options_only() {
+        let request = InferenceRequest::builder().input_bytes(vec![1, 2, 3]).build();
+        let key = mask_cache_key(&request, ModelKind::Rmbg14).unwrap();
+
+        let mut retimed = request.clone();
+        retimed.collect_timings = true;
+        retimed.inference_retries = 3;
+        retimed.on_empty_mask = EmptyMaskPolicy::Flag;
+        retimed.input_path = Some(PathBuf::from("photo.png"));
+        assert_eq!(mask_cache_key(&retimed, ModelKind::Rmbg14).unwrap(), key);
+
+        let mut thresholded = request.clone();
+        thresholded.mask_threshold = Some(0.5);
+        assert_ne!(mask_cache_key(&thresholded, ModelKind::Rmbg14).unwrap(), key);
+        let mut curved = request.clone();
+        curved.alpha_curve = Some(vec![(0.0, 0.0), (1.0, 1.0)]);
+        assert_ne!(mask_cache_key(&curved, ModelKind::Rmbg14).unwrap(), key);
+
+        // A path input is read once by the cache and handed on as bytes.
+        let dir = tempfile::tempdir().expect("input dir");
+        let path = dir.path().join("empty.png");
+        fs::write(&path, [0]).unwrap();
+        let cached = CachedBackend::new(
+            counting_backend(),
+            MaskCacheConfig {
+                capacity: 4,
+                persist_dir: None,
+            },
+        );
+        let from_path = InferenceRequest::builder().input_path(path).build();
+        let result = cached.infer(&from_path, ModelKind::Rmbg14).unwrap();
+        assert_eq!(result.foreground_ratio, Some(0.0), "the inner backend saw the file's bytes");
+        let from_bytes = InferenceRequest::builder().input_bytes(vec![0]).build();
+        cached.infer(&from_bytes, ModelKind::Rmbg14).unwrap();
+        assert_eq!(cached.inner.calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn recorded_run_time_predicts_the_next_run() {
+        let dir = tempfile::tempdir().expect("model dir");
+        let model_dir = Some(dir.path());
+        let size = ImageSize { width: 1600, height: 1200 };
+        assert_eq!(estimate_inference_ms(ModelKind::Rmbg14, "cpu", size, model_dir), None);
+
+        record_inference_ms("rmbg14", ProviderChoice::Cpu, size, 840, model_dir);
+        assert_eq!(estimate_inference_ms(ModelKind::Rmbg14, "cpu", size, model_dir), Some(840));
+        record_inference_ms("rmbg14", ProviderChoice::Cpu, size, 860, model_dir);
+        assert_eq!(estimate_inference_ms(ModelKind::Rmbg14, "cpu", size, model_dir), Some(850));
+
+        // A file that does not parse is kept rather than overwritten with a fresh history.
+        let history_path = timing_history_file(model_dir).unwrap();
+        let saved = fs::read_to_string(&history_path).unwrap();
+        fs::write(&history_path, &saved[..saved.len() / 2]).unwrap();
+        record_inference_ms("rmbg14", ProviderChoice::Cpu, size, 10, model_dir);
+        assert_eq!(fs::read_to_string(&history_path).unwrap(), saved[..saved.len() / 2]);
+        fs::write(&history_path, saved).unwrap();
+
+        // Unrecorded sizes borrow the closest bucket; other models and providers have no data.
+        let small = ImageSize { width: 640, height: 480 };
+        assert_eq!(estimate_inference_ms(ModelKind::Rmbg14, "cpu", small, model_dir), Some(850));
+        assert_eq!(estimate_inference_ms(ModelKind::Rmbg20, "cpu", size, model_dir), None);
+        assert_eq!(estimate_inference_ms(ModelKind::Rmbg14, "cuda", size, model_dir), None);
+        assert_eq!(estimate_inference_ms(ModelKind::Rmbg14, "tpu", size, model_dir), None);
+    }
+
+    #[test]
+    fn second_lookup_on_same_thread_hits_session_cache() {
+        let before = session_cache_stats();
+        let cache = SessionCache::<u32>::default();
+        let mut builds = 0;
+        for _ in 0..2 {
+            let session = cache
+                .with_session(
+                    "model.onnx|cpu",
+                    || {
+                        builds += 1;
+                        Ok(7)
+                    },
+                    |session| Ok(*session),
+                )
+                .unwrap();
+            assert_eq!(session, 7);
+        }
+        assert_eq!(builds, 1);
+        assert_eq!(cache.stats(), SessionCacheStats { hits: 1, misses: 1 });
+        let after = session_cache_stats();
+        assert!(after.hits > before.hits && after.misses > before.misses);
+    }
+
+    #[test]
+    fn threads_sharing_the_cache_reuse_one_session() {
+        let cache = SessionCache::<Arc<u32>>::default();
+        let builds = AtomicU64::new(0);
+        let handles: Vec<Arc<u32>> = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..2)
+                .map(|_| {
+                    scope.spawn(|| {
+                        cache
+                            .with_session(
+                                "model.onnx|cpu",
+                                || {
+                                    builds.fetch_add(1, Ordering::SeqCst);
+                                    Ok(Arc::new(7))
+                                },
+                                |session| Ok(session.clone()),
+                            )
+                            .unwrap()
+                    })
+                })
+                .collect();
+            workers.into_iter().map(|worker| worker.join().unwrap()).collect()
+        });
+        assert_eq!(builds.load(Ordering::SeqCst), 1);
+        assert!(Arc::ptr_eq(&handles[0], &handles[1]));
+        assert_eq!(cache.stats(), SessionCacheStats { hits: 1, misses: 1 });
+
+        cache.remove("model.onnx|cpu");
+        let rebuilt = cache.with_session("model.onnx|cpu", || Ok(Arc::new(8)), |session| Ok(session.clone())).unwrap();
+        assert_eq!(*rebuilt, 8);
+    }
+
+    #[test]
+    fn provider_cache_key_separates_gpu_backend_preferences() {
+        let mut request = InferenceRequest::default();
+        request.gpu_backend = GpuBackendPreference::Cuda;
+        let cuda = provider_cache_key(ModelKind::Rmbg14, &request);
+        request.gpu_backend = GpuBackendPreference::DirectML;
+        let directml = provider_cache_key(ModelKind::Rmbg14, &request);
+        assert_ne!(cuda, directml);
+        assert!(cuda.starts_with("v2|rmbg14|fp16|cuda|"), "{}", cuda);
+
+        let dir = tempfile::tempdir().unwrap();
+        let cache_file = provider_cache_file(Some(dir.path())).unwrap();
+        fs::create_dir_all(cache_file.parent().unwrap()).unwrap();
+        let legacy_key = cuda.trim_start_matches("v2|").replacen("|cuda", "", 1);
+        fs::write(&cache_file, serde_json::json!({ "providers": { legacy_key: "cuda" } }).to_string()).unwrap();
+        assert_eq!(load_cached_provider(&cuda, Some(dir.path())), None);
+    }
+
+    #[test]
+    fn each_run_appends_a_history_entry() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let log = dir.path().join("history").join("runs.jsonl");
+        // The models dir is empty, so the run fails unless the placeholder mask stands in.
+        let backend = LocalOrtBackend::default().with_run_history(log.clone());
+        let input = dir.path().join("input.png");
+        fs::write(&input, synthetic_png(8, 6).unwrap()).unwrap();
+        let request = InferenceRequest::builder()
+            .requested_model(ModelKind::Rmbg14)
+            .execution_provider(ExecutionProvider::Cpu)
+            .input_path(input.clone())
+            .model_dir(Some(dir.path().to_path_buf()))
+            .dimensions(8, 6)
+            .build();
+        let err = backend
+            .clone()
+            .with_placeholder_fallback(false)
+            .infer(&request, ModelKind::Rmbg14)
+            .unwrap_err();
+        let result = backend.with_placeholder_fallback(true).infer(&request, ModelKind::Rmbg14).unwrap();
+
+        let lines = fs::read_to_string(&log).unwrap();
+        assert_eq!(lines.lines().count(), 2);
+        let entries: Vec<RunHistoryEntry> = read_run_history(&log).unwrap().collect();
+        assert_eq!(entries.len(), 2);
+        let (failed, entry) = (&entries[0], &entries[1]);
+        assert_eq!(failed.error.as_deref(), Some(err.to_string().as_str()));
+        assert_eq!(failed.provider, None);
+        assert_eq!(failed.input_sha256, None, "a failed file input is not read again for the log");
+        assert_eq!(entry.model, ModelKind::Rmbg14);
+        assert_eq!(entry.onnx_variant, OnnxVariant::Fp16);
+        assert_eq!(entry.error, None);
+        assert_eq!(entry.provider.as_deref(), Some(result.execution_provider_selected.as_str()));
+        assert_eq!(entry.input_path.as_deref(), Some(input.as_path()));
+        assert_eq!(
+            entry.input_sha256.as_deref(),
+            Some(hex::encode(Sha256::digest(fs::read(&input).unwrap())).as_str())
+        );
+        assert!(failed.timestamp_ms > 0 && failed.timestamp_ms <= entry.timestamp_ms);
+
+        fs::write(&log, format!("{}{{\"truncated\n", lines)).unwrap();
+        assert_eq!(read_run_history(&log).unwrap().count(), 2);
+        assert_eq!(read_run_history(&dir.path().join("missing.jsonl")).unwrap().count(), 0);
+        assert_eq!(run_history_file(Some("off"), Some(dir.path())), None);
+        assert_eq!(run_history_file(None, Some(dir.path())), None);
+        assert_eq!(run_history_file(Some("/tmp/runs.jsonl"), None), Some(PathBuf::from("/tmp/runs.jsonl")));
+    }
+
+    #[test]
+    fn failed_batches_leave_an_entry_per_input() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let log = dir.path().join("runs.jsonl");
+        let backend = LocalOrtBackend::default().with_run_history(log.clone());
+        let requests: Vec<InferenceRequest> = [vec![1], vec![2]]
+            .into_iter()
+            .map(|bytes| {
+                InferenceRequest::builder()
+                    .input_bytes(bytes)
+                    .model_dir(Some(dir.path().to_path_buf()))
+                    .dimensions(8, 6)
+                    .build()
+            })
+            .collect();
+        let err = backend
+            .infer_batch_with(&requests, ModelKind::Rmbg14, BatchOptions::default())
+            .unwrap_err();
+
+        let entries: Vec<RunHistoryEntry> = read_run_history(&log).unwrap().collect();
+        assert_eq!(entries.len(), 2);
+        for (entry, request) in entries.iter().zip(&requests) {
+            assert_eq!(entry.error.as_deref(), Some(err.to_string().as_str()));
+            assert_eq!(
+                entry.input_sha256.as_deref(),
+                Some(hex::encode(Sha256::digest(request.input_bytes.as_ref().unwrap())).as_str())
+            );
+        }
+    }
+
+    #[test]
+    fn provider_cache_lookups_are_counted() {
+        let dir = tempfile::tempdir().unwrap();
+        let before = cache_stats().provider;
+        assert_eq!(load_cached_provider("stats-test|missing", Some(dir.path())), None);
+        persist_cached_provider("stats-test|cpu", ProviderChoice::Cpu, Some(dir.path()));
+        for _ in 0..2 {
+            assert_eq!(load_cached_provider("stats-test|cpu", Some(dir.path())), Some(ProviderChoice::Cpu));
+        }
+        let after = cache_stats().provider;
+        assert!(after.hits >= before.hits + 2, "{:?} -> {:?}", before, after);
+        assert!(after.misses > before.misses, "{:?} -> {:?}", before, after);
+    }
+
+    #[test]
+    fn ensemble_blends_member_masks() {
+        let mask = |value: u8| png_bytes(&DynamicImage::ImageLuma8(GrayImage::from_pixel(2, 1, Luma([value]))));
+        let run = |_: &InferenceRequest, model: ModelKind| -> Result<InferenceResult, CoreError> {
+            Ok(InferenceResult::new(model, mask(if model == ModelKind::Rmbg14 { 100 } else { 200 }), 2, 1, "cpu"))
+        };
+        let request = sample_request();
+        let blended = |mode| {
+            let result = infer_ensemble(&request, mode, run).unwrap();
+            assert!(result.ensemble_used);
+            image::load_from_memory(&result.mask_png).unwrap().to_luma8().get_pixel(0, 0)[0]
+        };
+        assert_eq!(blended(EnsembleMode::Weighted { weight: 0.75 }), 175);
+        assert_eq!(blended(EnsembleMode::Average), 150);
+        assert_eq!(blended(EnsembleMode::Max), 200);
+
+        let only_fast = |member: &InferenceRequest, model: ModelKind| match model {
+            ModelKind::Rmbg20 => Err(CoreError::Backend("rmbg-2.0 is not installed".to_string())),
+            _ => run(member, model),
+        };
+        let result = infer_ensemble(&request, EnsembleMode::Average, only_fast).unwrap();
+        assert!(result.model_fallback_used && !result.ensemble_used);
+        assert_eq!(result.model_used, ModelKind::Rmbg14);
+        let mut strict = request.clone();
+        strict.strict_model = true;
+        assert!(infer_ensemble(&strict, EnsembleMode::Average, only_fast).is_err());
+    }
+
+    #[test]
+    fn auto_falls_back_to_rmbg14_only_when_it_is_installed() {
+        let backend = LocalOrtBackend::default();
+        let missing = InferenceRequest::builder()
+            .input_bytes(synthetic_png(8, 8).unwrap())
+            .model_dir(Some(PathBuf::from("/nonexistent/unbg-models")))
+            .build();
+        // Nothing to fall back to, so the placeholder stands in for the selected model.
+        assert_eq!(fallback_model(&missing, ModelKind::Rmbg20), None);
+        let result = backend.infer_with(&missing, ModelKind::Rmbg20, true, None).unwrap();
+        assert_eq!(result.model_used, ModelKind::Rmbg20);
+        assert!(!result.model_fallback_used);
+
+        let mut strict = missing.clone();
+        strict.strict_model = true;
+        let result = backend.infer_with(&strict, ModelKind::Rmbg20, true, None).unwrap();
+        assert_eq!(result.model_used, ModelKind::Rmbg20);
+        assert!(!result.model_fallback_used);
+        assert!(backend.infer_with(&strict, ModelKind::Rmbg20, false, None).is_err());
+
+        // Once RMBG-1.4 resolves it is the fallback, but never for a strict or explicit request.
+        let dir = tempfile::tempdir().expect("model dir");
+        let paths = resolve_model_paths(Some(dir.path())).expect("paths");
+        unbg_model_registry::write_lockfile(
+            &paths,
+            &unbg_model_registry::merge_lock_models(
+                None,
+                vec![unbg_model_registry::LockModel {
+                    model_id: KnownModel::Rmbg14.model_id().to_string(),
+                    revision: "main".to_string(),
+                    source: "huggingface".to_string(),
+                    files: vec![],
+                }],
+                "1".to_string(),
+            ),
+        )
+        .expect("write lockfile");
+        let onnx_dir = model_revision_dir(&paths, KnownModel::Rmbg14, "main").join("onnx");
+        fs::create_dir_all(&onnx_dir).expect("onnx dir");
+        fs::write(onnx_dir.join("model_fp16.onnx"), b"x").expect("onnx file");
+        let mut request = missing;
+        request.model_dir = Some(dir.path().to_path_buf());
+        assert_eq!(fallback_model(&request, ModelKind::Rmbg20), Some(ModelKind::Rmbg14));
+        strict.model_dir = request.model_dir.clone();
+        assert_eq!(fallback_model(&strict, ModelKind::Rmbg20), None);
+        let mut explicit = request;
+        explicit.requested_model = ModelKind::Rmbg20;
+        assert_eq!(fallback_model(&explicit, ModelKind::Rmbg20), None);
+    }
+
+    #[test]
+    fn foreground_channel_selects_and_validates_output_channel() {
+        // [1, 2, 1, 2]: channel 0 is background, channel 1 the subject.
+        let shape = [1, 2, 1, 2];
+        let output = [0.9, 0.2, 0.1, 0.8];
+        assert_eq!(extract_mask(&shape, &output, &[]).unwrap().data, vec![0.9, 0.2]);
+        assert_eq!(extract_mask(&shape, &output, &[1]).unwrap().data, vec![0.1, 0.8]);
+        assert_eq!(extract_mask(&shape, &output, &[0, 1]).unwrap().data, vec![0.9, 0.8]);
+        let err = extract_mask(&shape, &output, &[2]).unwrap_err();
+        assert!(err.to_string().contains("has 2 channel(s)"), "{}", err);
+    }
+
+    #[test]
+    fn quantized_models_get_distinct_cpu_session_config() {
+        let quantized = onnx_file_variant(Path::new("/models/rev/onnx/model_quantized.onnx"));
+        assert_eq!(quantized, OnnxVariant::Quantized);
+        let config = cpu_session_config(quantized);
+        assert!(config.denormal_as_zero && config.qdq_cleanup);
+
+        for file in ["/models/rev/onnx/model_fp16.onnx", "/models/rev/onnx/model.onnx"] {
+            assert_eq!(cpu_session_config(onnx_file_variant(Path::new(file))), CpuSessionConfig::default());
+        }
+        let under_quantized_root = onnx_file_variant(Path::new("/home/q8/quantized-models/rev/onnx/model.onnx"));
+        assert_eq!(under_quantized_root, OnnxVariant::Fp32);
+    }
+
+    #[test]
+    fn inference_retries_keep_a_flaky_provider_selected() {
+        let candidates = [ProviderChoice::Cuda, ProviderChoice::Cpu];
+        let flaky = || {
+            let mut cuda_calls = 0;
+            move |provider: ProviderChoice| -> Result<&'static str> {
+                if provider == ProviderChoice::Cuda {
+                    cuda_calls += 1;
+                    if cuda_calls == 1 {
+                        return Err(anyhow!("CUDA_ERROR_LAUNCH_FAILED"));
+                    }
+                }
+                Ok(provider_label(provider))
+            }
+        };
+
+        let (_, provider, errors) = try_providers(&candidates, 1, flaky()).expect("retry succeeds");
+        assert_eq!(provider, ProviderChoice::Cuda);
+        assert_eq!(errors, vec!["cuda: CUDA_ERROR_LAUNCH_FAILED".to_string()]);
+
+        let (_, provider, _) = try_providers(&candidates, 0, flaky()).expect("cpu fallback succeeds");
+        assert_eq!(provider, ProviderChoice::Cpu);
+    }
+
+    #[test]
+    fn tensor_specs_report_shapes_and_dynamic_dimensions() {
+        // Regenerate with scripts/make-onnx-fixtures.py.
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/segmentation.onnx");
+        // `load-dynamic` panics when no onnxruntime library can be found; there is nothing to inspect then.
+        let Ok(loaded) = std::panic::catch_unwind(|| inspect_model(&fixture)) else {
+            eprintln!("skipping: onnxruntime could not be loaded");
+            return;
+        };
+        let spec = loaded.expect("fixture loads");
+        assert_eq!(spec.inputs[0].name, "input");
+        assert_eq!(spec.inputs[0].shape, vec![-1, 3, 32, 32]);
+        assert_eq!(spec.inputs[0].element_type, "f32");
+        assert_eq!(spec.inputs[0].dimension_names[0], "batch");
+        assert_eq!(spec.outputs[0].name, "mask");
+        assert_eq!(spec.outputs[0].shape, vec![-1, 1, 32, 32]);
+        assert!(spec.is_supported_segmentation_model());
+
+        let two_outputs = ModelSpec {
+            outputs: vec![spec.outputs[0].clone(), spec.outputs[0].clone()],
+            ..spec
+        };
+        assert!(!two_outputs.is_supported_segmentation_model());
+    }
+
+    #[test]
+    fn trimap_splits_ramp_into_three_regions() {
+        let mut ramp = GrayImage::from_fn(256, 1, |x, _| Luma([x as u8]));
+        apply_trimap(&mut ramp, TrimapThresholds::new(0.25, 0.75).unwrap()).unwrap();
+        let values: Vec<u8> = ramp.pixels().map(|p| p[0]).collect();
+
+        assert!(values[..=63].iter().all(|&v| v == 0));
+        assert!(values[64..=191].iter().all(|&v| v == 128));
+        assert!(values[192..].iter().all(|&v| v == 255));
+
+        assert!(matches!(TrimapThresholds::new(0.8, 0.2), Err(CoreError::InvalidArgument(_))));
+        let mut unchecked = GrayImage::new(1, 1);
+        assert!(apply_trimap(&mut unchecked, TrimapThresholds { low: 0.5, high: 0.5 }).is_err());
+
+        // Rejected before the model is looked up, so no install is needed to see the error.
+        let request = InferenceRequest::builder()
+            .input_bytes(vec![1, 2, 3])
+            .trimap(Some(TrimapThresholds { low: 0.5, high: 0.5 }))
+            .model_dir(Some(PathBuf::from("/nonexistent")))
+            .build();
+        let err = LocalOrtBackend::default().infer(&request, ModelKind::Rmbg14).unwrap_err();
+        assert!(matches!(err, CoreError::InvalidArgument(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn large_batch_is_split_into_ordered_sub_batches() {
+        let large = ImageSize {
+            width: 4000,
+            height: 3000,
+        };
+        let per_image = estimate_rgba_bytes(large) + BATCH_TENSOR_BYTES;
+        let budget = BATCH_MODEL_OVERHEAD_BYTES + 3 * per_image;
+        let sizes = vec![large; 10];
+
+        let mut chunk_lens = Vec::new();
+        let results = run_sub_batches(&sizes, budget, |range| {
+            chunk_lens.push(range.len());
+            Ok(range.collect::<Vec<_>>())
+        })
+        .unwrap();
+        assert_eq!(chunk_lens, vec![3, 3, 3, 1]);
+        assert_eq!(results, (0..10).collect::<Vec<_>>());
+
+        assert_eq!(plan_sub_batches(&sizes[..2], 0), vec![0..1, 1..2]);
+        assert_eq!(plan_sub_batches(&sizes, u64::MAX), vec![0..10]);
+        assert!(run_sub_batches(&sizes, budget, |_| Ok(vec![0])).is_err());
+    }
+
+    #[test]
+    fn last_provider_reflects_a_cpu_forced_run() {
+        let backend = LocalOrtBackend::new(RuntimeDescriptor::new(ExecutionProvider::Cpu, GpuBackendPreference::Auto));
+        assert_eq!(backend.descriptor().execution_provider, ExecutionProvider::Cpu);
+        assert_eq!(backend.last_provider(), None);
+
+        let request = InferenceRequest::builder()
+            .input_bytes(synthetic_png(8, 8).unwrap())
+            .model_dir(Some(PathBuf::from("/nonexistent/unbg-models")))
+            .build();
+        let preferred = backend.with_descriptor_preference(&request);
+        assert_eq!(preferred.execution_provider, ExecutionProvider::Cpu);
+        let gpu = InferenceRequest::builder().execution_provider(ExecutionProvider::Gpu).build();
+        assert_eq!(backend.with_descriptor_preference(&gpu).execution_provider, ExecutionProvider::Gpu);
+
+        let result = backend.infer_with(&request, ModelKind::Rmbg14, true, None).unwrap();
+        assert_eq!(result.execution_provider_selected, "cpu");
+        assert_eq!(backend.last_provider().as_deref(), Some("cpu"));
+        assert_eq!(backend.clone().last_provider().as_deref(), Some("cpu"));
+    }
+
+    #[test]
+    fn lowered_thread_priority_applies_to_the_worker_only() {
+        let caller = std::thread::current().id();
+        let worker = run_with_thread_priority(ThreadPriority::Low, || std::thread::current().id()).expect("lower priority");
+        assert_ne!(worker, caller);
+        assert_eq!(run_with_thread_priority(ThreadPriority::Normal, || std::thread::current().id()).unwrap(), caller);
+
+        #[cfg(target_os = "linux")]
+        {
+            let nice = || unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) };
+            let before = nice();
+            assert_eq!(run_with_thread_priority(ThreadPriority::Lowest, nice).unwrap(), 19);
+            assert_eq!(nice(), before);
+        }
+    }
+
+    #[test]
+    fn cancelled_request_skips_the_placeholder_fallback() {
+        let backend = LocalOrtBackend::default();
+        let request = InferenceRequest::builder()
+            .input_bytes(synthetic_png(8, 8).unwrap())
+            .model_dir(Some(PathBuf::from("/nonexistent/unbg-models")))
+            .build();
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let err = backend.infer_with(&request, ModelKind::Rmbg14, true, Some(&cancel)).unwrap_err();
+        assert!(matches!(err, CoreError::Cancelled), "{}", err);
+        let err = backend.infer_cancellable(&request, ModelKind::Rmbg14, &cancel).unwrap_err();
+        assert!(matches!(err, CoreError::Cancelled), "{}", err);
+        assert_eq!(backend.last_provider(), None);
+    }
+
+    #[test]
+    fn provider_benchmark_keeps_every_timing_and_the_fastest_result() {
+        let candidates = [ProviderChoice::Cuda, ProviderChoice::DirectML, ProviderChoice::Cpu];
+        let benchmark = benchmark_providers(&candidates, |provider| {
+            let result = LocalOrtBackend::default()
+                .infer_fallback(ModelKind::Rmbg14, DynamicImage::new_rgb8(2, 2), &InferenceRequest::default())
+                .map_err(|e| anyhow!(e.to_string()))?;
+            match provider {
+                ProviderChoice::Cuda => Err(anyhow!("CUDA driver version is insufficient")),
+                ProviderChoice::DirectML => Ok((result, 40)),
+                _ => Ok((result, 25)),
+            }
+        });
+
+        assert_eq!(
+            benchmark.timings,
+            vec![
+                ("cuda".to_string(), PROVIDER_BENCHMARK_FAILED),
+                ("directml".to_string(), 40),
+                ("cpu".to_string(), 25),
+            ]
+        );
+        assert_eq!(benchmark.best.map(|(_, provider)| provider), Some(ProviderChoice::Cpu));
+        assert_eq!(benchmark.errors.len(), 1);
+    }
+
+    #[test]
+    fn batch_output_must_hold_one_mask_per_input() {
+        let (item_shape, item_len) = batch_item_shape(&[3, 1, 4, 4], 48, 3).unwrap();
+        assert_eq!((item_shape, item_len), (vec![1, 1, 4, 4], 16));
+
+        let err = batch_item_shape(&[1, 1, 4, 4], 16, 3).unwrap_err().to_string();
+        assert!(err.contains("returned 1 masks") && err.contains("batch of 3"), "{}", err);
+        assert!(batch_item_shape(&[4, 4], 16, 3).unwrap_err().to_string().contains("no batch dimension"));
+        assert!(batch_item_shape(&[3, 1, 4, 4], 40, 3).is_err());
+
+        let unbatchable = InferenceRequest::builder().max_dimension(Some(512)).build();
+        assert!(!batchable(&unbatchable));
+        let err = LocalOrtBackend::default()
+            .infer_batch_with(&[unbatchable], ModelKind::Rmbg14, BatchOptions::default())
+            .unwrap_err();
+        assert!(err.to_string().contains("does not support"), "{}", err);
+    }
+
+    #[test]
+    fn mask_threshold_binarizes_at_cutoff() {
+        let mut ramp = GrayImage::from_fn(256, 1, |x, _| Luma([x as u8]));
+        apply_mask_threshold(&mut ramp, 0.5).unwrap();
+        let values: Vec<u8> = ramp.pixels().map(|p| p[0]).collect();
+
+        assert!(values[..=127].iter().all(|&v| v == 0));
+        assert!(values[128..].iter().all(|&v| v == 255));
+
+        let mut edge = GrayImage::from_pixel(1, 1, Luma([255]));
+        apply_mask_threshold(&mut edge, 1.0).unwrap();
+        assert_eq!(edge.get_pixel(0, 0)[0], 255);
+        assert!(apply_mask_threshold(&mut edge, 1.5).is_err());
+
+        // Rejected before the model is looked up, so no install is needed to see the error.
+        let request = InferenceRequest::builder()
+            .input_bytes(vec![1, 2, 3])
+            .mask_threshold(Some(1.5))
+            .model_dir(Some(PathBuf::from("/nonexistent")))
+            .build();
+        let err = LocalOrtBackend::default().infer(&request, ModelKind::Rmbg14).unwrap_err();
+        assert!(matches!(err, CoreError::InvalidArgument(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn alpha_curve_interpolates_between_its_points() {
+        let curve = [(0.0, 0.0), (0.25, 0.5), (1.0, 1.0)];
+        let mut alpha = vec![0.0, 0.125, 0.25, 0.625, 1.0, -0.5, 1.5];
+        apply_alpha_curve(&mut alpha, &curve).unwrap();
+        let expected = [0.0, 0.25, 0.5, 0.75, 1.0, 0.0, 1.0];
+        for (got, want) in alpha.iter().zip(expected) {
+            assert!((got - want).abs() < 1e-6, "got {:?}, expected {:?}", alpha, expected);
+        }
+
+        let mut untouched = vec![0.5];
+        assert!(apply_alpha_curve(&mut untouched, &[(0.1, 0.0), (1.0, 1.0)]).is_err());
+        assert_eq!(untouched, vec![0.5]);
+
+        // Rejected before the model is looked up, so no install is needed to see the error.
+        let request = InferenceRequest::builder()
+            .input_bytes(vec![1, 2, 3])
+            .alpha_curve(Some(vec![(0.0, 0.0), (1.0, 1.5)]))
+            .model_dir(Some(PathBuf::from("/nonexistent")))
+            .build();
+        let err = LocalOrtBackend::default().infer(&request, ModelKind::Rmbg14).unwrap_err();
+        assert!(matches!(err, CoreError::InvalidArgument(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn feather_runs_once_on_the_full_size_mask() {
+        let image = DynamicImage::new_rgb8(400, 40);
+        let request = InferenceRequest::builder()
+            .max_dimension(Some(200))
+            .feather_radius(Some(2.0))
+            .build();
+        let result = infer_within_max_dimension(&image, &request, MaskScale::Input, |small, inner| {
+            assert_eq!(inner.feather_radius, None, "downscaled run must not feather");
+            let mut result = LocalOrtBackend::default().infer_fallback(ModelKind::Rmbg14, small.clone(), &InferenceRequest::default())?;
+            let hard_edge = GrayImage::from_fn(small.width(), small.height(), |x, _| Luma([if x < 100 { 255 } else { 0 }]));
+            result.mask_png = encode_mask(hard_edge, None)?;
+            Ok(result)
+        })
+        .unwrap();
+        let mask = image::load_from_memory(&result.mask_png).unwrap().to_luma8();
+        assert_eq!(mask.dimensions(), (400, 40));
+        let edge: Vec<u8> = (194..206).map(|x| mask.get_pixel(x, 20)[0]).collect();
+        assert!(edge.iter().filter(|&&v| v > 0 && v < 255).count() > 4, "edge not feathered: {:?}", edge);
+
+        let mut unchanged = GrayImage::from_pixel(2, 2, Luma([200]));
+        assert!(apply_feather(&mut unchanged, -1.0).is_err());
+        assert!(apply_feather(&mut unchanged, f32::NAN).is_err());
+        assert!(apply_feather(&mut unchanged, 1e9).is_err());
+        assert!(apply_feather(&mut unchanged, 2.0).is_ok());
+
+        // Rejected before the model is looked up, so no install is needed to see the error.
+        let request = InferenceRequest::builder()
+            .input_bytes(vec![1, 2, 3])
+            .feather_radius(Some(-1.0))
+            .model_dir(Some(PathBuf::from("/nonexistent")))
+            .build();
+        let err = LocalOrtBackend::default().infer(&request, ModelKind::Rmbg14).unwrap_err();
+        assert!(matches!(err, CoreError::InvalidArgument(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn feather_runs_after_the_threshold_so_edges_stay_soft() {
+        let request = InferenceRequest::builder()
+            .mask_threshold(Some(0.5))
+            .feather_radius(Some(2.0))
+            .build();
+        let mut mask = GrayImage::from_fn(20, 4, |x, _| Luma([(x * 255 / 19) as u8]));
+        finish_ensemble_mask(&mut mask, &request).unwrap();
+        let row: Vec<u8> = (0..20).map(|x| mask.get_pixel(x, 2)[0]).collect();
+        assert_eq!((row[0], row[19]), (0, 255));
+        assert!(row.iter().filter(|&&v| v > 0 && v < 255).count() > 2, "threshold erased the feather: {:?}", row);
+    }
+
+    #[test]
+    fn input_size_must_be_a_positive_multiple_of_32() {
+        assert!(validate_input_size(None).is_ok());
+        assert!(validate_input_size(Some(512)).is_ok());
+        assert!(validate_input_size(Some(2048)).is_ok());
+        for bad in [0, 500, 1000] {
+            let err = validate_input_size(Some(bad)).unwrap_err();
+            assert!(matches!(err, CoreError::InvalidArgument(_)), "{:?}", err);
+            assert!(err.to_string().contains("multiple of 32"), "{}", err);
+        }
+
+        let request = InferenceRequest::builder()
+            .input_bytes(vec![1, 2, 3])
+            .input_size(Some(1000))
+            .build();
+        let err = LocalOrtBackend::default().infer(&request, ModelKind::Rmbg14).unwrap_err();
+        assert!(err.to_string().contains("multiple of 32"), "{}", err);
+    }
+
+    #[test]
+    fn raw_mask_is_kept_at_model_resolution_before_the_resize() {
+        let preprocessor = default_preprocessor(ModelKind::Rmbg14);
+        let postprocess = PostprocessChain::default_for(ModelKind::Rmbg14);
+        let pipeline = Pipeline {
+            preprocessor: preprocessor.as_ref(),
+            postprocess: &postprocess,
+            cancel: None,
+        };
+        let logits = MaskBuffer {
+            width: 2,
+            height: 2,
+            data: vec![-4.0, 0.0, 0.0, 4.0],
+        };
+        let image = DynamicImage::new_rgb8(40, 30);
+
+        let plain = finish_mask(logits.clone(), &image, &InferenceRequest::default(), &pipeline).unwrap();
+        assert!(plain.raw.is_none());
+
+        let request = InferenceRequest::builder().emit_mask_raw(true).build();
+        let finished = finish_mask(logits, &image, &request, &pipeline).unwrap();
+        let raw = finished.raw.expect("raw mask requested");
+        assert_eq!((raw.width, raw.height), (2, 2));
+        assert!(raw.data[0] < 0.1 && (raw.data[1] - 0.5).abs() < 1e-6 && raw.data[3] > 0.9);
+        let png = image::load_from_memory(&finished.png).unwrap();
+        assert_eq!((png.width(), png.height()), (40, 30));
+    }
+
+    #[test]
+    fn mask_rle_matches_the_encoded_mask_at_every_stage() {
+        let preprocessor = default_preprocessor(ModelKind::Rmbg14);
+        let postprocess = PostprocessChain::default_for(ModelKind::Rmbg14);
+        let pipeline = Pipeline {
+            preprocessor: preprocessor.as_ref(),
+            postprocess: &postprocess,
+            cancel: None,
+        };
+        let logits = MaskBuffer {
+            width: 2,
+            height: 2,
+            data: vec![-4.0, 0.0, 0.0, 4.0],
+        };
+        let image = DynamicImage::new_rgb8(40, 30);
+        let decoded = |png: &[u8]| image::load_from_memory(png).unwrap().to_luma8().into_raw();
+
+        let plain = finish_mask(logits.clone(), &image, &InferenceRequest::default(), &pipeline).unwrap();
+        assert!(plain.rle.is_none());
+
+        let request = InferenceRequest::builder().emit_mask_rle(true).mask_threshold(Some(0.5)).build();
+        let finished = finish_mask(logits, &image, &request, &pipeline).unwrap();
+        assert_eq!(decode_mask_rle(&finished.rle.expect("rle requested")), decoded(&finished.png));
+
+        // Stages that rewrite the mask after `finish_mask` keep the runs in step with the PNG.
+        let roi = RegionOfInterest {
+            x: 4,
+            y: 2,
+            width: 10,
+            height: 8,
+        };
+        let request = InferenceRequest::builder().emit_mask_rle(true).roi(Some(roi)).build();
+        let framed = infer_within_roi(&image, &request, |region| {
+            LocalOrtBackend::default().infer_fallback(ModelKind::Rmbg14, region.clone(), &request)
+        })
+        .unwrap();
+        let runs = framed.mask_rle.expect("rle requested");
+        assert_eq!(decode_mask_rle(&runs), decoded(&framed.mask_png));
+        assert_eq!(runs.iter().map(|&(_, run)| run).sum::<u32>(), 40 * 30);
+    }
+
+    #[test]
+    fn pixel_budget_downscales_before_inference() {
+        let request = InferenceRequest::builder().max_inference_pixels(Some(2_000_000)).build();
+        let huge = ImageSize {
+            width: 8000,
+            height: 8000,
+        };
+        let capped = inference_size(huge, &request);
+        assert!(capped.width as u64 * capped.height as u64 <= 2_000_000, "processed at {:?}", capped);
+        assert_eq!(capped.width, capped.height);
+        let within = ImageSize {
+            width: 1000,
+            height: 1000,
+        };
+        let unchanged = inference_size(within, &request);
+        assert_eq!((unchanged.width, unchanged.height), (1000, 1000));
+
+        // The inner run sees the small copy and a soft-mask request; the cutoffs apply once, at full size.
+        let image = DynamicImage::ImageRgb8(image::RgbImage::from_fn(200, 100, |x, _| {
+            image::Rgb(if x < 100 { [0, 0, 0] } else { [255, 255, 255] })
+        }));
+        let request = InferenceRequest::builder()
+            .max_inference_pixels(Some(5_000))
+            .mask_threshold(Some(0.5))
+            .trimap(Some(TrimapThresholds::new(0.2, 0.8).unwrap()))
+            .build();
+        let mut seen = (0, 0);
+        let result = infer_within_max_dimension(&image, &request, MaskScale::Input, |small, inner| {
+            seen = (small.width(), small.height());
+            assert_eq!((inner.mask_threshold, inner.feather_radius, inner.trimap), (None, None, None));
+            LocalOrtBackend::default().infer_fallback(ModelKind::Rmbg14, small.clone(), inner)
+        })
+        .unwrap();
+        assert!(seen.0 * seen.1 <= 5_000, "processed at {:?}", seen);
+        assert_eq!((result.width, result.height), (200, 100));
+        let mask = image::load_from_memory(&result.mask_png).unwrap().to_luma8();
+        assert_eq!(mask.dimensions(), (200, 100));
+        assert!(mask.pixels().all(|p| p[0] == 0 || p[0] == 255), "thresholded once at full size");
+    }
+
+    #[test]
+    fn batches_only_stack_requests_with_matching_session_settings() {
+        let sized = |w, h| InferenceRequest::builder().dimensions(w, h).input_bytes(vec![1, 2, 3]);
+        let a = sized(64, 64).build();
+        let b = sized(32, 48).build();
+        let other_dir = sized(64, 64).model_dir(Some(PathBuf::from("/elsewhere"))).build();
+        let mut pinned = sized(64, 64).build();
+        pinned.free_dimension_overrides = vec![("batch".to_string(), 1)];
+        assert_eq!(BatchKey::of(&a), BatchKey::of(&b));
+        assert_ne!(BatchKey::of(&a), BatchKey::of(&other_dir));
+        assert_ne!(BatchKey::of(&a), BatchKey::of(&pinned));
+
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let err = LocalOrtBackend::default()
+            .infer_batch_cancellable(&[a, other_dir, b, pinned], ModelKind::Rmbg14, &cancel)
+            .unwrap_err();
+        assert!(matches!(err, CoreError::Cancelled), "{}", err);
+    }
+
+    #[test]
+    fn pixel_budget_keeps_oversized_inputs_out_of_batches() {
+        let within = InferenceRequest::builder()
+            .dimensions(1000, 1000)
+            .max_inference_pixels(Some(2_000_000))
+            .build();
+        let over = InferenceRequest::builder()
+            .dimensions(8000, 8000)
+            .max_inference_pixels(Some(2_000_000))
+            .build();
+        assert!(batchable(&within));
+        assert!(!batchable(&over));
+        assert!(batchable(&InferenceRequest::builder().dimensions(8000, 8000).build()));
+
+        // Batching would stack the full-resolution tensor, so the batch entry point refuses it
+        // before decoding anything and `infer_batch` sends it through the downscaling path.
+        let err = LocalOrtBackend::default()
+            .infer_batch_with(&[within, over], ModelKind::Rmbg14, BatchOptions::default())
+            .unwrap_err();
+        assert!(err.to_string().contains("max_inference_pixels"), "{}", err);
+    }
+
+    #[test]
+    fn inference_scale_leaves_the_downscaled_mask_for_tiling() {
+        let image = DynamicImage::new_rgb8(800, 60);
+        let request = InferenceRequest::builder().max_dimension(Some(200)).build();
+        let result = infer_within_max_dimension(&image, &request, MaskScale::Inference, |small, _| {
+            LocalOrtBackend::default().infer_fallback(ModelKind::Rmbg14, small.clone(), &InferenceRequest::default())
+        })
+        .unwrap();
+        assert_eq!((result.width, result.height), (800, 60));
+        let mask = image::load_from_memory(&result.mask_png).unwrap();
+        assert_eq!((mask.width(), mask.height()), (200, 15));
+
+        let feathered = InferenceRequest::builder()
+            .input_bytes(vec![1, 2, 3])
+            .feather_radius(Some(2.0))
+            .build();
+        let err = LocalOrtBackend::default()
+            .infer_mask_tiles(&feathered, ModelKind::Rmbg14, 64, |_, _| Ok(()))
+            .unwrap_err();
+        assert!(err.to_string().contains("streamed mask tiles"), "{}", err);
+    }
+
+    #[test]
+    fn max_dimension_downscales_input_and_restores_mask_size() {
+        let image = DynamicImage::new_rgb8(8000, 60);
+        let request = InferenceRequest::builder().max_dimension(Some(1024)).build();
+        let mut seen = (0, 0);
+        let result = infer_within_max_dimension(&image, &request, MaskScale::Input, |small, _| {
+            seen = (small.width(), small.height());
+            LocalOrtBackend::default().infer_fallback(ModelKind::Rmbg14, small.clone(), &InferenceRequest::default())
+        })
+        .unwrap();
+
+        assert!(seen.0 <= 1024 && seen.1 <= 1024, "processed at {:?}", seen);
+        assert_eq!((result.width, result.height), (8000, 60));
+        let mask = image::load_from_memory(&result.mask_png).unwrap();
+        assert_eq!((mask.width(), mask.height()), (8000, 60));
+    }
+
+    #[test]
+    fn roi_mask_is_background_outside_the_region() {
+        let image = DynamicImage::new_rgb8(40, 30);
+        let roi = RegionOfInterest {
+            x: 10,
+            y: 5,
+            width: 20,
+            height: 10,
+        };
+        let request = InferenceRequest::builder().roi(Some(roi)).build();
+        let result = infer_within_roi(&image, &request, |region| {
+            assert_eq!((region.width(), region.height()), (20, 10));
+            let encoded = png_bytes(&DynamicImage::ImageLuma8(GrayImage::from_pixel(20, 10, Luma([255]))));
+            let mut result = LocalOrtBackend::default().infer_fallback(ModelKind::Rmbg14, region.clone(), &InferenceRequest::default())?;
+            result.mask_png = encoded;
+            Ok(result)
+        })
+        .unwrap();
+
+        assert_eq!((result.width, result.height), (40, 30));
+        let mask = image::load_from_memory(&result.mask_png).unwrap().to_luma8();
+        for (x, y, pixel) in mask.enumerate_pixels() {
+            let inside = (10..30).contains(&x) && (5..15).contains(&y);
+            assert_eq!(pixel[0], if inside { 255 } else { 0 }, "pixel {},{}", x, y);
+        }
+
+        let outside = InferenceRequest::builder()
+            .roi(Some(RegionOfInterest { x: 30, ..roi }))
+            .build();
+        let err = infer_within_roi(&image, &outside, |_| unreachable!()).unwrap_err();
+        assert!(matches!(err, CoreError::InvalidArgument(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn opset_mismatch_errors_are_rewritten_with_a_fix() {
+        let opset = "Load model from model.onnx failed:/onnxruntime_src/onnxruntime/core/graph/model_load_utils.h:46 \
+            ONNX Runtime only *guarantees* support for models stamped with official released onnx opset versions. \
+            Opset 22 is under development and support for this is limited. \
+            Current official support for domain ai.onnx is till opset 21.";
+        let help = explain_version_mismatch(opset).expect("opset error recognized");
+        assert!(help.starts_with("onnxruntime too old for model opset 22; update onnxruntime"), "{}", help);
+        assert!(help.contains("ORT_DYLIB_PATH"), "{}", help);
+
+        let ir = explain_version_mismatch("Unsupported model IR version: 10, max supported IR version: 9").unwrap();
+        assert!(ir.starts_with("onnxruntime too old for model IR version 10;"), "{}", ir);
+
+        assert_eq!(explain_version_mismatch("No such file or directory"), None);
+    }
+
+    #[test]
+    fn choose_model_trades_quality_for_latency_among_installed_models() {
+        let both = KnownModel::all();
+        let quality = ModelConstraints {
+            max_memory: Some(4 * 1024 * 1024 * 1024),
+            target_latency_ms: Some(2_000),
+            quality_priority: 1.0,
+        };
+        let speed = ModelConstraints {
+            quality_priority: 0.0,
+            ..quality
+        };
+        assert_eq!(choose_model(quality, &both), ModelKind::Rmbg20);
+        assert_eq!(choose_model(speed, &both), ModelKind::Rmbg14);
+
+        assert_eq!(choose_model(speed, &[KnownModel::Rmbg20]), ModelKind::Rmbg20);
+        let tight = ModelConstraints {
+            max_memory: Some(1024 * 1024 * 1024),
+            ..quality
+        };
+        assert_eq!(choose_model(tight, &both), ModelKind::Rmbg14);
+        assert_eq!(choose_model(tight, &[KnownModel::Rmbg20]), ModelKind::Rmbg20);
+        assert_eq!(choose_model(quality, &[]), ModelKind::Auto);
+    }
+
+    #[test]
+    fn seed_mask_forces_marked_regions_over_model_output() {
+        let predicted = png_bytes(&DynamicImage::ImageLuma8(GrayImage::from_raw(4, 1, vec![0, 40, 255, 200]).unwrap()));
+        let seed = GrayImage::from_raw(4, 1, vec![255, 255, 0, 128]).unwrap();
+
+        let forced = apply_seed_mask(&predicted, &seed).unwrap();
+        assert_eq!(forced.into_raw(), vec![255, 255, 0, 200]);
+
+        let err = apply_seed_mask(&predicted, &GrayImage::new(2, 2)).unwrap_err();
+        assert!(matches!(err, CoreError::InvalidArgument(_)), "{:?}", err);
+        assert!(err.to_string().contains("seed mask is 2x2"), "{}", err);
+
+        // Rejected before the model is looked up, so no install is needed to see the error.
+        let request = InferenceRequest::builder()
+            .input_bytes(vec![1, 2, 3])
+            .dimensions(4, 1)
+            .seed_mask(Some(png_bytes(&DynamicImage::ImageLuma8(GrayImage::new(2, 2)))))
+            .model_dir(Some(PathBuf::from("/nonexistent")))
+            .build();
+        let err = LocalOrtBackend::default().infer(&request, ModelKind::Rmbg14).unwrap_err();
+        assert!(matches!(err, CoreError::InvalidArgument(_)), "{:?}", err);
+    }
 }
@@ -1,6 +1,10 @@
 use std::fs::OpenOptions;
 use std::io::Write;
 use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
 
 use anyhow::{Context, Result};
 use reqwest::blocking::Client;
@@ -33,6 +37,8 @@ struct TelemetryEnvelope {
     platform: String,
     duration_ms: Option<u64>,
     detail: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fields: Option<serde_json::Value>,
 }
 
 impl From<&TelemetryEvent> for TelemetryEnvelope {
@@ -43,6 +49,7 @@ impl From<&TelemetryEvent> for TelemetryEnvelope {
             platform: format!("{:?}", event.platform),
             duration_ms: event.duration_ms,
             detail: event.detail.clone(),
+            fields: event.fields.clone(),
         }
     }
 }
@@ -88,23 +95,254 @@ impl TelemetrySink for FileSink {
     }
 }
 
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    /// Limit for a single POST, so a stalled collector cannot hold the worker indefinitely.
+    pub request_timeout: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(200),
+            request_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// How long dropping an [`HttpSink`] waits for queued events before giving up on them.
+pub const DEFAULT_FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Posts events from a background thread so `emit` never waits on the network.
+///
+/// Failed posts are retried with doubling backoff up to `RetryPolicy::max_retries` times, then
+/// dropped. Dropping the sink flushes whatever is still queued for up to the flush timeout, then
+/// detaches the worker so process exit is never held up by the collector.
 pub struct HttpSink {
-    endpoint: String,
-    client: Client,
+    sender: Option<Sender<TelemetryEnvelope>>,
+    /// The worker and a channel it signals (or drops) once it has drained the queue.
+    worker: Mutex<Option<(JoinHandle<()>, Receiver<()>)>>,
+    flush_timeout: Duration,
 }
 
 impl HttpSink {
     pub fn new(endpoint: String) -> Self {
+        Self::with_retry(endpoint, RetryPolicy::default())
+    }
+
+    pub fn with_retry(endpoint: String, retry: RetryPolicy) -> Self {
+        let (sender, receiver) = mpsc::channel::<TelemetryEnvelope>();
+        let (done_sender, done) = mpsc::channel();
+        let worker = thread::spawn(move || {
+            let client = Client::builder()
+                .timeout(retry.request_timeout)
+                .build()
+                .unwrap_or_else(|_| Client::new());
+            for payload in receiver {
+                post_with_retry(&client, &endpoint, &payload, retry);
+            }
+            let _ = done_sender.send(());
+        });
         Self {
-            endpoint,
-            client: Client::new(),
+            sender: Some(sender),
+            worker: Mutex::new(Some((worker, done))),
+            flush_timeout: DEFAULT_FLUSH_TIMEOUT,
         }
     }
+
+    pub fn with_flush_timeout(mut self, flush_timeout: Duration) -> Self {
+        self.flush_timeout = flush_timeout;
+        self
+    }
+}
+
+fn post_with_retry(
+    client: &Client,
+    endpoint: &str,
+    payload: &TelemetryEnvelope,
+    retry: RetryPolicy,
+) -> bool {
+    let mut backoff = retry.initial_backoff;
+    for attempt in 0..=retry.max_retries {
+        match client.post(endpoint).json(payload).send() {
+            Ok(response) if response.status().is_success() => return true,
+            Ok(response)
+                if response.status().is_client_error() && response.status().as_u16() != 429 =>
+            {
+                return false
+            }
+            _ => {}
+        }
+        if attempt < retry.max_retries {
+            thread::sleep(backoff);
+            backoff = backoff.saturating_mul(2);
+        }
+    }
+    false
 }
 
 impl TelemetrySink for HttpSink {
     fn emit(&self, event: TelemetryEvent) {
-        let payload = TelemetryEnvelope::from(&event);
-        let _ = self.client.post(&self.endpoint).json(&payload).send();
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(TelemetryEnvelope::from(&event));
+        }
+    }
+}
+
+impl Drop for HttpSink {
+    fn drop(&mut self) {
+        self.sender.take();
+        let Some((worker, done)) = self.worker.lock().ok().and_then(|mut w| w.take()) else {
+            return;
+        };
+        match done.recv_timeout(self.flush_timeout) {
+            // Finished, or the worker died and dropped its end of the channel.
+            Ok(()) | Err(mpsc::RecvTimeoutError::Disconnected) => {
+                if worker.join().is_err() {
+                    eprintln!("unbg telemetry: http worker panicked; queued events were lost");
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                eprintln!(
+                    "unbg telemetry: gave up flushing events after {:?}; detaching the http worker",
+                    self.flush_timeout
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use unbg_core::{ModelKind, PlatformTarget, TelemetryEventType};
+
+    /// Answers the first `failures` requests with 503 and the rest with 200.
+    fn flaky_endpoint(failures: usize) -> (String, Arc<AtomicUsize>, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock collector");
+        let addr = listener.local_addr().expect("collector addr");
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let delivered = Arc::new(AtomicUsize::new(0));
+        let (attempts_srv, delivered_srv) = (attempts.clone(), delivered.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { continue };
+                let mut reader = BufReader::new(stream.try_clone().expect("clone stream"));
+                let mut content_length = 0usize;
+                let mut line = String::new();
+                while reader.read_line(&mut line).map(|n| n > 2).unwrap_or(false) {
+                    if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                        content_length = value.trim().parse().unwrap_or(0);
+                    }
+                    line.clear();
+                }
+                let mut body = vec![0u8; content_length];
+                let _ = reader.read_exact(&mut body);
+                let status = if attempts_srv.fetch_add(1, Ordering::SeqCst) < failures {
+                    "503 Service Unavailable"
+                } else {
+                    delivered_srv.fetch_add(1, Ordering::SeqCst);
+                    "200 OK"
+                };
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                );
+            }
+        });
+        (format!("http://{}/events", addr), attempts, delivered)
+    }
+
+    fn sample_event() -> TelemetryEvent {
+        TelemetryEvent {
+            event_type: TelemetryEventType::InferenceSuccess,
+            model: ModelKind::Rmbg14,
+            platform: PlatformTarget::Cli,
+            duration_ms: Some(12),
+            detail: None,
+            fields: None,
+        }
+    }
+
+    #[test]
+    fn http_sink_retries_until_collector_recovers() {
+        let (endpoint, attempts, delivered) = flaky_endpoint(2);
+        let sink = HttpSink::with_retry(
+            endpoint,
+            RetryPolicy {
+                max_retries: 3,
+                initial_backoff: Duration::from_millis(10),
+                ..RetryPolicy::default()
+            },
+        );
+        sink.emit(sample_event());
+        drop(sink);
+
+        assert_eq!(delivered.load(Ordering::SeqCst), 1);
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn http_sink_gives_up_after_bounded_retries() {
+        let (endpoint, attempts, delivered) = flaky_endpoint(usize::MAX);
+        let sink = HttpSink::with_retry(
+            endpoint,
+            RetryPolicy {
+                max_retries: 2,
+                initial_backoff: Duration::from_millis(5),
+                ..RetryPolicy::default()
+            },
+        );
+        sink.emit(sample_event());
+        drop(sink);
+
+        assert_eq!(delivered.load(Ordering::SeqCst), 0);
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn dropping_the_sink_does_not_wait_on_a_stalled_collector() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind stalled collector");
+        let addr = listener.local_addr().expect("collector addr");
+        // Accepts connections and never answers.
+        thread::spawn(move || {
+            let held: Vec<_> = listener.incoming().take(4).collect();
+            thread::sleep(Duration::from_secs(30));
+            drop(held);
+        });
+        let sink = HttpSink::with_retry(
+            format!("http://{}/events", addr),
+            RetryPolicy {
+                max_retries: 0,
+                request_timeout: Duration::from_secs(30),
+                ..RetryPolicy::default()
+            },
+        )
+        .with_flush_timeout(Duration::from_millis(100));
+        sink.emit(sample_event());
+
+        let started = std::time::Instant::now();
+        drop(sink);
+        assert!(started.elapsed() < Duration::from_secs(5), "drop took {:?}", started.elapsed());
+    }
+
+    #[test]
+    fn envelope_nests_structured_fields() {
+        let mut event = sample_event();
+        let plain = serde_json::to_value(TelemetryEnvelope::from(&event)).unwrap();
+        assert!(plain.get("fields").is_none());
+
+        event.fields = Some(serde_json::json!({ "provider": "cuda", "fallback": false }));
+        let envelope = serde_json::to_value(TelemetryEnvelope::from(&event)).unwrap();
+        assert_eq!(envelope["fields"]["provider"], "cuda");
+        assert_eq!(envelope["fields"]["fallback"], false);
     }
 }
@@ -1,15 +1,16 @@
 use std::path::PathBuf;
+use std::sync::Mutex;
 
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use unbg_core::{
-    run_inference_with_telemetry, v1, CoreError, ErrorInfo, ExecutionProvider, GpuBackendPreference, InferenceRequest, ModelKind,
-    OnnxVariant, PlatformTarget, RuntimeConfig, RuntimePolicy,
+    run_inference_with_telemetry, v1, BackgroundFill, CancelToken, CoreError, ErrorInfo, ExecutionProvider, GpuBackendPreference, InferenceRequest, InferenceTimings, ModelKind,
+    OnnxVariant, PlatformTarget, RuntimeConfig, RuntimePolicy, ThreadPriority,
 };
-use unbg_image::{estimate_rgba_bytes, ImageSize};
+use unbg_image::{clamp_to_max_pixels, composite_png, estimate_rgba_bytes, Backdrop, CutoutError, EncodeOptions, ImageSize};
 use unbg_model_registry::default_model_dir;
 use unbg_telemetry::sink_from_env;
-use unbg_runtime_ort::LocalOrtBackend;
+use unbg_runtime_ort::{default_registry, run_with_thread_priority};
 
 uniffi::setup_scaffolding!();
 
@@ -25,6 +26,9 @@ pub struct FfiRemoveBackgroundRequest {
     pub benchmark_provider: Option<bool>,
     pub model_dir: Option<String>,
     pub max_inference_pixels: Option<u32>,
+    pub mask_threshold: Option<f32>,
+    pub collect_timings: Option<bool>,
+    pub emit_mask_rle: Option<bool>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -36,6 +40,18 @@ pub struct FfiRemoveBackgroundResponse {
     pub provider_selected: String,
     pub backend_selected: Option<String>,
     pub fallback_used: bool,
+    pub timings: Option<InferenceTimings>,
+    /// `mask_png` as `(value, run)` pairs, cheaper to expand than a PNG; set when
+    /// `emit_mask_rle` was requested.
+    pub mask_rle: Option<Vec<(u8, u32)>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FfiMemoryEstimate {
+    pub estimated_bytes: u64,
+    pub suggested_width: u32,
+    pub suggested_height: u32,
 }
 
 #[derive(Debug, Error, uniffi::Error)]
@@ -44,30 +60,61 @@ pub enum FfiError {
     InvalidArgument,
     #[error("inference")]
     Inference,
+    /// The model has to be installed (`unbg models install`) before inference can run.
+    #[error("model-not-installed")]
+    ModelNotInstalled,
+    /// [`UnbgApi::cancel`] stopped the call before it finished.
+    #[error("cancelled")]
+    Cancelled,
 }
 
 #[derive(uniffi::Object)]
-pub struct UnbgApi;
+pub struct UnbgApi {
+    /// Priority of the worker that runs [`UnbgApi::remove_background_async`].
+    thread_priority: Mutex<ThreadPriority>,
+    /// Token handed to the calls running now; [`UnbgApi::cancel`] fires it and swaps in a fresh one.
+    in_flight: Mutex<CancelToken>,
+}
 
 #[uniffi::export]
 impl UnbgApi {
     #[uniffi::constructor]
     pub fn new() -> Self {
-        Self
+        Self {
+            thread_priority: Mutex::new(ThreadPriority::Normal),
+            in_flight: Mutex::new(CancelToken::new()),
+        }
     }
 
-    pub fn remove_background_v1_json(&self, request_json: String) -> String {
-        let request: v1::RemoveBackgroundRequest = match serde_json::from_str(&request_json) {
-            Ok(request) => request,
-            Err(_) => return "{\"code\":\"invalid-argument\",\"message\":\"invalid request json\"}".to_string(),
-        };
-        match remove_background_v1(request) {
-            Ok(response) => serde_json::to_string(&response)
-                .unwrap_or_else(|_| "{\"code\":\"inference\",\"message\":\"response encode failed\"}".to_string()),
-            Err(err) => format!("{{\"code\":\"{}\",\"message\":\"{}\"}}", error_code(&err), err),
+    /// Cancels every call started on this instance that has not returned yet; they end with the
+    /// `cancelled` error code at the next stage boundary. Calls made afterwards run normally.
+    pub fn cancel(&self) {
+        let mut in_flight = self.in_flight.lock().expect("cancel token lock poisoned");
+        in_flight.cancel();
+        *in_flight = CancelToken::new();
+    }
+
+    /// Sets the OS priority (`normal`, `low` or `lowest`) for later async calls; `false` for an
+    /// unknown value, which leaves the current setting in place.
+    ///
+    /// Only the worker thread that drives each call is lowered. ONNX Runtime's intra-op pool is
+    /// created with the session, and its threads inherit the priority only on Linux and Android,
+    /// and only when that worker is the one that builds the session; sessions are cached, so one
+    /// built earlier at normal priority keeps its pool at normal priority.
+    pub fn set_thread_priority(&self, priority: String) -> bool {
+        match parse_thread_priority_opt(Some(&priority)) {
+            Ok(Some(parsed)) => {
+                *self.thread_priority.lock().expect("thread priority lock poisoned") = parsed;
+                true
+            }
+            _ => false,
         }
     }
 
+    pub fn remove_background_v1_json(&self, request_json: String) -> String {
+        remove_background_v1_json(&request_json, Some(&self.cancel_token()))
+    }
+
     pub fn default_model_dir_string(&self) -> String {
         match default_model_dir_string() {
             Ok(path) => path,
@@ -78,9 +125,91 @@ impl UnbgApi {
     pub fn supported_model_aliases_json(&self) -> String {
         serde_json::to_string(&supported_model_aliases()).unwrap_or_else(|_| "[]".to_string())
     }
+
+    pub fn estimate_memory_json(&self, width: u32, height: u32, max_inference_pixels: u32) -> String {
+        serde_json::to_string(&estimate_memory(width, height, max_inference_pixels))
+            .unwrap_or_else(|_| "{\"code\":\"inference\",\"message\":\"response encode failed\"}".to_string())
+    }
+}
+
+#[uniffi::export(async_runtime = "tokio")]
+impl UnbgApi {
+    /// [`UnbgApi::remove_background_v1_json`] as a suspending call: decoding and inference run on
+    /// tokio's blocking pool, so Swift `async` / Kotlin coroutine callers never block their thread.
+    pub async fn remove_background_async(&self, request_json: String) -> String {
+        let priority = *self.thread_priority.lock().expect("thread priority lock poisoned");
+        let cancel = self.cancel_token();
+        tokio::task::spawn_blocking(move || {
+            run_with_thread_priority(priority, || remove_background_v1_json(&request_json, Some(&cancel)))
+                .unwrap_or_else(|err| format!("{{\"code\":\"inference\",\"message\":\"{}\"}}", err))
+        })
+        .await
+        .unwrap_or_else(|_| "{\"code\":\"inference\",\"message\":\"inference task failed\"}".to_string())
+    }
+
+    /// Runs a JSON array of v1 requests one after another on a single worker at the configured
+    /// priority. Returns a JSON array in the same order holding each response or error object.
+    pub async fn remove_background_batch_async(&self, requests_json: String) -> String {
+        let priority = *self.thread_priority.lock().expect("thread priority lock poisoned");
+        let cancel = self.cancel_token();
+        tokio::task::spawn_blocking(move || {
+            run_with_thread_priority(priority, || remove_background_batch_v1_json(&requests_json, &cancel))
+                .unwrap_or_else(|err| format!("{{\"code\":\"inference\",\"message\":\"{}\"}}", err))
+        })
+        .await
+        .unwrap_or_else(|_| "{\"code\":\"inference\",\"message\":\"inference task failed\"}".to_string())
+    }
+}
+
+impl Default for UnbgApi {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UnbgApi {
+    fn cancel_token(&self) -> CancelToken {
+        self.in_flight.lock().expect("cancel token lock poisoned").clone()
+    }
+}
+
+fn remove_background_v1_json(request_json: &str, cancel: Option<&CancelToken>) -> String {
+    let request: v1::RemoveBackgroundRequest = match serde_json::from_str(request_json) {
+        Ok(request) => request,
+        Err(_) => return "{\"code\":\"invalid-argument\",\"message\":\"invalid request json\"}".to_string(),
+    };
+    match remove_background_v1_cancellable(request, cancel) {
+        Ok(response) => serde_json::to_string(&response)
+            .unwrap_or_else(|_| "{\"code\":\"inference\",\"message\":\"response encode failed\"}".to_string()),
+        Err(err) => format!("{{\"code\":\"{}\",\"message\":\"{}\"}}", error_code(&err), err),
+    }
+}
+
+fn remove_background_batch_v1_json(requests_json: &str, cancel: &CancelToken) -> String {
+    let requests: Vec<v1::RemoveBackgroundRequest> = match serde_json::from_str(requests_json) {
+        Ok(requests) => requests,
+        Err(_) => return "{\"code\":\"invalid-argument\",\"message\":\"invalid request json\"}".to_string(),
+    };
+    let results: Vec<serde_json::Value> = requests
+        .into_iter()
+        .map(|request| match remove_background_v1_cancellable(request, Some(cancel)) {
+            Ok(response) => serde_json::to_value(response)
+                .unwrap_or_else(|_| serde_json::json!({ "code": "inference", "message": "response encode failed" })),
+            Err(err) => serde_json::json!({ "code": error_code(&err), "message": err.to_string() }),
+        })
+        .collect();
+    serde_json::to_string(&results).unwrap_or_else(|_| "{\"code\":\"inference\",\"message\":\"response encode failed\"}".to_string())
 }
 
 pub fn remove_background(request: FfiRemoveBackgroundRequest) -> Result<FfiRemoveBackgroundResponse, FfiError> {
+    remove_background_cancellable(request, None)
+}
+
+/// [`remove_background`] that ends with [`FfiError::Cancelled`] once `cancel` fires.
+pub fn remove_background_cancellable(
+    request: FfiRemoveBackgroundRequest,
+    cancel: Option<&CancelToken>,
+) -> Result<FfiRemoveBackgroundResponse, FfiError> {
     let runtime_cfg = unbg_core::resolve_runtime_config(RuntimeConfig {
         model: request.model.clone(),
         onnx_variant: request.onnx_variant.clone().unwrap_or_else(|| "fp16".to_string()),
@@ -88,37 +217,43 @@ pub fn remove_background(request: FfiRemoveBackgroundRequest) -> Result<FfiRemov
         gpu_backend: request.gpu_backend.clone().unwrap_or_else(|| "auto".to_string()),
         benchmark_provider: request.benchmark_provider.unwrap_or(true),
         model_dir: request.model_dir.clone(),
+        backend: String::new(),
+        thread_priority: String::new(),
     });
-    let backend = LocalOrtBackend::default();
     let estimated_bytes = estimate_rgba_bytes(ImageSize {
         width: request.width,
         height: request.height,
     });
     let telemetry = sink_from_env();
     let telemetry_ref = telemetry.as_ref().map(|sink| sink.as_ref());
+    let backend = default_registry().create(&runtime_cfg.backend).map_err(map_core_error)?;
     let inference = run_inference_with_telemetry(
-        &backend,
-        &InferenceRequest {
-            requested_model: parse_model_alias(&runtime_cfg.model)?,
-            onnx_variant: parse_onnx_variant_opt(Some(&runtime_cfg.onnx_variant))?.unwrap_or(OnnxVariant::Fp16),
-            execution_provider: parse_execution_provider_opt(Some(&runtime_cfg.execution_provider))?
-                .unwrap_or(ExecutionProvider::Auto),
-            gpu_backend: parse_gpu_backend_opt(Some(&runtime_cfg.gpu_backend))?.unwrap_or(GpuBackendPreference::Auto),
-            benchmark_provider: runtime_cfg.benchmark_provider,
-            emit_mask_png: true,
-            input_path: None,
-            input_bytes: Some(request.image_bytes),
-            model_dir: runtime_cfg.model_dir.map(PathBuf::from),
-            width: request.width,
-            height: request.height,
-        },
+        backend.as_ref(),
+        &InferenceRequest::builder()
+            .requested_model(parse_model_alias(&runtime_cfg.model)?)
+            .onnx_variant(parse_onnx_variant_opt(Some(&runtime_cfg.onnx_variant))?.unwrap_or(OnnxVariant::Fp16))
+            .execution_provider(
+                parse_execution_provider_opt(Some(&runtime_cfg.execution_provider))?.unwrap_or(ExecutionProvider::Auto),
+            )
+            .gpu_backend(parse_gpu_backend_opt(Some(&runtime_cfg.gpu_backend))?.unwrap_or(GpuBackendPreference::Auto))
+            .benchmark_provider(runtime_cfg.benchmark_provider)
+            .input_bytes(request.image_bytes)
+            .model_dir(runtime_cfg.model_dir.clone().map(PathBuf::from))
+            .dimensions(request.width, request.height)
+            .mask_threshold(request.mask_threshold)
+            .collect_timings(request.collect_timings.unwrap_or(false))
+            .emit_mask_rle(request.emit_mask_rle.unwrap_or(false))
+            .build(),
         &RuntimePolicy {
-            max_inference_pixels: request.max_inference_pixels.unwrap_or(2_000_000),
-            max_latency_ms: 1_500,
+            max_inference_pixels: request
+                .max_inference_pixels
+                .unwrap_or(RuntimePolicy::mobile().max_inference_pixels),
             allow_rmbg20: estimated_bytes <= 64 * 1024 * 1024,
+            ..RuntimePolicy::mobile()
         },
         PlatformTarget::Cli,
         telemetry_ref,
+        cancel,
     )
     .map_err(map_core_error)?;
 
@@ -130,9 +265,22 @@ pub fn remove_background(request: FfiRemoveBackgroundRequest) -> Result<FfiRemov
         provider_selected: inference.execution_provider_selected,
         backend_selected: inference.gpu_backend_selected,
         fallback_used: inference.fallback_used,
+        timings: inference.timings,
+        mask_rle: inference.mask_rle,
     })
 }
 
+/// Estimates the decoded RGBA footprint and the size the image would be clamped to for a pixel budget.
+pub fn estimate_memory(width: u32, height: u32, max_inference_pixels: u32) -> FfiMemoryEstimate {
+    let size = ImageSize { width, height };
+    let suggested = clamp_to_max_pixels(size, max_inference_pixels);
+    FfiMemoryEstimate {
+        estimated_bytes: estimate_rgba_bytes(size),
+        suggested_width: suggested.width,
+        suggested_height: suggested.height,
+    }
+}
+
 pub fn supported_model_aliases() -> Vec<String> {
     vec![
         "auto".to_string(),
@@ -143,19 +291,55 @@ pub fn supported_model_aliases() -> Vec<String> {
     ]
 }
 
+/// The v1 `compositePng`: `image_bytes` composited over `background`, or `None` when it is
+/// transparent.
+pub fn composite_v1(image_bytes: &[u8], mask_png: &[u8], background: &BackgroundFill) -> Result<Option<Vec<u8>>, FfiError> {
+    let backdrop = match background {
+        BackgroundFill::Transparent => return Ok(None),
+        BackgroundFill::Color(color) => Backdrop::Color(*color),
+        BackgroundFill::Image(bytes) => Backdrop::Image(bytes),
+    };
+    composite_png(image_bytes, mask_png, &backdrop, &EncodeOptions::default())
+        .map(Some)
+        .map_err(|err| map_core_error(composite_error(err)))
+}
+
+/// Undecodable inputs are the caller's to fix; an encoder failure is ours.
+fn composite_error(err: CutoutError) -> CoreError {
+    match err {
+        CutoutError::Encode(_) => CoreError::Backend(err.to_string()),
+        _ => CoreError::InvalidArgument(err.to_string()),
+    }
+}
+
 pub fn remove_background_v1(request: v1::RemoveBackgroundRequest) -> Result<v1::RemoveBackgroundResponse, FfiError> {
-    let out = remove_background(FfiRemoveBackgroundRequest {
-        image_bytes: request.image_bytes,
-        width: request.width,
-        height: request.height,
-        model: request.model,
-        onnx_variant: request.onnx_variant,
-        execution_provider: request.execution_provider,
-        gpu_backend: request.gpu_backend,
-        benchmark_provider: request.benchmark_provider,
-        model_dir: request.model_dir,
-        max_inference_pixels: request.max_inference_pixels,
-    })?;
+    remove_background_v1_cancellable(request, None)
+}
+
+fn remove_background_v1_cancellable(
+    request: v1::RemoveBackgroundRequest,
+    cancel: Option<&CancelToken>,
+) -> Result<v1::RemoveBackgroundResponse, FfiError> {
+    let source = (request.background != BackgroundFill::Transparent).then(|| request.image_bytes.clone());
+    let out = remove_background_cancellable(
+        FfiRemoveBackgroundRequest {
+            image_bytes: request.image_bytes,
+            width: request.width,
+            height: request.height,
+            model: request.model,
+            onnx_variant: request.onnx_variant,
+            execution_provider: request.execution_provider,
+            gpu_backend: request.gpu_backend,
+            benchmark_provider: request.benchmark_provider,
+            model_dir: request.model_dir,
+            max_inference_pixels: request.max_inference_pixels,
+            mask_threshold: request.mask_threshold,
+            collect_timings: Some(request.collect_timings),
+            emit_mask_rle: Some(request.emit_mask_rle),
+        },
+        cancel,
+    )?;
+    let composite_png = composite_v1(source.as_deref().unwrap_or_default(), &out.mask_png, &request.background)?;
     Ok(v1::RemoveBackgroundResponse {
         model_used: out.model_used,
         width: out.width,
@@ -164,6 +348,9 @@ pub fn remove_background_v1(request: v1::RemoveBackgroundRequest) -> Result<v1::
         provider_selected: out.provider_selected,
         backend_selected: out.backend_selected,
         fallback_used: out.fallback_used,
+        composite_png,
+        timings: out.timings,
+        mask_rle: out.mask_rle,
     })
 }
 
@@ -220,10 +407,24 @@ fn parse_gpu_backend_opt(raw: Option<&str>) -> Result<Option<GpuBackendPreferenc
     }
 }
 
+fn parse_thread_priority_opt(raw: Option<&str>) -> Result<Option<ThreadPriority>, FfiError> {
+    match raw.map(|value| value.to_ascii_lowercase()) {
+        None => Ok(None),
+        Some(value) => match value.as_str() {
+            "normal" => Ok(Some(ThreadPriority::Normal)),
+            "low" => Ok(Some(ThreadPriority::Low)),
+            "lowest" => Ok(Some(ThreadPriority::Lowest)),
+            _other => Err(FfiError::InvalidArgument),
+        },
+    }
+}
+
 fn map_core_error(err: CoreError) -> FfiError {
     let info: ErrorInfo = err.as_error_info();
     match info.code {
-        unbg_core::ErrorCode::MissingInput => FfiError::InvalidArgument,
+        unbg_core::ErrorCode::MissingInput | unbg_core::ErrorCode::InvalidArgument => FfiError::InvalidArgument,
+        unbg_core::ErrorCode::ModelNotInstalled | unbg_core::ErrorCode::OnnxFileMissing => FfiError::ModelNotInstalled,
+        unbg_core::ErrorCode::Cancelled => FfiError::Cancelled,
         _ => FfiError::Inference,
     }
 }
@@ -240,6 +441,8 @@ fn error_code(err: &FfiError) -> &'static str {
     match err {
         FfiError::InvalidArgument => "invalid-argument",
         FfiError::Inference => "inference",
+        FfiError::ModelNotInstalled => "model-not-installed",
+        FfiError::Cancelled => "cancelled",
     }
 }
 
@@ -1,7 +1,7 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use unbg_core::{v1, ExecutionProvider, GpuBackendPreference, ModelKind};
-use unbg_uniffi::{remove_background, FfiRemoveBackgroundRequest};
+use unbg_core::{v1, BackgroundFill, ExecutionProvider, GpuBackendPreference, ModelKind, RuntimePolicy};
+use unbg_uniffi::{composite_v1, remove_background, FfiRemoveBackgroundRequest};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IosBridgeRequest {
@@ -36,7 +36,11 @@ pub fn process_image(request: IosBridgeRequest) -> Result<IosBridgeResponse> {
         gpu_backend: request.gpu_backend.map(gpu_backend_label),
         benchmark_provider: request.benchmark_provider,
         model_dir: request.model_dir,
-        max_inference_pixels: Some(1_500_000),
+        max_inference_pixels: Some(RuntimePolicy::mobile().max_inference_pixels),
+        mask_threshold: None,
+        background: BackgroundFill::Transparent,
+        collect_timings: false,
+        emit_mask_rle: false,
     })?;
     Ok(IosBridgeResponse {
         model_used: parse_model_kind(&output.model_used)?,
@@ -48,6 +52,7 @@ pub fn process_image(request: IosBridgeRequest) -> Result<IosBridgeResponse> {
 }
 
 pub fn process_image_v1(request: v1::RemoveBackgroundRequest) -> Result<v1::RemoveBackgroundResponse> {
+    let source = (request.background != BackgroundFill::Transparent).then(|| request.image_bytes.clone());
     let out = remove_background(FfiRemoveBackgroundRequest {
         image_bytes: request.image_bytes,
         width: request.width,
@@ -58,8 +63,14 @@ pub fn process_image_v1(request: v1::RemoveBackgroundRequest) -> Result<v1::Remo
         gpu_backend: request.gpu_backend,
         benchmark_provider: request.benchmark_provider,
         model_dir: request.model_dir,
-        max_inference_pixels: request.max_inference_pixels.or(Some(1_500_000)),
+        max_inference_pixels: request
+            .max_inference_pixels
+            .or(Some(RuntimePolicy::mobile().max_inference_pixels)),
+        mask_threshold: request.mask_threshold,
+        collect_timings: Some(request.collect_timings),
+        emit_mask_rle: Some(request.emit_mask_rle),
     })?;
+    let composite_png = composite_v1(source.as_deref().unwrap_or_default(), &out.mask_png, &request.background)?;
     Ok(v1::RemoveBackgroundResponse {
         model_used: out.model_used,
         width: out.width,
@@ -68,6 +79,9 @@ pub fn process_image_v1(request: v1::RemoveBackgroundRequest) -> Result<v1::Remo
         provider_selected: out.provider_selected,
         backend_selected: out.backend_selected,
         fallback_used: out.fallback_used,
+        composite_png,
+        timings: out.timings,
+        mask_rle: out.mask_rle,
     })
 }
 
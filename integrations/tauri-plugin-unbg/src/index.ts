@@ -2,6 +2,7 @@ export type ModelAlias = "auto" | "fast" | "quality" | "rmbg-1.4" | "rmbg-2.0";
 export type OnnxVariant = "auto" | "fp16" | "fp32" | "quantized";
 export type ExecutionProvider = "auto" | "gpu" | "cpu";
 export type GpuBackend = "auto" | "directml" | "cuda" | "coreml" | "metal";
+export type BackgroundFill = "transparent" | { color: [number, number, number, number] } | { image: number[] };
 
 export interface RemoveBackgroundRequest {
   imageBytes: number[];
@@ -14,6 +15,17 @@ export interface RemoveBackgroundRequest {
   benchmarkProvider?: boolean;
   onnxVariant?: OnnxVariant;
   modelDir?: string;
+  maskThreshold?: number;
+  background?: BackgroundFill;
+  collectTimings?: boolean;
+  emitMaskRle?: boolean;
+}
+
+export interface InferenceTimings {
+  preprocessMs: number;
+  sessionRunMs: number;
+  postprocessMs: number;
+  totalMs: number;
 }
 
 export interface RemoveBackgroundResponse {
@@ -24,12 +36,24 @@ export interface RemoveBackgroundResponse {
   providerSelected: string;
   backendSelected?: string | null;
   fallbackUsed: boolean;
+  compositePng?: number[] | null;
+  timings?: InferenceTimings | null;
+  /** `maskPng` as `[value, run]` pairs, when `emitMaskRle` was set. */
+  maskRle?: [number, number][] | null;
+}
+
+export interface MemoryEstimate {
+  estimatedBytes: number;
+  suggestedWidth: number;
+  suggestedHeight: number;
 }
 
 export type InvokeLike = <T>(cmd: string, args?: Record<string, unknown>) => Promise<T>;
 
 export const TAURI_UNBG_COMMANDS_V1 = {
-  removeBackground: "plugin:unbg|tauri_remove_background_command"
+  removeBackground: "plugin:unbg|tauri_remove_background_command",
+  cancelRemoveBackground: "plugin:unbg|tauri_cancel_remove_background_command",
+  estimateMemory: "plugin:unbg|tauri_estimate_memory_command"
 } as const;
 
 export type RemoveBackgroundRequestV1 = RemoveBackgroundRequest;
@@ -51,6 +75,30 @@ export async function removeBackground(
   });
 }
 
+/**
+ * Cancels every `removeBackground` call still running; they reject with an "inference cancelled"
+ * error. Calls made afterwards are unaffected.
+ */
+export async function cancelRemoveBackground(invoke: InvokeLike): Promise<void> {
+  return invoke<void>(TAURI_UNBG_COMMANDS_V1.cancelRemoveBackground);
+}
+
+/**
+ * Estimates decoded memory and the size an image would be clamped to before inference.
+ */
+export async function estimateMemory(
+  invoke: InvokeLike,
+  width: number,
+  height: number,
+  maxInferencePixels?: number
+): Promise<MemoryEstimate> {
+  return invoke<MemoryEstimate>(TAURI_UNBG_COMMANDS_V1.estimateMemory, {
+    width,
+    height,
+    maxInferencePixels
+  });
+}
+
 export const MODEL_ALIASES: readonly ModelAlias[] = [
   "auto",
   "fast",
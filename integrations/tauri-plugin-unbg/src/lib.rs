@@ -1,12 +1,14 @@
+use std::sync::{LazyLock, Mutex};
+
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use unbg_core::{
-    run_inference_with_telemetry, v1, ExecutionProvider, GpuBackendPreference, InferenceRequest, ModelKind, OnnxVariant, PlatformTarget,
+    run_inference_with_telemetry, v1, BackgroundFill, CancelToken, CoreError, ExecutionProvider, GpuBackendPreference, InferenceRequest, InferenceTimings, ModelKind, OnnxVariant, PlatformTarget,
     RuntimeConfig, RuntimePolicy,
 };
-use unbg_image::{estimate_rgba_bytes, ImageSize};
+use unbg_image::{clamp_to_max_pixels, composite_png, estimate_rgba_bytes, Backdrop, EncodeOptions, ImageSize};
 use unbg_telemetry::sink_from_env;
-use unbg_runtime_ort::LocalOrtBackend;
+use unbg_runtime_ort::default_registry;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TauriRemoveRequest {
@@ -20,6 +22,11 @@ pub struct TauriRemoveRequest {
     pub benchmark_provider: Option<bool>,
     pub onnx_variant: Option<OnnxVariant>,
     pub model_dir: Option<String>,
+    pub mask_threshold: Option<f32>,
+    #[serde(default)]
+    pub collect_timings: bool,
+    #[serde(default)]
+    pub emit_mask_rle: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -31,9 +38,24 @@ pub struct TauriRemoveResponse {
     pub provider_selected: String,
     pub backend_selected: Option<String>,
     pub fallback_used: bool,
+    pub timings: Option<InferenceTimings>,
+    pub mask_rle: Option<Vec<(u8, u32)>>,
+}
+
+/// Token shared by the commands running now; [`cancel_remove_background`] fires it and swaps in
+/// a fresh one, so commands started afterwards are unaffected.
+static IN_FLIGHT: LazyLock<Mutex<CancelToken>> = LazyLock::new(Mutex::default);
+
+/// Cancels every `remove_background*` call that has not returned yet; they fail with
+/// [`CoreError::Cancelled`] at the next stage boundary.
+pub fn cancel_remove_background() {
+    let mut in_flight = IN_FLIGHT.lock().expect("cancel token lock poisoned");
+    in_flight.cancel();
+    *in_flight = CancelToken::new();
 }
 
 pub fn remove_background(request: TauriRemoveRequest) -> Result<TauriRemoveResponse> {
+    let cancel = IN_FLIGHT.lock().expect("cancel token lock poisoned").clone();
     let runtime_cfg = unbg_core::resolve_runtime_config(RuntimeConfig {
         model: model_label(request.model).to_string(),
         onnx_variant: request
@@ -50,43 +72,52 @@ pub fn remove_background(request: TauriRemoveRequest) -> Result<TauriRemoveRespo
             .unwrap_or_else(|| "auto".to_string()),
         benchmark_provider: request.benchmark_provider.unwrap_or(true),
         model_dir: request.model_dir.clone(),
+        backend: String::new(),
+        thread_priority: String::new(),
     });
-    let backend = LocalOrtBackend::default();
     let estimated_bytes = estimate_rgba_bytes(ImageSize {
         width: request.width,
         height: request.height,
     });
     let policy = RuntimePolicy {
         max_inference_pixels: request.max_inference_pixels,
-        max_latency_ms: 1_500,
         allow_rmbg20: estimated_bytes <= 64 * 1024 * 1024,
+        ..RuntimePolicy::desktop()
     };
     let telemetry = sink_from_env();
     let telemetry_ref = telemetry.as_ref().map(|sink| sink.as_ref());
+    let backend = default_registry().create(&runtime_cfg.backend)?;
     let inference = run_inference_with_telemetry(
-        &backend,
-        &InferenceRequest {
-            requested_model: parse_model_alias(&runtime_cfg.model).map_err(anyhow::Error::msg)?,
-            onnx_variant: parse_onnx_variant_opt(Some(&runtime_cfg.onnx_variant))
-                .map_err(anyhow::Error::msg)?
-                .unwrap_or(OnnxVariant::Fp16),
-            execution_provider: parse_execution_provider_opt(Some(&runtime_cfg.execution_provider))
-                .map_err(anyhow::Error::msg)?
-                .unwrap_or(ExecutionProvider::Auto),
-            gpu_backend: parse_gpu_backend_opt(Some(&runtime_cfg.gpu_backend))
-                .map_err(anyhow::Error::msg)?
-                .unwrap_or(GpuBackendPreference::Auto),
-            benchmark_provider: runtime_cfg.benchmark_provider,
-            emit_mask_png: true,
-            input_path: None,
-            input_bytes: Some(request.image_bytes),
-            model_dir: runtime_cfg.model_dir.map(std::path::PathBuf::from),
-            width: request.width,
-            height: request.height,
-        },
+        backend.as_ref(),
+        &InferenceRequest::builder()
+            .requested_model(parse_model_alias(&runtime_cfg.model).map_err(anyhow::Error::msg)?)
+            .onnx_variant(
+                parse_onnx_variant_opt(Some(&runtime_cfg.onnx_variant))
+                    .map_err(anyhow::Error::msg)?
+                    .unwrap_or(OnnxVariant::Fp16),
+            )
+            .execution_provider(
+                parse_execution_provider_opt(Some(&runtime_cfg.execution_provider))
+                    .map_err(anyhow::Error::msg)?
+                    .unwrap_or(ExecutionProvider::Auto),
+            )
+            .gpu_backend(
+                parse_gpu_backend_opt(Some(&runtime_cfg.gpu_backend))
+                    .map_err(anyhow::Error::msg)?
+                    .unwrap_or(GpuBackendPreference::Auto),
+            )
+            .benchmark_provider(runtime_cfg.benchmark_provider)
+            .input_bytes(request.image_bytes)
+            .model_dir(runtime_cfg.model_dir.clone().map(std::path::PathBuf::from))
+            .dimensions(request.width, request.height)
+            .mask_threshold(request.mask_threshold)
+            .collect_timings(request.collect_timings)
+            .emit_mask_rle(request.emit_mask_rle)
+            .build(),
         &policy,
         PlatformTarget::Tauri,
         telemetry_ref,
+        Some(&cancel),
     )?;
     Ok(TauriRemoveResponse {
         model_used: inference.model_used,
@@ -96,6 +127,8 @@ pub fn remove_background(request: TauriRemoveRequest) -> Result<TauriRemoveRespo
         provider_selected: inference.execution_provider_selected,
         backend_selected: inference.gpu_backend_selected,
         fallback_used: inference.fallback_used,
+        timings: inference.timings,
+        mask_rle: inference.mask_rle,
     })
 }
 
@@ -111,6 +144,13 @@ pub struct TauriCommandRequest {
     pub benchmark_provider: Option<bool>,
     pub onnx_variant: Option<String>,
     pub model_dir: Option<String>,
+    pub mask_threshold: Option<f32>,
+    #[serde(default)]
+    pub background: BackgroundFill,
+    #[serde(default)]
+    pub collect_timings: bool,
+    #[serde(default)]
+    pub emit_mask_rle: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -122,6 +162,9 @@ pub struct TauriCommandResponse {
     pub provider_selected: String,
     pub backend_selected: Option<String>,
     pub fallback_used: bool,
+    pub composite_png: Option<Vec<u8>>,
+    pub timings: Option<InferenceTimings>,
+    pub mask_rle: Option<Vec<(u8, u32)>>,
 }
 
 pub fn remove_background_command(request: TauriCommandRequest) -> std::result::Result<TauriCommandResponse, String> {
@@ -130,12 +173,18 @@ pub fn remove_background_command(request: TauriCommandRequest) -> std::result::R
         width: request.width,
         height: request.height,
         model: request.model.unwrap_or_else(|| "auto".to_string()),
-        max_inference_pixels: request.max_inference_pixels.or(Some(2_000_000)),
+        max_inference_pixels: request
+            .max_inference_pixels
+            .or(Some(RuntimePolicy::desktop().max_inference_pixels)),
         execution_provider: request.execution_provider,
         gpu_backend: request.gpu_backend,
         benchmark_provider: request.benchmark_provider,
         onnx_variant: request.onnx_variant,
         model_dir: request.model_dir,
+        mask_threshold: request.mask_threshold,
+        background: request.background,
+        collect_timings: request.collect_timings,
+        emit_mask_rle: request.emit_mask_rle,
     })?;
     Ok(TauriCommandResponse {
         model_used: v1_result.model_used,
@@ -145,23 +194,41 @@ pub fn remove_background_command(request: TauriCommandRequest) -> std::result::R
         provider_selected: v1_result.provider_selected,
         backend_selected: v1_result.backend_selected,
         fallback_used: v1_result.fallback_used,
+        composite_png: v1_result.composite_png,
+        timings: v1_result.timings,
+        mask_rle: v1_result.mask_rle,
     })
 }
 
 pub fn remove_background_v1(request: v1::RemoveBackgroundRequest) -> std::result::Result<v1::RemoveBackgroundResponse, String> {
+    let source = (request.background != BackgroundFill::Transparent).then(|| request.image_bytes.clone());
     let response = remove_background(TauriRemoveRequest {
         image_bytes: request.image_bytes,
         width: request.width,
         height: request.height,
         model: parse_model_alias(&request.model)?,
-        max_inference_pixels: request.max_inference_pixels.unwrap_or(2_000_000),
+        max_inference_pixels: request
+            .max_inference_pixels
+            .unwrap_or(RuntimePolicy::desktop().max_inference_pixels),
         execution_provider: parse_execution_provider_opt(request.execution_provider.as_deref())?,
         gpu_backend: parse_gpu_backend_opt(request.gpu_backend.as_deref())?,
         benchmark_provider: request.benchmark_provider,
         onnx_variant: parse_onnx_variant_opt(request.onnx_variant.as_deref())?,
         model_dir: request.model_dir,
+        mask_threshold: request.mask_threshold,
+        collect_timings: request.collect_timings,
+        emit_mask_rle: request.emit_mask_rle,
     })
-    .map_err(|err| err.to_string())?;
+    .map_err(command_error)?;
+    let backdrop = match &request.background {
+        BackgroundFill::Transparent => None,
+        BackgroundFill::Color(color) => Some(Backdrop::Color(*color)),
+        BackgroundFill::Image(bytes) => Some(Backdrop::Image(bytes)),
+    };
+    let composite_png = match (source, backdrop) {
+        (Some(bytes), Some(fill)) => Some(composite_png(&bytes, &response.mask_png, &fill, &EncodeOptions::default()).map_err(|err| err.to_string())?),
+        _ => None,
+    };
     Ok(v1::RemoveBackgroundResponse {
         model_used: model_label(response.model_used).to_string(),
         width: response.width,
@@ -170,22 +237,69 @@ pub fn remove_background_v1(request: v1::RemoveBackgroundRequest) -> std::result
         provider_selected: response.provider_selected,
         backend_selected: response.backend_selected,
         fallback_used: response.fallback_used,
+        composite_png,
+        timings: response.timings,
+        mask_rle: response.mask_rle,
     })
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TauriMemoryEstimate {
+    pub estimated_bytes: u64,
+    pub suggested_width: u32,
+    pub suggested_height: u32,
+}
+
+pub fn estimate_memory(width: u32, height: u32, max_inference_pixels: Option<u32>) -> TauriMemoryEstimate {
+    let size = ImageSize { width, height };
+    let suggested = clamp_to_max_pixels(size, max_inference_pixels.unwrap_or(RuntimePolicy::desktop().max_inference_pixels));
+    TauriMemoryEstimate {
+        estimated_bytes: estimate_rgba_bytes(size),
+        suggested_width: suggested.width,
+        suggested_height: suggested.height,
+    }
+}
+
+#[cfg(feature = "tauri-plugin")]
+#[tauri::command]
+fn tauri_estimate_memory_command(width: u32, height: u32, max_inference_pixels: Option<u32>) -> TauriMemoryEstimate {
+    estimate_memory(width, height, max_inference_pixels)
+}
+
 #[cfg(feature = "tauri-plugin")]
 #[tauri::command]
 fn tauri_remove_background_command(request: TauriCommandRequest) -> std::result::Result<TauriCommandResponse, String> {
     remove_background_command(request)
 }
 
+#[cfg(feature = "tauri-plugin")]
+#[tauri::command]
+fn tauri_cancel_remove_background_command() {
+    cancel_remove_background()
+}
+
 #[cfg(feature = "tauri-plugin")]
 pub fn init<R: tauri::Runtime>() -> tauri::plugin::TauriPlugin<R> {
     tauri::plugin::Builder::new("unbg")
-        .invoke_handler(tauri::generate_handler![tauri_remove_background_command])
+        .invoke_handler(tauri::generate_handler![
+            tauri_remove_background_command,
+            tauri_cancel_remove_background_command,
+            tauri_estimate_memory_command
+        ])
         .build()
 }
 
+/// Error string for the frontend; a missing model says how to install it instead of just failing.
+fn command_error(err: anyhow::Error) -> String {
+    match err.downcast_ref::<CoreError>() {
+        Some(CoreError::ModelNotInstalled { .. } | CoreError::OnnxFileMissing { .. }) => {
+            format!("{}; install it with `unbg models install` or point model_dir at an existing install", err)
+        }
+        _ => err.to_string(),
+    }
+}
+
 fn parse_model_alias(raw: &str) -> std::result::Result<ModelKind, String> {
     match raw.to_ascii_lowercase().as_str() {
         "auto" => Ok(ModelKind::Auto),
@@ -326,6 +440,10 @@ mod tests {
             benchmark_provider: None,
             onnx_variant: Some("fp16".to_string()),
             model_dir: None,
+            mask_threshold: None,
+            background: BackgroundFill::Transparent,
+            collect_timings: false,
+            emit_mask_rle: false,
         })
         .expect("command should succeed");
 
@@ -336,6 +454,22 @@ mod tests {
         assert!(!response.provider_selected.is_empty());
     }
 
+    #[test]
+    fn cancel_reaches_only_the_calls_already_running() {
+        let running = IN_FLIGHT.lock().unwrap().clone();
+        cancel_remove_background();
+        assert!(running.is_cancelled());
+        assert!(!IN_FLIGHT.lock().unwrap().is_cancelled());
+    }
+
+    #[test]
+    fn estimate_memory_suggests_clamped_size() {
+        let estimate = estimate_memory(4000, 3000, Some(1_000_000));
+        assert_eq!(estimate.estimated_bytes, 4000 * 3000 * 4);
+        assert!(estimate.suggested_width * estimate.suggested_height <= 1_000_000);
+        assert!(estimate.suggested_width > estimate.suggested_height);
+    }
+
     #[test]
     fn command_rejects_invalid_model() {
         let error = remove_background_command(TauriCommandRequest {
@@ -349,6 +483,10 @@ mod tests {
             benchmark_provider: None,
             onnx_variant: None,
             model_dir: None,
+            mask_threshold: None,
+            background: BackgroundFill::Transparent,
+            collect_timings: false,
+            emit_mask_rle: false,
         })
         .expect_err("should fail for invalid model");
 
@@ -0,0 +1,68 @@
+#!/usr/bin/env python3
+"""Writes the tiny ONNX models the runtime tests load, without needing the onnx package.
+
+Run from anywhere; the files land in crates/unbg-runtime-ort/tests/fixtures.
+"""
+from pathlib import Path
+
+ROOT = Path(__file__).resolve().parent.parent
+FIXTURES = ROOT / "crates" / "unbg-runtime-ort" / "tests" / "fixtures"
+
+FLOAT = 1
+ATTR_INTS = 7
+
+
+def varint(value: int) -> bytes:
+    value &= (1 << 64) - 1
+    out = bytearray()
+    while True:
+        byte = value & 0x7F
+        value >>= 7
+        if value:
+            out.append(byte | 0x80)
+        else:
+            out.append(byte)
+            return bytes(out)
+
+
+def field_varint(number: int, value: int) -> bytes:
+    return varint(number << 3) + varint(value)
+
+
+def field_bytes(number: int, payload: bytes) -> bytes:
+    return varint((number << 3) | 2) + varint(len(payload)) + payload
+
+
+def field_str(number: int, text: str) -> bytes:
+    return field_bytes(number, text.encode())
+
+
+def tensor_value(name: str, dims) -> bytes:
+    shape = b"".join(
+        field_bytes(1, field_str(2, dim) if isinstance(dim, str) else field_varint(1, dim)) for dim in dims
+    )
+    tensor_type = field_varint(1, FLOAT) + field_bytes(2, shape)
+    return field_str(1, name) + field_bytes(2, field_bytes(1, tensor_type))
+
+
+def segmentation_model() -> bytes:
+    """NCHW image in, one-channel mask out: the channel mean of the input."""
+    axes = field_str(1, "axes") + field_varint(8, 1) + field_varint(20, ATTR_INTS)
+    node = field_str(1, "input") + field_str(2, "mask") + field_str(4, "ReduceMean") + field_bytes(5, axes)
+    graph = (
+        field_bytes(1, node)
+        + field_str(2, "segmentation-fixture")
+        + field_bytes(11, tensor_value("input", ["batch", 3, 32, 32]))
+        + field_bytes(12, tensor_value("mask", ["batch", 1, 32, 32]))
+    )
+    opset = field_str(1, "") + field_varint(2, 13)
+    return field_varint(1, 8) + field_bytes(8, opset) + field_bytes(7, graph)
+
+
+def main() -> None:
+    FIXTURES.mkdir(parents=True, exist_ok=True)
+    (FIXTURES / "segmentation.onnx").write_bytes(segmentation_model())
+
+
+if __name__ == "__main__":
+    main()